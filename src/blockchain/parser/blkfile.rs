@@ -1,14 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::From;
 use std::fs::{self, DirEntry, File};
-use std::io::{self, BufReader, Seek, SeekFrom};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use memmap2::Mmap;
 
 use crate::blockchain::parser::reader::BlockchainRead;
 use crate::blockchain::proto::block::Block;
-use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::script::AddressParams;
+use crate::errors::{DataLocation, OpError, OpErrorKind, OpResult};
+
+/// Defines how raw blocks are fetched from blk files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlkReadMode {
+    /// Reads each block with a single read() from a cached file handle.
+    /// At most the given number of files are kept open.
+    Buffered(usize),
+    /// Maps blk files into memory and decodes blocks directly from the mapped slice,
+    /// scripts and witnesses refer to the mapping instead of being copied.
+    /// At most the given number of files are kept mapped, blocks still in use keep their file mapped.
+    /// Needs a lot of address space, so this should only be used on 64-bit systems.
+    Mmap(usize),
+}
+
+/// Default number of blk files kept open or mapped
+pub const DEFAULT_OPEN_FILES: usize = 32;
+
+impl Default for BlkReadMode {
+    fn default() -> Self {
+        BlkReadMode::Buffered(DEFAULT_OPEN_FILES)
+    }
+}
 
 /// Holds all necessary data about a raw blk file
 #[derive(Debug)]
@@ -23,12 +49,46 @@ impl BlkFile {
         BlkFile { path, size }
     }
 
-    #[inline]
-    pub fn read_block(&self, offset: u64, version_id: u8) -> OpResult<Block> {
-        let mut f = BufReader::new(File::open(&self.path)?);
+    /// Reads the size prefix of the block at `offset` and then the whole block with a single read.
+    fn read_raw_block(&self, f: &mut File, offset: u64) -> OpResult<Vec<u8>> {
+        self.check_offset(offset)?;
         f.seek(SeekFrom::Start(offset - 4))?;
         let block_size = f.read_u32::<LittleEndian>()?;
-        f.read_block(block_size, version_id)
+        if offset + u64::from(block_size) > self.size {
+            return Err(self.out_of_bounds(offset));
+        }
+        let mut raw = vec![0u8; block_size as usize];
+        f.read_exact(&mut raw)?;
+        Ok(raw)
+    }
+
+    /// Returns the raw block at `offset` from a mapped blk file without copying it.
+    fn mapped_block(&self, map: Arc<Mmap>, offset: u64) -> OpResult<Bytes> {
+        self.check_offset(offset)?;
+        let start = offset as usize;
+        let mut size_bytes = map
+            .get(start - 4..start)
+            .ok_or_else(|| self.out_of_bounds(offset))?;
+        let block_size = size_bytes.read_u32::<LittleEndian>()? as usize;
+        Bytes::from_shared(map)
+            .slice(start..start + block_size)
+            .ok_or_else(|| self.out_of_bounds(offset))
+    }
+
+    #[inline]
+    fn check_offset(&self, offset: u64) -> OpResult<()> {
+        if offset < 4 || offset > self.size {
+            return Err(self.out_of_bounds(offset));
+        }
+        Ok(())
+    }
+
+    fn out_of_bounds(&self, offset: u64) -> OpError {
+        OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+            "Block offset {} is out of bounds for {}.",
+            offset,
+            self.path.display()
+        ))
     }
 
     /// Collects all blk*.dat paths in the given directory
@@ -92,9 +152,183 @@ impl BlkFile {
     }
 }
 
+/// Gives concurrent access to all blk files using the configured BlkReadMode
+pub struct BlkStore {
+    files: HashMap<usize, BlkFile>,
+    mode: BlkReadMode,
+    max_open_files: usize,
+    // Least recently used memory maps, most recent at the back (BlkReadMode::Mmap)
+    maps: Mutex<VecDeque<(usize, Arc<Mmap>)>>,
+    // Least recently used file handles, most recent at the back (BlkReadMode::Buffered)
+    handles: Mutex<VecDeque<(usize, File)>>,
+}
+
+impl BlkStore {
+    pub fn new(files: HashMap<usize, BlkFile>, mode: BlkReadMode) -> Self {
+        let max_open_files = match mode {
+            BlkReadMode::Buffered(n) | BlkReadMode::Mmap(n) => n,
+        };
+        BlkStore {
+            files,
+            mode,
+            max_open_files,
+            maps: Mutex::new(VecDeque::with_capacity(max_open_files)),
+            handles: Mutex::new(VecDeque::with_capacity(max_open_files)),
+        }
+    }
+
+    /// Reads and decodes the block stored at `offset` in blk file `n_file`.
+    /// `aux_pow` enables parsing of merged mining proofs.
+    pub fn read_block(
+        &self,
+        n_file: usize,
+        offset: u64,
+        address_params: &Arc<AddressParams>,
+        aux_pow: bool,
+    ) -> OpResult<Block> {
+        let blk_file = match self.files.get(&n_file) {
+            Some(f) => f,
+            None => {
+                return Err(OpError::new(OpErrorKind::RuntimeError)
+                    .join_msg(&format!("Missing blk file with index {}.", n_file)))
+            }
+        };
+        match self.mode {
+            BlkReadMode::Mmap(_) => {
+                let raw = blk_file.mapped_block(self.get_map(n_file, blk_file)?, offset)?;
+                Cursor::new(raw.clone()).read_block(raw.len() as u32, address_params, aux_pow)
+            }
+            BlkReadMode::Buffered(_) => {
+                let mut f = self.checkout_handle(n_file, blk_file)?;
+                let raw = blk_file.read_raw_block(&mut f, offset);
+                self.return_handle(n_file, f)?;
+                // Scripts refer to the block buffer instead of being copied again
+                let raw = Bytes::from(raw?);
+                Cursor::new(raw.clone()).read_block(raw.len() as u32, address_params, aux_pow)
+            }
+        }
+    }
+
+    /// Describes the position of a block for error messages
+    pub fn location(&self, n_file: usize, offset: u64, height: usize) -> DataLocation {
+        DataLocation {
+            file: self.files.get(&n_file).map(|f| f.path.clone()),
+            offset: Some(offset),
+            height: Some(height as u64),
+        }
+    }
+
+    /// Returns the memory map for the given file and creates it if it isn't cached.
+    /// The least recently used map is dropped if the cache is full, it is unmapped once
+    /// no decoded block refers to it anymore.
+    fn get_map(&self, n_file: usize, blk_file: &BlkFile) -> OpResult<Arc<Mmap>> {
+        let mut maps = self.maps.lock()?;
+        let map = match maps.iter().position(|(n, _)| *n == n_file) {
+            Some(pos) => transform!(maps.remove(pos)).1,
+            None => {
+                trace!(target: "blkfile", "Mapping {} ...", blk_file.path.display());
+                let file = File::open(&blk_file.path)?;
+                // Safety: blk files are append-only, the node never rewrites mapped regions.
+                Arc::new(unsafe { Mmap::map(&file)? })
+            }
+        };
+        maps.push_back((n_file, Arc::clone(&map)));
+        while maps.len() > self.max_open_files {
+            maps.pop_front();
+        }
+        Ok(map)
+    }
+
+    /// Takes an open handle for the given file out of the cache or opens a new one.
+    /// Concurrent readers of the same file get separate handles, so seeking is safe.
+    fn checkout_handle(&self, n_file: usize, blk_file: &BlkFile) -> OpResult<File> {
+        {
+            let mut handles = self.handles.lock()?;
+            if let Some(pos) = handles.iter().position(|(n, _)| *n == n_file) {
+                if let Some((_, f)) = handles.remove(pos) {
+                    return Ok(f);
+                }
+            }
+        }
+        Ok(File::open(&blk_file.path)?)
+    }
+
+    /// Puts the handle back as most recently used and closes the least recently used one if full
+    fn return_handle(&self, n_file: usize, f: File) -> OpResult<()> {
+        let mut handles = self.handles.lock()?;
+        handles.push_back((n_file, f));
+        while handles.len() > self.max_open_files {
+            handles.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of currently open file handles or maps
+    #[cfg(test)]
+    fn open_files(&self) -> usize {
+        self.handles.lock().unwrap().len() + self.maps.lock().unwrap().len()
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
+    use crate::blockchain::parser::index::BlockIndexRecord;
+    use crate::common::fixtures::GENESIS;
+    use crate::common::utils;
+    use std::io::Write;
+
+    /// Writes `n` copies of the genesis block to a temporary blk file.
+    /// The nonce of each copy is set to its index, so blocks can be told apart.
+    pub fn write_blk_file(name: &str, n: u32) -> (BlkFile, Vec<BlockIndexRecord>) {
+        let path = std::env::temp_dir().join(format!("{}-{}.dat", name, std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        let mut records = Vec::new();
+        let mut pos = 0u64;
+        for i in 0..n {
+            let mut raw = utils::hex_to_vec(GENESIS);
+            raw[76..80].copy_from_slice(&i.to_le_bytes());
+            file.write_all(&0xd9b4bef9u32.to_le_bytes()).unwrap();
+            file.write_all(&(raw.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(&raw).unwrap();
+            records.push(BlockIndexRecord::at(i as usize, 0, pos + 8));
+            pos += 8 + raw.len() as u64;
+        }
+        (BlkFile { path, size: pos }, records)
+    }
+
+    #[test]
+    fn test_blk_store_read_modes() {
+        let (blk_file, records) = write_blk_file("blkstore", 3);
+        let path = blk_file.path.clone();
+        let size = blk_file.size;
+
+        for mode in &[BlkReadMode::Buffered(2), BlkReadMode::Mmap(2)] {
+            let mut files = HashMap::new();
+            // Register the same file under several indices to exercise the handle cache
+            for n_file in 0..4 {
+                files.insert(n_file, BlkFile::new(path.clone(), size));
+            }
+            let store = BlkStore::new(files, *mode);
+            for n_file in 0..4 {
+                for (i, record) in records.iter().enumerate() {
+                    let block = store
+                        .read_block(n_file, record.n_data_pos, &Arc::default(), false)
+                        .unwrap();
+                    assert_eq!(block.header.value.nonce, i as u32);
+                }
+            }
+            // The least recently used files were closed or unmapped
+            assert_eq!(store.open_files(), 2);
+            assert!(store
+                .read_block(0, size + 1, &Arc::default(), false)
+                .is_err());
+            assert!(store
+                .read_block(5, records[0].n_data_pos, &Arc::default(), false)
+                .is_err());
+        }
+        fs::remove_file(path).unwrap();
+    }
 
     #[test]
     fn test_parse_blk_index() {
@@ -117,13 +351,7 @@ mod tests {
             13412451,
             BlkFile::parse_blk_index("blk13412451.dat", prefix, ext).unwrap()
         );
-        assert_eq!(
-            true,
-            BlkFile::parse_blk_index("blkindex.dat", prefix, ext).is_none()
-        );
-        assert_eq!(
-            true,
-            BlkFile::parse_blk_index("invalid.dat", prefix, ext).is_none()
-        );
+        assert!(BlkFile::parse_blk_index("blkindex.dat", prefix, ext).is_none());
+        assert!(BlkFile::parse_blk_index("invalid.dat", prefix, ext).is_none());
     }
 }
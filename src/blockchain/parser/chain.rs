@@ -1,86 +1,173 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::blockchain::parser::blkfile::BlkFile;
+use crate::blockchain::parser::blkfile::{BlkFile, BlkStore};
+use crate::blockchain::parser::consensus::{self, ConsensusError};
 use crate::blockchain::parser::index::{get_block_index, BlockIndexRecord};
+use crate::blockchain::parser::pipeline::BlockPipeline;
+use crate::blockchain::parser::range;
+use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
 use crate::common::utils;
-use crate::errors::OpResult;
+use crate::errors::{OpError, OpResult};
 use crate::ParserOptions;
 
-/// Holds the index of longest valid chain
-pub struct ChainStorage<'a> {
-    blocks: Vec<BlockIndexRecord>,
-    index: usize,
-    blk_files: HashMap<usize, BlkFile>,
-    options: &'a RefCell<ParserOptions>,
+/// Holds the index of longest valid chain.
+/// Iterates over `(height, block)` of the configured range or block selection.
+pub struct ChainStorage {
+    blocks: Arc<Vec<BlockIndexRecord>>,
+    heights: Arc<Vec<usize>>, // Heights to iterate over, ascending
+    pos: usize,
+    pipeline: BlockPipeline,
+    blk_store: Arc<BlkStore>,
+    verify: bool,
+    coin_type: CoinType,
 }
 
-impl<'a> ChainStorage<'a> {
+impl ChainStorage {
     #[inline]
-    pub fn new(options: &'a RefCell<ParserOptions>) -> OpResult<Self> {
-        let blockchain_dir = options.borrow().blockchain_dir.clone();
-        Ok(Self {
-            blocks: get_block_index(blockchain_dir.join("index").as_path())?,
-            blk_files: BlkFile::from_path(blockchain_dir.as_path())?,
-            index: options.borrow().range.start,
-            options,
-        })
+    pub fn new(options: &ParserOptions) -> OpResult<Self> {
+        let blocks = ChainStorage::load_index(options)?;
+        let (_, heights) = range::resolve_heights(options, &blocks)?;
+        Self::with_index(options, blocks, heights)
     }
 
-    /// Returns the next hash without removing it
-    pub fn get_next(&mut self) -> Option<Block> {
-        if let Some(end) = self.options.borrow().range.end {
-            if self.index == end {
-                return None;
-            }
-        }
-
-        let meta = self.blocks.get(self.index)?;
-        let block = self
-            .blk_files
-            .get(&meta.n_file)?
-            .read_block(meta.n_data_pos, self.options.borrow().coin_type.version_id)
-            .ok()?;
+    /// Reads the longest chain from the block index.
+    /// In follow mode the node is expected to be running, so a copy of the index is read.
+    pub fn load_index(options: &ParserOptions) -> OpResult<Vec<BlockIndexRecord>> {
+        let path = options.blockchain_dir.join("index");
+        get_block_index(path.as_path(), options.follow.is_some())
+    }
 
+    /// Iterates over the given `heights` of the chain index.
+    /// The blk files are scanned after reading the index, so they contain all indexed blocks.
+    pub fn with_index(
+        options: &ParserOptions,
+        blocks: Vec<BlockIndexRecord>,
+        heights: Vec<usize>,
+    ) -> OpResult<Self> {
+        let blocks = Arc::new(blocks);
+        let heights = Arc::new(heights);
+        let blk_store = Arc::new(BlkStore::new(
+            BlkFile::from_path(options.blockchain_dir.as_path())?,
+            options.read_mode,
+        ));
 
-        if self.options.borrow().verify {
-            self.verify(&block);
-        }
+        let pipeline = BlockPipeline::new(
+            Arc::clone(&blocks),
+            Arc::clone(&blk_store),
+            options.coin_type.clone(),
+            Arc::clone(&heights),
+            options.worker_threads,
+        )?;
+        Ok(Self {
+            blocks,
+            heights,
+            pos: 0,
+            pipeline,
+            blk_store,
+            verify: options.verify,
+            coin_type: options.coin_type.clone(),
+        })
+    }
 
-        self.index += 1;
-        Some(block)
+    /// Returns the next block in height order.
+    /// Blocks are decoded ahead of time by the pipeline workers.
+    pub fn get_next(&mut self) -> Option<OpResult<Block>> {
+        let height = *self.heights.get(self.pos)?;
+        let result = self.pipeline.next()?.and_then(|block| {
+            if self.verify {
+                self.verify(&block, height).map_err(|e| {
+                    let meta = &self.blocks[height];
+                    OpError::from(e)
+                        .in_block(
+                            self.blk_store
+                                .location(meta.n_file, meta.n_data_pos, height),
+                        )
+                        .join_msg(&format!(
+                            "Block {} is invalid.",
+                            utils::arr_to_hex_swapped(&block.header.hash)
+                        ))
+                })?;
+            }
+            Ok(block)
+        });
+        self.pos += 1;
+        Some(result)
     }
 
-    /// Verifies the given block in a chain.
-    /// Panics if not valid
-    fn verify(&self, block: &Block) {
-        block.verify_merkle_root();
-        if self.index == 0 {
-            let genesis_hash = self.options.borrow().coin_type.genesis_hash;
-            if block.header.hash != genesis_hash {
-                panic!(
-                    "Hash of genesis doesn't match!\n  -> expected: {}\n  -> got: {}\n",
-                    utils::arr_to_hex_swapped(&genesis_hash),
-                    utils::arr_to_hex_swapped(&block.header.hash),
-                );
+    /// Verifies the given block against its predecessors in the chain index:
+    /// merkle root, linkage, proof of work and difficulty.
+    fn verify(&self, block: &Block, height: usize) -> Result<(), ConsensusError> {
+        block.verify_merkle_root()?;
+        let header = &block.header;
+        if height == 0 {
+            if header.hash != self.coin_type.genesis_hash {
+                return Err(ConsensusError::GenesisHash {
+                    expected: self.coin_type.genesis_hash,
+                    got: header.hash,
+                });
             }
         } else {
-            let prev_hash = self.blocks.get(self.index - 1).unwrap().block_hash;
-            if block.header.value.prev_hash != prev_hash {
-                panic!(
-                    "prev_hash for block {} doesn't match!\n  -> expected: {}\n  -> got: {}\n",
-                    utils::arr_to_hex_swapped(&block.header.hash),
-                    utils::arr_to_hex_swapped(&block.header.value.prev_hash),
-                    utils::arr_to_hex_swapped(&prev_hash)
-                );
+            let prev_hash = self.blocks[height - 1].block_hash;
+            if header.value.prev_hash != prev_hash {
+                return Err(ConsensusError::PrevHash {
+                    expected: prev_hash,
+                    got: header.value.prev_hash,
+                });
+            }
+        }
+
+        let difficulty = self.coin_type.difficulty.as_ref();
+        if let Some(algorithm) = self.coin_type.pow_algorithm {
+            let pow_limit = difficulty.map(|rules| rules.pow_limit);
+            consensus::check_proof_of_work(header, algorithm, pow_limit)?;
+        }
+        if let Some(rules) = difficulty {
+            if height > 0 {
+                let expected =
+                    rules.next_work_required(height, header.value.timestamp, &self.blocks);
+                if header.value.bits != expected {
+                    return Err(ConsensusError::Difficulty {
+                        expected,
+                        got: header.value.bits,
+                    });
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Returns the hash of the block at `height` in the longest chain
+    #[inline]
+    pub fn block_hash(&self, height: usize) -> Option<[u8; 32]> {
+        self.blocks.get(height).map(|b| b.block_hash)
+    }
+
+    /// Returns the number of transactions of the block at `height` according to the index
+    #[inline]
+    pub fn tx_count(&self, height: usize) -> Option<usize> {
+        self.blocks.get(height).map(|b| b.n_tx)
     }
 
     /// Returns number of remaining blocks
     #[inline]
     pub fn remaining(&self) -> usize {
-        self.blocks.len().saturating_sub(self.index)
+        self.heights.len().saturating_sub(self.pos)
+    }
+
+    /// Returns number of blocks decoded ahead and waiting to be processed
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.pipeline.buffered()
+    }
+}
+
+impl Iterator for ChainStorage {
+    type Item = OpResult<(u64, Block)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let height = *self.heights.get(self.pos)? as u64;
+        self.get_next()
+            .map(|result| result.map(|block| (height, block)))
     }
 }
@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::blockchain::parser::prevouts::PrevoutStore;
+use crate::blockchain::proto::script::AddressParams;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const MAGIC: &[u8; 8] = b"RBPCKPT4";
+
+/// Progress of a run, persisted to continue it later with --resume
+pub struct Checkpoint {
+    /// First height which was not processed yet
+    pub next_height: u64,
+    /// Hash of the block at next_height - 1, to detect reorgs in between
+    pub last_hash: [u8; 32],
+    /// Callback names and their states, in command line order
+    pub states: Vec<(String, Vec<u8>)>,
+    /// Unspent outputs for --verify-scripts and the transaction hooks, if they were kept
+    pub prevouts: Option<PrevoutStore>,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to a temporary file first, so a crash never leaves a partial checkpoint
+    pub fn save(&self, path: &Path) -> OpResult<()> {
+        // Callbacks use .tmp for their outputs, which might be in the same folder
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".ckpt.tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(MAGIC)?;
+            writer.write_u64::<LittleEndian>(self.next_height)?;
+            writer.write_all(&self.last_hash)?;
+            writer.write_u32::<LittleEndian>(self.states.len() as u32)?;
+            for (name, state) in &self.states {
+                writer.write_u32::<LittleEndian>(name.len() as u32)?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_u64::<LittleEndian>(state.len() as u64)?;
+                writer.write_all(state)?;
+            }
+            match self.prevouts {
+                Some(ref prevouts) => {
+                    writer.write_u8(1)?;
+                    prevouts.write_to(&mut writer)?;
+                }
+                None => writer.write_u8(0)?,
+            }
+            writer
+                .into_inner()
+                .map_err(|e| OpError::from(e.into_error()))?
+                .sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Loads a checkpoint, the unspent outputs are evaluated with `address_params`
+    pub fn load(path: &Path, address_params: &AddressParams) -> OpResult<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("{} is not a checkpoint file.", path.display())));
+        }
+        let next_height = reader.read_u64::<LittleEndian>()?;
+        let mut last_hash = [0u8; 32];
+        reader.read_exact(&mut last_hash)?;
+        let n_states = reader.read_u32::<LittleEndian>()?;
+        let mut states = Vec::with_capacity(n_states as usize);
+        for _ in 0..n_states {
+            let mut name = vec![0u8; reader.read_u32::<LittleEndian>()? as usize];
+            reader.read_exact(&mut name)?;
+            let mut state = vec![0u8; reader.read_u64::<LittleEndian>()? as usize];
+            reader.read_exact(&mut state)?;
+            states.push((String::from_utf8(name)?, state));
+        }
+        let prevouts = match reader.read_u8()? {
+            0 => None,
+            _ => Some(PrevoutStore::read_from(&mut reader, address_params)?),
+        };
+        Ok(Checkpoint {
+            next_height,
+            last_hash,
+            states,
+            prevouts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        let checkpoint = Checkpoint {
+            next_height: 123456,
+            last_hash: [7u8; 32],
+            states: vec![
+                (String::from("csvdump"), vec![1, 2, 3]),
+                (String::from("balances"), Vec::new()),
+            ],
+            prevouts: None,
+        };
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path, &AddressParams::default()).unwrap();
+        assert_eq!(loaded.next_height, 123456);
+        assert_eq!(loaded.last_hash, [7u8; 32]);
+        assert_eq!(loaded.states, checkpoint.states);
+        assert!(loaded.prevouts.is_none());
+
+        fs::write(&path, b"garbage!").unwrap();
+        assert!(Checkpoint::load(&path, &AddressParams::default()).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,878 @@
+use std::error;
+use std::fmt;
+
+use crypto::scrypt::{scrypt, ScryptParams};
+use rayon::prelude::*;
+
+use crate::blockchain::parser::index::BlockIndexRecord;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::interpreter::{TxVerifier, VerifyError};
+use crate::blockchain::proto::target::Target;
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutput};
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::common::utils;
+
+/// Dogecoin switched to per-block DigiShield retargeting at this height
+const DIGISHIELD_HEIGHT: usize = 145000;
+
+/// Hash function used for the proof of work
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowAlgorithm {
+    Sha256d,
+    /// scrypt with N=1024, r=1, p=1 (Litecoin, Dogecoin)
+    Scrypt,
+}
+
+impl PowAlgorithm {
+    /// Returns the proof-of-work hash of the header
+    pub fn hash(&self, header: &Hashed<BlockHeader>) -> [u8; 32] {
+        match *self {
+            PowAlgorithm::Sha256d => header.hash,
+            PowAlgorithm::Scrypt => {
+                let raw = header.value.to_bytes();
+                let mut hash = [0u8; 32];
+                scrypt(&raw, &raw, &ScryptParams::new(10, 1, 1), &mut hash);
+                hash
+            }
+        }
+    }
+}
+
+/// How the difficulty is adjusted
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetargetAlgorithm {
+    /// Retarget every `timespan / spacing` blocks, limited to a factor of 4
+    Bitcoin,
+    /// Like Bitcoin, but the window covers a full interval and the target is shifted to avoid an overflow
+    Litecoin,
+    /// Litecoin with looser limits on the first 10000 blocks and per-block DigiShield from block 145000
+    Dogecoin,
+}
+
+/// Difficulty parameters of a coin
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct DifficultyRules {
+    pub algorithm: RetargetAlgorithm,
+    #[serde(deserialize_with = "crate::blockchain::parser::types::deserialize_u32")]
+    pub pow_limit: u32, // Easiest allowed target in compact form
+    pub timespan: u32,              // Seconds per retarget window
+    pub spacing: u32,               // Seconds between two blocks
+    pub allow_min_difficulty: bool, // Testnet: blocks 20 minutes after their predecessor may use pow_limit
+    #[serde(default)]
+    pub no_retargeting: bool, // Regtest: the difficulty never changes
+    #[serde(default)]
+    pub enforce_bip94: bool, // Testnet4: retarget from the first block of the window, not a min difficulty block
+}
+
+impl DifficultyRules {
+    /// Returns the `nBits` required for the block at `height` with timestamp `time`.
+    /// `chain` must contain the longest chain up to at least `height - 1`.
+    pub fn next_work_required(&self, height: usize, time: u32, chain: &[BlockIndexRecord]) -> u32 {
+        let last = &chain[height - 1];
+        let (interval, timespan) = match self.algorithm {
+            RetargetAlgorithm::Dogecoin if height >= DIGISHIELD_HEIGHT => (1, self.spacing),
+            _ => ((self.timespan / self.spacing) as usize, self.timespan),
+        };
+
+        if !height.is_multiple_of(interval) {
+            if self.allow_min_difficulty {
+                if time > last.time + 2 * self.spacing {
+                    return self.pow_limit;
+                }
+                // Return the last block which didn't fall back to the minimum difficulty
+                let mut h = height - 1;
+                while h > 0 && !h.is_multiple_of(interval) && chain[h].bits == self.pow_limit {
+                    h -= 1;
+                }
+                return chain[h].bits;
+            }
+            return last.bits;
+        }
+        if self.no_retargeting {
+            return last.bits;
+        }
+
+        let blocks_back = match self.algorithm {
+            RetargetAlgorithm::Bitcoin => interval - 1,
+            // Litecoin's fix for the time warp attack, except for the first window
+            RetargetAlgorithm::Litecoin | RetargetAlgorithm::Dogecoin if height != interval => {
+                interval
+            }
+            RetargetAlgorithm::Litecoin | RetargetAlgorithm::Dogecoin => interval - 1,
+        };
+        let first = &chain[height - 1 - blocks_back];
+        let actual = i64::from(last.time) - i64::from(first.time);
+        let bits = if self.enforce_bip94 {
+            first.bits
+        } else {
+            last.bits
+        };
+        self.retarget(height, bits, actual, i64::from(timespan))
+    }
+
+    /// Scales the target of `bits` by the actual timespan of the last window
+    fn retarget(&self, height: usize, bits: u32, actual: i64, timespan: i64) -> u32 {
+        let (actual, min, max) = match self.algorithm {
+            RetargetAlgorithm::Dogecoin if height >= DIGISHIELD_HEIGHT => {
+                // Amplitude filter
+                let actual = timespan + (actual - timespan) / 8;
+                (actual, timespan - timespan / 4, timespan + timespan / 2)
+            }
+            RetargetAlgorithm::Dogecoin if height <= 5000 => (actual, timespan / 16, timespan * 4),
+            RetargetAlgorithm::Dogecoin if height <= 10000 => (actual, timespan / 8, timespan * 4),
+            _ => (actual, timespan / 4, timespan * 4),
+        };
+        let actual = actual.max(min).min(max) as u64;
+
+        let limit = Target::from_compact(self.pow_limit).target;
+        let mut target = Target::from_compact(bits).target;
+        let shift =
+            self.algorithm == RetargetAlgorithm::Litecoin && target.bits() > limit.bits() - 1;
+        if shift {
+            target = target.shr(1);
+        }
+        target = target.mul_u64(actual).div_u64(timespan as u64);
+        if shift {
+            target = target.shl(1);
+        }
+        if target > limit {
+            target = limit;
+        }
+        target.to_compact()
+    }
+}
+
+/// Checks the header hash against the target encoded in its `nBits`.
+/// Merge-mined blocks must be committed to by their parent block, whose hash is checked instead.
+pub fn check_proof_of_work(
+    header: &Hashed<BlockHeader>,
+    algorithm: PowAlgorithm,
+    pow_limit: Option<u32>,
+) -> Result<(), ConsensusError> {
+    let bits = header.value.bits;
+    let decoded = Target::from_compact(bits);
+    let above_limit = match pow_limit {
+        Some(limit) => decoded.target > Target::from_compact(limit).target,
+        None => false,
+    };
+    if decoded.negative || decoded.overflow || decoded.target.is_zero() || above_limit {
+        return Err(ConsensusError::InvalidTarget { bits });
+    }
+    let hash = match header.value.aux_pow {
+        Some(ref aux_pow) => {
+            aux_pow.check(&header.hash, header.value.version >> 16)?;
+            algorithm.hash(&aux_pow.parent_header)
+        }
+        None => algorithm.hash(header),
+    };
+    if Target::from_hash(&hash) > decoded.target {
+        return Err(ConsensusError::ProofOfWork { hash, bits });
+    }
+    Ok(())
+}
+
+/// Inputs handled by verify_scripts()
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptCounts {
+    pub verified: u64,
+    /// Inputs spending outputs which couldn't be resolved
+    pub unresolved: u64,
+    /// Resolved inputs which weren't verified, because another input of the transaction is unresolved
+    pub skipped: u64,
+}
+
+/// Verifies the scripts of all inputs of a block with the given `VERIFY_*` flags, transactions in parallel.
+/// `prevouts` holds the spent outputs as returned by PrevoutStore::resolve().
+/// Taproot signatures commit to all spent outputs, so transactions with an unresolved one are left out.
+pub fn verify_scripts(
+    block: &Block,
+    prevouts: &[Vec<Option<EvaluatedTxOut>>],
+    flags: u32,
+) -> Result<ScriptCounts, ConsensusError> {
+    let results: Vec<Result<ScriptCounts, ConsensusError>> = block
+        .txs
+        .par_iter()
+        .zip(prevouts.par_iter())
+        .map(|(tx, spent)| {
+            let n_inputs = tx.value.inputs.len() as u64;
+            if tx.value.is_coinbase() {
+                return Ok(ScriptCounts::default());
+            }
+            let resolved: Option<Vec<&TxOutput>> =
+                spent.iter().map(|o| o.as_ref().map(|o| &o.out)).collect();
+            let resolved = match resolved {
+                Some(resolved) => resolved,
+                None => {
+                    let unresolved = spent.iter().filter(|o| o.is_none()).count() as u64;
+                    return Ok(ScriptCounts {
+                        verified: 0,
+                        unresolved,
+                        skipped: n_inputs - unresolved,
+                    });
+                }
+            };
+            let verifier = TxVerifier::new(&tx.value, resolved);
+            for input in 0..tx.value.inputs.len() {
+                verifier
+                    .verify_input(input, flags)
+                    .map_err(|error| ConsensusError::Script {
+                        txid: tx.hash,
+                        input,
+                        error,
+                    })?;
+            }
+            Ok(ScriptCounts {
+                verified: n_inputs,
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    let mut counts = ScriptCounts::default();
+    for result in results {
+        let tx_counts = result?;
+        counts.verified += tx_counts.verified;
+        counts.unresolved += tx_counts.unresolved;
+        counts.skipped += tx_counts.skipped;
+    }
+    Ok(counts)
+}
+
+/// Violations of the consensus rules found with `--verify` and `--verify-scripts`
+#[derive(Debug, PartialEq)]
+pub enum ConsensusError {
+    MerkleRoot {
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+    GenesisHash {
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+    PrevHash {
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+    /// nBits is negative, overflows, is zero or easier than the coin's limit
+    InvalidTarget {
+        bits: u32,
+    },
+    /// Proof-of-work hash is above the target
+    ProofOfWork {
+        hash: [u8; 32],
+        bits: u32,
+    },
+    /// nBits doesn't follow the retarget rules
+    Difficulty {
+        expected: u32,
+        got: u32,
+    },
+    /// The parent block of a merge-mined block doesn't commit to it
+    AuxPow(&'static str),
+    /// An input script failed
+    Script {
+        txid: [u8; 32],
+        input: usize,
+        error: VerifyError,
+    },
+}
+
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConsensusError::MerkleRoot {
+                ref expected,
+                ref got,
+            } => write!(
+                f,
+                "Invalid merkle root (expected: {}, got: {})",
+                utils::arr_to_hex_swapped(expected),
+                utils::arr_to_hex_swapped(got)
+            ),
+            ConsensusError::GenesisHash {
+                ref expected,
+                ref got,
+            } => write!(
+                f,
+                "Hash of genesis doesn't match (expected: {}, got: {})",
+                utils::arr_to_hex_swapped(expected),
+                utils::arr_to_hex_swapped(got)
+            ),
+            ConsensusError::PrevHash {
+                ref expected,
+                ref got,
+            } => write!(
+                f,
+                "prev_hash doesn't match (expected: {}, got: {})",
+                utils::arr_to_hex_swapped(expected),
+                utils::arr_to_hex_swapped(got)
+            ),
+            ConsensusError::InvalidTarget { bits } => {
+                write!(f, "Invalid target (nBits: {:08x})", bits)
+            }
+            ConsensusError::ProofOfWork { ref hash, bits } => write!(
+                f,
+                "Proof of work {} doesn't meet target (nBits: {:08x})",
+                utils::arr_to_hex_swapped(hash),
+                bits
+            ),
+            ConsensusError::Difficulty { expected, got } => {
+                write!(
+                    f,
+                    "Unexpected difficulty (expected nBits: {:08x}, got: {:08x})",
+                    expected, got
+                )
+            }
+            ConsensusError::AuxPow(reason) => write!(f, "Invalid AuxPoW: {}", reason),
+            ConsensusError::Script {
+                ref txid,
+                input,
+                error,
+            } => write!(
+                f,
+                "Script of input {} of transaction {} failed: {}",
+                input,
+                utils::arr_to_hex_swapped(txid),
+                error
+            ),
+        }
+    }
+}
+
+impl error::Error for ConsensusError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use crate::blockchain::proto::interpreter::{
+        VERIFY_CHECKLOCKTIMEVERIFY, VERIFY_CHECKSEQUENCEVERIFY, VERIFY_DERSIG, VERIFY_NULLDUMMY,
+        VERIFY_P2SH, VERIFY_TAPROOT, VERIFY_WITNESS,
+    };
+    use crate::blockchain::proto::opcodes::All;
+    use crate::blockchain::proto::script::AddressParams;
+    use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+    use crate::common::fixtures;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    fn coin(name: &str) -> CoinType {
+        name.parse().unwrap()
+    }
+
+    /// Returns a chain with blocks every `spacing` seconds, all with the given bits
+    fn chain(len: usize, spacing: u32, bits: u32) -> Vec<BlockIndexRecord> {
+        (0..len)
+            .map(|height| {
+                let mut record = BlockIndexRecord::at(height, 0, 0);
+                record.time = 1_000_000 + height as u32 * spacing;
+                record.bits = bits;
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bitcoin_retarget() {
+        let rules = coin("bitcoin").difficulty.unwrap();
+        // Block 32256, the first retarget of the main chain
+        let mut blocks = chain(32256, 600, 0x1d00ffff);
+        blocks[30240].time = 1261130161;
+        blocks[32255].time = 1262152739;
+        assert_eq!(
+            rules.next_work_required(32256, 1262153464, &blocks),
+            0x1d00d86a
+        );
+        assert_eq!(
+            rules.next_work_required(32255, 1262153464, &blocks),
+            0x1d00ffff
+        );
+
+        // Test vectors from bitcoin/src/test/pow_tests.cpp
+        let retarget = |height: usize, first_time: u32, last_time: u32, bits: u32| {
+            let mut blocks = chain(height, 600, bits);
+            blocks[height - 2016].time = first_time;
+            blocks[height - 1].time = last_time;
+            rules.next_work_required(height, 0, &blocks)
+        };
+        // Never easier than the limit
+        assert_eq!(
+            retarget(2016, 1231006505, 1233061996, 0x1d00ffff),
+            0x1d00ffff
+        );
+        // Too fast and too slow windows are limited to a factor of 4
+        assert_eq!(
+            retarget(68544, 1279008237, 1279297671, 0x1c05a3f4),
+            0x1c0168fd
+        );
+        assert_eq!(
+            retarget(46368, 1263163443, 1269211443, 0x1c387f6f),
+            0x1d00e1fd
+        );
+    }
+
+    #[test]
+    fn test_testnet_min_difficulty() {
+        let rules = coin("testnet3").difficulty.unwrap();
+        let mut blocks = chain(2100, 600, 0x1c0ffff0);
+        let last = blocks[2099].time;
+        // More than 20 minutes after the last block
+        assert_eq!(
+            rules.next_work_required(2100, last + 1201, &blocks),
+            0x1d00ffff
+        );
+        assert_eq!(
+            rules.next_work_required(2100, last + 1200, &blocks),
+            0x1c0ffff0
+        );
+        // Skips blocks which fell back to the minimum difficulty
+        blocks[2098].bits = 0x1d00ffff;
+        blocks[2099].bits = 0x1d00ffff;
+        assert_eq!(
+            rules.next_work_required(2100, last + 600, &blocks),
+            0x1c0ffff0
+        );
+    }
+
+    #[test]
+    fn test_regtest_and_testnet4_retarget() {
+        let rules = coin("regtest").difficulty.unwrap();
+        let blocks = chain(2016, 1, 0x207fffff);
+        assert_eq!(rules.next_work_required(2016, 0, &blocks), 0x207fffff);
+
+        // The last block of the window fell back to the minimum difficulty
+        let rules = coin("testnet4").difficulty.unwrap();
+        let mut blocks = chain(2016, 600, 0x1c0ffff0);
+        blocks[2015].bits = 0x1d00ffff;
+        assert_eq!(rules.next_work_required(2016, 0, &blocks), 0x1c0ffde7);
+        let testnet3 = coin("testnet3").difficulty.unwrap();
+        assert_eq!(testnet3.next_work_required(2016, 0, &blocks), 0x1d00ffde);
+    }
+
+    #[test]
+    fn test_litecoin_retarget() {
+        let rules = coin("litecoin").difficulty.unwrap();
+        // The first window only covers interval - 1 blocks, the following ones a full interval
+        let blocks = chain(4032, 150, 0x1e0ffff0);
+        assert_eq!(rules.next_work_required(2016, 0, &blocks), 0x1e0ffde7);
+        assert_eq!(rules.next_work_required(4032, 0, &blocks), 0x1e0ffff0);
+    }
+
+    #[test]
+    fn test_dogecoin_digishield() {
+        let rules = coin("dogecoin").difficulty.unwrap();
+        let mut blocks = chain(DIGISHIELD_HEIGHT + 2, 60, 0x1b0fffff);
+        // Every block is retargeted, 2 minutes instead of 1 only increase the target by 7/60
+        blocks[DIGISHIELD_HEIGHT + 1].time += 60;
+        assert_eq!(
+            rules.next_work_required(DIGISHIELD_HEIGHT + 2, 0, &blocks),
+            0x1b11dddc
+        );
+        assert_eq!(
+            rules.next_work_required(DIGISHIELD_HEIGHT + 1, 0, &blocks),
+            0x1b0fffff
+        );
+        // Before DigiShield difficulty only changes every 240 blocks
+        assert_eq!(rules.next_work_required(1001, 0, &blocks), 0x1b0fffff);
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        let header = Hashed::double_sha256(BlockHeader::new(
+            1,
+            [0u8; 32],
+            utils::hex_to_arr32_swapped(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            ),
+            1231006505,
+            0x1d00ffff,
+            2083236893,
+        ));
+        assert_eq!(
+            check_proof_of_work(&header, PowAlgorithm::Sha256d, Some(0x1d00ffff)),
+            Ok(())
+        );
+        let err = check_proof_of_work(&header, PowAlgorithm::Sha256d, Some(0x1c00ffff));
+        assert_eq!(err, Err(ConsensusError::InvalidTarget { bits: 0x1d00ffff }));
+
+        let mut header = header;
+        header.value.bits = 0x1b0404cb;
+        let err = check_proof_of_work(&header, PowAlgorithm::Sha256d, None).unwrap_err();
+        assert_eq!(
+            err,
+            ConsensusError::ProofOfWork {
+                hash: header.hash,
+                bits: 0x1b0404cb
+            }
+        );
+        header.value.bits = 0x04923456;
+        let err = check_proof_of_work(&header, PowAlgorithm::Sha256d, None).unwrap_err();
+        assert_eq!(err, ConsensusError::InvalidTarget { bits: 0x04923456 });
+    }
+
+    #[test]
+    fn test_test_network_genesis() {
+        let genesis = |merkle_root: &str, time: u32, bits: u32, nonce: u32| {
+            Hashed::double_sha256(BlockHeader::new(
+                1,
+                [0u8; 32],
+                utils::hex_to_arr32_swapped(merkle_root),
+                time,
+                bits,
+                nonce,
+            ))
+        };
+        let satoshi_root = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let networks = [
+            ("regtest", genesis(satoshi_root, 1296688602, 0x207fffff, 2)),
+            (
+                "signet",
+                genesis(satoshi_root, 1598918400, 0x1e0377ae, 52613770),
+            ),
+            (
+                "testnet4",
+                genesis(
+                    "7aa0a7ae1e223414cb807e40cd57e667b718e42aaf9306db9102fe28912b7b4e",
+                    1714777860,
+                    0x1d00ffff,
+                    393743547,
+                ),
+            ),
+        ];
+        for (name, header) in networks.iter() {
+            let coin = coin(name);
+            assert_eq!(header.hash, coin.genesis_hash, "{}", name);
+            let pow_limit = coin.difficulty.map(|rules| rules.pow_limit);
+            assert_eq!(
+                check_proof_of_work(header, PowAlgorithm::Sha256d, pow_limit),
+                Ok(()),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_scrypt_genesis() {
+        // Litecoin genesis block
+        let header = Hashed::double_sha256(BlockHeader::new(
+            1,
+            [0u8; 32],
+            utils::hex_to_arr32_swapped(
+                "97ddfbbae6be97fd6cdf3e7ca13232a3afff2353e29badfab7f73011edd4ced9",
+            ),
+            1317972665,
+            0x1e0ffff0,
+            2084524493,
+        ));
+        assert_eq!(header.hash, coin("litecoin").genesis_hash);
+        // The sha256d hash doesn't meet the target
+        assert!(check_proof_of_work(&header, PowAlgorithm::Sha256d, Some(0x1e0fffff)).is_err());
+        assert_eq!(
+            check_proof_of_work(&header, PowAlgorithm::Scrypt, Some(0x1e0fffff)),
+            Ok(())
+        );
+    }
+
+    /// Parses the script notation of Bitcoin Core's test vectors: numbers, 0x prefixed raw bytes,
+    /// 'quoted' pushes and opcode names with or without OP_ prefix.
+    fn parse_script(asm: &str) -> Vec<u8> {
+        let mut script = Vec::new();
+        for token in asm.split_whitespace() {
+            if let Ok(n) = token.parse::<i64>() {
+                match n {
+                    0 => script.push(0x00),
+                    -1 => script.push(0x4f),
+                    1..=16 => script.push(0x50 + n as u8),
+                    _ => push_data(&mut script, &script_num(n)),
+                }
+            } else if let Some(hex) = token.strip_prefix("0x") {
+                script.extend(utils::hex_to_vec(hex));
+            } else if token.len() >= 2 && token.starts_with('\'') && token.ends_with('\'') {
+                push_data(&mut script, &token.as_bytes()[1..token.len() - 1]);
+            } else {
+                let name = token.strip_prefix("OP_").unwrap_or(token);
+                let opcode = match name {
+                    "FALSE" => 0x00,
+                    "TRUE" => 0x51,
+                    "1NEGATE" => 0x4f,
+                    "CHECKLOCKTIMEVERIFY" => 0xb1,
+                    "CHECKSEQUENCEVERIFY" => 0xb2,
+                    "CHECKSIGADD" => 0xba,
+                    _ => (0..=255u8)
+                        .find(|b| format!("{:?}", All::from(*b)) == format!("OP_{}", name))
+                        .unwrap_or_else(|| panic!("Unknown opcode {}", token)),
+                };
+                script.push(opcode);
+            }
+        }
+        script
+    }
+
+    fn script_num(n: i64) -> Vec<u8> {
+        let mut abs = n.unsigned_abs();
+        let mut bytes = Vec::new();
+        while abs > 0 {
+            bytes.push((abs & 0xff) as u8);
+            abs >>= 8;
+        }
+        if bytes.last().is_some_and(|b| b & 0x80 != 0) {
+            bytes.push(if n < 0 { 0x80 } else { 0x00 });
+        } else if n < 0 {
+            *bytes.last_mut().unwrap() |= 0x80;
+        }
+        bytes
+    }
+
+    fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+        match data.len() {
+            len if len < 0x4c => script.push(len as u8),
+            len if len <= 0xff => script.extend_from_slice(&[0x4c, len as u8]),
+            len if len <= 0xffff => {
+                script.push(0x4d);
+                script.extend_from_slice(&(len as u16).to_le_bytes());
+            }
+            len => {
+                script.push(0x4e);
+                script.extend_from_slice(&(len as u32).to_le_bytes());
+            }
+        }
+        script.extend_from_slice(data);
+    }
+
+    /// Consensus flags of a vector, None if it also uses policy flags, which aren't enforced
+    fn parse_flags(flags: &str) -> Option<u32> {
+        flags.split(',').try_fold(0, |acc, flag| {
+            let flag = match flag {
+                "NONE" | "" => 0,
+                "P2SH" => VERIFY_P2SH,
+                "DERSIG" => VERIFY_DERSIG,
+                "NULLDUMMY" => VERIFY_NULLDUMMY,
+                "CHECKLOCKTIMEVERIFY" => VERIFY_CHECKLOCKTIMEVERIFY,
+                "CHECKSEQUENCEVERIFY" => VERIFY_CHECKSEQUENCEVERIFY,
+                "WITNESS" => VERIFY_WITNESS,
+                "TAPROOT" => VERIFY_TAPROOT,
+                "STRICTENC"
+                | "LOW_S"
+                | "MINIMALDATA"
+                | "SIGPUSHONLY"
+                | "CLEANSTACK"
+                | "MINIMALIF"
+                | "NULLFAIL"
+                | "WITNESS_PUBKEYTYPE"
+                | "CONST_SCRIPTCODE"
+                | "DISCOURAGE_UPGRADABLE_NOPS"
+                | "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM"
+                | "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION"
+                | "DISCOURAGE_OP_SUCCESS"
+                | "DISCOURAGE_UPGRADABLE_PUBKEYTYPE" => return None,
+                _ => panic!("Unknown flag {}", flag),
+            };
+            Some(acc | flag)
+        })
+    }
+
+    /// Name of the error in Bitcoin Core's script_tests.json
+    fn core_error(error: &VerifyError) -> &'static str {
+        match error {
+            VerifyError::EvalFalse => "EVAL_FALSE",
+            VerifyError::OpReturn => "OP_RETURN",
+            VerifyError::ScriptSize => "SCRIPT_SIZE",
+            VerifyError::PushSize => "PUSH_SIZE",
+            VerifyError::OpCount => "OP_COUNT",
+            VerifyError::StackSize => "STACK_SIZE",
+            VerifyError::SigCount => "SIG_COUNT",
+            VerifyError::PubkeyCount => "PUBKEY_COUNT",
+            VerifyError::Verify => "VERIFY",
+            VerifyError::EqualVerify => "EQUALVERIFY",
+            VerifyError::CheckMultisigVerify => "CHECKMULTISIGVERIFY",
+            VerifyError::CheckSigVerify => "CHECKSIGVERIFY",
+            VerifyError::NumEqualVerify => "NUMEQUALVERIFY",
+            VerifyError::BadOpcode => "BAD_OPCODE",
+            VerifyError::DisabledOpcode => "DISABLED_OPCODE",
+            VerifyError::InvalidStackOperation => "INVALID_STACK_OPERATION",
+            VerifyError::InvalidAltstackOperation => "INVALID_ALTSTACK_OPERATION",
+            VerifyError::UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+            VerifyError::NegativeLocktime => "NEGATIVE_LOCKTIME",
+            VerifyError::UnsatisfiedLocktime => "UNSATISFIED_LOCKTIME",
+            VerifyError::SigDer => "SIG_DER",
+            VerifyError::SigPushOnly => "SIG_PUSHONLY",
+            VerifyError::SigNullDummy => "SIG_NULLDUMMY",
+            VerifyError::PubkeyType => "PUBKEYTYPE",
+            VerifyError::CleanStack => "CLEANSTACK",
+            VerifyError::WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+            VerifyError::WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+            VerifyError::WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+            VerifyError::WitnessMalleated => "WITNESS_MALLEATED",
+            VerifyError::WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+            VerifyError::WitnessUnexpected => "WITNESS_UNEXPECTED",
+            VerifyError::SchnorrSigSize => "SCHNORR_SIG_SIZE",
+            VerifyError::SchnorrSigHashtype => "SCHNORR_SIG_HASHTYPE",
+            VerifyError::SchnorrSig => "SCHNORR_SIG",
+            VerifyError::TaprootWrongControlSize => "TAPROOT_WRONG_CONTROL_SIZE",
+            VerifyError::TapscriptValidationWeight => "TAPSCRIPT_VALIDATION_WEIGHT",
+            VerifyError::TapscriptCheckMultisig => "TAPSCRIPT_CHECKMULTISIG",
+            VerifyError::TapscriptMinimalIf => "TAPSCRIPT_MINIMALIF",
+            // Core's CScriptNum throws scriptnum_error, which the interpreter doesn't name
+            VerifyError::NumOverflow => "UNKNOWN_ERROR",
+        }
+    }
+
+    fn btc_to_sat(amount: &serde_json::Value) -> u64 {
+        (amount.as_f64().unwrap() * 100_000_000.0).round() as u64
+    }
+
+    fn vectors(json: &str) -> Vec<Vec<serde_json::Value>> {
+        let entries: Vec<Vec<serde_json::Value>> = serde_json::from_str(json).unwrap();
+        // Single strings are comments
+        entries
+            .into_iter()
+            .filter(|entry| entry.len() > 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_script_vectors() {
+        let entries = vectors(include_str!("testdata/script_tests.json"));
+        let mut checked = 0;
+        for entry in &entries {
+            let (witness, amount, fields) = match entry[0].as_array() {
+                Some(witness) => {
+                    let (amount, items) = witness.split_last().unwrap();
+                    let items = items
+                        .iter()
+                        .map(|item| utils::hex_to_vec(item.as_str().unwrap()))
+                        .collect();
+                    (items, btc_to_sat(amount), &entry[1..])
+                }
+                None => (Vec::new(), 0, &entry[..]),
+            };
+            let flags = match parse_flags(fields[2].as_str().unwrap()) {
+                Some(flags) => flags,
+                None => continue,
+            };
+            let script_sig = parse_script(fields[0].as_str().unwrap());
+            let script_pubkey = parse_script(fields[1].as_str().unwrap());
+            let expected = fields[3].as_str().unwrap();
+
+            // Spends the output of a crediting transaction like Bitcoin Core's BuildCreditingTransaction
+            let coinbase = fixtures::input(
+                TxOutpoint::new([0u8; 32], 0xffffffff),
+                &[0x00, 0x00],
+                0xffffffff,
+                Vec::new(),
+            );
+            let crediting = fixtures::tx(
+                1,
+                vec![coinbase],
+                vec![fixtures::output(amount, &script_pubkey)],
+                0,
+            );
+            let crediting = Hashed::double_sha256(EvaluatedTx::from(crediting));
+            let spent = crediting.value.outputs[0].clone();
+            let input = fixtures::input(
+                TxOutpoint::new(crediting.hash, 0),
+                &script_sig,
+                0xffffffff,
+                witness,
+            );
+            let spending = fixtures::tx(1, vec![input], vec![fixtures::output(amount, &[])], 0);
+            let block = fixtures::block(vec![spending]);
+            let got = match verify_scripts(&block, &[vec![Some(spent)]], flags) {
+                Ok(counts) => {
+                    assert_eq!(counts.verified, 1);
+                    "OK"
+                }
+                Err(ConsensusError::Script { error, .. }) => core_error(&error),
+                Err(e) => panic!("Unexpected error {}", e),
+            };
+            assert_eq!(got, expected, "{:?}", entry);
+            checked += 1;
+        }
+        // Only a few vectors use policy flags
+        assert!(
+            checked > 100 && checked + 5 > entries.len(),
+            "{} of {}",
+            checked,
+            entries.len()
+        );
+    }
+
+    /// Runs the transactions of tx_valid.json or tx_invalid.json, returns if their scripts are valid
+    fn run_tx_vectors(json: &str) -> Vec<(bool, String)> {
+        vectors(json)
+            .into_iter()
+            .map(|entry| {
+                let raw = utils::hex_to_vec(entry[1].as_str().unwrap());
+                let tx = Cursor::new(raw).read_txs(1, &Arc::default()).unwrap();
+                let block = fixtures::block(tx);
+                let prevouts: Vec<Option<EvaluatedTxOut>> = block.txs[0]
+                    .value
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        let prevout = entry[0]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .find(|prevout| {
+                                utils::hex_to_arr32_swapped(prevout[0].as_str().unwrap())
+                                    == input.input.outpoint.txid
+                                    && prevout[1].as_u64()
+                                        == Some(input.input.outpoint.index as u64)
+                            })
+                            .unwrap();
+                        let script_pubkey = parse_script(prevout[2].as_str().unwrap());
+                        let value = prevout
+                            .get(3)
+                            .and_then(|amount| amount.as_u64())
+                            .unwrap_or(0);
+                        let out = fixtures::output(value, &script_pubkey);
+                        Some(EvaluatedTxOut::eval_script(out, &AddressParams::default()))
+                    })
+                    .collect();
+                let flags = parse_flags(entry[2].as_str().unwrap()).expect("only consensus flags");
+                let valid = verify_scripts(&block, &[prevouts], flags).is_ok();
+                (valid, format!("{:?}", entry))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tx_vectors() {
+        for (valid, entry) in run_tx_vectors(include_str!("testdata/tx_valid.json")) {
+            assert!(valid, "{}", entry);
+        }
+        for (valid, entry) in run_tx_vectors(include_str!("testdata/tx_invalid.json")) {
+            assert!(!valid, "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_skipped_inputs() {
+        let input = |txid| fixtures::input(TxOutpoint::new(txid, 0), &[], 0xffffffff, Vec::new());
+        let spending = fixtures::tx(1, vec![input([0x01; 32]), input([0x02; 32])], Vec::new(), 0);
+        let spent = fixtures::output(0, &[0x51]);
+        let block = fixtures::block(vec![spending]);
+        let prevouts = vec![vec![
+            Some(EvaluatedTxOut::eval_script(
+                spent,
+                &AddressParams::default(),
+            )),
+            None,
+        ]];
+        let counts = verify_scripts(&block, &prevouts, VERIFY_P2SH).unwrap();
+        assert_eq!(
+            counts,
+            ScriptCounts {
+                verified: 0,
+                unresolved: 1,
+                skipped: 1
+            }
+        );
+    }
+}
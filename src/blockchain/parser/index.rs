@@ -1,15 +1,21 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
-use std::io::Cursor;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use byteorder::ReadBytesExt;
+use byteorder::{LittleEndian, ReadBytesExt};
 use rusty_leveldb::{LdbIterator, Options, DB};
 
-use crate::errors::OpResult;
+use crate::common::utils;
+use crate::errors::{DataLocation, OpError, OpErrorKind, OpResult};
 
-const BLOCK_VALID_CHAIN: usize = 4;
+const BLOCK_VALID_MASK: usize = 7;
 const BLOCK_HAVE_DATA: usize = 8;
+const BLOCK_HAVE_UNDO: usize = 16;
+const BLOCK_FAILED_MASK: usize = 32 | 64;
 
 /// https://bitcoin.stackexchange.com/questions/28168/what-are-the-keys-used-in-the-blockchain-leveldb-ie-what-are-the-keyvalue-pair
 pub struct BlockIndexRecord {
@@ -17,22 +23,48 @@ pub struct BlockIndexRecord {
     version: usize,
     height: usize,
     status: usize,
-    n_tx: usize,
+    pub n_tx: usize,
     pub n_file: usize,
     pub n_data_pos: u64,
+    pub prev_hash: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
 }
 
 impl BlockIndexRecord {
     fn from(key: &[u8], values: &[u8]) -> OpResult<Self> {
         let mut reader = Cursor::new(values);
 
-        let block_hash: [u8; 32] = key.try_into().expect("leveldb: malformed blockhash");
+        let block_hash: [u8; 32] = key.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed block hash with {} bytes.", key.len()),
+            )
+        })?;
         let version = read_varint(&mut reader)?;
         let height = read_varint(&mut reader)?;
         let status = read_varint(&mut reader)?;
         let n_tx = read_varint(&mut reader)?;
-        let n_file = read_varint(&mut reader)?;
-        let n_data_pos = read_varint(&mut reader)? as u64;
+        let n_file = if status & (BLOCK_HAVE_DATA | BLOCK_HAVE_UNDO) > 0 {
+            read_varint(&mut reader)?
+        } else {
+            0
+        };
+        let n_data_pos = if status & BLOCK_HAVE_DATA > 0 {
+            read_varint(&mut reader)? as u64
+        } else {
+            0
+        };
+        if status & BLOCK_HAVE_UNDO > 0 {
+            read_varint(&mut reader)?;
+        }
+        // Followed by the block header, skip its version and merkle root
+        let mut prev_hash = [0u8; 32];
+        reader.set_position(reader.position() + 4);
+        reader.read_exact(&mut prev_hash)?;
+        reader.set_position(reader.position() + 32);
+        let time = reader.read_u32::<LittleEndian>()?;
+        let bits = reader.read_u32::<LittleEndian>()?;
 
         Ok(BlockIndexRecord {
             block_hash,
@@ -42,10 +74,32 @@ impl BlockIndexRecord {
             n_tx,
             n_file,
             n_data_pos,
+            prev_hash,
+            time,
+            bits,
         })
     }
 }
 
+#[cfg(test)]
+impl BlockIndexRecord {
+    /// Creates a minimal record pointing to a block in a blk file
+    pub fn at(height: usize, n_file: usize, n_data_pos: u64) -> Self {
+        BlockIndexRecord {
+            block_hash: [0u8; 32],
+            version: 0,
+            height,
+            status: BLOCK_HAVE_DATA,
+            n_tx: 1,
+            n_file,
+            n_data_pos,
+            prev_hash: [0u8; 32],
+            time: 0,
+            bits: 0,
+        }
+    }
+}
+
 impl fmt::Debug for BlockIndexRecord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("BlockIndexRecord")
@@ -56,35 +110,162 @@ impl fmt::Debug for BlockIndexRecord {
             .field("n_tx", &self.n_tx)
             .field("n_file", &self.n_file)
             .field("n_data_pos", &self.n_data_pos)
+            .field("prev_hash", &self.prev_hash)
+            .field("time", &self.time)
+            .field("bits", &self.bits)
             .finish()
     }
 }
 
-pub fn get_block_index(path: &Path) -> OpResult<Vec<BlockIndexRecord>> {
+/// Reads the longest chain from the LevelDB block index at `path`.
+/// If `copy` is set or the index can't be opened (e.g. because a running node holds the lock),
+/// a copy of the index is read instead.
+pub fn get_block_index(path: &Path, copy: bool) -> OpResult<Vec<BlockIndexRecord>> {
     info!(target: "index", "Reading index from {} ...", path.display());
 
-    let mut block_index = Vec::with_capacity(800000);
-    let mut db = DB::open(path, Options::default())?;
+    let records = if copy {
+        read_index_copy(path)?
+    } else {
+        match DB::open(path, Options::default()) {
+            Ok(db) => read_index(db, path)?,
+            Err(e) => {
+                debug!(target: "index", "Unable to open index ({}), reading a copy instead.", e);
+                read_index_copy(path)?
+            }
+        }
+    };
+    let block_index = longest_chain(records)?;
+    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
+    Ok(block_index)
+}
+
+/// Returns all records of blocks whose data is stored in the blk files.
+/// `path` is only used to locate malformed records in error messages.
+fn read_index(mut db: DB, path: &Path) -> OpResult<Vec<BlockIndexRecord>> {
+    let mut records = Vec::with_capacity(800000);
     let mut iter = db.new_iter()?;
     let (mut k, mut v) = (vec![], vec![]);
 
     while iter.advance() {
         iter.current(&mut k, &mut v);
         if is_block_index_record(&k) {
-            let record = BlockIndexRecord::from(&k[1..], &v)?;
-            if record.status & (BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA | BLOCK_VALID_CHAIN) > 0 {
-                block_index.push(record);
+            let record = BlockIndexRecord::from(&k[1..], &v).map_err(|e| {
+                let location = DataLocation {
+                    file: Some(path.to_path_buf()),
+                    ..Default::default()
+                };
+                e.in_index(location).join_msg(&format!(
+                    "Malformed record with key {}.",
+                    utils::arr_to_hex(&k)
+                ))
+            })?;
+            if record.status & BLOCK_HAVE_DATA > 0 && record.status & BLOCK_FAILED_MASK == 0 {
+                records.push(record);
             }
         }
     }
-    block_index.sort_by_key(|b| b.height);
-    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
-    Ok(block_index)
+    Ok(records)
+}
+
+/// Copies the index without its LOCK file to a temporary directory and reads the copy.
+/// LevelDB files are only appended or replaced, so the copy is consistent enough
+/// to be read while the node is writing. The copy is kept for the next call.
+fn read_index_copy(path: &Path) -> OpResult<Vec<BlockIndexRecord>> {
+    let copy = index_copy_dir();
+    sync_index(path, &copy)?;
+    let db = DB::open(&copy, Options::default())?;
+    read_index(db, path)
+}
+
+/// Temporary directory of the index copy, one per process
+fn index_copy_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("rusty-blockparser-index-{}", process::id()))
+}
+
+/// Removes the index copy, if there is one
+pub fn remove_index_copy() -> OpResult<()> {
+    let copy = index_copy_dir();
+    if copy.exists() {
+        fs::remove_dir_all(copy)?;
+    }
+    Ok(())
+}
+
+/// Updates the copy of the index at `path`: only new or changed files are copied, usually
+/// the log, the manifest and new *.ldb tables. Copied files get the modification time of the
+/// original, so files which were changed while reading the copy are copied again as well.
+fn sync_index(path: &Path, copy: &Path) -> OpResult<()> {
+    fs::create_dir_all(copy)?;
+    let mut n_copied = 0;
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name() == "LOCK" || !entry.file_type()?.is_file() {
+            continue;
+        }
+        let target = copy.join(entry.file_name());
+        names.push(entry.file_name());
+        let metadata = entry.metadata()?;
+        let unchanged = match fs::metadata(&target) {
+            Ok(copied) => {
+                copied.len() == metadata.len() && copied.modified()? == metadata.modified()?
+            }
+            Err(_) => false,
+        };
+        if !unchanged {
+            fs::copy(entry.path(), &target)?;
+            File::options()
+                .write(true)
+                .open(&target)?
+                .set_modified(metadata.modified()?)?;
+            n_copied += 1;
+        }
+    }
+    // Files written while reading the copy
+    for entry in fs::read_dir(copy)? {
+        let entry = entry?;
+        if !names.contains(&entry.file_name()) && entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    trace!(target: "index", "Copied {} of {} index files to {}", n_copied, names.len(), copy.display());
+    Ok(())
+}
+
+/// Follows the prev hashes from the best tip down to the genesis block.
+/// The tip is the highest block, preferring fully validated blocks on ties.
+fn longest_chain(records: Vec<BlockIndexRecord>) -> OpResult<Vec<BlockIndexRecord>> {
+    let tip = match records
+        .iter()
+        .max_by_key(|r| (r.height, r.status & BLOCK_VALID_MASK))
+    {
+        Some(tip) => tip.block_hash,
+        None => return Ok(Vec::new()),
+    };
+    let mut by_hash: HashMap<[u8; 32], BlockIndexRecord> =
+        records.into_iter().map(|r| (r.block_hash, r)).collect();
+
+    let mut chain = Vec::new();
+    let mut hash = tip;
+    while let Some(record) = by_hash.remove(&hash) {
+        hash = record.prev_hash;
+        chain.push(record);
+    }
+    chain.reverse();
+    match chain.first() {
+        Some(genesis) if genesis.height == 0 => Ok(chain),
+        Some(first) => Err(OpError::new(OpErrorKind::ValidateError).join_msg(&format!(
+            "Longest chain is incomplete, block {} ({}) is missing. Pruned node?",
+            first.height - 1,
+            utils::arr_to_hex_swapped(&first.prev_hash)
+        ))),
+        None => Ok(chain),
+    }
 }
 
 #[inline]
 fn is_block_index_record(data: &[u8]) -> bool {
-    *data.get(0).unwrap() == b'b'
+    data.first() == Some(&b'b')
 }
 
 /// TODO: this is a wonky 1:1 translation from https://github.com/bitcoin/bitcoin
@@ -93,13 +274,13 @@ fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<usize> {
     let mut n = 0;
     loop {
         let ch_data = reader.read_u8()?;
-        if n > usize::max_value() >> 7 {
-            panic!("size too large");
+        if n > usize::MAX >> 7 {
+            return Err(varint_too_large());
         }
         n = (n << 7) | (ch_data & 0x7F) as usize;
         if ch_data & 0x80 > 0 {
-            if n == usize::max_value() {
-                panic!("size too large");
+            if n == usize::MAX {
+                return Err(varint_too_large());
             }
             n += 1;
         } else {
@@ -108,3 +289,121 @@ fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<usize> {
     }
     Ok(n)
 }
+
+fn varint_too_large() -> OpError {
+    OpError::from(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "VarInt exceeds usize.",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(height: usize, hash: u8, prev: u8, status: usize) -> BlockIndexRecord {
+        let mut record = BlockIndexRecord::at(height, 0, 0);
+        record.block_hash = [hash; 32];
+        record.prev_hash = if height == 0 { [0u8; 32] } else { [prev; 32] };
+        record.status = status;
+        record
+    }
+
+    #[test]
+    fn test_longest_chain() {
+        let valid = BLOCK_HAVE_DATA | 5;
+        // 0 <- 1 <- 2 <- 3 with a stale block 12 at height 2 and an unconnected tip 13 at height 3
+        let records = vec![
+            record(3, 3, 2, valid),
+            record(2, 12, 1, valid),
+            record(0, 0xF0, 0, valid),
+            record(2, 2, 1, valid),
+            record(1, 1, 0xF0, valid),
+            record(3, 13, 12, BLOCK_HAVE_DATA | 3),
+        ];
+        let chain = longest_chain(records).unwrap();
+        let hashes: Vec<u8> = chain.iter().map(|r| r.block_hash[0]).collect();
+        assert_eq!(hashes, vec![0xF0, 1, 2, 3]);
+        assert!(chain.iter().enumerate().all(|(i, r)| r.height == i));
+
+        // Missing block 1
+        let records = vec![record(0, 0xF0, 0, valid), record(2, 2, 1, valid)];
+        assert!(longest_chain(records).is_err());
+        assert!(longest_chain(Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_record() {
+        // version, height=2, status=HAVE_DATA|HAVE_UNDO|3, n_tx=1, n_file=1, n_data_pos=8, n_undo_pos=9
+        let mut value = vec![0x01, 0x02, 0x1B, 0x01, 0x01, 0x08, 0x09];
+        value.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        value.extend_from_slice(&[0xAB; 32]);
+        value.extend_from_slice(&[0x00; 32]);
+        value.extend_from_slice(&1231006505u32.to_le_bytes());
+        value.extend_from_slice(&0x1d00ffffu32.to_le_bytes());
+        value.extend_from_slice(&[0x00; 4]);
+        let record = BlockIndexRecord::from(&[0xCD; 32], &value).unwrap();
+        assert_eq!(record.height, 2);
+        assert_eq!(record.n_file, 1);
+        assert_eq!(record.n_data_pos, 8);
+        assert_eq!(record.prev_hash, [0xAB; 32]);
+        assert_eq!(record.time, 1231006505);
+        assert_eq!(record.bits, 0x1d00ffff);
+        assert_eq!(record.block_hash, [0xCD; 32]);
+    }
+
+    #[test]
+    fn test_malformed_record() {
+        let value = [0x01, 0x02, 0x1B, 0x01];
+        assert!(BlockIndexRecord::from(&[0xCD; 31], &value).is_err());
+        // Truncated header
+        assert!(BlockIndexRecord::from(&[0xCD; 32], &value).is_err());
+        assert!(!is_block_index_record(&[]));
+    }
+
+    #[test]
+    fn test_read_varint() {
+        assert_eq!(read_varint(&mut Cursor::new(&[0x7F][..])).unwrap(), 0x7F);
+        assert_eq!(
+            read_varint(&mut Cursor::new(&[0x80, 0x00][..])).unwrap(),
+            0x80
+        );
+        let overflow = [0xFF; 16];
+        assert!(read_varint(&mut Cursor::new(&overflow[..])).is_err());
+    }
+
+    #[test]
+    fn test_sync_index() {
+        let dir = std::env::temp_dir().join(format!("test_sync_index-{}", process::id()));
+        let copy = dir.join("copy");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("LOCK"), b"").unwrap();
+        fs::write(dir.join("000001.ldb"), b"data").unwrap();
+        fs::write(dir.join("000002.log"), b"log").unwrap();
+
+        sync_index(&dir, &copy).unwrap();
+        assert!(!copy.join("LOCK").exists());
+        assert_eq!(fs::read(copy.join("000001.ldb")).unwrap(), b"data");
+
+        // Unchanged files are kept, changed, deleted and new files are synced
+        let table = copy.join("000001.ldb");
+        let modified = fs::metadata(&table).unwrap().modified().unwrap();
+        fs::write(&table, b"kept").unwrap();
+        File::options()
+            .write(true)
+            .open(&table)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::write(dir.join("000002.log"), b"log appended").unwrap();
+        fs::write(copy.join("000003.log"), b"written by the reader").unwrap();
+        fs::write(dir.join("000004.ldb"), b"new").unwrap();
+
+        sync_index(&dir, &copy).unwrap();
+        assert_eq!(fs::read(&table).unwrap(), b"kept");
+        assert_eq!(fs::read(copy.join("000002.log")).unwrap(), b"log appended");
+        assert!(!copy.join("000003.log").exists());
+        assert_eq!(fs::read(copy.join("000004.ldb")).unwrap(), b"new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGINT or SIGTERM
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Lets SIGINT and SIGTERM stop the parser after the current blocks instead of killing it.
+/// A second signal kills the process as usual.
+#[cfg(unix)]
+pub fn install_handlers() {
+    extern "C" fn on_signal(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // Only async-signal-safe calls are allowed here
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handlers() {}
+
+/// Returns true once SIGINT or SIGTERM was received
+#[inline]
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -1,107 +1,656 @@
-use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::blockchain::parser::checkpoint::Checkpoint;
+use crate::blockchain::parser::consensus::ScriptCounts;
+use crate::blockchain::parser::prevouts::PrevoutStore;
 use crate::blockchain::proto::block::Block;
-use crate::ParserOptions;
-use errors::OpResult;
+use crate::blockchain::proto::tx::EvaluatedTxOut;
+use crate::callbacks::{self, Callback};
+use crate::common::utils;
+use crate::{BlockRef, ErrorPolicy, ParserOptions};
+use errors::{DataLocation, OpError, OpErrorKind, OpResult};
 
-mod blkfile;
+pub mod blkfile;
 pub mod chain;
+mod checkpoint;
+pub mod consensus;
 mod index;
+mod interrupt;
+mod pipeline;
+mod prevouts;
+mod range;
 pub mod reader;
 pub mod types;
 
+/// Number of blocks per worker thread handed to parallel callbacks at once
+const BLOCKS_PER_BATCH: usize = 8;
+
 /// Small struct to hold statistics together
 struct WorkerStats {
+    pub n_processed: u64,
     pub n_height: u64,
+    pub n_bytes: u64,
     pub t_started: Instant,
     pub t_last_log: Instant,
     pub t_measure_frame: Duration,
+    pub t_callbacks: Vec<Duration>,
+    pub t_last_checkpoint: Instant,
+    pub n_scripts: ScriptCounts, // inputs handled with --verify-scripts
+    pub n_script_failures: u64,  // blocks with a failed script, if not aborted
 }
 
 impl Default for WorkerStats {
     fn default() -> Self {
         Self {
+            n_processed: 0,
             n_height: 0,
+            n_bytes: 0,
             t_started: Instant::now(),
             t_last_log: Instant::now(),
             t_measure_frame: Duration::from_secs(10),
+            t_callbacks: Vec::new(),
+            t_last_checkpoint: Instant::now(),
+            n_scripts: ScriptCounts::default(),
+            n_script_failures: 0,
         }
     }
 }
 
-pub struct BlockchainParser<'a> {
-    options: &'a RefCell<ParserOptions>, // struct to hold cli arguments
-    chain_storage: chain::ChainStorage<'a>, // Hash storage with the longest chain
+/// Block which was left out because of malformed or invalid data
+struct SkippedBlock {
+    height: u64,
+    n_tx: usize,
+    reason: String,
+}
+
+pub struct BlockchainParser {
+    options: ParserOptions,              // struct to hold cli arguments
+    chain_storage: chain::ChainStorage,  // Hash storage with the longest chain
     stats: WorkerStats,                  // struct for thread management & statistics
+    prevouts: Option<PrevoutStore>,      // only kept if a callback needs resolved prevouts
+    resume_states: Option<Vec<Vec<u8>>>, // callback states to resume from
+    skipped: Vec<SkippedBlock>,          // blocks left out according to the error policy
 }
 
-impl<'a> BlockchainParser<'a> {
-    /// Instantiates a new Parser but does not start the workers.
-    pub fn new(
-        options: &'a RefCell<ParserOptions>,
-        chain_storage: chain::ChainStorage<'a>,
-    ) -> Self {
-        info!(target: "parser", "Parsing {} blockchain (range={}) ...", options.borrow().coin_type.name, options.borrow().range);
-        Self {
+impl BlockchainParser {
+    /// Loads the chain index and instantiates a new Parser, but does not start it.
+    /// If resuming, the range starts after the last checkpoint.
+    pub fn new(options: ParserOptions) -> OpResult<Self> {
+        BlockchainParser::init(options).map_err(BlockchainParser::cleanup)
+    }
+
+    fn init(mut options: ParserOptions) -> OpResult<Self> {
+        let checkpoint = match options.checkpoint {
+            Some(ref path) if options.resume => {
+                Some(BlockchainParser::load_checkpoint(path.as_path(), &options)?)
+            }
+            _ => None,
+        };
+        if let Some(ref checkpoint) = checkpoint {
+            options.start = BlockRef::Height(checkpoint.next_height as usize);
+        }
+
+        let blocks = chain::ChainStorage::load_index(&options).map_err(|mut e| {
+            e.message.insert_str(
+                0,
+                &format!(
+                    "Cannot load blockchain from: '{}'. ",
+                    options.blockchain_dir.display()
+                ),
+            );
+            e
+        })?;
+        let (range, heights) = range::resolve_heights(&options, &blocks)?;
+        match options.selection {
+            Some(_) => {
+                info!(target: "parser", "Parsing {} selected blocks of {} blockchain (range={}) ...",
+                             heights.len(), options.coin_type.name, range)
+            }
+            None => {
+                info!(target: "parser", "Parsing {} blockchain (range={}) ...", options.coin_type.name, range)
+            }
+        }
+        let n_start = heights.first().cloned().unwrap_or(range.start) as u64;
+        let chain_storage =
+            chain::ChainStorage::with_index(&options, blocks, heights).map_err(|mut e| {
+                e.message.insert_str(
+                    0,
+                    &format!(
+                        "Cannot load blockchain from: '{}'. ",
+                        options.blockchain_dir.display()
+                    ),
+                );
+                e
+            })?;
+        let needs_prevouts =
+            options.verify_scripts || options.callbacks.iter().any(|(_, cb)| cb.needs_prevouts());
+        let mut prevouts = None;
+        let resume_states = match checkpoint {
+            Some(mut checkpoint) => {
+                let last_height = checkpoint.next_height as usize - 1;
+                if chain_storage.block_hash(last_height) != Some(checkpoint.last_hash) {
+                    return Err(OpError::new(OpErrorKind::ValidateError).join_msg(&format!(
+                        "Block {} ({}) of the checkpoint is no longer part of the longest chain.",
+                        last_height,
+                        utils::arr_to_hex_swapped(&checkpoint.last_hash)
+                    )));
+                }
+                if needs_prevouts {
+                    prevouts = match checkpoint.prevouts.take() {
+                        Some(store) => Some(store),
+                        None => {
+                            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(
+                                "Checkpoint doesn't contain the unspent outputs needed by --verify-scripts or the \
+                                 callbacks, start again without --resume.",
+                            ))
+                        }
+                    };
+                }
+                info!(target: "parser", "Resuming at block {} ...", checkpoint.next_height);
+                Some(
+                    checkpoint
+                        .states
+                        .into_iter()
+                        .map(|(_, state)| state)
+                        .collect(),
+                )
+            }
+            None => {
+                if needs_prevouts {
+                    if options.verify_scripts && n_start > 0 {
+                        warn!(target: "parser", "Inputs spending outputs created before block {} can't be verified.", n_start);
+                    }
+                    prevouts = Some(PrevoutStore::default());
+                }
+                None
+            }
+        };
+        let stats = WorkerStats {
+            n_height: n_start,
+            ..Default::default()
+        };
+        Ok(Self {
             options,
             chain_storage,
-            stats: WorkerStats::default(),
+            stats,
+            prevouts,
+            resume_states,
+            skipped: Vec::new(),
+        })
+    }
+
+    /// Loads the checkpoint and makes sure it was written for the same callbacks
+    fn load_checkpoint(path: &Path, options: &ParserOptions) -> OpResult<Checkpoint> {
+        let checkpoint =
+            Checkpoint::load(path, &options.coin_type.address_params()).map_err(|mut e| {
+                e.message.insert_str(
+                    0,
+                    &format!("Cannot load checkpoint from: '{}'. ", path.display()),
+                );
+                e
+            })?;
+        let expected: Vec<&str> = checkpoint
+            .states
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let given: Vec<&str> = options
+            .callbacks
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if expected != given || checkpoint.next_height == 0 {
+            return Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "Checkpoint was written for callbacks {:?} at height {}, got {:?}.",
+                    expected, checkpoint.next_height, given
+                )),
+            );
         }
+        Ok(checkpoint)
     }
 
+    /// Parses all blocks. If interrupted, the outputs are flushed and a checkpoint is saved instead of
+    /// completing them, so the run can be continued with --resume.
     pub fn start(&mut self) -> OpResult<()> {
+        match self.run() {
+            Ok(()) => index::remove_index_copy(),
+            Err(e) => Err(BlockchainParser::cleanup(e)),
+        }
+    }
+
+    /// Removes temporary files after `err` stopped the parser
+    fn cleanup(err: OpError) -> OpError {
+        if let Err(e) = index::remove_index_copy() {
+            warn!(target: "parser", "Unable to remove the copy of the block index: {}", e);
+        }
+        err
+    }
+
+    fn run(&mut self) -> OpResult<()> {
         debug!(target: "parser", "Starting worker ...");
 
         self.on_start()?;
-        while let Some(block) = self.chain_storage.get_next() {
-            self.on_block(&block)?;
+        let parallel = self
+            .options
+            .callbacks
+            .iter()
+            .any(|(_, cb)| cb.is_parallel());
+        let batch_size = if parallel {
+            self.options.worker_threads * BLOCKS_PER_BATCH
+        } else {
+            1
+        };
+        interrupt::install_handlers();
+        self.parse_available(batch_size)?;
+        while let Some(interval) = self.options.follow {
+            if !self.wait(interval) {
+                break;
+            }
+            let n_height = self.stats.n_height;
+            self.follow()?;
+            self.parse_available(batch_size)?;
+            // Make the new blocks visible in the outputs
+            if self.stats.n_height != n_height {
+                self.flush()?;
+            }
+        }
+        if interrupt::is_interrupted() {
+            return self.on_interrupt();
         }
         self.on_complete()
     }
 
-    /// Triggers the on_start() callback and initializes state.
+    /// Flushes the outputs and saves a checkpoint. The callbacks aren't completed and the
+    /// checkpoint is kept, the outputs stay incomplete until the run is resumed.
+    fn on_interrupt(&mut self) -> OpResult<()> {
+        self.flush()?;
+        match self.options.checkpoint {
+            Some(ref path) => {
+                info!(target: "parser", "Interrupted at block {}, continue with --checkpoint {} --resume.",
+                                    self.stats.n_height, path.display())
+            }
+            None => {
+                warn!(target: "parser", "Interrupted at block {}, the outputs are incomplete.", self.stats.n_height)
+            }
+        }
+        Ok(())
+    }
+
+    /// Sleeps for `interval`, returns false if the parser got interrupted in the meantime.
+    fn wait(&self, interval: Duration) -> bool {
+        let started = Instant::now();
+        while !interrupt::is_interrupted() {
+            let elapsed = started.elapsed();
+            if elapsed >= interval {
+                return true;
+            }
+            thread::sleep((interval - elapsed).min(Duration::from_millis(200)));
+        }
+        false
+    }
+
+    /// Passes all blocks of the chain storage to the callbacks, up to `batch_size` consecutive blocks at once.
+    fn parse_available(&mut self, batch_size: usize) -> OpResult<()> {
+        let mut batch = Vec::with_capacity(batch_size);
+        while !interrupt::is_interrupted() {
+            let result = match self.chain_storage.next() {
+                Some(result) => result,
+                None => break,
+            };
+            let (height, block) = match result {
+                Ok(item) => item,
+                Err(e) => {
+                    self.skip_block(e)?;
+                    continue;
+                }
+            };
+            // Skip gaps between selected blocks
+            if height != self.stats.n_height + batch.len() as u64 {
+                if !batch.is_empty() {
+                    self.on_blocks(&batch)?;
+                    batch.clear();
+                }
+                self.stats.n_height = height;
+            }
+            batch.push(block);
+            if batch.len() >= batch_size {
+                self.on_blocks(&batch)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.on_blocks(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a block which couldn't be read or verified according to the error policy.
+    /// Errors which aren't caused by the block data itself always abort.
+    fn skip_block(&mut self, err: OpError) -> OpResult<()> {
+        let height = match err.location().and_then(|location| location.height) {
+            Some(height) if self.options.on_error != ErrorPolicy::Abort => height,
+            _ => return Err(err),
+        };
+        let reason = err.to_string();
+        if self.options.on_error == ErrorPolicy::Log {
+            warn!(target: "parser", "Skipping block {}: {}", height, reason);
+        } else {
+            debug!(target: "parser", "Skipping block {}: {}", height, reason);
+        }
+        self.skipped.push(SkippedBlock {
+            height,
+            n_tx: self.chain_storage.tx_count(height as usize).unwrap_or(0),
+            reason,
+        });
+        Ok(())
+    }
+
+    /// Re-reads the block index and continues with the blocks appended by the node.
+    /// If already processed blocks are no longer part of the longest chain,
+    /// the callbacks are rolled back to the fork with on_reorg().
+    /// Following stops before the reorg if a callback doesn't support reorgs or unspent outputs are kept.
+    fn follow(&mut self) -> OpResult<()> {
+        let blocks = chain::ChainStorage::load_index(&self.options)?;
+        let n_height = self.stats.n_height as usize;
+
+        // Lowest processed height which differs from the new chain
+        let mut fork_height = n_height;
+        while fork_height > 0
+            && blocks.get(fork_height - 1).map(|b| b.block_hash)
+                != self.chain_storage.block_hash(fork_height - 1)
+        {
+            fork_height -= 1;
+        }
+        if fork_height < n_height {
+            warn!(target: "parser", "Reorg: blocks {}..{} are no longer part of the longest chain.", fork_height, n_height);
+            let unsupported: Vec<&str> = self
+                .options
+                .callbacks
+                .iter()
+                .filter(|(_, cb)| !cb.supports_reorg())
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if !unsupported.is_empty() {
+                warn!(target: "parser", "Callbacks {:?} don't support reorgs, stopping at block {}.", unsupported, n_height);
+                self.options.follow = None;
+                return Ok(());
+            }
+            // Outputs spent by the disconnected blocks are gone
+            if self.prevouts.is_some() {
+                warn!(target: "parser", "Unspent outputs can't be rolled back, stopping at block {}.", n_height);
+                self.options.follow = None;
+                return Ok(());
+            }
+            self.for_each_callback(|cb| cb.on_reorg(fork_height as u64))?;
+            trace!(target: "parser", "on_reorg(fork_height={}) called", fork_height);
+            self.stats.n_height = fork_height as u64;
+            self.skipped
+                .retain(|skipped| skipped.height < fork_height as u64);
+        } else if blocks.len() <= n_height {
+            trace!(target: "parser", "No new blocks after {}", n_height);
+            return Ok(());
+        }
+        info!(target: "parser", "Following {} new blocks ...", blocks.len().saturating_sub(fork_height));
+        let heights = (fork_height..blocks.len()).collect();
+        self.chain_storage = chain::ChainStorage::with_index(&self.options, blocks, heights)?;
+        Ok(())
+    }
+
+    /// Triggers the on_start() callbacks and initializes state.
     fn on_start(&mut self) -> OpResult<()> {
-        let coin_type = self.options.borrow().coin_type.clone();
+        let coin_type = self.options.coin_type.clone();
         self.stats.t_started = Instant::now();
         self.stats.t_last_log = Instant::now();
-        (*self.options.borrow_mut().callback).on_start(&coin_type, self.stats.n_height)?;
-        trace!(target: "parser", "on_start() called");
+        self.stats.t_last_checkpoint = Instant::now();
+        let n_height = self.stats.n_height;
+        self.stats.t_callbacks = vec![Duration::default(); self.options.callbacks.len()];
+        match self.resume_states.take() {
+            Some(states) => {
+                let mut states = states.iter();
+                self.for_each_callback(|cb| {
+                    cb.on_resume(&coin_type, n_height, transform!(states.next()))
+                })?;
+                trace!(target: "parser", "on_resume() called");
+            }
+            None => {
+                self.for_each_callback(|cb| cb.on_start(&coin_type, n_height))?;
+                trace!(target: "parser", "on_start() called");
+            }
+        }
         Ok(())
     }
 
-    /// Triggers the on_block() callback and updates statistics.
-    fn on_block(&mut self, block: &Block) -> OpResult<()> {
-        (*self.options.borrow_mut().callback).on_block(block, self.stats.n_height)?;
-        trace!(target: "parser", "on_block(height={}) called", self.stats.n_height);
-        self.stats.n_height += 1;
+    /// Triggers the on_blocks() callbacks and updates statistics. Callbacks which don't take batches
+    /// get each block followed by its transaction hooks before the next block.
+    fn on_blocks(&mut self, blocks: &[Block]) -> OpResult<()> {
+        let n_height = self.stats.n_height;
+        let prevouts: Vec<_> = match self.prevouts {
+            Some(ref mut store) => blocks.iter().map(|block| store.resolve(block)).collect(),
+            None => Vec::new(),
+        };
+        if self.options.verify_scripts {
+            for (i, block) in blocks.iter().enumerate() {
+                self.verify_scripts(block, n_height + i as u64, &prevouts[i])?;
+            }
+        }
+        self.for_each_callback(|cb| {
+            if cb.is_parallel() {
+                return cb.on_blocks(blocks, n_height);
+            }
+            for (i, block) in blocks.iter().enumerate() {
+                let block_height = n_height + i as u64;
+                cb.on_blocks(std::slice::from_ref(block), block_height)?;
+                let block_prevouts = prevouts.get(i).map(|p| p.as_slice()).unwrap_or(&[]);
+                callbacks::walk_txs(cb, block, block_height, block_prevouts)?;
+            }
+            Ok(())
+        })?;
+        trace!(target: "parser", "on_blocks(height={}, n={}) called", self.stats.n_height, blocks.len());
+        self.stats.n_height += blocks.len() as u64;
+        self.stats.n_processed += blocks.len() as u64;
+        self.stats.n_bytes += blocks.iter().map(|b| b.size as u64).sum::<u64>();
 
         // Some performance measurements and logging
         let now = Instant::now();
         if now - self.stats.t_last_log > self.stats.t_measure_frame {
-            info!(target: "parser", "Status: {:6} Blocks processed. (left: {:6}, avg: {:5.2} blocks/sec)",
-                  self.stats.n_height, self.chain_storage.remaining(), self.blocks_sec());
+            info!(target: "parser", "Status: {:6} Blocks processed. (left: {:6}, avg: {:5.2} blocks/sec, {:6.2} MiB/sec, buffered: {:3})",
+                  self.stats.n_height, self.chain_storage.remaining(), self.blocks_sec(),
+                  self.mib_sec(), self.chain_storage.buffered());
             self.stats.t_last_log = now;
         }
+        if self.options.checkpoint.is_some()
+            && now - self.stats.t_last_checkpoint > self.options.checkpoint_interval
+        {
+            if let Some(last) = blocks.last() {
+                self.checkpoint(last.header.hash)?;
+            }
+            self.stats.t_last_checkpoint = now;
+        }
+        Ok(())
+    }
+
+    /// Verifies the input scripts of a block, failures are handled according to the error policy.
+    /// Failed blocks are still passed to the callbacks, their outputs are already part of the prevouts.
+    fn verify_scripts(
+        &mut self,
+        block: &Block,
+        height: u64,
+        prevouts: &[Vec<Option<EvaluatedTxOut>>],
+    ) -> OpResult<()> {
+        let flags = self.options.coin_type.script_flags(height);
+        match consensus::verify_scripts(block, prevouts, flags) {
+            Ok(counts) => {
+                self.stats.n_scripts.verified += counts.verified;
+                self.stats.n_scripts.unresolved += counts.unresolved;
+                self.stats.n_scripts.skipped += counts.skipped;
+                if counts.skipped > 0 {
+                    debug!(target: "parser", "Skipped {} inputs of block {} with unresolved outputs in the same transaction.",
+                           counts.skipped, height);
+                }
+            }
+            Err(e) => {
+                let location = DataLocation {
+                    height: Some(height),
+                    ..Default::default()
+                };
+                let err = OpError::from(e).in_block(location).join_msg(&format!(
+                    "Block {} is invalid.",
+                    utils::arr_to_hex_swapped(&block.header.hash)
+                ));
+                match self.options.on_error {
+                    ErrorPolicy::Abort => return Err(err),
+                    ErrorPolicy::Log => warn!(target: "parser", "{}", err),
+                    ErrorPolicy::Skip => debug!(target: "parser", "{}", err),
+                }
+                self.stats.n_script_failures += 1;
+            }
+        }
         Ok(())
     }
 
+    /// Flushes the outputs of all callbacks and saves a checkpoint if enabled.
+    fn flush(&mut self) -> OpResult<()> {
+        let n_height = self.stats.n_height as usize;
+        if let Some(last_hash) = n_height
+            .checked_sub(1)
+            .and_then(|h| self.chain_storage.block_hash(h))
+        {
+            self.checkpoint(last_hash)?;
+            self.stats.t_last_checkpoint = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Collects the state of all callbacks and saves a checkpoint.
+    /// Disables checkpoints if a callback doesn't support them.
+    fn checkpoint(&mut self, last_hash: [u8; 32]) -> OpResult<()> {
+        let n_height = self.stats.n_height;
+        let mut states = Vec::with_capacity(self.options.callbacks.len());
+        self.for_each_callback(|cb| {
+            states.push(cb.on_checkpoint(n_height)?);
+            Ok(())
+        })?;
+
+        let mut named_states = Vec::with_capacity(states.len());
+        for ((name, _), state) in self.options.callbacks.iter().zip(states) {
+            match state {
+                Some(state) => named_states.push((name.clone(), state)),
+                None => {
+                    if self.options.checkpoint.is_some() {
+                        warn!(target: "parser", "Callback {} doesn't support resuming, disabling checkpoints.", name);
+                        self.options.checkpoint = None;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        // The unspent outputs are moved into the checkpoint while it is saved
+        let checkpoint = Checkpoint {
+            next_height: n_height,
+            last_hash,
+            states: named_states,
+            prevouts: self.prevouts.take(),
+        };
+        let result = match self.options.checkpoint {
+            Some(ref path) => checkpoint.save(path).map(|_| {
+                debug!(target: "parser", "Saved checkpoint at block {} to {}", n_height, path.display());
+            }),
+            None => Ok(()),
+        };
+        self.prevouts = checkpoint.prevouts;
+        result
+    }
+
     /// Triggers the on_complete() callback and updates statistics.
     fn on_complete(&mut self) -> OpResult<()> {
-        info!(target: "parser", "Done. Processed {} blocks in {:.2} minutes. (avg: {:5.2} blocks/sec)",
-              self.stats.n_height, (Instant::now() - self.stats.t_started).as_secs_f32() / 60.0,
-              self.blocks_sec());
+        info!(target: "parser", "Done. Processed {} blocks in {:.2} minutes. (avg: {:5.2} blocks/sec, {:6.2} MiB/sec)",
+              self.stats.n_processed, (Instant::now() - self.stats.t_started).as_secs_f32() / 60.0,
+              self.blocks_sec(), self.mib_sec());
 
-        (*self.options.borrow_mut().callback).on_complete(self.stats.n_height)?;
+        if !self.skipped.is_empty() {
+            let n_tx: usize = self.skipped.iter().map(|skipped| skipped.n_tx).sum();
+            warn!(target: "parser", "Skipped {} blocks with {} transactions because of malformed data:",
+                  self.skipped.len(), n_tx);
+            for skipped in &self.skipped {
+                warn!(target: "parser", "   -> {:8} ({} txs): {}", skipped.height, skipped.n_tx, skipped.reason);
+            }
+        }
+
+        if self.options.verify_scripts {
+            info!(target: "parser", "Verified the scripts of {} inputs, {} inputs spend outputs from before the parsed range.",
+                  self.stats.n_scripts.verified, self.stats.n_scripts.unresolved);
+            if self.stats.n_scripts.skipped > 0 {
+                warn!(target: "parser", "Skipped {} inputs of transactions which also spend outputs from before the parsed range.",
+                      self.stats.n_scripts.skipped);
+            }
+            if self.stats.n_script_failures > 0 {
+                warn!(target: "parser", "{} blocks contain inputs with failing scripts.", self.stats.n_script_failures);
+            }
+        }
+
+        let n_height = self.stats.n_height;
+        self.for_each_callback(|cb| cb.on_complete(n_height))?;
         trace!(target: "parser", "on_complete() called");
+
+        // Outputs are final now, there is nothing left to resume
+        if let Some(ref path) = self.options.checkpoint {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let options = &self.options;
+        if options.callbacks.len() > 1 {
+            let t_total: Duration = self.stats.t_callbacks.iter().sum();
+            info!(target: "parser", "Time spent in callbacks:");
+            for ((name, _), t) in options.callbacks.iter().zip(&self.stats.t_callbacks) {
+                info!(target: "parser", "   -> {:15} {:8.2} minutes ({:5.1}%)", name,
+                      t.as_secs_f32() / 60.0, t.as_secs_f32() / t_total.as_secs_f32().max(f32::EPSILON) * 100.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` for every callback in the order given on the command line and measures the time spent.
+    fn for_each_callback<F>(&mut self, mut f: F) -> OpResult<()>
+    where
+        F: FnMut(&mut dyn Callback) -> OpResult<()>,
+    {
+        for ((name, callback), t) in self
+            .options
+            .callbacks
+            .iter_mut()
+            .zip(&mut self.stats.t_callbacks)
+        {
+            let started = Instant::now();
+            f(callback.as_mut()).map_err(|mut e| {
+                e.message.insert_str(0, &format!("{}: ", name));
+                e
+            })?;
+            *t += started.elapsed();
+        }
         Ok(())
     }
 
     /// Returns the number of avg processed blocks
     fn blocks_sec(&self) -> u64 {
-        self.stats
-            .n_height
+        let n_processed = self.stats.n_processed;
+        n_processed
             .checked_div((Instant::now() - self.stats.t_started).as_secs())
-            .unwrap_or(self.stats.n_height)
+            .unwrap_or(n_processed)
+    }
+
+    /// Returns the avg amount of processed raw block data in MiB
+    fn mib_sec(&self) -> f64 {
+        let secs = (Instant::now() - self.stats.t_started).as_secs_f64();
+        if secs > 0.0 {
+            self.stats.n_bytes as f64 / secs / (1024.0 * 1024.0)
+        } else {
+            0.0
+        }
     }
 }
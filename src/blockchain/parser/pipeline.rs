@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::blockchain::parser::blkfile::BlkStore;
+use crate::blockchain::parser::index::BlockIndexRecord;
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::AddressParams;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Number of blocks each worker is allowed to decode ahead of the consumer.
+/// Bounds the memory used by the pipeline to `n_workers * BLOCKS_PER_WORKER` blocks.
+const BLOCKS_PER_WORKER: usize = 4;
+
+/// State shared between the consumer and all decoder threads
+struct Shared {
+    next_pos: AtomicUsize,  // Position of the next height to be claimed by a worker
+    consumed: Mutex<usize>, // Position of the next height the consumer is waiting for
+    window_moved: Condvar,  // Signaled every time the consumer advances
+    shutdown: AtomicBool,
+}
+
+/// Reads and decodes blocks on a pool of worker threads
+/// and hands them out strictly in the order of the given heights.
+pub struct BlockPipeline {
+    shared: Arc<Shared>,
+    rx: Receiver<(usize, OpResult<Block>)>,
+    // Decoded blocks which arrived before their predecessors, by position
+    pending: HashMap<usize, OpResult<Block>>,
+    heights: Arc<Vec<usize>>,
+    next: usize,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl BlockPipeline {
+    /// Spawns `n_workers` threads decoding the blocks at `heights`.
+    pub fn new(
+        blocks: Arc<Vec<BlockIndexRecord>>,
+        blk_store: Arc<BlkStore>,
+        coin_type: CoinType,
+        heights: Arc<Vec<usize>>,
+        n_workers: usize,
+    ) -> OpResult<Self> {
+        let n_workers = n_workers.max(1);
+        let window = n_workers * BLOCKS_PER_WORKER;
+        let shared = Arc::new(Shared {
+            next_pos: AtomicUsize::new(0),
+            consumed: Mutex::new(0),
+            window_moved: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let address_params = Arc::new(coin_type.address_params());
+        let (tx, rx) = mpsc::channel();
+        let mut workers = Vec::with_capacity(n_workers);
+        for i in 0..n_workers {
+            let worker = Worker {
+                shared: Arc::clone(&shared),
+                blocks: Arc::clone(&blocks),
+                blk_store: Arc::clone(&blk_store),
+                heights: Arc::clone(&heights),
+                tx: tx.clone(),
+                coin_type: coin_type.clone(),
+                address_params: Arc::clone(&address_params),
+                window,
+            };
+            let handle = thread::Builder::new()
+                .name(format!("decoder-{}", i))
+                .spawn(move || {
+                    if let Err(e) = worker.run() {
+                        error!(target: "pipeline", "Worker stopped: {}", e);
+                    }
+                })?;
+            workers.push(handle);
+        }
+        debug!(target: "pipeline", "Started {} decoder threads (window={} blocks)", n_workers, window);
+
+        Ok(Self {
+            shared,
+            rx,
+            pending: HashMap::with_capacity(window),
+            heights,
+            next: 0,
+            workers,
+        })
+    }
+
+    /// Returns the block for the next height, blocking until it has been decoded.
+    /// Returns None if all heights are handed out.
+    pub fn next(&mut self) -> Option<OpResult<Block>> {
+        if self.next >= self.heights.len() {
+            return None;
+        }
+        let result = loop {
+            if let Some(result) = self.pending.remove(&self.next) {
+                break result;
+            }
+            match self.rx.recv() {
+                Ok((height, result)) => {
+                    self.pending.insert(height, result);
+                }
+                // All workers are gone, nothing will arrive anymore
+                Err(_) => {
+                    return Some(Err(OpError::new(OpErrorKind::RuntimeError).join_msg(
+                        &format!(
+                            "Decoder threads exited before block {}.",
+                            self.heights[self.next]
+                        ),
+                    )))
+                }
+            }
+        };
+
+        self.next += 1;
+        match self.shared.consumed.lock() {
+            Ok(mut consumed) => *consumed = self.next,
+            Err(e) => return Some(Err(OpError::from(e))),
+        }
+        self.shared.window_moved.notify_all();
+        Some(result)
+    }
+
+    /// Returns the number of decoded blocks waiting to be consumed
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl Drop for BlockPipeline {
+    fn drop(&mut self) {
+        {
+            // Hold the lock so no worker misses the wakeup between its check and wait()
+            let _consumed = self.shared.consumed.lock();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+        }
+        self.shared.window_moved.notify_all();
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Claims heights from the shared counter and decodes the corresponding blocks
+struct Worker {
+    shared: Arc<Shared>,
+    blocks: Arc<Vec<BlockIndexRecord>>,
+    blk_store: Arc<BlkStore>,
+    heights: Arc<Vec<usize>>,
+    tx: Sender<(usize, OpResult<Block>)>,
+    coin_type: CoinType,
+    address_params: Arc<AddressParams>,
+    window: usize,
+}
+
+impl Worker {
+    fn run(&self) -> OpResult<()> {
+        loop {
+            let pos = self.shared.next_pos.fetch_add(1, Ordering::SeqCst);
+            let height = match self.heights.get(pos) {
+                Some(&height) => height,
+                None => return Ok(()),
+            };
+
+            // Wait until the consumer caught up far enough
+            {
+                let mut consumed = self.shared.consumed.lock()?;
+                while pos >= *consumed + self.window {
+                    if self.shared.shutdown.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    consumed = self.shared.window_moved.wait(consumed)?;
+                }
+            }
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            // Fails if the consumer is gone
+            self.tx.send((pos, self.decode(height)))?;
+        }
+    }
+
+    fn decode(&self, height: usize) -> OpResult<Block> {
+        let meta = transform!(self.blocks.get(height));
+        self.blk_store
+            .read_block(
+                meta.n_file,
+                meta.n_data_pos,
+                &self.address_params,
+                self.coin_type.aux_pow_at(height as u64),
+            )
+            .map_err(|e| {
+                e.in_block(
+                    self.blk_store
+                        .location(meta.n_file, meta.n_data_pos, height),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::blkfile::tests::write_blk_file;
+    use crate::blockchain::parser::blkfile::BlkReadMode;
+    use std::fs;
+
+    #[test]
+    fn test_pipeline_ordered_delivery() {
+        for mode in &[BlkReadMode::Buffered(2), BlkReadMode::Mmap(2)] {
+            let (blk_file, records) = write_blk_file("pipeline", 50);
+            let path = blk_file.path.clone();
+            let mut blk_files = HashMap::new();
+            blk_files.insert(0, blk_file);
+            let blk_store = Arc::new(BlkStore::new(blk_files, *mode));
+
+            let heights: Vec<usize> = (5..40).chain(vec![42, 45, 49]).collect();
+            let mut pipeline = BlockPipeline::new(
+                Arc::new(records),
+                blk_store,
+                CoinType::default(),
+                Arc::new(heights.clone()),
+                4,
+            )
+            .unwrap();
+            for &expected in &heights {
+                let block = pipeline.next().unwrap().unwrap();
+                assert_eq!(block.header.value.nonce, expected as u32);
+                assert!(pipeline.buffered() <= 4 * BLOCKS_PER_WORKER);
+            }
+            assert!(pipeline.next().is_none());
+            drop(pipeline);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_pipeline_malformed_block() {
+        let (blk_file, mut records) = write_blk_file("pipeline-malformed", 3);
+        let path = blk_file.path.clone();
+        // Points into the middle of block 0
+        records[1].n_data_pos = 100;
+        let mut blk_files = HashMap::new();
+        blk_files.insert(0, blk_file);
+        let blk_store = Arc::new(BlkStore::new(blk_files, BlkReadMode::Buffered(2)));
+
+        let mut pipeline = BlockPipeline::new(
+            Arc::new(records),
+            blk_store,
+            CoinType::default(),
+            Arc::new(vec![0, 1, 2]),
+            2,
+        )
+        .unwrap();
+        assert_eq!(pipeline.next().unwrap().unwrap().header.value.nonce, 0);
+        let err = pipeline.next().unwrap().unwrap_err();
+        let location = err.location().unwrap();
+        assert_eq!(location.height, Some(1));
+        assert_eq!(location.offset, Some(100));
+        assert_eq!(location.file.as_ref(), Some(&path));
+        // Following blocks are still delivered
+        assert_eq!(pipeline.next().unwrap().unwrap().header.value.nonce, 2);
+        drop(pipeline);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::script::{AddressParams, ScriptPattern};
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutpoint, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::errors::OpResult;
+
+/// Keeps all unspent outputs seen so far to resolve the outputs spent by inputs
+#[derive(Default)]
+pub struct PrevoutStore {
+    unspent: HashMap<TxOutpoint, EvaluatedTxOut>,
+}
+
+impl PrevoutStore {
+    /// Applies the block to the unspent set and returns the spent outputs for each input,
+    /// indexed by transaction and input. Outputs created before the parsed range are None.
+    pub fn resolve(&mut self, block: &Block) -> Vec<Vec<Option<EvaluatedTxOut>>> {
+        let mut resolved = Vec::with_capacity(block.txs.len());
+        for tx in &block.txs {
+            let spent = if tx.value.is_coinbase() {
+                vec![None]
+            } else {
+                tx.value
+                    .inputs
+                    .iter()
+                    .map(|input| self.unspent.remove(&input.input.outpoint))
+                    .collect()
+            };
+            resolved.push(spent);
+
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                // Provably unspendable
+                if let ScriptPattern::DataOutput(_) = output.script.pattern {
+                    continue;
+                }
+                // Copy the script, so the block buffer isn't kept alive until the output is spent
+                let mut output = output.clone();
+                output.out.script_pubkey = output.out.script_pubkey.detach();
+                self.unspent
+                    .insert(TxOutpoint::new(tx.hash, i as u32), output);
+            }
+        }
+        resolved
+    }
+
+    /// Serializes the unspent outputs for a checkpoint, only values and scripts are kept
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> OpResult<()> {
+        writer.write_u64::<LittleEndian>(self.unspent.len() as u64)?;
+        for (outpoint, output) in &self.unspent {
+            writer.write_all(&outpoint.txid)?;
+            writer.write_u32::<LittleEndian>(outpoint.index)?;
+            writer.write_u64::<LittleEndian>(output.out.value)?;
+            writer.write_u32::<LittleEndian>(output.out.script_pubkey.len() as u32)?;
+            writer.write_all(&output.out.script_pubkey)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the unspent outputs serialized by write_to() and evaluates their scripts again
+    pub fn read_from<R: Read>(reader: &mut R, address_params: &AddressParams) -> OpResult<Self> {
+        let len = reader.read_u64::<LittleEndian>()? as usize;
+        let mut unspent = HashMap::with_capacity(len);
+        for _ in 0..len {
+            let mut txid = [0u8; 32];
+            reader.read_exact(&mut txid)?;
+            let outpoint = TxOutpoint::new(txid, reader.read_u32::<LittleEndian>()?);
+            let value = reader.read_u64::<LittleEndian>()?;
+            let mut script_pubkey = vec![0u8; reader.read_u32::<LittleEndian>()? as usize];
+            reader.read_exact(&mut script_pubkey)?;
+            let out = TxOutput {
+                value,
+                script_len: VarUint::compact(script_pubkey.len() as u64),
+                script_pubkey: Bytes::from(script_pubkey),
+            };
+            unspent.insert(outpoint, EvaluatedTxOut::eval_script(out, address_params));
+        }
+        Ok(PrevoutStore { unspent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxIn, RawTx};
+    use crate::blockchain::proto::Hashed;
+    use crate::callbacks::{self, Callback, TxContext};
+    use crate::common::{fixtures, utils};
+    use crate::errors::OpResult;
+
+    fn raw_tx(outpoint: TxOutpoint, value: u64) -> RawTx {
+        // P2PKH to 1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn
+        let script_pubkey = utils::hex_to_vec("76a914c398efa9c392ba6013c5e04ee729755ef7f58b3288ac");
+        let input = fixtures::input(outpoint, &[], 0xFFFFFFFF, Vec::new());
+        fixtures::tx(
+            1,
+            vec![input],
+            vec![fixtures::output(value, &script_pubkey)],
+            0,
+        )
+    }
+
+    /// Collects (tx_index, vin, prevout value) for every input
+    #[derive(Default)]
+    struct InputCollector {
+        seen: Vec<(usize, usize, Option<u64>)>,
+        n_outputs: usize,
+    }
+
+    impl Callback for InputCollector {
+        fn build_subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+            clap::SubCommand::with_name("inputcollector")
+        }
+
+        fn new(_: &clap::ArgMatches) -> OpResult<Self> {
+            Ok(InputCollector::default())
+        }
+
+        fn on_start(
+            &mut self,
+            _: &crate::blockchain::parser::types::CoinType,
+            _: u64,
+        ) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_input(
+            &mut self,
+            ctx: &TxContext,
+            vin: usize,
+            _: &EvaluatedTxIn,
+            prevout: Option<&EvaluatedTxOut>,
+        ) -> OpResult<()> {
+            self.seen
+                .push((ctx.tx_index, vin, prevout.map(|p| p.out.value)));
+            Ok(())
+        }
+
+        fn on_output(&mut self, _: &TxContext, _: usize, _: &EvaluatedTxOut) -> OpResult<()> {
+            self.n_outputs += 1;
+            Ok(())
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_resolve_prevouts() {
+        let coinbase = raw_tx(TxOutpoint::new([0u8; 32], 0xFFFFFFFF), 50);
+        let block1 = fixtures::block(vec![coinbase]);
+        let coinbase_txid = block1.txs[0].hash;
+
+        // Spends the first coinbase, and the spend gets spent again within the same block
+        let spend = raw_tx(TxOutpoint::new(coinbase_txid, 0), 40);
+        let spend_txid = Hashed::double_sha256(EvaluatedTx::from(raw_tx(
+            TxOutpoint::new(coinbase_txid, 0),
+            40,
+        )))
+        .hash;
+        let respend = raw_tx(TxOutpoint::new(spend_txid, 0), 30);
+        let unknown = raw_tx(TxOutpoint::new([1u8; 32], 0), 10);
+        let block2 = fixtures::block(vec![spend, respend, unknown]);
+
+        let mut store = PrevoutStore::default();
+        let resolved1 = store.resolve(&block1);
+        let resolved2 = store.resolve(&block2);
+        assert!(resolved1[0][0].is_none());
+
+        let mut collector = InputCollector::default();
+        callbacks::walk_txs(&mut collector, &block2, 1, &resolved2).unwrap();
+        assert_eq!(
+            collector.seen,
+            vec![(0, 0, Some(50)), (1, 0, Some(40)), (2, 0, None)]
+        );
+        assert_eq!(collector.n_outputs, 3);
+
+        // Already spent outputs are gone
+        assert!(store.resolve(&block2)[0][0].is_none());
+    }
+
+    #[test]
+    fn test_prevouts_roundtrip() {
+        let coinbase = raw_tx(TxOutpoint::new([0u8; 32], 0xFFFFFFFF), 50);
+        let block1 = fixtures::block(vec![coinbase]);
+        let spend = raw_tx(TxOutpoint::new(block1.txs[0].hash, 0), 40);
+        let block2 = fixtures::block(vec![spend]);
+
+        let mut store = PrevoutStore::default();
+        store.resolve(&block1);
+        let mut state = Vec::new();
+        store.write_to(&mut state).unwrap();
+        let mut restored =
+            PrevoutStore::read_from(&mut state.as_slice(), &AddressParams::default()).unwrap();
+
+        let resolved = restored.resolve(&block2);
+        let prevout = resolved[0][0].as_ref().unwrap();
+        assert_eq!(prevout.out.value, 50);
+        assert_eq!(
+            prevout.script.address,
+            Some(String::from("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"))
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use crate::blockchain::parser::index::BlockIndexRecord;
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::{BlockRef, ParseRange, ParserOptions};
+
+/// Number of blocks the median time past is taken over
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Returns the median time past of each block, i.e. the median timestamp of the block
+/// and its 10 predecessors. Unlike the timestamps themselves it never decreases.
+pub fn median_time_past(blocks: &[BlockIndexRecord]) -> Vec<u32> {
+    let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(height, _)| {
+            times.clear();
+            let first = (height + 1).saturating_sub(MEDIAN_TIME_SPAN);
+            times.extend(blocks[first..=height].iter().map(|b| b.time));
+            times.sort_unstable();
+            times[times.len() / 2]
+        })
+        .collect()
+}
+
+/// Resolves block references against the longest chain
+pub struct Resolver<'a> {
+    blocks: &'a [BlockIndexRecord],
+    mtp: Option<Vec<u32>>, // Only calculated if a timestamp is resolved
+    heights: Option<HashMap<[u8; 32], usize>>, // Only built if a hash is resolved
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(blocks: &'a [BlockIndexRecord]) -> Self {
+        Self {
+            blocks,
+            mtp: None,
+            heights: None,
+        }
+    }
+
+    /// Returns the height of the referenced block.
+    /// Timestamps resolve to the first block whose median time past is not before it,
+    /// or the chain length if there is none.
+    pub fn height(&mut self, block: &BlockRef) -> OpResult<usize> {
+        match *block {
+            BlockRef::Height(height) => Ok(height),
+            BlockRef::Hash(ref hash) => {
+                let blocks = self.blocks;
+                let heights = self.heights.get_or_insert_with(|| {
+                    blocks
+                        .iter()
+                        .enumerate()
+                        .map(|(height, b)| (b.block_hash, height))
+                        .collect()
+                });
+                match heights.get(hash) {
+                    Some(&height) => Ok(height),
+                    None => Err(
+                        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                            "Block {} is not part of the longest chain.",
+                            utils::arr_to_hex_swapped(hash)
+                        )),
+                    ),
+                }
+            }
+            BlockRef::Time(time) => {
+                let blocks = self.blocks;
+                let mtp = self.mtp.get_or_insert_with(|| median_time_past(blocks));
+                Ok(mtp.partition_point(|&t| i64::from(t) < time))
+            }
+        }
+    }
+}
+
+/// Resolves the configured start, end and block selection.
+/// Returns the resulting range and the heights to parse in ascending order.
+pub fn resolve_heights(
+    options: &ParserOptions,
+    blocks: &[BlockIndexRecord],
+) -> OpResult<(ParseRange, Vec<usize>)> {
+    let mut resolver = Resolver::new(blocks);
+    let start = resolver.height(&options.start)?;
+    let end = match options.end {
+        Some(ref end) => Some(resolver.height(end)?),
+        None => None,
+    };
+    let range = ParseRange::new(start, end)?;
+
+    let end = end.unwrap_or(blocks.len()).min(blocks.len());
+    let heights = match options.selection {
+        Some(ref selection) => {
+            let mut heights = selection
+                .iter()
+                .map(|block| resolver.height(block))
+                .collect::<OpResult<Vec<usize>>>()?;
+            heights.retain(|&height| height >= start && height < end);
+            heights.sort_unstable();
+            heights.dedup();
+            heights
+        }
+        None => (start..end).collect(),
+    };
+    Ok((range, heights))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(times: &[u32]) -> Vec<BlockIndexRecord> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(height, &time)| {
+                let mut record = BlockIndexRecord::at(height, 0, 0);
+                record.block_hash = [height as u8; 32];
+                record.time = time;
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        // Timestamps may go backwards, the median time past doesn't
+        let blocks = chain(&[
+            100, 200, 300, 250, 400, 350, 500, 600, 550, 700, 800, 900, 1000, 650,
+        ]);
+        let mtp = median_time_past(&blocks);
+        assert_eq!(mtp[0], 100);
+        assert_eq!(mtp[3], 250);
+        assert_eq!(mtp[10], 400);
+        assert_eq!(mtp[13], 600);
+        assert!(mtp.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let blocks = chain(&[100, 200, 300, 250, 400, 500]);
+        let mut resolver = Resolver::new(&blocks);
+        assert_eq!(resolver.height(&BlockRef::Height(3)).unwrap(), 3);
+        assert_eq!(resolver.height(&BlockRef::Hash([4; 32])).unwrap(), 4);
+        assert!(resolver.height(&BlockRef::Hash([9; 32])).is_err());
+        // mtp: 100, 200, 200, 250, 250, 300
+        assert_eq!(resolver.height(&BlockRef::Time(250)).unwrap(), 3);
+        assert_eq!(resolver.height(&BlockRef::Time(260)).unwrap(), 5);
+        assert_eq!(resolver.height(&BlockRef::Time(1000)).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_resolve_heights() {
+        let blocks = chain(&[100, 200, 300, 400, 500, 600]);
+        let options = ParserOptions::builder()
+            .start(BlockRef::Hash([1; 32]))
+            .end(BlockRef::Time(300))
+            .build()
+            .unwrap();
+        let (_, heights) = resolve_heights(&options, &blocks).unwrap();
+        assert_eq!(heights, vec![1, 2]);
+
+        let options = ParserOptions::builder()
+            .range(1, None)
+            .selection(vec![
+                BlockRef::Height(5),
+                BlockRef::Hash([2; 32]),
+                BlockRef::Height(0),
+                BlockRef::Height(5),
+            ])
+            .build()
+            .unwrap();
+        let (_, heights) = resolve_heights(&options, &blocks).unwrap();
+        assert_eq!(heights, vec![2, 5]);
+    }
+}
@@ -1,13 +1,25 @@
 use std::borrow::BorrowMut;
-use std::io::{self};
+use std::io::{self, Cursor};
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
+use crate::blockchain::proto::auxpow::{AuxPow, MerkleBranch, VERSION_AUXPOW};
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::bytes::Bytes;
 use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::mweb::{MwebBlock, MwebHeader, MwebTxBody, MWEB_FLAG, WITNESS_FLAG};
+use crate::blockchain::proto::script::AddressParams;
+use crate::blockchain::proto::tx::EvaluatedTx;
 use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
 use crate::blockchain::proto::varuint::VarUint;
-use crate::errors::OpResult;
+use crate::blockchain::proto::Hashed;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Upper bound for preallocated items, so malformed counts can't exhaust memory up front
+const MAX_PREALLOC: u64 = 4096;
+/// Byte vectors are read in chunks of this size
+const READ_CHUNK_SIZE: usize = 64 * 1024;
 
 /// Trait for structured reading of blockchain data
 pub trait BlockchainRead: io::Read {
@@ -20,17 +32,46 @@ pub trait BlockchainRead: io::Read {
 
     #[inline]
     fn read_u8_vec(&mut self, count: u32) -> OpResult<Vec<u8>> {
-        let mut arr = vec![0u8; count as usize];
-        self.read_exact(arr.borrow_mut())?;
+        // Grow in chunks, so a malformed length fails at EOF before allocating it all
+        let mut arr = Vec::new();
+        let mut remaining = count as usize;
+        while remaining > 0 {
+            let start = arr.len();
+            let chunk = remaining.min(READ_CHUNK_SIZE);
+            arr.resize(start + chunk, 0u8);
+            self.read_exact(arr[start..].borrow_mut())?;
+            remaining -= chunk;
+        }
         Ok(arr)
     }
 
-    /// Does not pop magic nor blocksize
-    fn read_block(&mut self, size: u32, version_id: u8) -> OpResult<Block> {
-        let header = self.read_block_header()?;
+    /// Reads bytes of a script or witness item, readers over a shared buffer return a slice of it
+    #[inline]
+    fn read_bytes(&mut self, count: u32) -> OpResult<Bytes> {
+        Ok(Bytes::from(self.read_u8_vec(count)?))
+    }
+
+    /// Does not pop magic nor blocksize.
+    /// If `aux_pow` is set, headers with the AuxPoW version bit are followed by an AuxPoW.
+    fn read_block(
+        &mut self,
+        size: u32,
+        address_params: &Arc<AddressParams>,
+        aux_pow: bool,
+    ) -> OpResult<Block> {
+        let mut header = self.read_block_header()?;
+        if aux_pow && header.version & VERSION_AUXPOW != 0 {
+            header.aux_pow = Some(Arc::new(self.read_aux_pow(address_params)?));
+        }
         let tx_count = VarUint::read_from(self)?;
-        let txs = self.read_txs(tx_count.value, version_id)?;
-        Ok(Block::new(size, header, tx_count, txs))
+        let txs = self.read_txs(tx_count.value, address_params)?;
+        // Litecoin: the extension block follows if the last transaction is a HogEx
+        let mweb = txs.len() >= 2 && txs.last().is_some_and(|tx| tx.hog_ex);
+        let mut block = Block::new(size, header, tx_count, txs);
+        if mweb {
+            block.mweb = self.read_mweb_block()?;
+        }
+        Ok(block)
     }
 
     #[inline]
@@ -45,32 +86,90 @@ pub trait BlockchainRead: io::Read {
         ))
     }
 
-    fn read_txs(&mut self, tx_count: u64, version_id: u8) -> OpResult<Vec<RawTx>> {
-        let mut txs = Vec::with_capacity(tx_count as usize);
+    /// Reads the merged mining proof following the header, see CAuxPow
+    fn read_aux_pow(&mut self, address_params: &Arc<AddressParams>) -> OpResult<AuxPow> {
+        let coinbase_tx = transform!(self.read_txs(1, address_params)?.pop());
+        Ok(AuxPow {
+            coinbase_tx: Hashed::double_sha256(EvaluatedTx::from(coinbase_tx)),
+            parent_hash: self.read_256hash()?,
+            coinbase_branch: self.read_merkle_branch()?,
+            blockchain_branch: self.read_merkle_branch()?,
+            parent_header: Hashed::double_sha256(self.read_block_header()?),
+        })
+    }
+
+    fn read_merkle_branch(&mut self) -> OpResult<MerkleBranch> {
+        let count = VarUint::read_from(self)?;
+        let mut hashes = Vec::with_capacity(count.value.min(MAX_PREALLOC) as usize);
+        for _ in 0..count.value {
+            hashes.push(self.read_256hash()?);
+        }
+        Ok(MerkleBranch {
+            hashes,
+            side_mask: self.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    fn read_txs(
+        &mut self,
+        tx_count: u64,
+        address_params: &Arc<AddressParams>,
+    ) -> OpResult<Vec<RawTx>> {
+        let mut txs = Vec::with_capacity(tx_count.min(MAX_PREALLOC) as usize);
         for _ in 0..tx_count {
             let mut flags = 0u8;
             let version = self.read_u32::<LittleEndian>()?;
 
-            // Parse transaction inputs and check if this transaction contains segwit data
+            // Parse transaction inputs and check if this transaction contains segwit or MWEB data
             let mut in_count = VarUint::read_from(self)?;
             if in_count.value == 0 {
                 flags = self.read_u8()?;
-                // TODO: handle segwit data
                 in_count = VarUint::read_from(self)?
             }
-            let inputs = self.read_tx_inputs(in_count.value)?;
+            if flags & !(WITNESS_FLAG | MWEB_FLAG) != 0 {
+                return Err(invalid_data(format!(
+                    "Unknown transaction flags {:#04x}.",
+                    flags
+                )));
+            }
+            let mut inputs = self.read_tx_inputs(in_count.value)?;
 
             // Parse transaction outputs
             let out_count = VarUint::read_from(self)?;
             let outputs = self.read_tx_outputs(out_count.value)?;
 
             // Check if the witness flag is present
-            if flags & 1 > 0 {
-                for _ in 0..in_count.value {
+            if flags & WITNESS_FLAG > 0 {
+                for input in inputs.iter_mut() {
                     let item_count = VarUint::read_from(self)?;
+                    let mut witness =
+                        Vec::with_capacity(item_count.value.min(MAX_PREALLOC) as usize);
                     for _ in 0..item_count.value {
                         let witness_len = VarUint::read_from(self)?;
-                        let _ = self.read_u8_vec(witness_len.value as u32)?;
+                        witness.push(self.read_bytes(script_len_u32(witness_len.value)?)?);
+                    }
+                    input.witness = witness;
+                }
+            }
+            // Litecoin: MWEB transaction or, if it is empty, the HogEx
+            let mut hog_ex = false;
+            if flags & MWEB_FLAG > 0 {
+                match self.read_u8()? {
+                    0 if outputs.is_empty() => {
+                        return Err(invalid_data(String::from("HogEx without outputs.")))
+                    }
+                    0 => hog_ex = true,
+                    1 => {
+                        // Only relayed, blocks aggregate MWEB transactions in the extension block
+                        let _kernel_offset = self.read_256hash()?;
+                        let _stealth_offset = self.read_256hash()?;
+                        let _ = self.read_mweb_tx_body()?;
+                    }
+                    n => {
+                        return Err(invalid_data(format!(
+                            "Invalid MWEB transaction marker {}.",
+                            n
+                        )))
                     }
                 }
             }
@@ -82,7 +181,8 @@ pub trait BlockchainRead: io::Read {
                 out_count,
                 outputs,
                 locktime,
-                version_id,
+                address_params: Arc::clone(address_params),
+                hog_ex,
             });
         }
         Ok(txs)
@@ -97,28 +197,29 @@ pub trait BlockchainRead: io::Read {
     }
 
     fn read_tx_inputs(&mut self, input_count: u64) -> OpResult<Vec<TxInput>> {
-        let mut inputs = Vec::with_capacity(input_count as usize);
+        let mut inputs = Vec::with_capacity(input_count.min(MAX_PREALLOC) as usize);
         for _ in 0..input_count {
             let outpoint = self.read_tx_outpoint()?;
             let script_len = VarUint::read_from(self)?;
-            let script_sig = self.read_u8_vec(script_len.value as u32)?;
+            let script_sig = self.read_bytes(script_len_u32(script_len.value)?)?;
             let seq_no = self.read_u32::<LittleEndian>()?;
             inputs.push(TxInput {
                 outpoint,
                 script_len,
                 script_sig,
                 seq_no,
+                witness: Vec::new(),
             });
         }
         Ok(inputs)
     }
 
     fn read_tx_outputs(&mut self, output_count: u64) -> OpResult<Vec<TxOutput>> {
-        let mut outputs = Vec::with_capacity(output_count as usize);
+        let mut outputs = Vec::with_capacity(output_count.min(MAX_PREALLOC) as usize);
         for _ in 0..output_count {
             let value = self.read_u64::<LittleEndian>()?;
             let script_len = VarUint::read_from(self)?;
-            let script_pubkey = self.read_u8_vec(script_len.value as u32)?;
+            let script_pubkey = self.read_bytes(script_len_u32(script_len.value)?)?;
             outputs.push(TxOutput {
                 value,
                 script_len,
@@ -127,22 +228,211 @@ pub trait BlockchainRead: io::Read {
         }
         Ok(outputs)
     }
+
+    /// Reads the optional extension block following the HogEx, see MWEB::Block
+    fn read_mweb_block(&mut self) -> OpResult<Option<MwebBlock>> {
+        match self.read_u8()? {
+            0 => return Ok(None),
+            1 => {}
+            n => {
+                return Err(invalid_data(format!(
+                    "Invalid extension block marker {}.",
+                    n
+                )))
+            }
+        }
+        let header = MwebHeader {
+            height: self.read_varint()?,
+            output_root: self.read_256hash()?,
+            kernel_root: self.read_256hash()?,
+            leafset_root: self.read_256hash()?,
+            kernel_offset: self.read_256hash()?,
+            stealth_offset: self.read_256hash()?,
+            output_mmr_size: self.read_varint()?,
+            kernel_mmr_size: self.read_varint()?,
+        };
+        Ok(Some(MwebBlock {
+            header,
+            body: self.read_mweb_tx_body()?,
+        }))
+    }
+
+    fn read_mweb_tx_body(&mut self) -> OpResult<MwebTxBody> {
+        Ok(MwebTxBody {
+            inputs: read_raw_items(self, skip_mweb_input)?,
+            outputs: read_raw_items(self, skip_mweb_output)?,
+            kernels: read_raw_items(self, skip_mweb_kernel)?,
+        })
+    }
+
+    /// Reads Bitcoin's VARINT, which is NOT the same as CompactSize. Used by MWEB.
+    fn read_varint(&mut self) -> OpResult<u64> {
+        let mut n = 0u64;
+        loop {
+            let ch_data = self.read_u8()?;
+            if n > u64::MAX >> 7 {
+                return Err(invalid_data(String::from("VarInt exceeds u64.")));
+            }
+            n = (n << 7) | u64::from(ch_data & 0x7F);
+            if ch_data & 0x80 == 0 {
+                return Ok(n);
+            }
+            n = n
+                .checked_add(1)
+                .ok_or_else(|| invalid_data(String::from("VarInt exceeds u64.")))?;
+        }
+    }
+
+    /// Reads a CompactSize prefixed byte vector
+    #[inline]
+    fn read_var_bytes(&mut self) -> OpResult<Vec<u8>> {
+        let len = VarUint::read_from(self)?;
+        self.read_u8_vec(script_len_u32(len.value)?)
+    }
 }
 
-/// All types that implement `Read` get methods defined in `BlockchainRead`
-/// for free.
-impl<R: io::Read + ?Sized> BlockchainRead for R {}
+/// Copies all bytes read from `inner`
+struct RecordingReader<'a, R: io::Read + ?Sized> {
+    inner: &'a mut R,
+    raw: Vec<u8>,
+}
+
+impl<'a, R: io::Read + ?Sized> io::Read for RecordingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.raw.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Reads a CompactSize prefixed vector of MWEB items, each in its serialized form
+fn read_raw_items<R: io::Read + ?Sized>(
+    reader: &mut R,
+    skip_item: fn(&mut dyn io::Read) -> OpResult<()>,
+) -> OpResult<Vec<Vec<u8>>> {
+    let count = VarUint::read_from(reader)?;
+    let mut items = Vec::with_capacity(count.value.min(MAX_PREALLOC) as usize);
+    for _ in 0..count.value {
+        let mut recorder = RecordingReader {
+            inner: &mut *reader,
+            raw: Vec::new(),
+        };
+        skip_item(&mut recorder)?;
+        items.push(recorder.raw);
+    }
+    Ok(items)
+}
+
+/// See mw::Input
+fn skip_mweb_input(reader: &mut dyn io::Read) -> OpResult<()> {
+    let features = reader.read_u8()?;
+    // Output id, commitment and output public key
+    reader.read_u8_vec(32 + 33 + 33)?;
+    if features & 0x01 > 0 {
+        // Input public key
+        reader.read_u8_vec(33)?;
+    }
+    if features & 0x02 > 0 {
+        reader.read_var_bytes()?;
+    }
+    // Signature
+    reader.read_u8_vec(64)?;
+    Ok(())
+}
+
+/// See mw::Output and mw::OutputMessage
+fn skip_mweb_output(reader: &mut dyn io::Read) -> OpResult<()> {
+    // Commitment, sender and receiver public keys
+    reader.read_u8_vec(33 + 33 + 33)?;
+    let features = reader.read_u8()?;
+    if features & 0x01 > 0 {
+        // Key exchange public key, view tag, masked value and masked nonce
+        reader.read_u8_vec(33 + 1 + 8 + 16)?;
+    }
+    if features & 0x02 > 0 {
+        reader.read_var_bytes()?;
+    }
+    // Range proof and signature
+    reader.read_var_bytes()?;
+    reader.read_u8_vec(64)?;
+    Ok(())
+}
+
+/// See mw::Kernel
+fn skip_mweb_kernel(reader: &mut dyn io::Read) -> OpResult<()> {
+    let features = reader.read_u8()?;
+    if features & 0x01 > 0 {
+        reader.read_varint()?; // Fee
+    }
+    if features & 0x02 > 0 {
+        reader.read_varint()?; // Peg-in amount
+    }
+    if features & 0x04 > 0 {
+        let pegouts = VarUint::read_from(reader)?;
+        for _ in 0..pegouts.value {
+            reader.read_varint()?;
+            reader.read_var_bytes()?;
+        }
+    }
+    if features & 0x08 > 0 {
+        reader.read_varint()?; // Lock height
+    }
+    if features & 0x10 > 0 {
+        reader.read_u8_vec(33)?; // Stealth excess
+    }
+    if features & 0x20 > 0 {
+        reader.read_var_bytes()?;
+    }
+    // Excess commitment and signature
+    reader.read_u8_vec(33 + 64)?;
+    Ok(())
+}
+
+#[inline]
+fn invalid_data(message: String) -> OpError {
+    OpError::from(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Rejects lengths which would be silently truncated to u32
+#[inline]
+fn script_len_u32(len: u64) -> OpResult<u32> {
+    if len > u64::from(u32::MAX) {
+        return Err(invalid_data(format!("Script length {} is too large.", len)));
+    }
+    Ok(len as u32)
+}
+
+// Readers over owned or borrowed data copy scripts and witness items
+impl BlockchainRead for Cursor<Vec<u8>> {}
+impl BlockchainRead for Cursor<&Vec<u8>> {}
+impl BlockchainRead for Cursor<&[u8]> {}
+impl<R: io::Read> BlockchainRead for io::BufReader<R> {}
+impl BlockchainRead for dyn io::Read + '_ {}
+
+/// Scripts and witness items refer to the shared buffer, e.g. a mapped blk file, instead of being copied
+impl BlockchainRead for Cursor<Bytes> {
+    fn read_bytes(&mut self, count: u32) -> OpResult<Bytes> {
+        let start = self.position() as usize;
+        let bytes = start
+            .checked_add(count as usize)
+            .and_then(|end| self.get_ref().slice(start..end))
+            .ok_or_else(|| OpError::from(io::Error::from(io::ErrorKind::UnexpectedEof)))?;
+        self.set_position((start + bytes.len()) as u64);
+        Ok(bytes)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::blockchain::parser::types::{Bitcoin, Coin};
+    use crate::blockchain::parser::types::CoinType;
+    use crate::blockchain::proto::mweb::{MWEB_FLAG, WITNESS_FLAG};
     use crate::blockchain::proto::script;
     use crate::blockchain::proto::tx::EvaluatedTx;
     use crate::common::utils;
     use blockchain::proto::script::ScriptPattern;
     use byteorder::{LittleEndian, ReadBytesExt};
-    use std::io::{BufReader, Cursor};
+    use std::io::BufReader;
 
     #[test]
     fn test_bitcoin_parse_genesis_block() {
@@ -213,7 +503,13 @@ mod tests {
         let block_size: u32 = reader.read_u32::<LittleEndian>().unwrap();
 
         // Parse block
-        let block = reader.read_block(block_size, Bitcoin.version_id()).unwrap();
+        let block = reader
+            .read_block(
+                block_size,
+                &Arc::new(CoinType::default().address_params()),
+                false,
+            )
+            .unwrap();
 
         // Block Metadata
         assert_eq!(0xd9b4bef9, magic);
@@ -248,13 +544,16 @@ mod tests {
         assert_eq!(0x01, block.txs[0].value.in_count.value);
         assert_eq!(
             "0000000000000000000000000000000000000000000000000000000000000000",
-            utils::arr_to_hex_swapped(&block.txs[0].value.inputs[0].outpoint.txid)
+            utils::arr_to_hex_swapped(&block.txs[0].value.inputs[0].input.outpoint.txid)
+        );
+        assert_eq!(
+            0xffffffff,
+            block.txs[0].value.inputs[0].input.outpoint.index
         );
-        assert_eq!(0xffffffff, block.txs[0].value.inputs[0].outpoint.index);
-        assert_eq!(0x4d, block.txs[0].value.inputs[0].script_len.value);
+        assert_eq!(0x4d, block.txs[0].value.inputs[0].input.script_len.value);
         assert_eq!("04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73",
-                                utils::arr_to_hex(&block.txs[0].value.inputs[0].script_sig));
-        assert_eq!(0xffffffff, block.txs[0].value.inputs[0].seq_no);
+                                utils::arr_to_hex(&block.txs[0].value.inputs[0].input.script_sig));
+        assert_eq!(0xffffffff, block.txs[0].value.inputs[0].input.seq_no);
 
         // Tx Outputs
         assert_eq!(0x01, block.txs[0].value.out_count.value);
@@ -266,15 +565,135 @@ mod tests {
 
         let script_pubkey = &block.txs[0].value.outputs[0].out.script_pubkey;
         assert_eq!("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
-                                utils::arr_to_hex(&script_pubkey));
+                                utils::arr_to_hex(script_pubkey));
         assert_eq!(0x00000000, block.txs[0].value.locktime);
 
         assert_eq!(
             Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")),
-            script::eval_from_bytes(script_pubkey, Bitcoin.version_id()).address
+            script::eval_from_bytes(script_pubkey, &CoinType::default().address_params()).address
         );
     }
 
+    #[test]
+    fn test_malformed_tx_counts() {
+        // Huge counts must fail with an error instead of preallocating memory
+        let mut reader = Cursor::new(vec![
+            0x01, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        assert!(reader.read_txs(u64::MAX, &Arc::default()).is_err());
+        let mut reader = Cursor::new(vec![0x01, 0x02]);
+        assert!(reader.read_u8_vec(u32::MAX).is_err());
+        assert_eq!(
+            Cursor::new(vec![0x01, 0x02]).read_u8_vec(2).unwrap(),
+            vec![0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_read_varint() {
+        // Test vectors from bitcoin/src/test/serialize_tests.cpp
+        let varint = |raw: Vec<u8>| Cursor::new(raw).read_varint().unwrap();
+        assert_eq!(varint(vec![0x7f]), 127);
+        assert_eq!(varint(vec![0x80, 0x00]), 128);
+        assert_eq!(varint(vec![0xfe, 0x7f]), 16383);
+        assert_eq!(varint(vec![0x82, 0xfe, 0x7f]), 65535);
+        assert_eq!(varint(vec![0x8e, 0xfe, 0xfe, 0xff, 0x00]), 1 << 32);
+        assert!(Cursor::new(vec![0xff; 11]).read_varint().is_err());
+    }
+
+    #[test]
+    fn test_litecoin_parse_mweb_block() {
+        let tx = |flags: u8, input_hash: u8, script_pubkey: &[u8], trailer: &[u8]| {
+            let mut raw = vec![0x02, 0x00, 0x00, 0x00, 0x00, flags, 0x01];
+            raw.extend_from_slice(&[input_hash; 32]);
+            raw.extend_from_slice(&[
+                0xff, 0xff, 0xff, 0xff, 0x01, 0x51, 0xff, 0xff, 0xff, 0xff, 0x01,
+            ]);
+            raw.extend_from_slice(&[
+                0x00,
+                0xe1,
+                0xf5,
+                0x05,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                script_pubkey.len() as u8,
+            ]);
+            raw.extend_from_slice(script_pubkey);
+            raw.extend_from_slice(trailer);
+            raw.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+            raw
+        };
+        let mut raw = vec![0x00, 0x00, 0x00, 0x20];
+        raw.extend_from_slice(&[0u8; 76]);
+        raw.push(0x02);
+        // Segwit coinbase with a 32 byte witness item
+        let mut witness = vec![0x01, 0x20];
+        witness.extend_from_slice(&[0u8; 32]);
+        raw.extend(tx(WITNESS_FLAG, 0x00, &[0x51], &witness));
+        // HogEx with an empty MWEB transaction, paying to the MWEB witness program
+        let mut hog_addr = vec![0x58, 0x20];
+        hog_addr.extend_from_slice(&[0x99; 32]);
+        raw.extend(tx(MWEB_FLAG, 0x11, &hog_addr, &[0x00]));
+
+        // Extension block
+        raw.extend_from_slice(&[0x01, 0x80, 0x89, 0xa6, 0x00]); // Present, height 2265984
+        raw.extend_from_slice(&[0xaa; 5 * 32]);
+        raw.extend_from_slice(&[0x05, 0x03]);
+        // One input with a stealth key
+        raw.extend_from_slice(&[0x01, 0x01]);
+        raw.extend_from_slice(&[0x01; 32 + 33 + 33 + 33 + 64]);
+        // One output with standard fields and a 675 byte range proof
+        raw.push(0x01);
+        raw.extend_from_slice(&[0x02; 99]);
+        raw.push(0x01);
+        raw.extend_from_slice(&[0x03; 58]);
+        raw.extend_from_slice(&[0xfd, 0xa3, 0x02]);
+        raw.extend_from_slice(&[0x04; 675 + 64]);
+        // A kernel with fee and peg-in, a kernel with a peg-out
+        raw.extend_from_slice(&[0x02, 0x03, 0x64, 0x80, 0x00]);
+        raw.extend_from_slice(&[0x05; 97]);
+        raw.extend_from_slice(&[0x04, 0x01, 0x05, 0x02, 0x51, 0x52]);
+        raw.extend_from_slice(&[0x06; 97]);
+        raw.extend_from_slice(&[0xf9, 0xbe, 0xb4, 0xd9]);
+
+        let mut reader = Cursor::new(&raw);
+        let block = reader
+            .read_block(raw.len() as u32, &Arc::default(), false)
+            .unwrap();
+        // Stopped right before the next block
+        assert_eq!(reader.position() as usize, raw.len() - 4);
+        assert_eq!(block.txs.len(), 2);
+        assert_eq!(block.txs[1].value.outputs[0].out.script_pubkey, hog_addr);
+
+        let mweb = block.mweb.unwrap();
+        assert_eq!(mweb.header.height, 2265984);
+        assert_eq!(mweb.header.output_root, [0xaa; 32]);
+        assert_eq!(
+            (mweb.header.output_mmr_size, mweb.header.kernel_mmr_size),
+            (5, 3)
+        );
+        assert_eq!(mweb.body.inputs.len(), 1);
+        assert_eq!(mweb.body.inputs[0].len(), 1 + 32 + 33 + 33 + 33 + 64);
+        assert_eq!(mweb.body.outputs.len(), 1);
+        assert_eq!(mweb.body.outputs[0].len(), 99 + 1 + 58 + 3 + 675 + 64);
+        assert_eq!(mweb.body.outputs[0][..99], [0x02; 99][..]);
+        assert_eq!(mweb.body.kernels.len(), 2);
+        assert_eq!(mweb.body.kernels[0][..4], [0x03, 0x64, 0x80, 0x00]);
+        assert_eq!(mweb.body.kernels[1].len(), 6 + 97);
+
+        // Unknown flags and a HogEx without outputs are rejected
+        assert!(Cursor::new(tx(0x02, 0x00, &[0x51], &[]))
+            .read_txs(1, &Arc::default())
+            .is_err());
+        let mut empty_hog_ex = vec![0x02, 0x00, 0x00, 0x00, 0x00, MWEB_FLAG, 0x00, 0x00, 0x00];
+        empty_hog_ex.extend_from_slice(&[0x00; 4]);
+        assert!(Cursor::new(empty_hog_ex)
+            .read_txs(1, &Arc::default())
+            .is_err());
+    }
+
     #[test]
     fn test_bitcoin_parse_segwit_tx() {
         // See: https://en.bitcoin.it/wiki/Weight_units#Weight_for_segwit_transactions
@@ -320,10 +739,10 @@ mod tests {
         let inner = Cursor::new(raw_data);
         let mut reader = BufReader::with_capacity(200, inner);
         let txs: Vec<EvaluatedTx> = reader
-            .read_txs(1, 0x00)
+            .read_txs(1, &Arc::default())
             .unwrap()
             .into_iter()
-            .map(|raw| EvaluatedTx::from(raw))
+            .map(EvaluatedTx::from)
             .collect();
         assert_eq!(txs.len(), 1);
 
@@ -338,10 +757,10 @@ mod tests {
             0xf2, 0xa2, 0x0d, 0xa7, 0x17, 0xe5, 0x54, 0x84, 0x06, 0xf7, 0xae, 0x8b, 0x4c, 0x81,
             0x10, 0x72, 0xf8, 0x56,
         ];
-        assert_eq!(tx.inputs[0].outpoint.txid, prev_hash);
-        assert_eq!(tx.inputs[0].outpoint.index, 3);
-        assert_eq!(tx.inputs[0].script_len.value, 23);
-        assert_eq!(tx.inputs[0].seq_no, 0xffffffff);
+        assert_eq!(tx.inputs[0].input.outpoint.txid, prev_hash);
+        assert_eq!(tx.inputs[0].input.outpoint.index, 3);
+        assert_eq!(tx.inputs[0].input.script_len.value, 23);
+        assert_eq!(tx.inputs[0].input.seq_no, 0xffffffff);
 
         // Assert outputs
         assert_eq!(tx.out_count.value, 1);
@@ -0,0 +1,719 @@
+#!/usr/bin/env python3
+"""Generates the consensus test vectors of this directory in the formats of Bitcoin Core's
+src/test/data/script_tests.json, tx_valid.json, tx_invalid.json and sighash.json.
+
+Signatures are deterministic (RFC6979 and BIP340 with zero aux data), so running it again
+reproduces the files. Only the Python standard library is needed:
+
+    $ python3 src/blockchain/parser/testdata/gen_vectors.py
+"""
+import hashlib, hmac, json, os, random, struct
+
+def sha256(b): return hashlib.sha256(b).digest()
+def dsha256(b): return sha256(sha256(b))
+def hash160(b): return hashlib.new('ripemd160', sha256(b)).digest()
+def tagged_hash(tag, b): return sha256(sha256(tag.encode()) * 2 + b)
+
+# secp256k1
+
+P = 2**256 - 2**32 - 977
+N = 0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141
+G = (0x79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798,
+     0x483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8)
+
+def point_add(p1, p2):
+    if p1 is None: return p2
+    if p2 is None: return p1
+    if p1[0] == p2[0] and (p1[1] + p2[1]) % P == 0: return None
+    if p1 == p2:
+        lam = 3 * p1[0] * p1[0] * pow(2 * p1[1], P - 2, P) % P
+    else:
+        lam = (p2[1] - p1[1]) * pow(p2[0] - p1[0], P - 2, P) % P
+    x = (lam * lam - p1[0] - p2[0]) % P
+    return (x, (lam * (p1[0] - x) - p1[1]) % P)
+
+def point_mul(point, k):
+    result = None
+    while k:
+        if k & 1: result = point_add(result, point)
+        point = point_add(point, point)
+        k >>= 1
+    return result
+
+def to32(i): return i.to_bytes(32, 'big')
+def from32(b): return int.from_bytes(b, 'big')
+
+def pubkey(sk, form='compressed'):
+    x, y = point_mul(G, sk)
+    if form == 'compressed': return bytes([2 + (y & 1)]) + to32(x)
+    if form == 'uncompressed': return b'\x04' + to32(x) + to32(y)
+    if form == 'hybrid': return bytes([6 + (y & 1)]) + to32(x) + to32(y)
+    if form == 'xonly': return to32(x)
+
+def rfc6979(sk, msg):
+    v, k = b'\x01' * 32, b'\x00' * 32
+    data = to32(sk) + to32(from32(msg) % N)
+    k = hmac.new(k, v + b'\x00' + data, 'sha256').digest()
+    v = hmac.new(k, v, 'sha256').digest()
+    k = hmac.new(k, v + b'\x01' + data, 'sha256').digest()
+    v = hmac.new(k, v, 'sha256').digest()
+    while True:
+        v = hmac.new(k, v, 'sha256').digest()
+        nonce = from32(v)
+        if 1 <= nonce < N: return nonce
+        k = hmac.new(k, v + b'\x00', 'sha256').digest()
+        v = hmac.new(k, v, 'sha256').digest()
+
+def der_int(i, pad=0):
+    b = i.to_bytes((i.bit_length() + 7) // 8, 'big')
+    if b[0] & 0x80: b = b'\x00' + b
+    b = b'\x00' * pad + b
+    return b'\x02' + bytes([len(b)]) + b
+
+def ecdsa_sign(sk, msg, low_s=True, r_padding=0):
+    """DER signature of `msg`, `r_padding` adds zero bytes which lax DER parsing accepts"""
+    k = rfc6979(sk, msg)
+    r = point_mul(G, k)[0] % N
+    s = pow(k, N - 2, N) * (from32(msg) + r * sk) % N
+    if low_s != (s <= N // 2): s = N - s
+    body = der_int(r, r_padding) + der_int(s)
+    return b'\x30' + bytes([len(body)]) + body
+
+def schnorr_sign(sk, msg):
+    point = point_mul(G, sk)
+    d = sk if point[1] % 2 == 0 else N - sk
+    t = to32(d ^ from32(tagged_hash("BIP0340/aux", bytes(32))))
+    k0 = from32(tagged_hash("BIP0340/nonce", t + to32(point[0]) + msg)) % N
+    r = point_mul(G, k0)
+    k = k0 if r[1] % 2 == 0 else N - k0
+    e = from32(tagged_hash("BIP0340/challenge", to32(r[0]) + to32(point[0]) + msg)) % N
+    return to32(r[0]) + to32((k + e * d) % N)
+
+def taproot_tweak(sk, merkle_root=b''):
+    """Secret key of the output key and its parity [BIP341]"""
+    point = point_mul(G, sk)
+    d = sk if point[1] % 2 == 0 else N - sk
+    tweaked = (d + from32(tagged_hash("TapTweak", to32(point[0]) + merkle_root))) % N
+    return tweaked, point_mul(G, tweaked)[1] & 1
+
+# Keys of Bitcoin Core's script_tests.cpp
+KEY0, KEY1, KEY2 = 1, 1 << 8, 1 << 16
+
+# Scripts, in the notation of Core's vectors and as bytes
+
+OPS = {
+    'NOP': 0x61, 'IF': 0x63, 'ELSE': 0x67, 'ENDIF': 0x68, 'VERIFY': 0x69, 'RETURN': 0x6a,
+    'DROP': 0x75, 'DUP': 0x76, 'EQUAL': 0x87, 'EQUALVERIFY': 0x88, 'NOT': 0x91, 'NUMEQUAL': 0x9c,
+    'HASH160': 0xa9, 'CODESEPARATOR': 0xab, 'CHECKSIG': 0xac, 'CHECKSIGVERIFY': 0xad,
+    'CHECKMULTISIG': 0xae, 'CHECKMULTISIGVERIFY': 0xaf, 'CHECKSIGADD': 0xba,
+}
+
+def push(data):
+    if len(data) < 0x4c: return bytes([len(data)]) + data
+    if len(data) <= 0xff: return b'\x4c' + bytes([len(data)]) + data
+    return b'\x4d' + struct.pack('<H', len(data)) + data
+
+class Script:
+    """Items are opcode names, small numbers and bytes to push"""
+    def __init__(self, *items):
+        self.items = items
+
+    def asm(self):
+        tokens = []
+        for item in self.items:
+            if isinstance(item, bytes):
+                pushed = push(item)
+                header = len(pushed) - len(item)
+                tokens.append('0x' + pushed[:header].hex())
+                if item: tokens.append('0x' + item.hex())
+            else:
+                tokens.append(str(item))
+        return ' '.join(tokens)
+
+    def raw(self):
+        out = b''
+        for item in self.items:
+            if isinstance(item, bytes): out += push(item)
+            elif isinstance(item, int): out += bytes([0x00 if item == 0 else 0x50 + item])
+            else: out += bytes([OPS[item]])
+        return out
+
+def p2pk(key): return Script(key, 'CHECKSIG')
+def p2pkh(key): return Script('DUP', 'HASH160', hash160(key), 'EQUALVERIFY', 'CHECKSIG')
+def multisig(m, keys): return Script(m, *keys, len(keys), 'CHECKMULTISIG')
+def p2sh(script): return Script('HASH160', hash160(script.raw()), 'EQUAL')
+def p2wpkh(key): return Script(0, hash160(key))
+def p2wsh(script): return Script(0, sha256(script.raw()))
+def p2tr(output_key): return Script(1, output_key)
+
+# Transactions
+
+def ser_varint(n):
+    if n < 0xfd: return bytes([n])
+    if n <= 0xffff: return b'\xfd' + struct.pack('<H', n)
+    return b'\xfe' + struct.pack('<I', n)
+
+def ser_outpoint(txid, index): return bytes.fromhex(txid)[::-1] + struct.pack('<I', index)
+def ser_output(value, spk): return struct.pack('<q', value) + ser_varint(len(spk)) + spk
+
+def ser_tx(version, inputs, outputs, locktime, witnesses=None):
+    out = struct.pack('<I', version)
+    if witnesses and any(witnesses): out += b'\x00\x01'
+    out += ser_varint(len(inputs))
+    for (txid, index, script_sig, seq) in inputs:
+        out += ser_outpoint(txid, index) + ser_varint(len(script_sig)) + script_sig + struct.pack('<I', seq)
+    out += ser_varint(len(outputs))
+    for (value, spk) in outputs:
+        out += ser_output(value, spk)
+    if witnesses and any(witnesses):
+        for items in witnesses:
+            out += ser_varint(len(items))
+            for item in items:
+                out += ser_varint(len(item)) + item
+    out += struct.pack('<I', locktime)
+    return out.hex()
+
+class Tx:
+    def __init__(self, inputs, outputs, version=1, locktime=0):
+        # inputs: [txid, index, script_sig, sequence], outputs: (value, scriptPubKey)
+        self.inputs = [list(i) for i in inputs]
+        self.outputs = list(outputs)
+        self.version = version
+        self.locktime = locktime
+        self.witnesses = [[] for _ in inputs]
+
+    def hex(self):
+        return ser_tx(self.version, self.inputs, self.outputs, self.locktime, self.witnesses)
+
+    def txid(self):
+        return dsha256(bytes.fromhex(ser_tx(self.version, self.inputs, self.outputs, self.locktime)))[::-1].hex()
+
+def without_codeseparators(script):
+    out, i = b'', 0
+    while i < len(script):
+        op, start = script[i], i
+        i += 1
+        if 0 < op < 0x4c: i += op
+        elif op == 0x4c: i += 1 + script[i]
+        elif op == 0x4d: i += 2 + struct.unpack('<H', script[i:i + 2])[0]
+        elif op == 0x4e: i += 4 + struct.unpack('<I', script[i:i + 4])[0]
+        if op != OPS['CODESEPARATOR']: out += script[start:i]
+    return out
+
+def sighash_legacy(tx, vin, script_code, hash_type):
+    base = hash_type & 0x1f
+    if base == 3 and vin >= len(tx.outputs):
+        return (1).to_bytes(32, 'little')
+    inputs = []
+    for i, (txid, index, _, seq) in enumerate(tx.inputs):
+        if hash_type & 0x80 and i != vin: continue
+        if i != vin and base in (2, 3): seq = 0
+        inputs.append((txid, index, without_codeseparators(script_code) if i == vin else b'', seq))
+    outputs = tx.outputs
+    if base == 2: outputs = []
+    elif base == 3: outputs = [(-1, b'')] * vin + [tx.outputs[vin]]
+    msg = bytes.fromhex(ser_tx(tx.version, inputs, outputs, tx.locktime)) + struct.pack('<I', hash_type)
+    return dsha256(msg)
+
+def sighash_v0(tx, vin, script_code, amount, hash_type):
+    base, acp = hash_type & 0x1f, hash_type & 0x80
+    zero = bytes(32)
+    prevouts = zero if acp else dsha256(b''.join(ser_outpoint(i[0], i[1]) for i in tx.inputs))
+    sequences = zero if acp or base in (2, 3) else dsha256(b''.join(struct.pack('<I', i[3]) for i in tx.inputs))
+    if base not in (2, 3): outputs = dsha256(b''.join(ser_output(*o) for o in tx.outputs))
+    elif base == 3 and vin < len(tx.outputs): outputs = dsha256(ser_output(*tx.outputs[vin]))
+    else: outputs = zero
+    txid, index, _, seq = tx.inputs[vin]
+    msg = (struct.pack('<I', tx.version) + prevouts + sequences + ser_outpoint(txid, index)
+           + ser_varint(len(script_code)) + script_code + struct.pack('<q', amount) + struct.pack('<I', seq)
+           + outputs + struct.pack('<I', tx.locktime) + struct.pack('<I', hash_type))
+    return dsha256(msg)
+
+def tapleaf_hash(script):
+    raw = script.raw()
+    return tagged_hash("TapLeaf", b'\xc0' + ser_varint(len(raw)) + raw)
+
+def sighash_taproot(tx, vin, spent, hash_type, leaf=None, annex=None):
+    """`spent` holds (amount, scriptPubKey) of each input"""
+    base, acp = hash_type & 3, hash_type & 0x80
+    msg = bytes([0, hash_type]) + struct.pack('<I', tx.version) + struct.pack('<I', tx.locktime)
+    if not acp:
+        msg += sha256(b''.join(ser_outpoint(i[0], i[1]) for i in tx.inputs))
+        msg += sha256(b''.join(struct.pack('<q', amount) for amount, _ in spent))
+        msg += sha256(b''.join(ser_varint(len(spk)) + spk for _, spk in spent))
+        msg += sha256(b''.join(struct.pack('<I', i[3]) for i in tx.inputs))
+    if base not in (2, 3):
+        msg += sha256(b''.join(ser_output(*o) for o in tx.outputs))
+    msg += bytes([(2 if leaf else 0) + (1 if annex else 0)])
+    if acp:
+        txid, index, _, seq = tx.inputs[vin]
+        amount, spk = spent[vin]
+        msg += ser_outpoint(txid, index) + struct.pack('<q', amount) + ser_varint(len(spk)) + spk + struct.pack('<I', seq)
+    else:
+        msg += struct.pack('<I', vin)
+    if annex:
+        msg += sha256(ser_varint(len(annex)) + annex)
+    if base == 3:
+        msg += sha256(ser_output(*tx.outputs[vin]))
+    if leaf:
+        msg += leaf + b'\x00' + struct.pack('<I', 0xffffffff)
+    return tagged_hash("TapSighash", msg)
+
+def sig_legacy(sk, tx, vin, script_code, hash_type=1, **kwargs):
+    return ecdsa_sign(sk, sighash_legacy(tx, vin, script_code.raw(), hash_type), **kwargs) + bytes([hash_type])
+
+def sig_v0(sk, tx, vin, script_code, amount, hash_type=1, **kwargs):
+    return ecdsa_sign(sk, sighash_v0(tx, vin, script_code.raw(), amount, hash_type), **kwargs) + bytes([hash_type])
+
+def sig_taproot(sk, tx, vin, spent, hash_type=0, leaf=None, annex=None):
+    if hash_type & 3 == 3 and vin >= len(tx.outputs):
+        # No message exists, any signature fails
+        msg = bytes(32)
+    else:
+        msg = sighash_taproot(tx, vin, spent, hash_type, leaf, annex)
+    sig = schnorr_sign(sk, msg)
+    return sig if hash_type == 0 else sig + bytes([hash_type])
+
+def damaged(sig):
+    """Flips a bit of S, the signature stays well formed"""
+    return sig[:-2] + bytes([sig[-2] ^ 1]) + sig[-1:]
+
+P2SH_TRUE = "HASH160 0x14 0x%s EQUAL" % hash160(b'\x51').hex()
+P2SH_FALSE = "HASH160 0x14 0x%s EQUAL" % hash160(b'\x00').hex()
+P2WSH_TRUE = "0 0x20 0x%s" % sha256(b'\x51').hex()
+P2WSH_FALSE = "0 0x20 0x%s" % sha256(b'\x00').hex()
+big = "0x4d 0x0902 0x" + "42" * 521
+ok_push = "0x4d 0x0802 0x" + "42" * 520
+
+script_tests = [
+    ["Format is: [[wit..., amount]?, scriptSig, scriptPubKey, flags, expected_scripterror, ... comments]"],
+    ["Consensus flag vectors in the format of Bitcoin Core's src/test/data/script_tests.json, mostly taken from it"],
+    ["0x01 0x0b", "11 EQUAL", "P2SH", "OK", "push 1 byte"],
+    ["0x02 0x417a", "'Az' EQUAL", "P2SH", "OK"],
+    ["0x4c 0x01 0x07", "7 EQUAL", "P2SH", "OK", "0x4c is OP_PUSHDATA1"],
+    ["0x4d 0x0100 0x08", "8 EQUAL", "P2SH", "OK", "0x4d is OP_PUSHDATA2"],
+    ["0x4e 0x01000000 0x09", "9 EQUAL", "P2SH", "OK", "0x4e is OP_PUSHDATA4"],
+    ["0x4c 0x00", "0 EQUAL", "P2SH", "OK"],
+    ["0x51", "0x5f ADD 0x60 EQUAL", "P2SH", "OK", "0x51 through 0x60 push 1 through 16 onto stack"],
+    ["1", "NOP", "P2SH", "OK"],
+    ["0", "IF VER ELSE 1 ENDIF", "P2SH", "OK", "VER non-functional (ok if not executed)"],
+    ["0", "IF RESERVED RESERVED1 RESERVED2 ELSE 1 ENDIF", "P2SH", "OK", "RESERVED ok in un-executed IF"],
+    ["1 1", "IF IF 1 ELSE 0 ENDIF ENDIF", "P2SH", "OK"],
+    ["1 0", "NOTIF IF 1 ELSE 0 ENDIF ENDIF", "P2SH", "OK"],
+    ["0", "IF RETURN ENDIF 1", "P2SH", "OK", "RETURN only works if executed"],
+    ["1 1", "VERIFY", "P2SH", "OK"],
+    ["1 0x05 0x01 0x00 0x00 0x00 0x00", "VERIFY", "P2SH", "OK", "values >4 bytes can be cast to boolean"],
+    ["10 0 11 TOALTSTACK DROP FROMALTSTACK", "ADD 21 EQUAL", "P2SH", "OK"],
+    ["'gavin_was_here' TOALTSTACK 11 FROMALTSTACK", "'gavin_was_here' EQUALVERIFY 11 EQUAL", "P2SH", "OK"],
+    ["0 IFDUP", "DEPTH 1 EQUALVERIFY 0 EQUAL", "P2SH", "OK"],
+    ["1 IFDUP", "DEPTH 2 EQUALVERIFY 1 EQUALVERIFY 1 EQUAL", "P2SH", "OK"],
+    ["0 DUP", "DEPTH 2 EQUALVERIFY 0 EQUALVERIFY 0 EQUAL", "P2SH", "OK"],
+    ["1 2", "SWAP 1 EQUALVERIFY 2 EQUAL", "P2SH", "OK"],
+    ["2147483647 DUP ADD", "4294967294 EQUAL", "P2SH", "OK", "arithmetic operands must be in range, results may overflow"],
+    ["-1 ABS", "1 EQUAL", "P2SH", "OK"],
+    ["'abcdefghijklmnopqrstuvwxyz'", "HASH160 0x4c 0x14 0xc286a1af0947f58d1ad787385b1c2c4a976f9e71 EQUAL", "P2SH", "OK"],
+    ["''", "SHA256 0x20 0xe3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 EQUAL", "P2SH", "OK"],
+    ["", "0 0 0 CHECKMULTISIG VERIFY DEPTH 0 EQUAL", "P2SH", "OK", "CHECKMULTISIG is allowed to have zero keys and/or sigs"],
+    ["", "0 0 'a' 'b' 2 CHECKMULTISIG VERIFY DEPTH 0 EQUAL", "P2SH", "OK", "Zero sigs means no sigs are checked"],
+    ["0", "NOP2 1", "P2SH", "OK", "NOP2 is a NOP without CHECKLOCKTIMEVERIFY"],
+    [ok_push, "DROP 1", "P2SH", "OK", "520 byte push"],
+    ["1", " ".join(["NOP"] * 201), "P2SH", "OK", "201 opcodes executed"],
+    ["'a' 'b'", "CAT", "P2SH", "DISABLED_OPCODE", "CAT disabled"],
+    ["'a' 'b' 0", "IF CAT ELSE 1 ENDIF", "P2SH", "DISABLED_OPCODE", "CAT disabled even when not executed"],
+    ["'abc' 1 1", "SUBSTR", "P2SH", "DISABLED_OPCODE"],
+    ["2 2", "MUL", "P2SH", "DISABLED_OPCODE"],
+    ["0", "IF VERIF ELSE 1 ENDIF", "P2SH", "BAD_OPCODE", "VERIF illegal everywhere"],
+    ["0", "IF VERNOTIF ELSE 1 ENDIF", "P2SH", "BAD_OPCODE", "VERNOTIF illegal everywhere"],
+    ["1", "IF 0x50 ENDIF 1", "P2SH", "BAD_OPCODE", "0x50 is reserved"],
+    ["1", "VER", "P2SH", "BAD_OPCODE", "VER is reserved"],
+    ["0x50", "1", "P2SH", "BAD_OPCODE", "opcode 0x50 is reserved"],
+    ["1", "IF", "P2SH", "UNBALANCED_CONDITIONAL", "IF without ENDIF"],
+    ["1", "ENDIF", "P2SH", "UNBALANCED_CONDITIONAL", "ENDIF without IF"],
+    ["1", "ELSE", "P2SH", "UNBALANCED_CONDITIONAL"],
+    ["0", "NOTIF", "P2SH", "UNBALANCED_CONDITIONAL"],
+    ["1", "RETURN", "P2SH", "OP_RETURN"],
+    ["1", "RETURN 'data'", "P2SH", "OP_RETURN", "canonical prunable txout format"],
+    ["", "DEPTH", "P2SH", "EVAL_FALSE", "Test the test: we should have an empty stack after scriptSig evaluation"],
+    ["", "", "P2SH", "EVAL_FALSE"],
+    ["0", "", "P2SH", "EVAL_FALSE"],
+    ["0x00", "'00' EQUAL", "P2SH", "EVAL_FALSE", "Basic OP_0 execution"],
+    ["", "TOALTSTACK 1", "P2SH", "INVALID_STACK_OPERATION"],
+    ["1", "FROMALTSTACK", "P2SH", "INVALID_ALTSTACK_OPERATION"],
+    ["", "DUP 1", "P2SH", "INVALID_STACK_OPERATION"],
+    ["1", "DROP DROP 1", "P2SH", "INVALID_STACK_OPERATION"],
+    ["0", "VERIFY 1", "P2SH", "VERIFY"],
+    ["1 2", "EQUALVERIFY 1", "P2SH", "EQUALVERIFY"],
+    ["1 2", "NUMEQUALVERIFY 1", "P2SH", "NUMEQUALVERIFY"],
+    ["2147483648 0 ADD", "NOP", "P2SH", "UNKNOWN_ERROR", "arithmetic operands must be in range [-2^31...2^31]"],
+    ["-2147483648 0 ADD", "NOP", "P2SH", "UNKNOWN_ERROR", "arithmetic operands must be in range [-2^31...2^31]"],
+    [big, "DROP 1", "P2SH", "PUSH_SIZE", ">520 byte push"],
+    ["1", " ".join(["NOP"] * 202), "P2SH", "OP_COUNT", "202 opcodes executed"],
+    ["1", "0x4d 0x1127 0x" + "61" * 10001, "P2SH", "SCRIPT_SIZE", "10,001-byte scriptPubKey"],
+    [" ".join(["1"] * 1000), "1", "P2SH", "STACK_SIZE", "1,001 stack elements"],
+    ["", "0 1 0 CHECKMULTISIG", "P2SH", "SIG_COUNT"],
+    ["", "0 0 21 CHECKMULTISIG", "P2SH", "PUBKEY_COUNT"],
+    ["0", "CHECKLOCKTIMEVERIFY 1", "CHECKLOCKTIMEVERIFY", "UNSATISFIED_LOCKTIME", "The input is final"],
+    ["-1", "CHECKLOCKTIMEVERIFY", "CHECKLOCKTIMEVERIFY", "NEGATIVE_LOCKTIME"],
+    ["", "CHECKLOCKTIMEVERIFY", "CHECKLOCKTIMEVERIFY", "INVALID_STACK_OPERATION"],
+    ["0x01 0x51", P2SH_TRUE, "P2SH", "OK", "P2SH(1)"],
+    ["0x01 0x00", P2SH_FALSE, "P2SH", "EVAL_FALSE", "P2SH(0)"],
+    ["0x01 0x00", P2SH_FALSE, "NONE", "OK", "P2SH(0) without P2SH"],
+    ["NOP 0x01 0x51", P2SH_TRUE, "P2SH", "SIG_PUSHONLY", "P2SH scriptSig must be push only"],
+    ["NOP 0x01 0x51", P2SH_TRUE, "NONE", "OK", "Only with P2SH"],
+    [["51", 0.00000001], "", P2WSH_TRUE, "P2SH,WITNESS", "OK", "P2WSH(1)"],
+    [["00", 0.00000001], "", P2WSH_FALSE, "P2SH,WITNESS", "EVAL_FALSE", "P2WSH(0)"],
+    [["00", 0.00000001], "", P2WSH_TRUE, "P2SH,WITNESS", "WITNESS_PROGRAM_MISMATCH", "Witness script doesn't match"],
+    [[0.00000001], "", P2WSH_TRUE, "P2SH,WITNESS", "WITNESS_PROGRAM_WITNESS_EMPTY"],
+    [[0.00000001], "", P2WSH_TRUE, "P2SH", "OK", "P2WSH without WITNESS"],
+    [["51", "51", 0.00000001], "", "0 0x20 0x%s" % sha256(b'\x51').hex(), "P2SH,WITNESS", "CLEANSTACK", "P2WSH must leave a single element"],
+    [["51", 0.00000001], "1", P2WSH_TRUE, "P2SH,WITNESS", "WITNESS_MALLEATED", "Native witness spends need an empty scriptSig"],
+    [["51", 0.00000001], "1", "1", "P2SH,WITNESS", "WITNESS_UNEXPECTED", "Witness for a non-witness output"],
+    [["51", 0.00000001], "", "0 0x10 0x" + "11" * 16, "P2SH,WITNESS", "WITNESS_PROGRAM_WRONG_LENGTH"],
+]
+
+def credit_spend(spk, amount=0):
+    """Crediting and spending transactions of Core's script tests"""
+    credit = Tx([("00" * 32, 0xffffffff, b'\x00\x00', 0xffffffff)], [(amount, spk.raw())])
+    return Tx([(credit.txid(), 0, b'', 0xffffffff)], [(amount, b'')])
+
+def sig_test(script_sig, spk, flags, expected, comment, witness=None, amount=0):
+    """Entry whose scriptSig and witness are built from the spending transaction"""
+    tx = credit_spend(spk, amount)
+    sig_items = script_sig(tx) if script_sig else ()
+    entry = [Script(*sig_items).asm(), spk.asm(), flags, expected, comment]
+    if witness:
+        entry.insert(0, [item.hex() for item in witness(tx)] + [amount / 1e8])
+    script_tests.append(entry)
+
+K0, K1, K2 = pubkey(KEY0), pubkey(KEY1), pubkey(KEY2)
+K0_U, K1_U = pubkey(KEY0, 'uncompressed'), pubkey(KEY1, 'uncompressed')
+K0_H = pubkey(KEY0, 'hybrid')
+SEGWIT = "P2SH,WITNESS"
+TAPROOT = "P2SH,WITNESS,TAPROOT"
+AMOUNT = 1000000
+
+script_tests.append(["ECDSA signatures and hash types, signed by the keys of Core's script_tests.cpp"])
+pk = p2pk(K0)
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk)], pk, "", "OK", "P2PK")
+sig_test(lambda tx: [damaged(sig_legacy(KEY0, tx, 0, pk))], pk, "", "EVAL_FALSE", "P2PK, bad sig")
+pk_not = Script(K0, 'CHECKSIG', 'NOT')
+sig_test(lambda tx: [damaged(sig_legacy(KEY0, tx, 0, pk_not))], pk_not, "", "OK", "P2PK NOT with bad sig")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk_not)], pk_not, "", "EVAL_FALSE", "P2PK NOT with good sig")
+pk_verify = Script(K0, 'CHECKSIGVERIFY', 1)
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk_verify)], pk_verify, "", "OK", "P2PK with CHECKSIGVERIFY")
+sig_test(lambda tx: [damaged(sig_legacy(KEY0, tx, 0, pk_verify))], pk_verify, "", "CHECKSIGVERIFY", "P2PK with CHECKSIGVERIFY, bad sig")
+pk_u = p2pk(K1_U)
+sig_test(lambda tx: [sig_legacy(KEY1, tx, 0, pk_u)], pk_u, "", "OK", "P2PK with uncompressed key")
+pk_h = p2pk(K0_H)
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk_h)], pk_h, "", "OK", "P2PK with hybrid key, only STRICTENC rejects it")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk_h)], pk_h, "STRICTENC", "PUBKEYTYPE", "P2PK with hybrid key and STRICTENC")
+pkh = p2pkh(K0)
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pkh), K0], pkh, "", "OK", "P2PKH")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pkh), K1], pkh, "", "EQUALVERIFY", "P2PKH, wrong key")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pkh, 0x02), K0], pkh, "", "OK", "P2PKH with SIGHASH_NONE")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pkh, 0x03), K0], pkh, "", "OK", "P2PKH with SIGHASH_SINGLE")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, 0x81)], pk, "", "OK", "P2PK anyonecanpay")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, 0x81)[:-1] + b'\x01'], pk, "", "EVAL_FALSE", "P2PK anyonecanpay marked with normal hashtype")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, 0x04)], pk, "", "OK", "Undefined hashtype 0x04 signs like SIGHASH_ALL")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, 0x04)], pk, "STRICTENC", "SIG_HASHTYPE", "Undefined hashtype with STRICTENC")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, 0x21)], pk, "", "OK", "Hashtype 0x21 signs like SIGHASH_ALL, only the low 5 bits select the type")
+
+script_tests.append(["P2SH wrapped and multisig scripts"])
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk), pk.raw()], p2sh(pk), "P2SH", "OK", "P2SH(P2PK)")
+sig_test(lambda tx: [damaged(sig_legacy(KEY0, tx, 0, pk)), pk.raw()], p2sh(pk), "P2SH", "EVAL_FALSE", "P2SH(P2PK), bad redeemscript sig")
+sig_test(lambda tx: [damaged(sig_legacy(KEY0, tx, 0, pk)), pk.raw()], p2sh(pk), "", "OK", "P2SH(P2PK) without P2SH only checks the hash")
+ms3 = multisig(3, [K0, K1, K2])
+sig_test(lambda tx: [0, sig_legacy(KEY0, tx, 0, ms3), sig_legacy(KEY1, tx, 0, ms3), sig_legacy(KEY2, tx, 0, ms3)], ms3, "", "OK", "3-of-3")
+sig_test(lambda tx: [0, sig_legacy(KEY0, tx, 0, ms3), sig_legacy(KEY1, tx, 0, ms3), damaged(sig_legacy(KEY2, tx, 0, ms3))], ms3, "", "EVAL_FALSE", "3-of-3, one bad sig")
+ms2 = multisig(2, [K0, K1, K2])
+sig_test(lambda tx: [0, sig_legacy(KEY0, tx, 0, ms2), sig_legacy(KEY2, tx, 0, ms2)], ms2, "", "OK", "2-of-3 skipping a key")
+sig_test(lambda tx: [0, sig_legacy(KEY2, tx, 0, ms2), sig_legacy(KEY0, tx, 0, ms2)], ms2, "", "EVAL_FALSE", "2-of-3 with sigs out of key order")
+sig_test(lambda tx: [0, sig_legacy(KEY1, tx, 0, ms2), sig_legacy(KEY2, tx, 0, ms2), ms2.raw()], p2sh(ms2), "P2SH", "OK", "P2SH(2-of-3)")
+ms_verify = Script(1, K0, 1, 'CHECKMULTISIGVERIFY', 1)
+sig_test(lambda tx: [0, damaged(sig_legacy(KEY0, tx, 0, ms_verify))], ms_verify, "", "CHECKMULTISIGVERIFY", "1-of-1 CHECKMULTISIGVERIFY, bad sig")
+ms1 = multisig(1, [K0])
+sig_test(lambda tx: [1, sig_legacy(KEY0, tx, 0, ms1)], ms1, "", "OK", "1-of-1 with a non-null dummy")
+sig_test(lambda tx: [1, sig_legacy(KEY0, tx, 0, ms1)], ms1, "NULLDUMMY", "SIG_NULLDUMMY", "1-of-1 with a non-null dummy and NULLDUMMY")
+sig_test(lambda tx: [1, damaged(sig_legacy(KEY0, tx, 0, ms1))], Script(1, K0, 1, 'CHECKMULTISIG', 'NOT'), "NULLDUMMY", "SIG_NULLDUMMY", "NULLDUMMY is checked even if the signature fails")
+
+script_tests.append(["Signature encodings: DERSIG [BIP66], LOW_S and STRICTENC are policy"])
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, r_padding=1)], pk, "", "OK", "P2PK with too much R padding")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, r_padding=1)], pk, "DERSIG", "SIG_DER", "P2PK with too much R padding and DERSIG")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk_not, r_padding=1)], pk_not, "DERSIG", "SIG_DER", "DERSIG fails even under NOT")
+sig_test(lambda tx: [bytes.fromhex("300602018102010101")], pk_not, "", "OK", "Negative R under NOT")
+sig_test(lambda tx: [bytes.fromhex("300602018102010101")], pk_not, "DERSIG", "SIG_DER", "Negative R under NOT with DERSIG")
+sig_test(lambda tx: [b''], pk_not, "DERSIG", "OK", "Empty signatures are always allowed")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, low_s=False)], pk, "DERSIG", "OK", "P2PK with high S, consensus accepts it")
+sig_test(lambda tx: [sig_legacy(KEY0, tx, 0, pk, low_s=False)], pk, "LOW_S", "SIG_HIGH_S", "P2PK with high S and LOW_S")
+
+script_tests.append(["Segwit v0 signatures [BIP143], the amount is the last witness item"])
+wpkh = p2wpkh(K0)
+sig_test(None, wpkh, SEGWIT, "OK", "P2WPKH", witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0), AMOUNT), K0], amount=AMOUNT)
+sig_test(None, wpkh, SEGWIT, "EVAL_FALSE", "P2WPKH signing another amount",
+         witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0), AMOUNT + 1), K0], amount=AMOUNT)
+sig_test(None, wpkh, SEGWIT, "OK", "P2WPKH with SIGHASH_SINGLE|ANYONECANPAY",
+         witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0), AMOUNT, 0x83), K0], amount=AMOUNT)
+wpkh_u = p2wpkh(K0_U)
+sig_test(None, wpkh_u, SEGWIT, "OK", "P2WPKH with uncompressed key, only WITNESS_PUBKEYTYPE rejects it",
+         witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0_U), AMOUNT), K0_U], amount=AMOUNT)
+sig_test(lambda tx: [wpkh.raw()], p2sh(wpkh), SEGWIT, "OK", "P2SH(P2WPKH)",
+         witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0), AMOUNT), K0], amount=AMOUNT)
+sig_test(lambda tx: [1, wpkh.raw()], p2sh(wpkh), SEGWIT, "WITNESS_MALLEATED_P2SH", "P2SH(P2WPKH) with more than the redeemscript",
+         witness=lambda tx: [sig_v0(KEY0, tx, 0, p2pkh(K0), AMOUNT), K0], amount=AMOUNT)
+wsh_ms = multisig(2, [K0, K1])
+sig_test(None, p2wsh(wsh_ms), SEGWIT, "OK", "P2WSH(2-of-2)", amount=AMOUNT,
+         witness=lambda tx: [b'', sig_v0(KEY0, tx, 0, wsh_ms, AMOUNT), sig_v0(KEY1, tx, 0, wsh_ms, AMOUNT, 0x82), wsh_ms.raw()])
+sig_test(None, p2wsh(wsh_ms), SEGWIT, "EVAL_FALSE", "P2WSH(2-of-2) signed with the legacy algorithm", amount=AMOUNT,
+         witness=lambda tx: [b'', sig_legacy(KEY0, tx, 0, wsh_ms), sig_legacy(KEY1, tx, 0, wsh_ms), wsh_ms.raw()])
+sig_test(None, p2wsh(wsh_ms), "P2SH,WITNESS,NULLDUMMY", "SIG_NULLDUMMY", "P2WSH(2-of-2) with a non-null dummy", amount=AMOUNT,
+         witness=lambda tx: [b'\x01', sig_v0(KEY0, tx, 0, wsh_ms, AMOUNT), sig_v0(KEY1, tx, 0, wsh_ms, AMOUNT), wsh_ms.raw()])
+
+script_tests.append(["Taproot key and script path spends [BIP341, BIP342]"])
+TWEAKED, _ = taproot_tweak(KEY2)
+tr = p2tr(pubkey(TWEAKED, 'xonly'))
+tr_spent = lambda: [(AMOUNT, tr.raw())]
+sig_test(None, tr, TAPROOT, "OK", "Key path with SIGHASH_DEFAULT",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, tr_spent())], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "OK", "Key path with SIGHASH_NONE|ANYONECANPAY",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, tr_spent(), 0x82)], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "SCHNORR_SIG_HASHTYPE", "Key path with an explicit SIGHASH_DEFAULT",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, tr_spent()) + b'\x00'], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "SCHNORR_SIG_HASHTYPE", "Key path with undefined hashtype 0x04",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, tr_spent()) + b'\x04'], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "SCHNORR_SIG", "Key path with a bad sig",
+         witness=lambda tx: [damaged(sig_taproot(TWEAKED, tx, 0, tr_spent()) + b'\x00')[:-1]], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "SCHNORR_SIG", "Key path signing another amount",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, [(AMOUNT + 1, tr.raw())])], amount=AMOUNT)
+sig_test(None, tr, TAPROOT, "SCHNORR_SIG_SIZE", "Key path with a 63 byte sig",
+         witness=lambda tx: [sig_taproot(TWEAKED, tx, 0, tr_spent())[:63]], amount=AMOUNT)
+sig_test(None, tr, "P2SH,WITNESS", "OK", "Key path with a bad sig without TAPROOT",
+         witness=lambda tx: [bytes(64)], amount=AMOUNT)
+
+leaf = Script(K1[1:], 'CHECKSIG')
+LEAF_TWEAKED, LEAF_PARITY = taproot_tweak(KEY2, tapleaf_hash(leaf))
+tr_leaf = p2tr(pubkey(LEAF_TWEAKED, 'xonly'))
+control = bytes([0xc0 | LEAF_PARITY]) + K2[1:]
+leaf_spent = lambda: [(AMOUNT, tr_leaf.raw())]
+sig_test(None, tr_leaf, TAPROOT, "OK", "Script path with a pk() leaf", amount=AMOUNT,
+         witness=lambda tx: [sig_taproot(KEY1, tx, 0, leaf_spent(), 0, tapleaf_hash(leaf)), leaf.raw(), control])
+sig_test(None, tr_leaf, TAPROOT, "OK", "Script path with an annex", amount=AMOUNT,
+         witness=lambda tx: [sig_taproot(KEY1, tx, 0, leaf_spent(), 0x01, tapleaf_hash(leaf), b'\x50\x01'), leaf.raw(), control, b'\x50\x01'])
+sig_test(None, tr_leaf, TAPROOT, "SCHNORR_SIG", "Script path signed like a key path", amount=AMOUNT,
+         witness=lambda tx: [sig_taproot(KEY1, tx, 0, leaf_spent()), leaf.raw(), control])
+sig_test(None, tr_leaf, TAPROOT, "EVAL_FALSE", "Script path with an empty sig leaves false",
+         witness=lambda tx: [b'', leaf.raw(), control], amount=AMOUNT)
+sig_test(None, tr_leaf, TAPROOT, "TAPROOT_WRONG_CONTROL_SIZE", "Control block with a partial node", amount=AMOUNT,
+         witness=lambda tx: [sig_taproot(KEY1, tx, 0, leaf_spent(), 0, tapleaf_hash(leaf)), leaf.raw(), control + b'\x00'])
+sig_test(None, tr_leaf, TAPROOT, "WITNESS_PROGRAM_MISMATCH", "Control block of another leaf", amount=AMOUNT,
+         witness=lambda tx: [b'', Script(K0[1:], 'CHECKSIG').raw(), control])
+ms_leaf = Script(1, K1[1:], 1, 'CHECKMULTISIG')
+MS_TWEAKED, MS_PARITY = taproot_tweak(KEY2, tapleaf_hash(ms_leaf))
+tr_ms = p2tr(pubkey(MS_TWEAKED, 'xonly'))
+sig_test(None, tr_ms, TAPROOT, "TAPSCRIPT_CHECKMULTISIG", "CHECKMULTISIG in a tapscript", amount=AMOUNT,
+         witness=lambda tx: [b'', b'', ms_leaf.raw(), bytes([0xc0 | MS_PARITY]) + K2[1:]])
+add_leaf = Script(K0[1:], 'CHECKSIG', K1[1:], 'CHECKSIGADD', 2, 'NUMEQUAL')
+ADD_TWEAKED, ADD_PARITY = taproot_tweak(KEY2, tapleaf_hash(add_leaf))
+tr_add = p2tr(pubkey(ADD_TWEAKED, 'xonly'))
+add_spent = lambda: [(AMOUNT, tr_add.raw())]
+sig_test(None, tr_add, TAPROOT, "OK", "2-of-2 with CHECKSIGADD", amount=AMOUNT,
+         witness=lambda tx: [sig_taproot(KEY1, tx, 0, add_spent(), 0, tapleaf_hash(add_leaf)),
+                             sig_taproot(KEY0, tx, 0, add_spent(), 0x83, tapleaf_hash(add_leaf)),
+                             add_leaf.raw(), bytes([0xc0 | ADD_PARITY]) + K2[1:]])
+
+# Transactions
+
+class TxBuilder:
+    """Spends outputs of fake previous transactions, ids counting up from 0x0100"""
+    def __init__(self, spent, outputs, version=1, locktime=0):
+        # spent: (scriptPubKey Script, amount)
+        self.spent = list(spent)
+        self.prevouts = ["%064x" % (0x100 + i) for i in range(len(spent))]
+        self.tx = Tx([(txid, 0, b'', 0xffffffff) for txid in self.prevouts], outputs, version, locktime)
+
+    def amounts(self):
+        return [(amount, spk.raw()) for spk, amount in self.spent]
+
+    def entry(self, flags, witness_amounts=True):
+        prevouts = []
+        for txid, (spk, amount) in zip(self.prevouts, self.spent):
+            prevout = [txid, 0, spk.asm()]
+            if witness_amounts: prevout.append(amount)
+            prevouts.append(prevout)
+        return [prevouts, self.tx.hex(), flags]
+
+OUTPUTS3 = [(1000, p2pkh(K0).raw()), (2000, p2pkh(K1).raw()), (3000, p2pkh(K2).raw())]
+
+def legacy_hashtypes(hash_types):
+    """P2PKH inputs signed with `hash_types`"""
+    b = TxBuilder([(p2pkh(K0), 10000)] * len(hash_types), OUTPUTS3)
+    for vin, hash_type in enumerate(hash_types):
+        b.tx.inputs[vin][2] = Script(sig_legacy(KEY0, b.tx, vin, p2pkh(K0), hash_type), K0).raw()
+    return b
+
+def segwit_mix():
+    """P2PKH, P2WPKH, P2SH(P2WPKH) and P2WSH(2-of-2) inputs with various hash types"""
+    ms = multisig(2, [K0, K1])
+    b = TxBuilder([(p2pkh(K0), 10000), (p2wpkh(K1), 20000), (p2sh(p2wpkh(K2)), 30000), (p2wsh(ms), 40000)], OUTPUTS3)
+    tx = b.tx
+    tx.witnesses[1] = [sig_v0(KEY1, tx, 1, p2pkh(K1), 20000, 0x02), K1]
+    tx.inputs[2][2] = Script(p2wpkh(K2).raw()).raw()
+    tx.witnesses[2] = [sig_v0(KEY2, tx, 2, p2pkh(K2), 30000, 0x83), K2]
+    tx.witnesses[3] = [b'', sig_v0(KEY0, tx, 3, ms, 40000, 0x01), sig_v0(KEY1, tx, 3, ms, 40000, 0x81), ms.raw()]
+    tx.inputs[0][2] = Script(sig_legacy(KEY0, tx, 0, p2pkh(K0), 0x01), K0).raw()
+    return b
+
+def taproot_mix(hash_types):
+    """Key path inputs signed with `hash_types` and a script path input with SIGHASH_ALL"""
+    key_path = [(tr, 10000 * (i + 1)) for i, _ in enumerate(hash_types)]
+    b = TxBuilder(key_path + [(tr_leaf, 5000)], OUTPUTS3, version=2)
+    tx = b.tx
+    for vin, hash_type in enumerate(hash_types):
+        tx.witnesses[vin] = [sig_taproot(TWEAKED, tx, vin, b.amounts(), hash_type)]
+    vin = len(hash_types)
+    tx.witnesses[vin] = [sig_taproot(KEY1, tx, vin, b.amounts(), 0x01, tapleaf_hash(leaf)), leaf.raw(), control]
+    return b
+
+def modified(b, change):
+    change(b.tx)
+    return b
+
+header = [
+    ["They are in the form"],
+    ["[[[prevout hash, prevout index, prevout scriptPubKey, amount?], [input 2], ...],"],
+    ["serializedTransaction, verifyFlags]"],
+]
+
+PREV = "0000000000000000000000000000000000000000000000000000000000000100"
+OUTPUTS = [(0, b'')]
+
+def spend(version=1, locktime=0, seq=0xffffffff, script_sig=b'', witness=None):
+    return ser_tx(version, [(PREV, 0, script_sig, seq)], OUTPUTS, locktime, [witness] if witness else None)
+
+def with_added_input(b):
+    """Adds an input spending OP_TRUE after signing"""
+    b.spent.append((Script(1), 0))
+    b.prevouts.append("%064x" % 0x1ff)
+    b.tx.inputs.append(["%064x" % 0x1ff, 0, b'', 0xffffffff])
+    b.tx.witnesses.append([])
+    return b
+
+def set_output_value(index, value):
+    def change(tx):
+        tx.outputs[index] = (value, tx.outputs[index][1])
+    return change
+
+def no_sig_spent(b, spent):
+    """Claims other amounts for the spent outputs"""
+    b.spent = spent
+    return b
+
+LEGACY_FLAGS = "P2SH,DERSIG,NULLDUMMY"
+TX_SEGWIT_FLAGS = "P2SH,DERSIG,NULLDUMMY,WITNESS"
+TX_TAPROOT_FLAGS = "P2SH,DERSIG,NULLDUMMY,WITNESS,TAPROOT"
+
+tx_valid = [["The following are deserialized transactions which are valid."]] + header + [
+    ["verifyFlags are the flags which are checked, vectors in the format of Bitcoin Core's tx_valid.json"],
+    ["CHECKLOCKTIMEVERIFY: the locktime of the transaction satisfies the argument"],
+    [[[PREV, 0, "0 CHECKLOCKTIMEVERIFY 1"]], spend(seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "499999999 CHECKLOCKTIMEVERIFY 1"]], spend(locktime=499999999, seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "500000000 CHECKLOCKTIMEVERIFY 1"]], spend(locktime=500000000, seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "4294967295 CHECKLOCKTIMEVERIFY 1"]], spend(locktime=4294967295, seq=0), "CHECKLOCKTIMEVERIFY"],
+    ["Without the flag CHECKLOCKTIMEVERIFY is a NOP"],
+    [[[PREV, 0, "1 CHECKLOCKTIMEVERIFY 1"]], spend(), "P2SH"],
+    ["CHECKSEQUENCEVERIFY: the sequence of the input satisfies the argument"],
+    [[[PREV, 0, "0 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=0), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "65535 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=65535), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "4194304 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=4194304), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "2147483648 CHECKSEQUENCEVERIFY 1"]], spend(version=1, seq=0xffffffff), "CHECKSEQUENCEVERIFY"],
+    ["P2SH and P2WSH of OP_TRUE"],
+    [[[PREV, 0, P2SH_TRUE]], spend(script_sig=b'\x01\x51'), "P2SH"],
+    [[[PREV, 0, P2WSH_TRUE, 1000]], spend(witness=[b'\x51']), "P2SH,WITNESS"],
+    ["P2PKH inputs signed with ALL, NONE and SINGLE, with and without ANYONECANPAY"],
+    legacy_hashtypes([0x01, 0x02, 0x03, 0x81, 0x82, 0x83]).entry(LEGACY_FLAGS, False),
+    ["SIGHASH_SINGLE without a matching output signs the hash 1"],
+    legacy_hashtypes([0x01, 0x01, 0x01, 0x03]).entry(LEGACY_FLAGS, False),
+    ["SIGHASH_NONE doesn't cover the outputs"],
+    modified(legacy_hashtypes([0x02, 0x02]), set_output_value(0, 1)).entry(LEGACY_FLAGS, False),
+    ["SIGHASH_SINGLE only covers the output of the same index"],
+    modified(legacy_hashtypes([0x03, 0x03]), set_output_value(2, 1)).entry(LEGACY_FLAGS, False),
+    ["ANYONECANPAY allows adding inputs"],
+    with_added_input(legacy_hashtypes([0x81, 0x83])).entry(LEGACY_FLAGS, False),
+    ["Segwit v0 inputs mixed with a legacy one"],
+    segwit_mix().entry(TX_SEGWIT_FLAGS),
+    ["Taproot key path inputs with each hash type and a script path input"],
+    taproot_mix([0x03, 0x83, 0x00, 0x01, 0x02, 0x81, 0x82]).entry(TX_TAPROOT_FLAGS),
+    taproot_mix([0x00, 0x03, 0x83]).entry(TX_TAPROOT_FLAGS),
+]
+
+tx_invalid = [["The following are deserialized transactions which are invalid."]] + header + [
+    ["verifyFlags are the flags which are checked, vectors in the format of Bitcoin Core's tx_invalid.json"],
+    ["CHECKLOCKTIMEVERIFY: argument above the locktime, of another type, negative or with a final input"],
+    [[[PREV, 0, "1 CHECKLOCKTIMEVERIFY 1"]], spend(seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "500000000 CHECKLOCKTIMEVERIFY 1"]], spend(locktime=499999999, seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "499999999 CHECKLOCKTIMEVERIFY 1"]], spend(locktime=500000000, seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "-1 CHECKLOCKTIMEVERIFY 1"]], spend(seq=0), "CHECKLOCKTIMEVERIFY"],
+    [[[PREV, 0, "0 CHECKLOCKTIMEVERIFY 1"]], spend(), "CHECKLOCKTIMEVERIFY"],
+    ["CHECKSEQUENCEVERIFY: version 1, disabled or smaller sequence, or of another type"],
+    [[[PREV, 0, "0 CHECKSEQUENCEVERIFY 1"]], spend(version=1, seq=0), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "0 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=0xffffffff), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "1 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=0), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "4194304 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=0), "CHECKSEQUENCEVERIFY"],
+    [[[PREV, 0, "0 CHECKSEQUENCEVERIFY 1"]], spend(version=2, seq=4194304), "CHECKSEQUENCEVERIFY"],
+    ["P2SH and P2WSH of OP_FALSE, P2WSH with the wrong witness script"],
+    [[[PREV, 0, P2SH_FALSE]], spend(script_sig=b'\x01\x00'), "P2SH"],
+    [[[PREV, 0, P2WSH_FALSE, 1000]], spend(witness=[b'\x00']), "P2SH,WITNESS"],
+    [[[PREV, 0, P2WSH_TRUE, 1000]], spend(witness=[b'\x52']), "P2SH,WITNESS"],
+    ["SIGHASH_ALL covers all outputs"],
+    modified(legacy_hashtypes([0x01, 0x02]), set_output_value(2, 1)).entry(LEGACY_FLAGS, False),
+    ["SIGHASH_SINGLE covers the output of the same index"],
+    modified(legacy_hashtypes([0x03, 0x03]), set_output_value(1, 1)).entry(LEGACY_FLAGS, False),
+    ["Without ANYONECANPAY no input can be added"],
+    with_added_input(legacy_hashtypes([0x81, 0x01])).entry(LEGACY_FLAGS, False),
+    ["Segwit v0 signatures commit to the spent amount"],
+    no_sig_spent(segwit_mix(), [(p2pkh(K0), 10000), (p2wpkh(K1), 20001), (p2sh(p2wpkh(K2)), 30000), (p2wsh(multisig(2, [K0, K1])), 40000)]).entry(TX_SEGWIT_FLAGS),
+    ["Taproot signatures commit to the amounts of all inputs, even with ANYONECANPAY on the others"],
+    no_sig_spent(taproot_mix([0x81, 0x00]), [(tr, 10000), (tr, 20001), (tr_leaf, 5000)]).entry(TX_TAPROOT_FLAGS),
+    ["Taproot SIGHASH_SINGLE without a matching output"],
+    taproot_mix([0x00, 0x00, 0x00, 0x03]).entry(TX_TAPROOT_FLAGS),
+]
+
+# Legacy signature hashes in the format of sighash.json: raw transaction, script, input index,
+# hash type and the hash in the byte order of uint256::GetHex
+
+def random_script(rng):
+    ops = [0x00, 0x51, 0x52, 0x53, 0x63, 0x65, 0x6a, 0xab, 0xab, 0xac]
+    script = b''
+    for _ in range(rng.randrange(1, 10)):
+        if rng.random() < 0.3:
+            script += push(bytes(rng.randrange(256) for _ in range(rng.randrange(1, 10))))
+        else:
+            script += bytes([rng.choice(ops)])
+    return script
+
+def random_tx(rng):
+    inputs = [("%064x" % rng.getrandbits(256), rng.randrange(4), random_script(rng), rng.choice([0xffffffff, rng.getrandbits(32)]))
+              for _ in range(rng.randrange(1, 5))]
+    outputs = [(rng.getrandbits(40), random_script(rng)) for _ in range(rng.randrange(1, 5))]
+    return Tx(inputs, outputs, rng.getrandbits(32) & 0x7fffffff, rng.choice([0, rng.getrandbits(32)]))
+
+sighash_tests = [["raw_transaction, script, input_index, hashType, signature_hash (result)"],
+                 ["Hash types are single bytes as in signatures, Core's vectors also use wider values"]]
+rng = random.Random(2024)
+for hash_type in list(range(0x00, 0x05)) + list(range(0x80, 0x85)) + [0x1f, 0x21, 0x43, 0xc3, 0xff]:
+    for _ in range(10):
+        tx = random_tx(rng)
+        vin = rng.randrange(len(tx.inputs))
+        script = random_script(rng)
+        sighash_tests.append([tx.hex(), script.hex(), vin, hash_type, sighash_legacy(tx, vin, script, hash_type)[::-1].hex()])
+
+def dump(path, entries):
+    with open(path, 'w') as f:
+        f.write("[\n")
+        f.write(",\n".join(json.dumps(e) for e in entries))
+        f.write("\n]\n")
+
+base = os.path.dirname(os.path.abspath(__file__))
+dump(os.path.join(base, "script_tests.json"), script_tests)
+dump(os.path.join(base, "tx_valid.json"), tx_valid)
+dump(os.path.join(base, "tx_invalid.json"), tx_invalid)
+dump(os.path.join(base, "sighash.json"), sighash_tests)
@@ -1,226 +1,352 @@
-use std::convert::From;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::OnceLock;
 
+use serde::de::{self, Deserialize, Deserializer};
+
+use crate::blockchain::parser::consensus::{DifficultyRules, PowAlgorithm};
+use crate::blockchain::proto::interpreter::{
+    VERIFY_CHECKLOCKTIMEVERIFY, VERIFY_CHECKSEQUENCEVERIFY, VERIFY_DERSIG, VERIFY_NULLDUMMY,
+    VERIFY_P2SH, VERIFY_TAPROOT, VERIFY_WITNESS,
+};
+use crate::blockchain::proto::script::AddressParams;
 use crate::common::utils;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Trait to specify the underlying coin of a blockchain
-/// Needs a proper magic value and a network id for address prefixes
-pub trait Coin {
-    fn name(&self) -> String; // Human readable coin name
-    fn magic(&self) -> u32; // Magic value to identify blocks
-    fn version_id(&self) -> u8; // https://en.bitcoin.it/wiki/List_of_address_prefixes
-    fn genesis(&self) -> [u8; 32]; // Returns genesis hash
-    fn default_folder(&self) -> PathBuf; // Default working directory, for example .bitcoin
+/// Definitions of the built-in coins, in the same format as files passed with `--coin-config`
+const BUILTIN_COINS: &str = include_str!("coins.toml");
+
+/// Algorithm used to compute the message hash signed by transaction inputs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SighashVariant {
+    /// Original algorithm, BIP143 for segwit inputs
+    #[default]
+    Legacy,
+    /// BIP143 style hashes for all inputs with SIGHASH_FORKID set (Bitcoin Cash and its forks)
+    ForkId,
 }
 
-// Implemented blockchain types.
-// If you want to add you own coin, create a struct with a Coin implementation
-// and add the coin name to from_str() below
-pub struct Bitcoin;
-pub struct TestNet3;
-pub struct Namecoin;
-pub struct Litecoin;
-pub struct Dogecoin;
-pub struct Myriadcoin;
-pub struct Unobtanium;
-//pub struct Dash;
-
-impl Coin for Bitcoin {
-    fn name(&self) -> String {
-        String::from("Bitcoin")
-    }
-    fn magic(&self) -> u32 {
-        0xd9b4bef9
-    }
-    fn version_id(&self) -> u8 {
-        0x00
-    }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
-        )
-    }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".bitcoin").join("blocks")
-    }
+// Holds the selected coin type information
+#[derive(Clone, Debug, Deserialize)]
+pub struct CoinType {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub magic: u32, // Magic value to identify blocks
+    #[serde(rename = "p2pkh_version")]
+    pub version_id: u8, // https://en.bitcoin.it/wiki/List_of_address_prefixes
+    pub p2sh_version: u8,
+    pub wif_prefix: u8,
+    pub bech32_hrp: Option<String>,
+    #[serde(deserialize_with = "deserialize_hash")]
+    pub genesis_hash: [u8; 32],
+    pub default_folder: PathBuf, // Relative to the home directory, for example .bitcoin/blocks
+    pub aux_pow_height: Option<u64>, // First height which can be merge-mined with an AuxPoW
+    pub segwit_height: Option<u64>,
+    pub p2sh_height: Option<u64>,
+    pub bip66_height: Option<u64>,
+    pub bip65_height: Option<u64>,
+    pub csv_height: Option<u64>,
+    pub taproot_height: Option<u64>,
+    #[serde(default)]
+    pub sighash: SighashVariant,
+    pub pow_algorithm: Option<PowAlgorithm>, // None if the proof of work can't be verified
+    pub difficulty: Option<DifficultyRules>, // None if retargeting can't be verified
 }
 
-/// Bitcoin testnet3
-impl Coin for TestNet3 {
-    fn name(&self) -> String {
-        String::from("TestNet3")
-    }
-    fn magic(&self) -> u32 {
-        0x0709110b
-    }
-    fn version_id(&self) -> u8 {
-        0x6f
+impl CoinType {
+    /// Returns whether blocks at `height` may carry an AuxPoW
+    #[inline]
+    pub fn aux_pow_at(&self, height: u64) -> bool {
+        self.aux_pow_height
+            .is_some_and(|activation| height >= activation)
     }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
-        )
+
+    /// Returns the script verification flags enforced at `height`.
+    /// Rules without an activation height are not enforced.
+    pub fn script_flags(&self, height: u64) -> u32 {
+        let active =
+            |activation: Option<u64>| activation.is_some_and(|activation| height >= activation);
+        let mut flags = 0;
+        if active(self.p2sh_height) {
+            flags |= VERIFY_P2SH;
+        }
+        if active(self.bip66_height) {
+            flags |= VERIFY_DERSIG;
+        }
+        if active(self.bip65_height) {
+            flags |= VERIFY_CHECKLOCKTIMEVERIFY;
+        }
+        if active(self.csv_height) {
+            flags |= VERIFY_CHECKSEQUENCEVERIFY;
+        }
+        if active(self.segwit_height) {
+            flags |= VERIFY_WITNESS | VERIFY_NULLDUMMY;
+        }
+        if active(self.taproot_height) {
+            flags |= VERIFY_TAPROOT;
+        }
+        flags
     }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".bitcoin").join("testnet3")
+
+    /// Returns the prefixes used to encode addresses
+    pub fn address_params(&self) -> AddressParams {
+        AddressParams {
+            p2pkh_version: self.version_id,
+            p2sh_version: self.p2sh_version,
+            bech32_hrp: self.bech32_hrp.clone(),
+            wif_prefix: self.wif_prefix,
+        }
     }
 }
 
-impl Coin for Namecoin {
-    fn name(&self) -> String {
-        String::from("Namecoin")
-    }
-    fn magic(&self) -> u32 {
-        0xfeb4bef9
-    }
-    fn version_id(&self) -> u8 {
-        0x34
-    }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "000000000062b72c5e2ceb45fbc8587e807c155b0da735e6483dfba2f0a9c770",
-        )
-    }
-    fn default_folder(&self) -> PathBuf {
-        PathBuf::from(".namecoin")
+impl Default for CoinType {
+    fn default() -> Self {
+        CoinRegistry::builtin_ref()
+            .get("bitcoin")
+            .expect("Built-in coin definitions are missing bitcoin!")
     }
 }
 
-impl Coin for Litecoin {
-    fn name(&self) -> String {
-        String::from("Litecoin")
-    }
-    fn magic(&self) -> u32 {
-        0xdbb6c0fb
-    }
-    fn version_id(&self) -> u8 {
-        0x30
-    }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2",
-        )
-    }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".litecoin").join("blocks")
+impl FromStr for CoinType {
+    type Err = OpError;
+    fn from_str(coin_name: &str) -> OpResult<Self> {
+        CoinRegistry::builtin_ref().get(coin_name)
     }
 }
 
-impl Coin for Dogecoin {
-    fn name(&self) -> String {
-        String::from("Dogecoin")
-    }
-    fn magic(&self) -> u32 {
-        0xc0c0c0c0
-    }
-    fn version_id(&self) -> u8 {
-        0x1e
-    }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "1a91e3dace36e2be3bf030a65679fe821aa1d6ef92e7c9902eb318182c355691",
-        )
-    }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".dogecoin").join("blocks")
-    }
+/// Known coins by id, built-in coins first.
+/// Adding a coin only requires a definition in a coin config file.
+#[derive(Clone)]
+pub struct CoinRegistry {
+    coins: Vec<(String, CoinType)>,
 }
 
-impl Coin for Myriadcoin {
-    fn name(&self) -> String {
-        String::from("Myriadcoin")
-    }
-    fn magic(&self) -> u32 {
-        0xee7645af
-    }
-    fn version_id(&self) -> u8 {
-        0x32
-    }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "00000ffde4c020b5938441a0ea3d314bf619eff0b38f32f78f7583cffa1ea485",
-        )
-    }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".myriadcoin").join("blocks")
-    }
+#[derive(Deserialize)]
+struct CoinFile {
+    #[serde(rename = "coin")]
+    coins: Vec<CoinEntry>,
 }
 
-impl Coin for Unobtanium {
-    fn name(&self) -> String {
-        String::from("Unobtanium")
-    }
-    fn magic(&self) -> u32 {
-        0x03b5d503
-    }
-    fn version_id(&self) -> u8 {
-        0x82
+#[derive(Deserialize)]
+struct CoinEntry {
+    id: String,
+    #[serde(flatten)]
+    coin: CoinType,
+}
+
+impl CoinRegistry {
+    /// Returns the built-in coins
+    pub fn builtin() -> Self {
+        CoinRegistry::builtin_ref().clone()
     }
-    fn genesis(&self) -> [u8; 32] {
-        utils::hex_to_arr32_swapped(
-            "000004c2fc5fffb810dccc197d603690099a68305232e552d96ccbe8e2c52b75",
-        )
+
+    /// Built-in coins, parsed once on first use
+    fn builtin_ref() -> &'static CoinRegistry {
+        static BUILTIN: OnceLock<CoinRegistry> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            let coins = CoinRegistry::parse(BUILTIN_COINS, false)
+                .expect("Built-in coin definitions are invalid!");
+            CoinRegistry { coins }
+        })
     }
-    fn default_folder(&self) -> PathBuf {
-        Path::new(".unobtanium").join("blocks")
+
+    /// Adds the coins defined in a TOML or JSON file, replacing coins with the same id
+    pub fn load(&mut self, path: &Path) -> OpResult<()> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            OpError::from(e).join_msg(&format!("Cannot read coin config '{}'.", path.display()))
+        })?;
+        let json = path.extension().is_some_and(|ext| ext == "json");
+        let coins = CoinRegistry::parse(&content, json).map_err(|mut e| {
+            e.message
+                .insert_str(0, &format!("Invalid coin config '{}': ", path.display()));
+            e
+        })?;
+        for (id, coin) in coins {
+            debug!(target: "parser", "Loaded coin {} ({}) from {}", id, coin.name, path.display());
+            match self.coins.iter_mut().find(|(known, _)| *known == id) {
+                Some(entry) => entry.1 = coin,
+                None => self.coins.push((id, coin)),
+            }
+        }
+        Ok(())
     }
-}
 
-/* TODO: implement X11
-impl Coin for Dash {
-    fn name(&self)        -> String { String::from("Dash") }
-    fn magic(&self)       -> u32 { 0xbd6b0cbf }
-    fn version_id(&self)  -> u8  { 0x4c }
-    fn genesis(&self)     -> [u8; 32] { hex_to_arr32_swapped("000007d91d1254d60e2dd1ae580383070a4ddffa4c64c2eeb4a2f9ecc0414343") }
-    fn default_folder(&self) -> PathBuf { Path::new(".dash").join("blocks") }
-}*/
+    fn parse(content: &str, json: bool) -> OpResult<Vec<(String, CoinType)>> {
+        let file: CoinFile = if json {
+            serde_json::from_str(content).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(content).map_err(|e| e.to_string())?
+        };
+        Ok(file
+            .coins
+            .into_iter()
+            .map(|entry| (entry.id, entry.coin))
+            .collect())
+    }
 
-#[derive(Clone)]
-// Holds the selected coin type information
-pub struct CoinType {
-    pub name: String,
-    pub magic: u32,
-    pub version_id: u8,
-    pub genesis_hash: [u8; 32],
-    pub default_folder: PathBuf,
-}
+    /// Returns the coin with the given id
+    pub fn get(&self, id: &str) -> OpResult<CoinType> {
+        match self.coins.iter().find(|(known, _)| known == id) {
+            Some((_, coin)) => Ok(coin.clone()),
+            None => Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "Unknown coin `{}`, expected one of: {}.",
+                    id,
+                    self.ids().join(", ")
+                )),
+            ),
+        }
+    }
 
-impl Default for CoinType {
-    fn default() -> Self {
-        CoinType::from(Bitcoin)
+    /// Returns the ids of all known coins
+    pub fn ids(&self) -> Vec<&str> {
+        self.coins.iter().map(|(id, _)| id.as_str()).collect()
     }
 }
 
-impl<T: Coin> From<T> for CoinType {
-    fn from(coin: T) -> Self {
-        CoinType {
-            name: coin.name(),
-            magic: coin.magic(),
-            version_id: coin.version_id(),
-            genesis_hash: coin.genesis(),
-            default_folder: coin.default_folder(),
+/// Accepts integers and hex strings like "0xd9b4bef9", JSON has no hex literals
+pub fn deserialize_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrHex {
+        Int(u32),
+        Hex(String),
+    }
+    match IntOrHex::deserialize(deserializer)? {
+        IntOrHex::Int(value) => Ok(value),
+        IntOrHex::Hex(s) => {
+            u32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(de::Error::custom)
         }
     }
 }
 
-impl FromStr for CoinType {
-    type Err = OpError;
-    fn from_str(coin_name: &str) -> OpResult<Self> {
-        match coin_name {
-            "bitcoin" => Ok(CoinType::from(Bitcoin)),
-            "testnet3" => Ok(CoinType::from(TestNet3)),
-            "namecoin" => Ok(CoinType::from(Namecoin)),
-            "litecoin" => Ok(CoinType::from(Litecoin)),
-            "dogecoin" => Ok(CoinType::from(Dogecoin)),
-            "myriadcoin" => Ok(CoinType::from(Myriadcoin)),
-            "unobtanium" => Ok(CoinType::from(Unobtanium)),
-            n => {
-                let e = OpError::new(OpErrorKind::InvalidArgsError)
-                    .join_msg(&format!("The is no impl for `{}`!", n));
-                Err(e)
-            }
-        }
+/// Parses a block hash in the byte order shown by block explorers
+fn deserialize_hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    let s = String::deserialize(deserializer)?;
+    if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(de::Error::custom(format!(
+            "`{}` is not a 64 digit hex hash",
+            s
+        )));
+    }
+    Ok(utils::hex_to_arr32_swapped(&s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::consensus::RetargetAlgorithm;
+    use std::fs;
+
+    #[test]
+    fn test_builtin_coins() {
+        let registry = CoinRegistry::builtin();
+        assert_eq!(
+            registry.ids(),
+            vec![
+                "bitcoin",
+                "testnet3",
+                "testnet4",
+                "signet",
+                "regtest",
+                "namecoin",
+                "litecoin",
+                "dogecoin",
+                "myriadcoin",
+                "unobtanium"
+            ]
+        );
+        let bitcoin = CoinType::default();
+        assert_eq!(bitcoin.name, "Bitcoin");
+        assert_eq!(bitcoin.magic, 0xd9b4bef9);
+        assert_eq!(bitcoin.version_id, 0x00);
+        assert_eq!(bitcoin.p2sh_version, 0x05);
+        assert_eq!(bitcoin.wif_prefix, 0x80);
+        assert_eq!(bitcoin.bech32_hrp.as_deref(), Some("bc"));
+        assert_eq!(bitcoin.default_folder, Path::new(".bitcoin").join("blocks"));
+        assert_eq!(bitcoin.sighash, SighashVariant::Legacy);
+        assert_eq!(bitcoin.difficulty.as_ref().unwrap().pow_limit, 0x1d00ffff);
+        assert_eq!(bitcoin.script_flags(173804), 0);
+        assert_eq!(bitcoin.script_flags(173805), VERIFY_P2SH);
+        assert_eq!(bitcoin.script_flags(481824) & VERIFY_TAPROOT, 0);
+        assert_eq!(bitcoin.script_flags(709632).count_ones(), 7);
+
+        let dogecoin: CoinType = "dogecoin".parse().unwrap();
+        assert_eq!(dogecoin.pow_algorithm, Some(PowAlgorithm::Scrypt));
+        assert_eq!(
+            dogecoin.difficulty.as_ref().unwrap().algorithm,
+            RetargetAlgorithm::Dogecoin
+        );
+        assert!(!dogecoin.aux_pow_at(371336));
+        assert!(dogecoin.aux_pow_at(371337));
+        assert!(dogecoin.bech32_hrp.is_none());
+        assert_eq!(dogecoin.script_flags(1034383), VERIFY_P2SH | VERIFY_DERSIG);
+        assert!("myriadcoin"
+            .parse::<CoinType>()
+            .unwrap()
+            .pow_algorithm
+            .is_none());
+        assert!("dash".parse::<CoinType>().is_err());
+    }
+
+    #[test]
+    fn test_load_coin_config() {
+        let dir = std::env::temp_dir();
+        let toml_path = dir.join(format!("coins-{}.toml", std::process::id()));
+        fs::write(
+            &toml_path,
+            r#"
+            [[coin]]
+            id = "bitcoin"
+            name = "Bitcoin Fork"
+            magic = 0xe8f3e1e3
+            p2pkh_version = 0
+            p2sh_version = 5
+            wif_prefix = 0x80
+            genesis_hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+            default_folder = ".fork/blocks"
+            sighash = "forkid"
+            "#,
+        )
+        .unwrap();
+        let json_path = dir.join(format!("coins-{}.json", std::process::id()));
+        fs::write(
+            &json_path,
+            r#"{"coin": [{"id": "examplecoin", "name": "Examplecoin", "magic": "0x0b110907",
+                "p2pkh_version": 111, "p2sh_version": 196, "wif_prefix": 239, "bech32_hrp": "ex",
+                "genesis_hash": "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+                "default_folder": ".example/blocks", "segwit_height": 10, "pow_algorithm": "sha256d",
+                "difficulty": {"algorithm": "bitcoin", "pow_limit": 486604799, "timespan": 1209600,
+                               "spacing": 600, "allow_min_difficulty": true}}]}"#,
+        )
+        .unwrap();
+
+        let mut registry = CoinRegistry::builtin();
+        registry.load(&toml_path).unwrap();
+        registry.load(&json_path).unwrap();
+        assert_eq!(registry.ids().len(), 11);
+
+        let fork = registry.get("bitcoin").unwrap();
+        assert_eq!(fork.name, "Bitcoin Fork");
+        assert_eq!(fork.magic, 0xe8f3e1e3);
+        assert_eq!(fork.sighash, SighashVariant::ForkId);
+        assert!(fork.difficulty.is_none());
+
+        let example = registry.get("examplecoin").unwrap();
+        assert_eq!(example.magic, 0x0b110907);
+        assert_eq!(
+            example.genesis_hash,
+            "testnet3".parse::<CoinType>().unwrap().genesis_hash
+        );
+        assert_eq!(example.segwit_height, Some(10));
+        assert_eq!(example.difficulty.unwrap().pow_limit, 0x1d00ffff);
+
+        // Missing fields are reported
+        fs::write(&toml_path, "[[coin]]\nid = \"broken\"\nname = \"Broken\"\n").unwrap();
+        assert!(registry.load(&toml_path).is_err());
+        fs::remove_file(toml_path).unwrap();
+        fs::remove_file(json_path).unwrap();
     }
 }
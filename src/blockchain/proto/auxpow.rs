@@ -0,0 +1,256 @@
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::blockchain::parser::consensus::ConsensusError;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::common::utils;
+
+/// Blocks with this version bit carry an AuxPoW after their header
+pub const VERSION_AUXPOW: u32 = 1 << 8;
+/// Marks the chain merkle root in the parent coinbase
+const MERGED_MINING_HEADER: [u8; 4] = [0xfa, 0xbe, 0x6d, 0x6d];
+/// Maximum depth of the chain merkle tree
+const MAX_CHAIN_BRANCH_LEN: usize = 30;
+
+/// Merkle branch from a leaf to the root.
+/// Bit n of `side_mask` is set if the leaf side is on the right at depth n.
+#[derive(Clone, Debug)]
+pub struct MerkleBranch {
+    pub hashes: Vec<[u8; 32]>,
+    pub side_mask: u32,
+}
+
+impl MerkleBranch {
+    /// Computes the merkle root of `leaf` along the branch, see CMerkleTx::CheckMerkleBranch()
+    pub fn root(&self, leaf: [u8; 32]) -> [u8; 32] {
+        let mut hash = leaf;
+        let mut concat = [0u8; 64];
+        for (i, other) in self.hashes.iter().enumerate() {
+            if (self.side_mask >> i) & 1 == 1 {
+                concat[..32].copy_from_slice(other);
+                concat[32..].copy_from_slice(&hash);
+            } else {
+                concat[..32].copy_from_slice(&hash);
+                concat[32..].copy_from_slice(other);
+            }
+            hash = utils::sha256(&utils::sha256(&concat));
+        }
+        hash
+    }
+}
+
+/// Merged mining proof (Namecoin, Dogecoin).
+/// The block hash is committed to in the coinbase of a parent block of another chain,
+/// whose proof of work is reused for this block.
+pub struct AuxPow {
+    pub coinbase_tx: Hashed<EvaluatedTx>,
+    pub parent_hash: [u8; 32], // Unused by consensus, usually zero
+    pub coinbase_branch: MerkleBranch,
+    pub blockchain_branch: MerkleBranch,
+    pub parent_header: Hashed<BlockHeader>,
+}
+
+impl AuxPow {
+    /// Verifies that the parent block commits to `block_hash`, see CAuxPow::check().
+    /// `chain_id` is the chain id of the merge-mined block (upper 16 bits of its version).
+    pub fn check(&self, block_hash: &[u8; 32], chain_id: u32) -> Result<(), ConsensusError> {
+        if self.coinbase_branch.side_mask != 0 {
+            return Err(ConsensusError::AuxPow(
+                "parent coinbase is not the first transaction",
+            ));
+        }
+        if self.parent_header.value.version >> 16 == chain_id {
+            return Err(ConsensusError::AuxPow("parent block has our chain id"));
+        }
+        if self.blockchain_branch.hashes.len() > MAX_CHAIN_BRANCH_LEN {
+            return Err(ConsensusError::AuxPow("chain merkle branch too long"));
+        }
+        if self.coinbase_branch.root(self.coinbase_tx.hash) != self.parent_header.value.merkle_root
+        {
+            return Err(ConsensusError::AuxPow(
+                "parent coinbase is not part of the parent block",
+            ));
+        }
+
+        // The root is stored in reversed byte order
+        let mut chain_root = self.blockchain_branch.root(*block_hash);
+        chain_root.reverse();
+        let script = match self.coinbase_tx.value.inputs.first() {
+            Some(input) => &input.input.script_sig,
+            None => return Err(ConsensusError::AuxPow("parent coinbase has no inputs")),
+        };
+        let root_pos = match find(script, &chain_root) {
+            Some(pos) => pos,
+            None => {
+                return Err(ConsensusError::AuxPow(
+                    "chain merkle root is missing in the parent coinbase",
+                ))
+            }
+        };
+        match find(script, &MERGED_MINING_HEADER) {
+            Some(pos) => {
+                if find(&script[pos + 1..], &MERGED_MINING_HEADER).is_some() {
+                    return Err(ConsensusError::AuxPow(
+                        "multiple merged mining headers in the parent coinbase",
+                    ));
+                }
+                if pos + MERGED_MINING_HEADER.len() != root_pos {
+                    return Err(ConsensusError::AuxPow(
+                        "merged mining header is not followed by the chain merkle root",
+                    ));
+                }
+            }
+            // Legacy coinbases without header must start with the root
+            None if root_pos > 20 => {
+                return Err(ConsensusError::AuxPow(
+                    "chain merkle root must start in the first 20 bytes",
+                ));
+            }
+            None => {}
+        }
+
+        let tail = &script[root_pos + chain_root.len()..];
+        if tail.len() < 8 {
+            return Err(ConsensusError::AuxPow(
+                "chain merkle tree size and nonce are missing",
+            ));
+        }
+        let size = LittleEndian::read_u32(&tail[0..4]);
+        let nonce = LittleEndian::read_u32(&tail[4..8]);
+        let branch_len = self.blockchain_branch.hashes.len() as u32;
+        if size != 1 << branch_len {
+            return Err(ConsensusError::AuxPow(
+                "chain merkle tree size doesn't match the branch",
+            ));
+        }
+        if self.blockchain_branch.side_mask != expected_index(nonce, chain_id, branch_len) {
+            return Err(ConsensusError::AuxPow("wrong chain index"));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AuxPow {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AuxPow")
+            .field(
+                "coinbase_txid",
+                &utils::arr_to_hex_swapped(&self.coinbase_tx.hash),
+            )
+            .field("coinbase_branch", &self.coinbase_branch)
+            .field("blockchain_branch", &self.blockchain_branch)
+            .field("parent_header", &self.parent_header)
+            .finish()
+    }
+}
+
+/// Returns the slot of the chain in the chain merkle tree, see CAuxPow::getExpectedIndex()
+fn expected_index(nonce: u32, chain_id: u32, branch_len: u32) -> u32 {
+    let mut rand = nonce;
+    rand = rand.wrapping_mul(1103515245).wrapping_add(12345);
+    rand = rand.wrapping_add(chain_id);
+    rand = rand.wrapping_mul(1103515245).wrapping_add(12345);
+    rand % (1 << branch_len)
+}
+
+#[inline]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::consensus::{check_proof_of_work, PowAlgorithm};
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::proto::block::Block;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    // Synthetic merge-mined blocks with chain id 1 (Namecoin) and 0x62 (Dogecoin, scrypt parent),
+    // not mainnet blocks. They follow the AuxPoW serialization but use regtest difficulty,
+    // so the parent proof of work is cheap to build.
+    // The Dogecoin block is merge-mined in a chain merkle tree with four slots.
+    const NAMECOIN_AUXPOW_BLOCK: &str = "0101010011111111111111111111111111111111111111111111111111111111111111116fa6f50064e5d55d818ba7e1524d43d8fcecfab8277688c25800264d69371943004e7253ffff7f200000000001000000010000000000000000000000000000000000000000000000000000000000000000ffffffff3104ffff001dfabe6d6d2919832cc056a655791f06d5fbed7986a9a31a43b04db215d5ef6e43c79fa11a0100000007000000ffffffff01807c814a000000000151000000000000000000000000000000000000000000000000000000000000000000000000013333333333333333333333333333333333333333333333333333333333333333000000000000000000020000004444444444444444444444444444444444444444444444444444444444444444c54bcc18d57b4f8c5c6be980b7e161c3ce9fc93016c696ac19d6cff0b61a65e0004e7253ffff7f20000000000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0403010203ffffffff0100f2052a01000000015100000000";
+    const DOGECOIN_AUXPOW_BLOCK: &str = "0201620011111111111111111111111111111111111111111111111111111111111111116fa6f50064e5d55d818ba7e1524d43d8fcecfab8277688c25800264d69371943004e7253ffff7f200000000001000000010000000000000000000000000000000000000000000000000000000000000000ffffffff3104ffff001dfabe6d6d438dfe1810891665776e0df1b81a67193064911419556eacf649728e11d0186f0400000007000000ffffffff01807c814a00000000015100000000000000000000000000000000000000000000000000000000000000000000000001333333333333333333333333333333333333333333333333333333333333333300000000022222222222222222222222222222222222222222222222222222222222222222232323232323232323232323232323232323232323232323232323232323232303000000020000004444444444444444444444444444444444444444444444444444444444444444cd8cb602cd6024ae7528667651ed93f9245599758c652ef03c57612b5db4d019004e7253ffff7f20010000000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0403010203ffffffff0100f2052a01000000015100000000";
+
+    fn read_aux_block(hex: &str) -> Block {
+        let raw = utils::hex_to_vec(hex);
+        Cursor::new(&raw)
+            .read_block(raw.len() as u32, &Arc::default(), true)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_namecoin_auxpow() {
+        let block = read_aux_block(NAMECOIN_AUXPOW_BLOCK);
+        assert_eq!(
+            utils::arr_to_hex_swapped(&block.header.hash),
+            "2919832cc056a655791f06d5fbed7986a9a31a43b04db215d5ef6e43c79fa11a"
+        );
+        assert_eq!(block.txs.len(), 1);
+        assert!(block.verify_merkle_root().is_ok());
+
+        let aux_pow = block.header.value.aux_pow.as_ref().unwrap();
+        assert_eq!(
+            utils::arr_to_hex_swapped(&aux_pow.coinbase_tx.hash),
+            "072c436e1658aeb8f5e15475a441520a730756fd8d2fc1ff76d526aaac38612d"
+        );
+        assert_eq!(aux_pow.coinbase_branch.hashes, vec![[0x33; 32]]);
+        assert!(aux_pow.blockchain_branch.hashes.is_empty());
+        assert_eq!(
+            utils::arr_to_hex_swapped(&aux_pow.parent_header.hash),
+            "2216ab5ad3cf062f68d56b45e1ad722d023735f617db4bd687b2d59a992d1940"
+        );
+        assert_eq!(aux_pow.check(&block.header.hash, 1), Ok(()));
+        assert_eq!(
+            check_proof_of_work(&block.header, PowAlgorithm::Sha256d, Some(0x207fffff)),
+            Ok(())
+        );
+        // Regtest difficulty is above the mainnet limit
+        assert_eq!(
+            check_proof_of_work(&block.header, PowAlgorithm::Sha256d, Some(0x1d00ffff)),
+            Err(ConsensusError::InvalidTarget { bits: 0x207fffff })
+        );
+    }
+
+    #[test]
+    fn test_dogecoin_auxpow() {
+        let block = read_aux_block(DOGECOIN_AUXPOW_BLOCK);
+        assert_eq!(
+            utils::arr_to_hex_swapped(&block.header.hash),
+            "d4558ec0125a53f9a32ce42aea88fcaa81bcbce6a9b19f5668576acf336b1705"
+        );
+        let aux_pow = block.header.value.aux_pow.as_ref().unwrap();
+        assert_eq!(
+            aux_pow.blockchain_branch.hashes,
+            vec![[0x22; 32], [0x23; 32]]
+        );
+        assert_eq!(aux_pow.blockchain_branch.side_mask, 3);
+        assert_eq!(aux_pow.check(&block.header.hash, 0x62), Ok(()));
+        assert_eq!(
+            check_proof_of_work(&block.header, PowAlgorithm::Scrypt, Some(0x207fffff)),
+            Ok(())
+        );
+
+        // Wrong chain id and a block hash which isn't committed to
+        assert!(aux_pow.check(&block.header.hash, 0x63).is_err());
+        assert_eq!(
+            aux_pow.check(&[0u8; 32], 0x62),
+            Err(ConsensusError::AuxPow(
+                "chain merkle root is missing in the parent coinbase"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_expected_index() {
+        assert_eq!(expected_index(7, 0x62, 0), 0);
+        assert_eq!(expected_index(7, 0x62, 2), 3);
+    }
+}
@@ -2,7 +2,9 @@ use std::fmt;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
+use crate::blockchain::parser::consensus::ConsensusError;
 use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::mweb::MwebBlock;
 use crate::blockchain::proto::tx::{EvaluatedTx, RawTx};
 use crate::blockchain::proto::varuint::VarUint;
 use crate::blockchain::proto::Hashed;
@@ -14,6 +16,7 @@ pub struct Block {
     pub header: Hashed<BlockHeader>,
     pub tx_count: VarUint,
     pub txs: Vec<Hashed<EvaluatedTx>>,
+    pub mweb: Option<MwebBlock>, // Litecoin extension block, the last transaction is its HogEx
 }
 
 impl Block {
@@ -28,6 +31,7 @@ impl Block {
             header: Hashed::double_sha256(header),
             tx_count,
             txs,
+            mweb: None,
         }
     }
 
@@ -38,16 +42,15 @@ impl Block {
     }
 
     /// Calculates merkle root and verifies it against the field in BlockHeader.
-    /// panics if not valid.
-    pub fn verify_merkle_root(&self) {
+    pub fn verify_merkle_root(&self) -> Result<(), ConsensusError> {
         let merkle_root = self.compute_merkle_root();
         if merkle_root != self.header.value.merkle_root {
-            panic!(
-                "Invalid merkle_root!\n  -> expected: {}\n  -> got: {}\n",
-                &utils::arr_to_hex_swapped(&self.header.value.merkle_root),
-                &utils::arr_to_hex_swapped(&merkle_root)
-            );
+            return Err(ConsensusError::MerkleRoot {
+                expected: self.header.value.merkle_root,
+                got: merkle_root,
+            });
         }
+        Ok(())
     }
 }
 
@@ -56,6 +59,7 @@ impl fmt::Debug for Block {
         fmt.debug_struct("Block")
             .field("header", &self.header)
             .field("tx_count", &self.tx_count)
+            .field("mweb", &self.mweb)
             .finish()
     }
 }
@@ -77,6 +81,6 @@ mod tests {
         assert_eq!(get_base_reward(419999), 2500000000);
         assert_eq!(get_base_reward(420000), 1250000000);
         assert_eq!(get_base_reward(629999), 1250000000);
-        assert_eq!(get_base_reward(630000), 0625000000);
+        assert_eq!(get_base_reward(630000), 625000000);
     }
 }
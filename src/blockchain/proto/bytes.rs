@@ -0,0 +1,127 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+/// Immutable bytes of a script or witness item.
+/// Decoding from a shared buffer, e.g. a mapped blk file, doesn't copy the data: all slices refer
+/// to the buffer and keep it alive. Cloning is cheap.
+#[derive(Clone)]
+pub struct Bytes {
+    buf: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    start: usize,
+    end: usize,
+}
+
+impl Bytes {
+    /// Wraps a whole buffer
+    pub fn from_shared(buf: Arc<dyn AsRef<[u8]> + Send + Sync>) -> Bytes {
+        let end = (*buf).as_ref().len();
+        Bytes { buf, start: 0, end }
+    }
+
+    /// Returns a subslice sharing the same buffer, None if `range` is out of bounds
+    pub fn slice(&self, range: Range<usize>) -> Option<Bytes> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        Some(Bytes {
+            buf: Arc::clone(&self.buf),
+            start: self.start + range.start,
+            end: self.start + range.end,
+        })
+    }
+
+    /// Copies the bytes into their own buffer.
+    /// Use this before keeping bytes beyond the lifetime of their block, so the block buffer can be freed.
+    #[inline]
+    pub fn detach(&self) -> Bytes {
+        Bytes::from(self.to_vec())
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &(*self.buf).as_ref()[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Self {
+        Bytes::from(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Self {
+        Bytes::from_shared(Arc::new(data))
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(data: &[u8]) -> Self {
+        Bytes::from(data.to_vec())
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Bytes {}
+
+impl PartialEq<[u8]> for Bytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Bytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == other[..]
+    }
+}
+
+impl Hash for Bytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice() {
+        let bytes = Bytes::from(vec![1, 2, 3, 4, 5]);
+        let slice = bytes.slice(1..4).unwrap();
+        assert_eq!(slice, vec![2, 3, 4]);
+        assert_eq!(slice.slice(1..3).unwrap(), vec![3, 4]);
+        assert!(slice.slice(2..4).is_none());
+        assert!(Arc::ptr_eq(&slice.buf, &bytes.buf));
+
+        let detached = slice.detach();
+        assert_eq!(detached, slice);
+        assert!(!Arc::ptr_eq(&detached.buf, &bytes.buf));
+        assert!(Bytes::default().is_empty());
+    }
+}
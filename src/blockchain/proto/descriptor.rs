@@ -0,0 +1,418 @@
+use secp256k1::PublicKey;
+
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::interpreter::ANNEX_TAG;
+use crate::blockchain::proto::script::{self, AddressParams, EvaluatedScript, ScriptPattern};
+use crate::blockchain::proto::sighash::TAPROOT_LEAF_TAPSCRIPT;
+use crate::common::utils;
+
+/// Characters allowed in descriptors, ordered by their checksum value [BIP380]
+const INPUT_CHARSET: &[u8] =
+    b"0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Bare multisig descriptors with more keys are rejected by Bitcoin Core
+const MAX_BARE_MULTISIG_KEYS: usize = 3;
+
+/// Inferred descriptor with the keys it contains, each exactly as it appears on chain
+pub struct Descriptor {
+    /// Descriptor with checksum
+    pub desc: String,
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// Infers the descriptor of a scriptPubKey like Bitcoin Core's InferDescriptor, without any wallet data.
+/// Keys are only known for P2PK, bare multisig and taproot, which use pk(), multi() and rawtr().
+/// Hash based outputs become addr(), everything else raw(), see infer_spend() for their keys.
+pub fn infer(script_pubkey: &[u8], script: &EvaluatedScript) -> Descriptor {
+    let mut keys = Vec::new();
+    let desc = match script.pattern {
+        ScriptPattern::Pay2PublicKey => match script::Instructions::new(script_pubkey).next() {
+            Some(Ok(script::Instruction::PushBytes(_, key))) => {
+                key_expression(key, false, &mut keys).map(|key| format!("pk({})", key))
+            }
+            _ => None,
+        },
+        ScriptPattern::Pay2MultiSig {
+            keys: ref multisig_keys,
+            ..
+        } if multisig_keys.len() <= MAX_BARE_MULTISIG_KEYS => {
+            multi_expression(script_pubkey, false, &mut keys)
+        }
+        ScriptPattern::Pay2Taproot => xonly_key_expression(&script_pubkey[2..], &mut keys)
+            .map(|key| format!("rawtr({})", key)),
+        ScriptPattern::Pay2PublicKeyHash
+        | ScriptPattern::Pay2ScriptHash
+        | ScriptPattern::Pay2WitnessPublicKeyHash
+        | ScriptPattern::Pay2WitnessScriptHash
+        | ScriptPattern::WitnessUnknown(_) => script
+            .address
+            .as_ref()
+            .map(|address| format!("addr({})", address)),
+        _ => None,
+    };
+    match desc {
+        Some(desc) => Descriptor {
+            desc: with_checksum(&desc),
+            keys,
+        },
+        None => Descriptor {
+            desc: with_checksum(&format!("raw({})", utils::arr_to_hex(script_pubkey))),
+            keys: Vec::new(),
+        },
+    }
+}
+
+/// Infers the descriptor of the output spent by `script_sig` and `witness` from the keys they reveal:
+/// pkh() and wpkh(), sh(multi()) and wsh(multi()), the witness ones optionally nested in sh(),
+/// and tr() for script path spends of a single pk() leaf. The spent output isn't known, so the
+/// spending data is matched by its shape. Returns None if no key is revealed.
+pub fn infer_spend(script_sig: &[u8], witness: &[Bytes]) -> Option<Descriptor> {
+    let pushes = script::Instructions::new(script_sig)
+        .map(|i| match i {
+            Ok(script::Instruction::PushBytes(_, data)) => Some(data),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut keys = Vec::new();
+    let desc = if witness.is_empty() {
+        match pushes.as_slice() {
+            // <sig> <pubkey>
+            [_, key] if key.len() == 33 || key.len() == 65 => {
+                format!("pkh({})", key_expression(key, false, &mut keys)?)
+            }
+            // OP_0 <sig>... <redeemScript>
+            [&[], .., redeem_script] if pushes.len() >= 2 => {
+                format!("sh({})", multi_expression(redeem_script, false, &mut keys)?)
+            }
+            _ => return None,
+        }
+    } else if let Some((desc, tr_keys)) = tapscript_spend(witness).filter(|_| pushes.is_empty()) {
+        // Taproot can't be nested
+        keys = tr_keys;
+        desc
+    } else {
+        let (program, desc) = match witness {
+            // <sig> <pubkey>
+            [_, key] if key.len() == 33 => {
+                let program = [&[0x00, 0x14][..], &utils::ridemp160(&utils::sha256(key))].concat();
+                (
+                    program,
+                    format!("wpkh({})", key_expression(key, true, &mut keys)?),
+                )
+            }
+            // <> <sig>... <witnessScript>
+            [first, .., witness_script] if first.is_empty() => {
+                let program = [&[0x00, 0x20][..], &utils::sha256(witness_script)].concat();
+                (
+                    program,
+                    format!(
+                        "wsh({})",
+                        multi_expression(witness_script, true, &mut keys)?
+                    ),
+                )
+            }
+            _ => return None,
+        };
+        match pushes.as_slice() {
+            [] => desc,
+            // The redeem script must be the witness program
+            [redeem_script] if *redeem_script == program.as_slice() => format!("sh({})", desc),
+            _ => return None,
+        }
+    };
+    Some(Descriptor {
+        desc: with_checksum(&desc),
+        keys,
+    })
+}
+
+/// tr() expression of a taproot script path spend with a single `<key> OP_CHECKSIG` leaf.
+/// Trees with more leaves only reveal the hashes of the others and can't be expressed.
+fn tapscript_spend(witness: &[Bytes]) -> Option<(String, Vec<Vec<u8>>)> {
+    let mut items = witness;
+    if items.len() >= 2 && items.last()?.first() == Some(&ANNEX_TAG) {
+        items = &items[..items.len() - 1];
+    }
+    let (leaf_script, control) = match items {
+        [.., leaf_script, control] => (leaf_script, control),
+        _ => return None,
+    };
+    if control.len() != 33 || control[0] & 0xfe != TAPROOT_LEAF_TAPSCRIPT {
+        return None;
+    }
+    if leaf_script.len() != 34 || leaf_script[0] != 0x20 || leaf_script[33] != 0xac {
+        return None;
+    }
+    let mut keys = Vec::new();
+    let internal_key = xonly_key_expression(&control[1..], &mut keys)?;
+    let leaf_key = xonly_key_expression(&leaf_script[1..33], &mut keys)?;
+    Some((format!("tr({},pk({}))", internal_key, leaf_key), keys))
+}
+
+/// multi() expression of a multisig script, segwit scripts only allow compressed keys
+fn multi_expression(script: &[u8], segwit: bool, keys: &mut Vec<Vec<u8>>) -> Option<String> {
+    match script::eval_from_bytes(script, &AddressParams::default()).pattern {
+        ScriptPattern::Pay2MultiSig {
+            m,
+            keys: ref multisig_keys,
+            ..
+        } => {
+            let exprs = multisig_keys
+                .iter()
+                .map(|key| key_expression(key, segwit, keys))
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("multi({},{})", m, exprs.join(",")))
+        }
+        _ => None,
+    }
+}
+
+/// Lowercase hex of a valid key, kept in its encoding so the descriptor reproduces the script.
+/// Hybrid keys can't be expressed in descriptors and segwit scripts only allow compressed keys.
+fn key_expression(key: &[u8], segwit: bool, keys: &mut Vec<Vec<u8>>) -> Option<String> {
+    let encoding_allowed = match key.first() {
+        Some(0x02) | Some(0x03) => key.len() == 33,
+        Some(0x04) => key.len() == 65 && !segwit,
+        _ => false,
+    };
+    if !encoding_allowed || PublicKey::from_slice(key).is_err() {
+        return None;
+    }
+    keys.push(key.to_vec());
+    Some(utils::arr_to_hex(key))
+}
+
+fn xonly_key_expression(key: &[u8], keys: &mut Vec<Vec<u8>>) -> Option<String> {
+    secp256k1::schnorrsig::PublicKey::from_slice(key).ok()?;
+    keys.push(key.to_vec());
+    Some(utils::arr_to_hex(key))
+}
+
+/// Appends `#` and the checksum of `desc`
+pub fn with_checksum(desc: &str) -> String {
+    match checksum(desc) {
+        Some(checksum) => format!("{}#{}", desc, checksum),
+        None => String::from(desc),
+    }
+}
+
+/// Computes the 8 character checksum [BIP380], None if `desc` contains invalid characters
+pub fn checksum(desc: &str) -> Option<String> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut cls_count = 0;
+    for ch in desc.bytes() {
+        let pos = INPUT_CHARSET.iter().position(|&x| x == ch)? as u64;
+        // Symbol within its group of 32 characters, the groups are checksummed in triples
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    Some(
+        (0..8)
+            .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+fn poly_mod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 != 0 {
+        c ^= 0xf5dee51989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9fdca3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1bab10e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x3706b1677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x644d626ffd;
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::{eval_from_bytes, AddressParams};
+
+    const KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const UNCOMPRESSED_KEY: &str = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+                                    483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+
+    fn infer_hex(hex: &str) -> String {
+        let script_pubkey = utils::hex_to_vec(hex);
+        infer(
+            &script_pubkey,
+            &eval_from_bytes(&script_pubkey, &AddressParams::default()),
+        )
+        .desc
+    }
+
+    fn infer_spend_hex(script_sig: &str, witness: &[&str]) -> Option<String> {
+        let witness: Vec<_> = witness
+            .iter()
+            .map(|item| Bytes::from(utils::hex_to_vec(item)))
+            .collect();
+        infer_spend(&utils::hex_to_vec(script_sig), &witness).map(|descriptor| descriptor.desc)
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(with_checksum("raw(deadbeef)"), "raw(deadbeef)#89f8spxm");
+        assert_eq!(checksum(&format!("pk({})", KEY)).unwrap(), "gn28ywm7");
+        assert_eq!(checksum("raw(\u{e9})"), None);
+    }
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(
+            infer_hex(&format!("21{}ac", KEY)),
+            format!("pk({})#gn28ywm7", KEY)
+        );
+        let multisig = infer_hex(&format!("5121{0}21{0}52ae", KEY));
+        assert!(
+            multisig.starts_with(&format!("multi(1,{0},{0})#", KEY)),
+            "{}",
+            multisig
+        );
+        assert_eq!(
+            infer_hex("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac"),
+            with_checksum("addr(1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH)")
+        );
+        assert_eq!(
+            infer_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+            with_checksum("addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)")
+        );
+        assert_eq!(
+            infer_hex(&format!("5120{}", &KEY[2..])),
+            with_checksum(&format!("rawtr({})", &KEY[2..]))
+        );
+        assert_eq!(
+            infer_hex("6a0474657374"),
+            with_checksum("raw(6a0474657374)")
+        );
+
+        // Keys which aren't on the curve, hybrid keys and big bare multisigs
+        assert_eq!(
+            infer_hex(&format!("2102{}ac", "00".repeat(32))),
+            with_checksum(&format!("raw(2102{}ac)", "00".repeat(32)))
+        );
+        assert!(infer_hex(&format!("21{}ac", KEY.replacen("02", "06", 1))).starts_with("raw("));
+        assert!(infer_hex(&format!("5121{0}21{0}21{0}21{0}54ae", KEY)).starts_with("raw("));
+    }
+
+    #[test]
+    fn test_infer_spend() {
+        let sig = "30".repeat(71);
+        let multisig = format!("5121{}51ae", KEY);
+        assert_eq!(
+            infer_spend_hex(&format!("47{}21{}", sig, KEY), &[]),
+            Some(with_checksum(&format!("pkh({})", KEY)))
+        );
+        assert_eq!(
+            infer_spend_hex("", &[&sig, KEY]),
+            Some(with_checksum(&format!("wpkh({})", KEY)))
+        );
+        assert_eq!(
+            infer_spend_hex(
+                "160014751e76e8199196d454941c45d1b3a323f1433bd6",
+                &[&sig, KEY]
+            ),
+            Some(with_checksum(&format!("sh(wpkh({}))", KEY)))
+        );
+        assert_eq!(
+            infer_spend_hex(&format!("0047{}25{}", sig, multisig), &[]),
+            Some(with_checksum(&format!("sh(multi(1,{}))", KEY)))
+        );
+        assert_eq!(
+            infer_spend_hex("", &["", &sig, &multisig]),
+            Some(with_checksum(&format!("wsh(multi(1,{}))", KEY)))
+        );
+        let xonly = &KEY[2..];
+        assert_eq!(
+            infer_spend_hex(
+                "",
+                &[&sig, &format!("20{}ac", xonly), &format!("c0{}", xonly)]
+            ),
+            Some(with_checksum(&format!("tr({0},pk({0}))", xonly)))
+        );
+
+        // P2PK and taproot key path spends, a nested program not matching the witness,
+        // taproot trees with more leaves
+        assert_eq!(infer_spend_hex(&format!("47{}", sig), &[]), None);
+        assert_eq!(infer_spend_hex("", &[&"30".repeat(64)]), None);
+        assert_eq!(
+            infer_spend_hex(&format!("1600140000{}", "00".repeat(18)), &[&sig, KEY]),
+            None
+        );
+        let control = format!("c0{}{}", xonly, "11".repeat(32));
+        assert_eq!(
+            infer_spend_hex("", &[&sig, &format!("20{}ac", xonly), &control]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_on_chain_keys() {
+        // Uncompressed keys are kept, so the descriptor reproduces the script
+        let sig = "30".repeat(71);
+        let script_pubkey = utils::hex_to_vec(&format!("41{}ac", UNCOMPRESSED_KEY));
+        let pk = infer(
+            &script_pubkey,
+            &eval_from_bytes(&script_pubkey, &AddressParams::default()),
+        );
+        assert_eq!(pk.desc, with_checksum(&format!("pk({})", UNCOMPRESSED_KEY)));
+        assert_eq!(pk.keys, vec![utils::hex_to_vec(UNCOMPRESSED_KEY)]);
+        assert_eq!(
+            infer_spend_hex(&format!("47{}41{}", sig, UNCOMPRESSED_KEY), &[]),
+            Some(with_checksum(&format!("pkh({})", UNCOMPRESSED_KEY)))
+        );
+        assert_eq!(
+            infer_hex(&format!("5141{}21{}52ae", UNCOMPRESSED_KEY, KEY)),
+            with_checksum(&format!("multi(1,{},{})", UNCOMPRESSED_KEY, KEY))
+        );
+
+        let xonly = &KEY[2..];
+        let witness: Vec<_> = [
+            sig.clone(),
+            format!("20{}ac", xonly),
+            format!("c0{}", xonly),
+        ]
+        .iter()
+        .map(|item| Bytes::from(utils::hex_to_vec(item)))
+        .collect();
+        let tr = infer_spend(&[], &witness).unwrap();
+        assert_eq!(
+            tr.keys,
+            vec![utils::hex_to_vec(xonly), utils::hex_to_vec(xonly)]
+        );
+
+        // Hybrid keys and uncompressed keys in segwit scripts can't be expressed
+        let hybrid = UNCOMPRESSED_KEY.replacen("04", "06", 1);
+        assert!(infer_hex(&format!("41{}ac", hybrid)).starts_with("raw("));
+        assert_eq!(
+            infer_spend_hex("", &["", &sig, &format!("5141{}51ae", UNCOMPRESSED_KEY)]),
+            None
+        );
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt;
+use std::sync::Arc;
 
+use crate::blockchain::proto::auxpow::AuxPow;
 use crate::blockchain::proto::ToRaw;
 use crate::common::utils;
 
-/// Block Header definition. Exact 80 bytes long
+/// Block Header definition. Exact 80 bytes long,
+/// merge-mined blocks are followed by an AuxPoW which is not part of the block hash.
 #[derive(Clone)]
 pub struct BlockHeader {
     pub version: u32,
@@ -12,6 +15,7 @@ pub struct BlockHeader {
     pub timestamp: u32,
     pub bits: u32,
     pub nonce: u32,
+    pub aux_pow: Option<Arc<AuxPow>>,
 }
 
 impl BlockHeader {
@@ -30,6 +34,7 @@ impl BlockHeader {
             timestamp,
             bits,
             nonce,
+            aux_pow: None,
         }
     }
 }
@@ -56,6 +61,7 @@ impl fmt::Debug for BlockHeader {
             .field("timestamp", &self.timestamp)
             .field("bits", &self.bits)
             .field("nonce", &self.nonce)
+            .field("aux_pow", &self.aux_pow)
             .finish()
     }
 }
@@ -0,0 +1,324 @@
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::interpreter::ANNEX_TAG;
+use crate::blockchain::proto::opcodes;
+use crate::blockchain::proto::script::{Instruction, Instructions};
+use crate::blockchain::proto::sighash::TAPROOT_LEAF_TAPSCRIPT;
+
+/// Marks an envelope as an inscription: OP_FALSE OP_IF "ord" ... OP_ENDIF
+pub const PROTOCOL_ID: &[u8] = b"ord";
+
+/// Field tags, see https://docs.ordinals.com/inscriptions.html
+pub const TAG_CONTENT_TYPE: u8 = 1;
+pub const TAG_POINTER: u8 = 2;
+pub const TAG_PARENT: u8 = 3;
+pub const TAG_METADATA: u8 = 5;
+pub const TAG_METAPROTOCOL: u8 = 7;
+pub const TAG_CONTENT_ENCODING: u8 = 9;
+pub const TAG_DELEGATE: u8 = 11;
+
+/// Inscription parsed from an envelope in a tapscript
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Inscription {
+    /// Tag and value pushes in envelope order, repeated tags are kept separately
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Concatenated pushes after the empty body separator
+    pub body: Option<Vec<u8>>,
+    /// The last tag had no value
+    pub incomplete_field: bool,
+    /// OP_1NEGATE or OP_1 to OP_16 were used as pushes
+    pub pushnum: bool,
+}
+
+impl Inscription {
+    /// Value of the first field with a single byte `tag`
+    pub fn field(&self, tag: u8) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(t, _)| t.as_slice() == [tag])
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// MIME type of the body, e.g. "text/plain;charset=utf-8"
+    #[inline]
+    pub fn content_type(&self) -> Option<&[u8]> {
+        self.field(TAG_CONTENT_TYPE)
+    }
+
+    #[inline]
+    pub fn content_encoding(&self) -> Option<&[u8]> {
+        self.field(TAG_CONTENT_ENCODING)
+    }
+
+    #[inline]
+    pub fn metaprotocol(&self) -> Option<&[u8]> {
+        self.field(TAG_METAPROTOCOL)
+    }
+
+    /// Output offset of the inscribed sat, a little endian number.
+    /// Values which don't fit into an u64 are ignored like in the ord indexer.
+    pub fn pointer(&self) -> Option<u64> {
+        let value = self.field(TAG_POINTER)?;
+        if value.len() > 8 && value[8..].iter().any(|&b| b != 0) {
+            return None;
+        }
+        Some(
+            value
+                .iter()
+                .take(8)
+                .rev()
+                .fold(0, |acc, &b| (acc << 8) | b as u64),
+        )
+    }
+
+    /// Parent inscriptions as txid and envelope index. Each value is the txid in internal byte order
+    /// followed by the little endian index with trailing zeros omitted.
+    pub fn parents(&self) -> Vec<([u8; 32], u32)> {
+        self.fields
+            .iter()
+            .filter(|(t, value)| t.as_slice() == [TAG_PARENT] && (32..=36).contains(&value.len()))
+            .map(|(_, value)| {
+                let mut txid = [0u8; 32];
+                txid.copy_from_slice(&value[..32]);
+                let index = value[32..]
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, &b| (acc << 8) | b as u32);
+                (txid, index)
+            })
+            .collect()
+    }
+
+    /// CBOR metadata. It may exceed the push limit and is split into several fields.
+    pub fn metadata(&self) -> Option<Vec<u8>> {
+        let mut chunks = self
+            .fields
+            .iter()
+            .filter(|(t, _)| t.as_slice() == [TAG_METADATA])
+            .peekable();
+        chunks.peek()?;
+        Some(
+            chunks
+                .flat_map(|(_, value)| value.iter().cloned())
+                .collect(),
+        )
+    }
+
+    /// Unknown even tags make the inscription "cursed", the ord indexer doesn't assign it a sat
+    pub fn has_unrecognized_even_field(&self) -> bool {
+        self.fields.iter().any(|(tag, _)| match tag.as_slice() {
+            [TAG_POINTER] | [TAG_PARENT] | [TAG_DELEGATE] => false,
+            // Tags are little endian numbers, empty ones are zero
+            [] => true,
+            tag => tag[0].is_multiple_of(2),
+        })
+    }
+}
+
+/// Returns the leaf script of a taproot script path spend [BIP341].
+/// The spent output isn't known here, so any witness ending with a tapscript control block matches.
+pub fn tapscript(witness: &[Bytes]) -> Option<&[u8]> {
+    let mut items = witness;
+    if items.len() >= 2 && items.last()?.first() == Some(&ANNEX_TAG) {
+        items = &items[..items.len() - 1];
+    }
+    if items.len() < 2 {
+        return None;
+    }
+    let control = &items[items.len() - 1];
+    if control.len() < 33
+        || !(control.len() - 33).is_multiple_of(32)
+        || control[0] & 0xfe != TAPROOT_LEAF_TAPSCRIPT
+    {
+        return None;
+    }
+    Some(&items[items.len() - 2])
+}
+
+/// Parses all inscription envelopes of a tapscript, like the ord indexer.
+/// Envelopes containing anything but pushes and unterminated ones are ignored.
+pub fn from_tapscript(script: &[u8]) -> Vec<Inscription> {
+    let mut inscriptions = Vec::new();
+    let mut instructions = Instructions::new(script);
+    while let Some(Ok(instruction)) = instructions.next() {
+        if let Instruction::PushBytes(_, &[]) = instruction {
+            if let Some(inscription) = parse_envelope(&mut instructions) {
+                inscriptions.push(inscription);
+            }
+        }
+    }
+    inscriptions
+}
+
+/// Parses the inscriptions of an input, empty for anything but tapscript spends
+#[inline]
+pub fn from_witness(witness: &[Bytes]) -> Vec<Inscription> {
+    match tapscript(witness) {
+        Some(script) => from_tapscript(script),
+        None => Vec::new(),
+    }
+}
+
+/// Continues after OP_FALSE. The header is only peeked at, so the OP_FALSE of an envelope
+/// right after another OP_FALSE isn't skipped. Within the envelope, the instructions are consumed
+/// up to the first mismatch.
+fn parse_envelope(instructions: &mut Instructions) -> Option<Inscription> {
+    let mut header = instructions.clone();
+    if header.next()?.ok()? != Instruction::Op(opcodes::All::OP_IF) {
+        return None;
+    }
+    match header.next()?.ok()? {
+        Instruction::PushBytes(_, id) if id == PROTOCOL_ID => {}
+        _ => return None,
+    }
+    *instructions = header;
+
+    let mut inscription = Inscription::default();
+    let mut pushes = Vec::new();
+    loop {
+        match instructions.next()?.ok()? {
+            Instruction::Op(opcodes::All::OP_ENDIF) => break,
+            Instruction::Op(opcodes::All::OP_PUSHNUM_NEG1) => {
+                inscription.pushnum = true;
+                pushes.push(vec![0x81]);
+            }
+            Instruction::Op(op) => match op.classify() {
+                opcodes::Class::PushNum(n) if (1..=16).contains(&n) => {
+                    inscription.pushnum = true;
+                    pushes.push(vec![n as u8]);
+                }
+                _ => return None,
+            },
+            Instruction::PushBytes(_, data) => pushes.push(data.to_vec()),
+        }
+    }
+
+    let mut pushes = pushes.into_iter();
+    while let Some(tag) = pushes.next() {
+        if tag.is_empty() {
+            inscription.body = Some(pushes.flatten().collect());
+            break;
+        }
+        match pushes.next() {
+            Some(value) => inscription.fields.push((tag, value)),
+            None => inscription.incomplete_field = true,
+        }
+    }
+    Some(inscription)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    fn witness(items: &[&[u8]]) -> Vec<Bytes> {
+        items.iter().map(|item| Bytes::from(*item)).collect()
+    }
+
+    /// Wraps `payload` (hex) into an envelope
+    fn envelope(payload: &str) -> Vec<u8> {
+        utils::hex_to_vec(&format!("0063036f7264{}68", payload))
+    }
+
+    #[test]
+    fn test_parse_envelope() {
+        // OP_FALSE OP_IF "ord" 1 "text/plain" 5 a1 5 02 OP_0 "Hello, " "world!" OP_ENDIF
+        let script =
+            envelope("01010a746578742f706c61696e010501a101050102000748656c6c6f2c2006776f726c6421");
+        let inscriptions = from_tapscript(&script);
+        assert_eq!(inscriptions.len(), 1);
+        let inscription = &inscriptions[0];
+        assert_eq!(inscription.content_type(), Some(&b"text/plain"[..]));
+        assert_eq!(inscription.metadata(), Some(vec![0xa1, 0x02]));
+        assert_eq!(inscription.body.as_deref(), Some(&b"Hello, world!"[..]));
+        assert!(!inscription.incomplete_field);
+        assert!(!inscription.pushnum);
+        assert!(!inscription.has_unrecognized_even_field());
+        assert_eq!(inscription.pointer(), None);
+        assert!(inscription.parents().is_empty());
+
+        // Pushnum tags, an unrecognized even tag and no body
+        let inscription = &from_tapscript(&envelope("510161010401ff"))[0];
+        assert_eq!(inscription.content_type(), Some(&b"a"[..]));
+        assert!(inscription.pushnum);
+        assert!(inscription.has_unrecognized_even_field());
+        assert_eq!(inscription.body, None);
+
+        // A missing value and two envelopes in one script
+        let script = [envelope("0101"), envelope("00")].concat();
+        let inscriptions = from_tapscript(&script);
+        assert_eq!(inscriptions.len(), 2);
+        assert!(inscriptions[0].incomplete_field);
+        assert_eq!(inscriptions[1].body, Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_tags() {
+        // 2 0x0201, 3 <txid> 0x01, 3 <txid>, 7 "brc-20", 9 "br"
+        let txid = "11".repeat(32);
+        let script = envelope(&format!(
+            "0102020102010321{0}01010320{0}0107066272632d3230010902627200",
+            txid
+        ));
+        let inscription = &from_tapscript(&script)[0];
+        assert_eq!(inscription.pointer(), Some(0x0201));
+        assert_eq!(
+            inscription.parents(),
+            vec![([0x11; 32], 1), ([0x11; 32], 0)]
+        );
+        assert_eq!(inscription.metaprotocol(), Some(&b"brc-20"[..]));
+        assert_eq!(inscription.content_encoding(), Some(&b"br"[..]));
+
+        // Pointers beyond u64 are ignored, trailing zeros aren't
+        let inscription = &from_tapscript(&envelope(&format!("010209{}0100", "00".repeat(8))))[0];
+        assert_eq!(inscription.pointer(), None);
+        let inscription = &from_tapscript(&envelope(&format!("01020905{}00", "00".repeat(8))))[0];
+        assert_eq!(inscription.pointer(), Some(5));
+    }
+
+    #[test]
+    fn test_ignored_envelopes() {
+        // Wrong protocol id, opcodes inside the envelope, missing OP_ENDIF
+        assert!(from_tapscript(&utils::hex_to_vec("0063036f726568")).is_empty());
+        assert!(from_tapscript(&envelope("010175")).is_empty());
+        assert!(from_tapscript(&utils::hex_to_vec("0063036f726400")).is_empty());
+        // Parsing continues after a broken envelope
+        let script = [utils::hex_to_vec("0063036f7264ac"), envelope("00")].concat();
+        assert_eq!(from_tapscript(&script).len(), 1);
+        // OP_FALSE right before an envelope
+        let script = [vec![0x00], envelope("00")].concat();
+        assert_eq!(from_tapscript(&script).len(), 1);
+        let script = [utils::hex_to_vec("0063"), envelope("00")].concat();
+        assert_eq!(from_tapscript(&script).len(), 1);
+    }
+
+    #[test]
+    fn test_tapscript() {
+        let script = envelope("00");
+        let control = [&[0xc1][..], &[0x11; 32]].concat();
+        let signature = vec![0x22; 64];
+        let witness = witness(&[&signature, &script, &control]);
+        assert_eq!(tapscript(&witness), Some(&script[..]));
+        assert_eq!(from_witness(&witness).len(), 1);
+
+        let annexed = self::witness(&[&signature, &script, &control, &[ANNEX_TAG]]);
+        assert_eq!(tapscript(&annexed), Some(&script[..]));
+
+        // Key path spends, unknown leaf versions and segwit v0
+        assert_eq!(tapscript(&self::witness(&[&signature])), None);
+        assert_eq!(
+            tapscript(&self::witness(&[
+                &script,
+                &[&[0xc2][..], &[0x11; 32]].concat()
+            ])),
+            None
+        );
+        assert_eq!(
+            tapscript(&self::witness(&[
+                &signature,
+                &utils::hex_to_vec("03aabbcc")
+            ])),
+            None
+        );
+    }
+}
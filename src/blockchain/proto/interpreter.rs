@@ -0,0 +1,1622 @@
+use std::error;
+use std::fmt;
+use std::sync::OnceLock;
+
+use secp256k1::{self, schnorrsig, Message, PublicKey, Secp256k1, Signature};
+
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::opcodes::All;
+use crate::blockchain::proto::script::{self, Instruction, Instructions};
+use crate::blockchain::proto::sighash::{self, SighashCache, SIGHASH_DEFAULT};
+use crate::blockchain::proto::timelock;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::ToRaw;
+use crate::common::utils;
+
+/// Evaluate P2SH subscripts [BIP16]
+pub const VERIFY_P2SH: u32 = 1 << 0;
+/// Enforce strict DER signatures [BIP66]
+pub const VERIFY_DERSIG: u32 = 1 << 2;
+/// The dummy element of OP_CHECKMULTISIG must be empty [BIP147]
+pub const VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// OP_CHECKLOCKTIMEVERIFY [BIP65]
+pub const VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// OP_CHECKSEQUENCEVERIFY [BIP112]
+pub const VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+/// Segregated witness [BIP141, BIP143]
+pub const VERIFY_WITNESS: u32 = 1 << 11;
+/// Taproot and tapscript [BIP341, BIP342]
+pub const VERIFY_TAPROOT: u32 = 1 << 17;
+
+/// Maximum size of a pushed element
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of non-push operations per script, not enforced in tapscripts
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of elements on the stack and altstack together
+pub const MAX_STACK_SIZE: usize = 1000;
+
+// Script numbers are signed, so the comparisons happen in i64
+const LOCKTIME_THRESHOLD: i64 = timelock::LOCKTIME_THRESHOLD as i64;
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = timelock::SEQUENCE_LOCKTIME_DISABLE_FLAG as i64;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = timelock::SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+const SEQUENCE_LOCKTIME_MASK: i64 = timelock::SEQUENCE_LOCKTIME_MASK as i64;
+
+/// First byte of the optional last witness item of taproot spends [BIP341]
+pub const ANNEX_TAG: u8 = 0x50;
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// OP_CHECKSIGADD [BIP342] takes the place of OP_RETURN_186
+const OP_CHECKSIGADD: All = All::OP_RETURN_186;
+
+/// Reasons for a script to fail, named after Bitcoin Core's ScriptError
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    EvalFalse,
+    OpReturn,
+    ScriptSize,
+    PushSize,
+    OpCount,
+    StackSize,
+    SigCount,
+    PubkeyCount,
+    Verify,
+    EqualVerify,
+    CheckMultisigVerify,
+    CheckSigVerify,
+    NumEqualVerify,
+    BadOpcode,
+    DisabledOpcode,
+    InvalidStackOperation,
+    InvalidAltstackOperation,
+    UnbalancedConditional,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+    SigDer,
+    SigPushOnly,
+    SigNullDummy,
+    PubkeyType,
+    CleanStack,
+    WitnessProgramWrongLength,
+    WitnessProgramWitnessEmpty,
+    WitnessProgramMismatch,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    SchnorrSigSize,
+    SchnorrSigHashtype,
+    SchnorrSig,
+    TaprootWrongControlSize,
+    TapscriptValidationWeight,
+    TapscriptCheckMultisig,
+    TapscriptMinimalIf,
+    /// Numeric operand longer than 4 bytes (5 for the locktime opcodes)
+    NumOverflow,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            VerifyError::EvalFalse => {
+                "Script evaluated without error but finished with a false/empty top stack element"
+            }
+            VerifyError::OpReturn => "OP_RETURN was encountered",
+            VerifyError::ScriptSize => "Script is too big",
+            VerifyError::PushSize => "Push value size limit exceeded",
+            VerifyError::OpCount => "Operation limit exceeded",
+            VerifyError::StackSize => "Stack size limit exceeded",
+            VerifyError::SigCount => "Signature count negative or greater than pubkey count",
+            VerifyError::PubkeyCount => "Pubkey count negative or limit exceeded",
+            VerifyError::Verify => "Script failed an OP_VERIFY operation",
+            VerifyError::EqualVerify => "Script failed an OP_EQUALVERIFY operation",
+            VerifyError::CheckMultisigVerify => "Script failed an OP_CHECKMULTISIGVERIFY operation",
+            VerifyError::CheckSigVerify => "Script failed an OP_CHECKSIGVERIFY operation",
+            VerifyError::NumEqualVerify => "Script failed an OP_NUMEQUALVERIFY operation",
+            VerifyError::BadOpcode => "Opcode missing or not understood",
+            VerifyError::DisabledOpcode => "Attempted to use a disabled opcode",
+            VerifyError::InvalidStackOperation => "Operation not valid with the current stack size",
+            VerifyError::InvalidAltstackOperation => {
+                "Operation not valid with the current altstack size"
+            }
+            VerifyError::UnbalancedConditional => "Invalid OP_IF construction",
+            VerifyError::NegativeLocktime => "Negative locktime",
+            VerifyError::UnsatisfiedLocktime => "Locktime requirement not satisfied",
+            VerifyError::SigDer => "Non-canonical DER signature",
+            VerifyError::SigPushOnly => "Only push operators allowed in signatures",
+            VerifyError::SigNullDummy => "Dummy CHECKMULTISIG argument must be zero",
+            VerifyError::PubkeyType => "Public key is neither compressed or uncompressed",
+            VerifyError::CleanStack => "Stack size must be exactly one after execution",
+            VerifyError::WitnessProgramWrongLength => "Witness program has incorrect length",
+            VerifyError::WitnessProgramWitnessEmpty => {
+                "Witness program was passed an empty witness"
+            }
+            VerifyError::WitnessProgramMismatch => "Witness program hash mismatch",
+            VerifyError::WitnessMalleated => "Witness requires empty scriptSig",
+            VerifyError::WitnessMalleatedP2sh => "Witness requires only-redeemscript scriptSig",
+            VerifyError::WitnessUnexpected => "Witness provided for non-witness script",
+            VerifyError::SchnorrSigSize => "Invalid Schnorr signature size",
+            VerifyError::SchnorrSigHashtype => "Invalid Schnorr signature hash type",
+            VerifyError::SchnorrSig => "Invalid Schnorr signature",
+            VerifyError::TaprootWrongControlSize => "Invalid Taproot control block size",
+            VerifyError::TapscriptValidationWeight => {
+                "Too much signature validation relative to witness weight"
+            }
+            VerifyError::TapscriptCheckMultisig => {
+                "OP_CHECKMULTISIG(VERIFY) is not available in tapscript"
+            }
+            VerifyError::TapscriptMinimalIf => "OP_IF/NOTIF argument must be minimal in tapscript",
+            VerifyError::NumOverflow => "Script number overflow",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl error::Error for VerifyError {}
+
+/// Signature which was successfully checked against a public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckedSignature {
+    pub pubkey: Vec<u8>, // 33 or 65 bytes for ECDSA, 32 byte x-only key for Schnorr
+    pub signature: Vec<u8>, // Including the hash type byte, if any
+    pub sighash: [u8; 32],
+    pub schnorr: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+    Taproot,
+    Tapscript,
+}
+
+/// Per input state of taproot spends, see ScriptExecutionData
+struct ExecData {
+    annex_hash: Option<[u8; 32]>,
+    tapleaf_hash: [u8; 32],
+    codesep_pos: u32,
+    validation_weight_left: i64,
+}
+
+impl Default for ExecData {
+    fn default() -> Self {
+        ExecData {
+            annex_hash: None,
+            tapleaf_hash: [0u8; 32],
+            codesep_pos: 0xffffffff,
+            validation_weight_left: 0,
+        }
+    }
+}
+
+/// Shared context, schnorrsig_verify of secp256k1 0.20 requires a signing capable one
+fn secp() -> &'static Secp256k1<secp256k1::All> {
+    static SECP: OnceLock<Secp256k1<secp256k1::All>> = OnceLock::new();
+    SECP.get_or_init(Secp256k1::new)
+}
+
+/// Verifies the inputs of a transaction, a port of Bitcoin Core's VerifyScript.
+/// Only consensus rules are enforced, policy rules like low S values or minimal pushes are not.
+pub struct TxVerifier<'a> {
+    cache: SighashCache<'a>,
+}
+
+impl<'a> TxVerifier<'a> {
+    /// `spent` holds the output spent by each input of `tx`
+    pub fn new(tx: &'a EvaluatedTx, spent: Vec<&'a TxOutput>) -> Self {
+        TxVerifier {
+            cache: SighashCache::new(tx, spent),
+        }
+    }
+
+    #[inline]
+    pub fn tx(&self) -> &'a EvaluatedTx {
+        self.cache.tx()
+    }
+
+    /// Verifies input `vin` under the given `VERIFY_*` flags.
+    /// Returns the signatures which checked out, valid scripts may also contain failing signature checks.
+    pub fn verify_input(
+        &self,
+        vin: usize,
+        flags: u32,
+    ) -> Result<Vec<CheckedSignature>, VerifyError> {
+        let input = &self.tx().inputs[vin].input;
+        let mut interpreter = Interpreter {
+            cache: &self.cache,
+            vin,
+            flags,
+            checked: Vec::new(),
+        };
+        interpreter.verify_script(
+            &input.script_sig,
+            &self.cache.spent(vin).script_pubkey,
+            &input.witness,
+        )?;
+        Ok(interpreter.checked)
+    }
+}
+
+struct Interpreter<'a, 'b> {
+    cache: &'b SighashCache<'a>,
+    vin: usize,
+    flags: u32,
+    checked: Vec<CheckedSignature>,
+}
+
+type Stack = Vec<Vec<u8>>;
+
+impl<'a, 'b> Interpreter<'a, 'b> {
+    #[inline]
+    fn flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn verify_script(
+        &mut self,
+        script_sig: &[u8],
+        script_pubkey: &[u8],
+        witness: &[Bytes],
+    ) -> Result<(), VerifyError> {
+        let mut stack = Stack::new();
+        let mut exec = ExecData::default();
+        self.eval_script(&mut stack, script_sig, SigVersion::Base, &mut exec)?;
+        let stack_copy = if self.flag(VERIFY_P2SH) {
+            stack.clone()
+        } else {
+            Stack::new()
+        };
+        self.eval_script(&mut stack, script_pubkey, SigVersion::Base, &mut exec)?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(VerifyError::EvalFalse);
+        }
+
+        let mut had_witness = false;
+        if self.flag(VERIFY_WITNESS) {
+            if let Some((version, program)) = script::witness_program(script_pubkey) {
+                had_witness = true;
+                if !script_sig.is_empty() {
+                    return Err(VerifyError::WitnessMalleated);
+                }
+                self.verify_witness_program(witness, version, program, false)?;
+                // The actual stack is obviously not clean for witness programs
+                stack.truncate(1);
+            }
+        }
+
+        if self.flag(VERIFY_P2SH) && is_p2sh(script_pubkey) {
+            if !is_push_only(script_sig) {
+                return Err(VerifyError::SigPushOnly);
+            }
+            // The scriptPubKey evaluation above fails on an empty stack
+            stack = stack_copy;
+            let redeem_script = stack.pop().unwrap();
+            self.eval_script(&mut stack, &redeem_script, SigVersion::Base, &mut exec)?;
+            if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+                return Err(VerifyError::EvalFalse);
+            }
+            if self.flag(VERIFY_WITNESS) {
+                if let Some((version, program)) = script::witness_program(&redeem_script) {
+                    had_witness = true;
+                    // A witness program is at most 42 bytes, so a direct push is the only valid scriptSig
+                    if script_sig.len() != redeem_script.len() + 1
+                        || script_sig[0] as usize != redeem_script.len()
+                        || script_sig[1..] != redeem_script[..]
+                    {
+                        return Err(VerifyError::WitnessMalleatedP2sh);
+                    }
+                    self.verify_witness_program(witness, version, program, true)?;
+                }
+            }
+        }
+
+        if self.flag(VERIFY_WITNESS) && !had_witness && !witness.is_empty() {
+            return Err(VerifyError::WitnessUnexpected);
+        }
+        Ok(())
+    }
+
+    fn verify_witness_program(
+        &mut self,
+        witness: &[Bytes],
+        version: u8,
+        program: &[u8],
+        is_p2sh: bool,
+    ) -> Result<(), VerifyError> {
+        let mut stack: Stack = witness.iter().map(|item| item.to_vec()).collect();
+        let mut exec = ExecData::default();
+        if version == 0 {
+            if program.len() == 32 {
+                // P2WSH
+                let script = stack.pop().ok_or(VerifyError::WitnessProgramWitnessEmpty)?;
+                if utils::sha256(&script)[..] != *program {
+                    return Err(VerifyError::WitnessProgramMismatch);
+                }
+                self.execute_witness_script(stack, &script, SigVersion::WitnessV0, &mut exec)
+            } else if program.len() == 20 {
+                // P2WPKH
+                if stack.len() != 2 {
+                    return Err(VerifyError::WitnessProgramMismatch);
+                }
+                let mut script = vec![All::OP_DUP as u8, All::OP_HASH160 as u8, 20];
+                script.extend_from_slice(program);
+                script.extend_from_slice(&[All::OP_EQUALVERIFY as u8, All::OP_CHECKSIG as u8]);
+                self.execute_witness_script(stack, &script, SigVersion::WitnessV0, &mut exec)
+            } else {
+                Err(VerifyError::WitnessProgramWrongLength)
+            }
+        } else if version == 1 && program.len() == 32 && !is_p2sh {
+            if !self.flag(VERIFY_TAPROOT) {
+                return Ok(());
+            }
+            if stack.is_empty() {
+                return Err(VerifyError::WitnessProgramWitnessEmpty);
+            }
+            if stack.len() >= 2
+                && stack
+                    .last()
+                    .is_some_and(|last| last.first() == Some(&ANNEX_TAG))
+            {
+                let annex = stack.pop().unwrap();
+                let mut data = VarUint::compact(annex.len() as u64).to_bytes();
+                data.extend_from_slice(&annex);
+                exec.annex_hash = Some(utils::sha256(&data));
+            }
+            if stack.len() == 1 {
+                // Key path spend
+                return self.check_schnorr_signature(
+                    &stack[0],
+                    program,
+                    SigVersion::Taproot,
+                    &exec,
+                );
+            }
+            // Script path spend
+            let control = stack.pop().unwrap();
+            let script = stack.pop().unwrap();
+            if control.len() < TAPROOT_CONTROL_BASE_SIZE
+                || control.len()
+                    > TAPROOT_CONTROL_BASE_SIZE
+                        + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT
+                || !(control.len() - TAPROOT_CONTROL_BASE_SIZE)
+                    .is_multiple_of(TAPROOT_CONTROL_NODE_SIZE)
+            {
+                return Err(VerifyError::TaprootWrongControlSize);
+            }
+            let leaf_version = control[0] & 0xfe;
+            exec.tapleaf_hash = sighash::tapleaf_hash(leaf_version, &script);
+            if !verify_taproot_commitment(&control, program, &exec.tapleaf_hash) {
+                return Err(VerifyError::WitnessProgramMismatch);
+            }
+            if leaf_version != sighash::TAPROOT_LEAF_TAPSCRIPT {
+                // Unknown leaf versions are reserved for soft forks
+                return Ok(());
+            }
+            exec.validation_weight_left =
+                serialized_stack_size(witness) as i64 + VALIDATION_WEIGHT_OFFSET;
+            self.execute_witness_script(stack, &script, SigVersion::Tapscript, &mut exec)
+        } else {
+            // Other versions and sizes are reserved for soft forks
+            Ok(())
+        }
+    }
+
+    fn execute_witness_script(
+        &mut self,
+        mut stack: Stack,
+        script: &[u8],
+        sig_version: SigVersion,
+        exec: &mut ExecData,
+    ) -> Result<(), VerifyError> {
+        if sig_version == SigVersion::Tapscript {
+            // OP_SUCCESSx makes the script succeed before any other check
+            for instruction in Instructions::new(script) {
+                match instruction {
+                    Ok(Instruction::Op(opcode)) if is_op_success(opcode as u8) => return Ok(()),
+                    Ok(_) => {}
+                    Err(_) => return Err(VerifyError::BadOpcode),
+                }
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(VerifyError::StackSize);
+            }
+        }
+        if stack
+            .iter()
+            .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+        {
+            return Err(VerifyError::PushSize);
+        }
+        self.eval_script(&mut stack, script, sig_version, exec)?;
+        // Witness scripts implicitly require a clean stack
+        if stack.len() != 1 {
+            return Err(VerifyError::CleanStack);
+        }
+        if !cast_to_bool(&stack[0]) {
+            return Err(VerifyError::EvalFalse);
+        }
+        Ok(())
+    }
+
+    /// Executes `script` on `stack`, see EvalScript
+    fn eval_script(
+        &mut self,
+        stack: &mut Stack,
+        script: &[u8],
+        sig_version: SigVersion,
+        exec: &mut ExecData,
+    ) -> Result<(), VerifyError> {
+        let tapscript = sig_version == SigVersion::Tapscript;
+        if !tapscript && script.len() > script::MAX_SCRIPT_SIZE {
+            return Err(VerifyError::ScriptSize);
+        }
+        let mut alt_stack = Stack::new();
+        let mut exec_stack: Vec<bool> = Vec::new();
+        let mut op_count = 0usize;
+        let mut code_start = 0usize;
+        let mut opcode_pos = 0u32;
+
+        let mut instructions = Instructions::new(script);
+        while let Some(instruction) = instructions.next() {
+            let executing = exec_stack.iter().all(|&b| b);
+            let instruction = instruction.map_err(|_| VerifyError::BadOpcode)?;
+            let opcode = match instruction {
+                Instruction::PushBytes(opcode, data) => {
+                    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(VerifyError::PushSize);
+                    }
+                    if executing {
+                        stack.push(data.to_vec());
+                    }
+                    opcode
+                }
+                Instruction::Op(opcode) => opcode,
+            };
+            if let Instruction::Op(_) = instruction {
+                if !tapscript && opcode as u8 > All::OP_PUSHNUM_16 as u8 {
+                    op_count += 1;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(VerifyError::OpCount);
+                    }
+                }
+                // Disabled opcodes fail even in unexecuted branches
+                if is_disabled(opcode) {
+                    return Err(VerifyError::DisabledOpcode);
+                }
+                let conditional =
+                    (All::OP_IF as u8..=All::OP_ENDIF as u8).contains(&(opcode as u8));
+                if executing || conditional {
+                    self.eval_op(
+                        opcode,
+                        stack,
+                        &mut alt_stack,
+                        &mut exec_stack,
+                        executing,
+                        &mut op_count,
+                        script,
+                        &mut code_start,
+                        instructions.position(),
+                        opcode_pos,
+                        sig_version,
+                        exec,
+                    )?;
+                }
+            }
+            if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+                return Err(VerifyError::StackSize);
+            }
+            opcode_pos += 1;
+        }
+        if !exec_stack.is_empty() {
+            return Err(VerifyError::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn eval_op(
+        &mut self,
+        opcode: All,
+        stack: &mut Stack,
+        alt_stack: &mut Stack,
+        exec_stack: &mut Vec<bool>,
+        executing: bool,
+        op_count: &mut usize,
+        script: &[u8],
+        code_start: &mut usize,
+        position: usize,
+        opcode_pos: u32,
+        sig_version: SigVersion,
+        exec: &mut ExecData,
+    ) -> Result<(), VerifyError> {
+        match opcode {
+            All::OP_PUSHNUM_NEG1 => stack.push(encode_num(-1)),
+            _ if (All::OP_PUSHNUM_1 as u8..=All::OP_PUSHNUM_16 as u8).contains(&(opcode as u8)) => {
+                stack.push(encode_num(
+                    (opcode as u8 - All::OP_PUSHNUM_1 as u8 + 1) as i64,
+                ))
+            }
+            All::OP_NOP => {}
+            All::OP_NOP2 if self.flag(VERIFY_CHECKLOCKTIMEVERIFY) => {
+                let locktime = decode_num(top(stack, 1)?, 5)?;
+                if locktime < 0 {
+                    return Err(VerifyError::NegativeLocktime);
+                }
+                if !self.check_locktime(locktime) {
+                    return Err(VerifyError::UnsatisfiedLocktime);
+                }
+            }
+            All::OP_NOP3 if self.flag(VERIFY_CHECKSEQUENCEVERIFY) => {
+                let sequence = decode_num(top(stack, 1)?, 5)?;
+                if sequence < 0 {
+                    return Err(VerifyError::NegativeLocktime);
+                }
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && !self.check_sequence(sequence)
+                {
+                    return Err(VerifyError::UnsatisfiedLocktime);
+                }
+            }
+            _ if (All::OP_NOP1 as u8..=All::OP_NOP10 as u8).contains(&(opcode as u8)) => {}
+            All::OP_IF | All::OP_NOTIF => {
+                let mut value = false;
+                if executing {
+                    let cond = stack.pop().ok_or(VerifyError::UnbalancedConditional)?;
+                    if sig_version == SigVersion::Tapscript
+                        && (cond.len() > 1 || (cond.len() == 1 && cond[0] != 1))
+                    {
+                        return Err(VerifyError::TapscriptMinimalIf);
+                    }
+                    value = cast_to_bool(&cond) == (opcode == All::OP_IF);
+                }
+                exec_stack.push(value);
+            }
+            All::OP_ELSE => {
+                let top = exec_stack
+                    .last_mut()
+                    .ok_or(VerifyError::UnbalancedConditional)?;
+                *top = !*top;
+            }
+            All::OP_ENDIF => {
+                exec_stack.pop().ok_or(VerifyError::UnbalancedConditional)?;
+            }
+            All::OP_VERIFY => {
+                if !cast_to_bool(top(stack, 1)?) {
+                    return Err(VerifyError::Verify);
+                }
+                stack.pop();
+            }
+            All::OP_RETURN => return Err(VerifyError::OpReturn),
+            All::OP_TOALTSTACK => {
+                let item = stack.pop().ok_or(VerifyError::InvalidStackOperation)?;
+                alt_stack.push(item);
+            }
+            All::OP_FROMALTSTACK => {
+                let item = alt_stack
+                    .pop()
+                    .ok_or(VerifyError::InvalidAltstackOperation)?;
+                stack.push(item);
+            }
+            All::OP_2DROP => {
+                require(stack, 2)?;
+                stack.truncate(stack.len() - 2);
+            }
+            All::OP_2DUP => copy_top(stack, 2, 2)?,
+            All::OP_3DUP => copy_top(stack, 3, 3)?,
+            All::OP_2OVER => copy_top(stack, 4, 2)?,
+            All::OP_2ROT => {
+                require(stack, 6)?;
+                let len = stack.len();
+                let moved: Vec<_> = stack.drain(len - 6..len - 4).collect();
+                stack.extend(moved);
+            }
+            All::OP_2SWAP => {
+                require(stack, 4)?;
+                let len = stack.len();
+                stack.swap(len - 4, len - 2);
+                stack.swap(len - 3, len - 1);
+            }
+            All::OP_IFDUP => {
+                let item = top(stack, 1)?;
+                if cast_to_bool(item) {
+                    let item = item.clone();
+                    stack.push(item);
+                }
+            }
+            All::OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+            All::OP_DROP => {
+                stack.pop().ok_or(VerifyError::InvalidStackOperation)?;
+            }
+            All::OP_DUP => copy_top(stack, 1, 1)?,
+            All::OP_NIP => {
+                require(stack, 2)?;
+                stack.remove(stack.len() - 2);
+            }
+            All::OP_OVER => copy_top(stack, 2, 1)?,
+            All::OP_PICK | All::OP_ROLL => {
+                require(stack, 2)?;
+                let n = decode_num(&stack.pop().unwrap(), 4)?;
+                if n < 0 || n as usize >= stack.len() {
+                    return Err(VerifyError::InvalidStackOperation);
+                }
+                let index = stack.len() - 1 - n as usize;
+                let item = if opcode == All::OP_ROLL {
+                    stack.remove(index)
+                } else {
+                    stack[index].clone()
+                };
+                stack.push(item);
+            }
+            All::OP_ROT => {
+                require(stack, 3)?;
+                let len = stack.len();
+                stack.swap(len - 3, len - 2);
+                stack.swap(len - 2, len - 1);
+            }
+            All::OP_SWAP => {
+                require(stack, 2)?;
+                let len = stack.len();
+                stack.swap(len - 2, len - 1);
+            }
+            All::OP_TUCK => {
+                let item = top(stack, 1)?.clone();
+                require(stack, 2)?;
+                let len = stack.len();
+                stack.insert(len - 2, item);
+            }
+            All::OP_SIZE => {
+                let size = top(stack, 1)?.len();
+                stack.push(encode_num(size as i64));
+            }
+            All::OP_EQUAL | All::OP_EQUALVERIFY => {
+                require(stack, 2)?;
+                let equal = stack.pop() == stack.pop();
+                if opcode == All::OP_EQUALVERIFY {
+                    if !equal {
+                        return Err(VerifyError::EqualVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(equal));
+                }
+            }
+            All::OP_1ADD
+            | All::OP_1SUB
+            | All::OP_NEGATE
+            | All::OP_ABS
+            | All::OP_NOT
+            | All::OP_0NOTEQUAL => {
+                let n = decode_num(top(stack, 1)?, 4)?;
+                let result = match opcode {
+                    All::OP_1ADD => n + 1,
+                    All::OP_1SUB => n - 1,
+                    All::OP_NEGATE => -n,
+                    All::OP_ABS => n.abs(),
+                    All::OP_NOT => (n == 0) as i64,
+                    _ => (n != 0) as i64,
+                };
+                stack.pop();
+                stack.push(encode_num(result));
+            }
+            All::OP_ADD
+            | All::OP_SUB
+            | All::OP_BOOLAND
+            | All::OP_BOOLOR
+            | All::OP_NUMEQUAL
+            | All::OP_NUMEQUALVERIFY
+            | All::OP_NUMNOTEQUAL
+            | All::OP_LESSTHAN
+            | All::OP_GREATERTHAN
+            | All::OP_LESSTHANOREQUAL
+            | All::OP_GREATERTHANOREQUAL
+            | All::OP_MIN
+            | All::OP_MAX => {
+                require(stack, 2)?;
+                let a = decode_num(&stack[stack.len() - 2], 4)?;
+                let b = decode_num(&stack[stack.len() - 1], 4)?;
+                let result = match opcode {
+                    All::OP_ADD => a + b,
+                    All::OP_SUB => a - b,
+                    All::OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    All::OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    All::OP_NUMEQUAL | All::OP_NUMEQUALVERIFY => (a == b) as i64,
+                    All::OP_NUMNOTEQUAL => (a != b) as i64,
+                    All::OP_LESSTHAN => (a < b) as i64,
+                    All::OP_GREATERTHAN => (a > b) as i64,
+                    All::OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    All::OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    All::OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                stack.truncate(stack.len() - 2);
+                if opcode == All::OP_NUMEQUALVERIFY {
+                    if result == 0 {
+                        return Err(VerifyError::NumEqualVerify);
+                    }
+                } else {
+                    stack.push(encode_num(result));
+                }
+            }
+            All::OP_WITHIN => {
+                require(stack, 3)?;
+                let len = stack.len();
+                let x = decode_num(&stack[len - 3], 4)?;
+                let min = decode_num(&stack[len - 2], 4)?;
+                let max = decode_num(&stack[len - 1], 4)?;
+                stack.truncate(len - 3);
+                stack.push(encode_bool(min <= x && x < max));
+            }
+            All::OP_RIPEMD160
+            | All::OP_SHA1
+            | All::OP_SHA256
+            | All::OP_HASH160
+            | All::OP_HASH256 => {
+                let item = stack.pop().ok_or(VerifyError::InvalidStackOperation)?;
+                let hash = match opcode {
+                    All::OP_RIPEMD160 => utils::ridemp160(&item).to_vec(),
+                    All::OP_SHA1 => utils::sha1(&item).to_vec(),
+                    All::OP_SHA256 => utils::sha256(&item).to_vec(),
+                    All::OP_HASH160 => utils::ridemp160(&utils::sha256(&item)).to_vec(),
+                    _ => utils::sha256(&utils::sha256(&item)).to_vec(),
+                };
+                stack.push(hash);
+            }
+            All::OP_CODESEPARATOR => {
+                *code_start = position;
+                exec.codesep_pos = opcode_pos;
+            }
+            All::OP_CHECKSIG | All::OP_CHECKSIGVERIFY => {
+                require(stack, 2)?;
+                let pubkey = stack.pop().unwrap();
+                let sig = stack.pop().unwrap();
+                let success =
+                    self.eval_checksig(&sig, &pubkey, &script[*code_start..], sig_version, exec)?;
+                if opcode == All::OP_CHECKSIGVERIFY {
+                    if !success {
+                        return Err(VerifyError::CheckSigVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(success));
+                }
+            }
+            OP_CHECKSIGADD if matches!(sig_version, SigVersion::Tapscript) => {
+                require(stack, 3)?;
+                let pubkey = stack.pop().unwrap();
+                let n = decode_num(&stack.pop().unwrap(), 4)?;
+                let sig = stack.pop().unwrap();
+                let success =
+                    self.eval_checksig(&sig, &pubkey, &script[*code_start..], sig_version, exec)?;
+                stack.push(encode_num(n + success as i64));
+            }
+            All::OP_CHECKMULTISIG | All::OP_CHECKMULTISIGVERIFY => {
+                if sig_version == SigVersion::Tapscript {
+                    return Err(VerifyError::TapscriptCheckMultisig);
+                }
+                let success =
+                    self.eval_checkmultisig(stack, op_count, &script[*code_start..], sig_version)?;
+                if opcode == All::OP_CHECKMULTISIGVERIFY {
+                    if !success {
+                        return Err(VerifyError::CheckMultisigVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(success));
+                }
+            }
+            _ => return Err(VerifyError::BadOpcode),
+        }
+        Ok(())
+    }
+
+    /// OP_CHECKMULTISIG with the stack [dummy, sigs.., sig_count, keys.., key_count]
+    fn eval_checkmultisig(
+        &mut self,
+        stack: &mut Stack,
+        op_count: &mut usize,
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> Result<bool, VerifyError> {
+        let mut i = 1;
+        require(stack, i)?;
+        let key_count = decode_num(&stack[stack.len() - i], 4)?;
+        if key_count < 0 || key_count > script::MAX_MULTISIG_KEYS as i64 {
+            return Err(VerifyError::PubkeyCount);
+        }
+        let mut key_count = key_count as usize;
+        *op_count += key_count;
+        if *op_count > MAX_OPS_PER_SCRIPT {
+            return Err(VerifyError::OpCount);
+        }
+        let mut ikey = i + 1;
+        i += 1 + key_count;
+        require(stack, i)?;
+        let sig_count = decode_num(&stack[stack.len() - i], 4)?;
+        if sig_count < 0 || sig_count as usize > key_count {
+            return Err(VerifyError::SigCount);
+        }
+        let mut sig_count = sig_count as usize;
+        let mut isig = i + 1;
+        i += 1 + sig_count;
+        require(stack, i)?;
+
+        // Signatures can't sign themselves
+        let mut script_code = script_code.to_vec();
+        if sig_version == SigVersion::Base {
+            for k in 0..sig_count {
+                find_and_delete(&mut script_code, &stack[stack.len() - isig - k]);
+            }
+        }
+
+        let mut success = true;
+        while success && sig_count > 0 {
+            let sig = &stack[stack.len() - isig];
+            let pubkey = &stack[stack.len() - ikey];
+            self.check_signature_encoding(sig)?;
+            if self.check_ecdsa_signature(sig, pubkey, &script_code, sig_version) {
+                isig += 1;
+                sig_count -= 1;
+            }
+            ikey += 1;
+            key_count -= 1;
+            // More signatures left than keys means failure
+            if sig_count > key_count {
+                success = false;
+            }
+        }
+
+        // Includes the dummy element, a bug of the original implementation
+        stack.truncate(stack.len() - (i - 1));
+        let dummy = stack.pop().ok_or(VerifyError::InvalidStackOperation)?;
+        if self.flag(VERIFY_NULLDUMMY) && !dummy.is_empty() {
+            return Err(VerifyError::SigNullDummy);
+        }
+        Ok(success)
+    }
+
+    fn eval_checksig(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+        exec: &mut ExecData,
+    ) -> Result<bool, VerifyError> {
+        match sig_version {
+            SigVersion::Base | SigVersion::WitnessV0 => {
+                let mut script_code = script_code.to_vec();
+                if sig_version == SigVersion::Base {
+                    find_and_delete(&mut script_code, sig);
+                }
+                self.check_signature_encoding(sig)?;
+                Ok(self.check_ecdsa_signature(sig, pubkey, &script_code, sig_version))
+            }
+            _ => {
+                let success = !sig.is_empty();
+                if success {
+                    exec.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+                    if exec.validation_weight_left < 0 {
+                        return Err(VerifyError::TapscriptValidationWeight);
+                    }
+                }
+                if pubkey.is_empty() {
+                    return Err(VerifyError::PubkeyType);
+                }
+                // Other key sizes are reserved for soft forks and always succeed
+                if pubkey.len() == 32 && success {
+                    self.check_schnorr_signature(sig, pubkey, sig_version, exec)?;
+                }
+                Ok(success)
+            }
+        }
+    }
+
+    /// Only consensus checks, empty signatures are always allowed
+    #[inline]
+    fn check_signature_encoding(&self, sig: &[u8]) -> Result<(), VerifyError> {
+        if !sig.is_empty() && self.flag(VERIFY_DERSIG) && !script::is_valid_signature_encoding(sig)
+        {
+            return Err(VerifyError::SigDer);
+        }
+        Ok(())
+    }
+
+    fn check_ecdsa_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        let (&hash_type, der) = match sig.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let key = match PublicKey::from_slice(pubkey) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        let mut signature = match Signature::from_der_lax(der) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        // libsecp256k1 only accepts low S values
+        signature.normalize_s();
+        let sighash = if sig_version == SigVersion::Base {
+            self.cache.legacy(self.vin, script_code, hash_type)
+        } else {
+            self.cache.witness_v0(
+                self.vin,
+                script_code,
+                self.cache.spent(self.vin).value,
+                hash_type,
+            )
+        };
+        let msg = Message::from_slice(&sighash).expect("sighash is 32 bytes");
+        let valid = secp().verify(&msg, &signature, &key).is_ok();
+        if valid {
+            self.record(pubkey, sig, sighash, false);
+        }
+        valid
+    }
+
+    /// Fails the script unless the signature is valid [BIP340]
+    fn check_schnorr_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        sig_version: SigVersion,
+        exec: &ExecData,
+    ) -> Result<(), VerifyError> {
+        let (signature, hash_type) = match sig.len() {
+            64 => (sig, SIGHASH_DEFAULT),
+            65 if sig[64] == SIGHASH_DEFAULT => return Err(VerifyError::SchnorrSigHashtype),
+            65 => (&sig[..64], sig[64]),
+            _ => return Err(VerifyError::SchnorrSigSize),
+        };
+        let leaf = match sig_version {
+            SigVersion::Tapscript => Some((&exec.tapleaf_hash, exec.codesep_pos)),
+            _ => None,
+        };
+        let sighash = self
+            .cache
+            .taproot(self.vin, hash_type, exec.annex_hash.as_ref(), leaf)
+            .ok_or(VerifyError::SchnorrSigHashtype)?;
+        let valid = match (
+            schnorrsig::PublicKey::from_slice(pubkey),
+            schnorrsig::Signature::from_slice(signature),
+        ) {
+            (Ok(key), Ok(signature)) => {
+                let msg = Message::from_slice(&sighash).expect("sighash is 32 bytes");
+                secp().schnorrsig_verify(&signature, &msg, &key).is_ok()
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(VerifyError::SchnorrSig);
+        }
+        self.record(pubkey, sig, sighash, true);
+        Ok(())
+    }
+
+    #[inline]
+    fn record(&mut self, pubkey: &[u8], sig: &[u8], sighash: [u8; 32], schnorr: bool) {
+        self.checked.push(CheckedSignature {
+            pubkey: pubkey.to_vec(),
+            signature: sig.to_vec(),
+            sighash,
+            schnorr,
+        });
+    }
+
+    /// OP_CHECKLOCKTIMEVERIFY against the transaction's locktime, see CheckLockTime
+    fn check_locktime(&self, locktime: i64) -> bool {
+        let tx = self.cache.tx();
+        let tx_locktime = tx.locktime as i64;
+        // Both must be heights or both timestamps
+        if (tx_locktime < LOCKTIME_THRESHOLD) != (locktime < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        // A final input would disable the transaction's locktime
+        locktime <= tx_locktime && tx.inputs[self.vin].input.seq_no != timelock::SEQUENCE_FINAL
+    }
+
+    /// OP_CHECKSEQUENCEVERIFY against the input's relative locktime [BIP68], see CheckSequence
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx = self.cache.tx();
+        let tx_sequence = tx.inputs[self.vin].input.seq_no as i64;
+        if tx.version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_masked = tx_sequence & mask;
+        let masked = sequence & mask;
+        if (tx_masked < SEQUENCE_LOCKTIME_TYPE_FLAG) != (masked < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        masked <= tx_masked
+    }
+}
+
+/// Checks the control block's merkle path from the leaf to the tweaked output key
+fn verify_taproot_commitment(control: &[u8], program: &[u8], tapleaf_hash: &[u8; 32]) -> bool {
+    let mut node = *tapleaf_hash;
+    for branch in control[TAPROOT_CONTROL_BASE_SIZE..].chunks(TAPROOT_CONTROL_NODE_SIZE) {
+        node = sighash::tapbranch_hash(&node, branch);
+    }
+    let internal_key = &control[1..TAPROOT_CONTROL_BASE_SIZE];
+    let mut tweak_data = internal_key.to_vec();
+    tweak_data.extend_from_slice(&node);
+    let tweak = sighash::tagged_hash("TapTweak", &tweak_data);
+    match (
+        schnorrsig::PublicKey::from_slice(internal_key),
+        schnorrsig::PublicKey::from_slice(program),
+    ) {
+        (Ok(internal), Ok(output)) => {
+            internal.tweak_add_check(secp(), &output, control[0] & 1 == 1, tweak)
+        }
+        _ => false,
+    }
+}
+
+/// Removes all pushes of `sig` at instruction boundaries, see FindAndDelete
+fn find_and_delete(script: &mut Vec<u8>, sig: &[u8]) {
+    if sig.is_empty() {
+        return;
+    }
+    // The signature as pushed by CScript() << sig
+    let mut pattern = match sig.len() {
+        len if len < All::OP_PUSHDATA1 as usize => vec![len as u8],
+        len if len <= 0xff => vec![All::OP_PUSHDATA1 as u8, len as u8],
+        len if len <= 0xffff => vec![All::OP_PUSHDATA2 as u8, len as u8, (len >> 8) as u8],
+        len => {
+            let mut prefix = vec![All::OP_PUSHDATA4 as u8];
+            prefix.extend_from_slice(&(len as u32).to_le_bytes());
+            prefix
+        }
+    };
+    pattern.extend_from_slice(sig);
+
+    let mut result = Vec::with_capacity(script.len());
+    let mut found = false;
+    let mut pos = 0;
+    let mut copied = 0;
+    loop {
+        result.extend_from_slice(&script[copied..pos]);
+        while script.len() - pos >= pattern.len() && script[pos..pos + pattern.len()] == pattern[..]
+        {
+            pos += pattern.len();
+            found = true;
+        }
+        copied = pos;
+        let mut instructions = Instructions::new(&script[pos..]);
+        match instructions.next() {
+            Some(Ok(_)) => pos += instructions.position(),
+            _ => break,
+        }
+    }
+    if found {
+        result.extend_from_slice(&script[copied..]);
+        *script = result;
+    }
+}
+
+/// Returns the n-th element from the top, 1 being the top
+#[inline]
+fn top(stack: &Stack, n: usize) -> Result<&Vec<u8>, VerifyError> {
+    require(stack, n)?;
+    Ok(&stack[stack.len() - n])
+}
+
+#[inline]
+fn require(stack: &Stack, n: usize) -> Result<(), VerifyError> {
+    if stack.len() < n {
+        return Err(VerifyError::InvalidStackOperation);
+    }
+    Ok(())
+}
+
+/// Pushes copies of `count` elements, starting `depth` elements below the top
+fn copy_top(stack: &mut Stack, depth: usize, count: usize) -> Result<(), VerifyError> {
+    require(stack, depth)?;
+    let start = stack.len() - depth;
+    for i in start..start + count {
+        let item = stack[i].clone();
+        stack.push(item);
+    }
+    Ok(())
+}
+
+/// Decodes a script number of at most `max_size` bytes, see CScriptNum.
+/// Non-minimal encodings are accepted, they are only rejected by policy.
+fn decode_num(data: &[u8], max_size: usize) -> Result<i64, VerifyError> {
+    if data.len() > max_size {
+        return Err(VerifyError::NumOverflow);
+    }
+    Ok(script::decode_script_num(data))
+}
+
+/// Minimal little endian sign-magnitude encoding, zero is empty
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut abs = n.unsigned_abs();
+    while abs > 0 {
+        result.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    match result.last_mut() {
+        Some(last) if *last & 0x80 != 0 => result.push(if n < 0 { 0x80 } else { 0x00 }),
+        Some(last) if n < 0 => *last |= 0x80,
+        _ => {}
+    }
+    result
+}
+
+#[inline]
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        Vec::new()
+    }
+}
+
+/// False for empty elements, zeros and negative zero
+fn cast_to_bool(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((&last, rest)) => rest.iter().any(|&b| b != 0) || (last != 0 && last != 0x80),
+        None => false,
+    }
+}
+
+/// OP_HASH160 <20 bytes> OP_EQUAL
+#[inline]
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23
+        && script[0] == All::OP_HASH160 as u8
+        && script[1] == 0x14
+        && script[22] == All::OP_EQUAL as u8
+}
+
+/// Only pushes and OP_RESERVED, see CScript::IsPushOnly
+fn is_push_only(script: &[u8]) -> bool {
+    Instructions::new(script).all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(..)) => true,
+        Ok(Instruction::Op(opcode)) => opcode as u8 <= All::OP_PUSHNUM_16 as u8,
+        Err(_) => false,
+    })
+}
+
+/// Opcodes disabled in 2010 (CVE-2010-5137), they fail scripts even in unexecuted branches
+fn is_disabled(opcode: All) -> bool {
+    matches!(
+        opcode,
+        All::OP_CAT
+            | All::OP_SUBSTR
+            | All::OP_LEFT
+            | All::OP_RIGHT
+            | All::OP_INVERT
+            | All::OP_AND
+            | All::OP_OR
+            | All::OP_XOR
+            | All::OP_2MUL
+            | All::OP_2DIV
+            | All::OP_MUL
+            | All::OP_DIV
+            | All::OP_MOD
+            | All::OP_LSHIFT
+            | All::OP_RSHIFT
+    )
+}
+
+/// OP_SUCCESSx opcodes of tapscript [BIP342]
+fn is_op_success(opcode: u8) -> bool {
+    matches!(opcode, 80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254)
+}
+
+/// Size of a serialized witness stack
+fn serialized_stack_size(witness: &[Bytes]) -> usize {
+    witness.iter().fold(
+        VarUint::compact(witness.len() as u64).to_bytes().len(),
+        |size, item| size + VarUint::compact(item.len() as u64).to_bytes().len() + item.len(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::sighash::SIGHASH_ALL;
+    use crate::common::fixtures;
+    use secp256k1::SecretKey;
+
+    const ALL_FLAGS: u32 = VERIFY_P2SH
+        | VERIFY_DERSIG
+        | VERIFY_NULLDUMMY
+        | VERIFY_CHECKLOCKTIMEVERIFY
+        | VERIFY_CHECKSEQUENCEVERIFY
+        | VERIFY_WITNESS
+        | VERIFY_TAPROOT;
+
+    fn verify(
+        script_sig: &[u8],
+        witness: Vec<Vec<u8>>,
+        script_pubkey: &[u8],
+        flags: u32,
+    ) -> Result<Vec<CheckedSignature>, VerifyError> {
+        let tx = fixtures::spending_tx(script_sig, witness, 0, 0xffffffff);
+        let spent = fixtures::output(100_000_000, script_pubkey);
+        TxVerifier::new(&tx, vec![&spent]).verify_input(0, flags)
+    }
+
+    /// Signs input 0 of the spending transaction, scriptSig and witness are not signed
+    fn sign_ecdsa(
+        secret: u8,
+        script_pubkey: &[u8],
+        script_code: &[u8],
+        witness_v0: bool,
+    ) -> Vec<u8> {
+        let tx = fixtures::spending_tx(&[], Vec::new(), 0, 0xffffffff);
+        let spent = fixtures::output(100_000_000, script_pubkey);
+        let cache = SighashCache::new(&tx, vec![&spent]);
+        let sighash = if witness_v0 {
+            cache.witness_v0(0, script_code, spent.value, SIGHASH_ALL)
+        } else {
+            cache.legacy(0, script_code, SIGHASH_ALL)
+        };
+        let key = SecretKey::from_slice(&[secret; 32]).unwrap();
+        let mut sig = secp()
+            .sign(&Message::from_slice(&sighash).unwrap(), &key)
+            .serialize_der()
+            .to_vec();
+        sig.push(SIGHASH_ALL);
+        sig
+    }
+
+    fn pubkey(secret: u8) -> Vec<u8> {
+        let key = SecretKey::from_slice(&[secret; 32]).unwrap();
+        PublicKey::from_secret_key(secp(), &key)
+            .serialize()
+            .to_vec()
+    }
+
+    fn push(data: &[u8]) -> Vec<u8> {
+        let mut script = vec![data.len() as u8];
+        script.extend_from_slice(data);
+        script
+    }
+
+    fn p2pkh(pubkey: &[u8]) -> Vec<u8> {
+        let mut script = vec![0x76, 0xa9, 0x14];
+        script.extend_from_slice(&utils::ridemp160(&utils::sha256(pubkey)));
+        script.extend_from_slice(&[0x88, 0xac]);
+        script
+    }
+
+    #[test]
+    fn test_script_numbers() {
+        assert_eq!(encode_num(0), Vec::<u8>::new());
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-128), vec![0x80, 0x80]);
+        assert_eq!(encode_num(-255), vec![0xff, 0x80]);
+        for n in [0, 1, -1, 127, 128, -129, 0x7fffffff, -0x7fffffff] {
+            assert_eq!(decode_num(&encode_num(n), 4), Ok(n));
+        }
+        assert_eq!(decode_num(&[0; 5], 4), Err(VerifyError::NumOverflow));
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+        assert!(!cast_to_bool(&[]));
+    }
+
+    #[test]
+    fn test_eval_script() {
+        // 2 3 ADD 5 EQUAL
+        assert!(verify(&[], Vec::new(), &[0x52, 0x53, 0x93, 0x55, 0x87], ALL_FLAGS).is_ok());
+        // 1 IF 0 ELSE 1 ENDIF
+        assert_eq!(
+            verify(
+                &[],
+                Vec::new(),
+                &[0x51, 0x63, 0x00, 0x67, 0x51, 0x68],
+                ALL_FLAGS
+            ),
+            Err(VerifyError::EvalFalse)
+        );
+        // Disabled opcodes and OP_VERIF fail in unexecuted branches, OP_VER doesn't
+        assert_eq!(
+            verify(&[], Vec::new(), &[0x00, 0x63, 0x7e, 0x68, 0x51], ALL_FLAGS),
+            Err(VerifyError::DisabledOpcode)
+        );
+        assert_eq!(
+            verify(&[], Vec::new(), &[0x00, 0x63, 0x65, 0x68, 0x51], ALL_FLAGS),
+            Err(VerifyError::BadOpcode)
+        );
+        assert!(verify(&[], Vec::new(), &[0x00, 0x63, 0x62, 0x68, 0x51], ALL_FLAGS).is_ok());
+        assert_eq!(
+            verify(&[], Vec::new(), &[0x51, 0x63], ALL_FLAGS),
+            Err(VerifyError::UnbalancedConditional)
+        );
+        assert_eq!(
+            verify(&[], Vec::new(), &[0x51, 0x6a], ALL_FLAGS),
+            Err(VerifyError::OpReturn)
+        );
+        // 5 byte operands only work with the locktime opcodes
+        assert_eq!(
+            verify(&[0x05, 1, 0, 0, 0, 0], Vec::new(), &[0x8b], ALL_FLAGS),
+            Err(VerifyError::NumOverflow)
+        );
+        // 1 2 3 ROT: 2 3 1, then 2DROP leaves 2
+        assert!(verify(
+            &[0x51, 0x52, 0x53],
+            Vec::new(),
+            &[0x7b, 0x6d, 0x52, 0x87],
+            ALL_FLAGS
+        )
+        .is_ok());
+        // Only push operations in the scriptSig of P2SH inputs
+        let redeem_script = [0x51];
+        let mut p2sh = vec![0xa9, 0x14];
+        p2sh.extend_from_slice(&utils::ridemp160(&utils::sha256(&redeem_script)));
+        p2sh.push(0x87);
+        assert!(verify(&push(&redeem_script), Vec::new(), &p2sh, ALL_FLAGS).is_ok());
+        let mut script_sig = vec![0x61];
+        script_sig.extend_from_slice(&push(&redeem_script));
+        assert_eq!(
+            verify(&script_sig, Vec::new(), &p2sh, ALL_FLAGS),
+            Err(VerifyError::SigPushOnly)
+        );
+        assert!(verify(&script_sig, Vec::new(), &p2sh, 0).is_ok());
+    }
+
+    #[test]
+    fn test_locktime_opcodes() {
+        // 100 CHECKLOCKTIMEVERIFY
+        let cltv = [0x01, 100, 0xb1];
+        let spent = fixtures::output(100_000_000, &cltv);
+        let check = |locktime, seq_no, flags| {
+            let tx = fixtures::spending_tx(&[0x51], Vec::new(), locktime, seq_no);
+            TxVerifier::new(&tx, vec![&spent]).verify_input(0, flags)
+        };
+        assert!(check(100, 0, ALL_FLAGS).is_ok());
+        assert_eq!(
+            check(99, 0, ALL_FLAGS),
+            Err(VerifyError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            check(100, 0xffffffff, ALL_FLAGS),
+            Err(VerifyError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            check(500_000_000, 0, ALL_FLAGS),
+            Err(VerifyError::UnsatisfiedLocktime)
+        );
+        // Before BIP65 the opcode was OP_NOP2
+        assert!(check(0, 0xffffffff, 0).is_ok());
+    }
+
+    #[test]
+    fn test_verify_p2pkh() {
+        let key = pubkey(1);
+        let script_pubkey = p2pkh(&key);
+        let sig = sign_ecdsa(1, &script_pubkey, &script_pubkey, false);
+        let mut script_sig = push(&sig);
+        script_sig.extend_from_slice(&push(&key));
+
+        let checked = verify(&script_sig, Vec::new(), &script_pubkey, ALL_FLAGS).unwrap();
+        assert_eq!(checked.len(), 1);
+        assert_eq!(checked[0].pubkey, key);
+        assert_eq!(checked[0].signature, sig);
+        assert!(!checked[0].schnorr);
+
+        // Signature for a different key
+        let mut script_sig = push(&sign_ecdsa(2, &script_pubkey, &script_pubkey, false));
+        script_sig.extend_from_slice(&push(&key));
+        assert_eq!(
+            verify(&script_sig, Vec::new(), &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::EvalFalse)
+        );
+        // Padded DER is only rejected after BIP66
+        let mut padded = sig.clone();
+        padded[1] += 1;
+        padded.insert(padded.len() - 1, 0x00);
+        let mut script_sig = push(&padded);
+        script_sig.extend_from_slice(&push(&key));
+        assert_eq!(
+            verify(&script_sig, Vec::new(), &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::SigDer)
+        );
+        // Unexpected witness
+        let mut script_sig = push(&sig);
+        script_sig.extend_from_slice(&push(&key));
+        assert_eq!(
+            verify(&script_sig, vec![vec![1]], &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::WitnessUnexpected)
+        );
+    }
+
+    #[test]
+    fn test_verify_p2sh_multisig() {
+        // 2-of-3, signed by the first and the last key
+        let mut redeem_script = vec![0x52];
+        for secret in 1..=3 {
+            redeem_script.extend_from_slice(&push(&pubkey(secret)));
+        }
+        redeem_script.extend_from_slice(&[0x53, 0xae]);
+        let mut script_pubkey = vec![0xa9, 0x14];
+        script_pubkey.extend_from_slice(&utils::ridemp160(&utils::sha256(&redeem_script)));
+        script_pubkey.push(0x87);
+
+        let script_sig = |dummy: &[u8]| {
+            let mut script_sig = if dummy.is_empty() {
+                vec![0x00]
+            } else {
+                push(dummy)
+            };
+            script_sig.extend_from_slice(&push(&sign_ecdsa(
+                1,
+                &script_pubkey,
+                &redeem_script,
+                false,
+            )));
+            script_sig.extend_from_slice(&push(&sign_ecdsa(
+                3,
+                &script_pubkey,
+                &redeem_script,
+                false,
+            )));
+            script_sig.push(0x4c);
+            script_sig.push(redeem_script.len() as u8);
+            script_sig.extend_from_slice(&redeem_script);
+            script_sig
+        };
+        // Signatures and keys are matched from the top of the stack
+        let checked = verify(&script_sig(&[]), Vec::new(), &script_pubkey, ALL_FLAGS).unwrap();
+        assert_eq!(
+            checked.iter().map(|c| c.pubkey.clone()).collect::<Vec<_>>(),
+            vec![pubkey(3), pubkey(1)]
+        );
+        // NULLDUMMY [BIP147]
+        assert_eq!(
+            verify(&script_sig(&[1]), Vec::new(), &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::SigNullDummy)
+        );
+        assert!(verify(&script_sig(&[1]), Vec::new(), &script_pubkey, VERIFY_P2SH).is_ok());
+    }
+
+    #[test]
+    fn test_verify_p2wpkh() {
+        let key = pubkey(2);
+        let mut script_pubkey = vec![0x00, 0x14];
+        script_pubkey.extend_from_slice(&utils::ridemp160(&utils::sha256(&key)));
+        let sig = sign_ecdsa(2, &script_pubkey, &p2pkh(&key), true);
+
+        let checked = verify(
+            &[],
+            vec![sig.clone(), key.clone()],
+            &script_pubkey,
+            ALL_FLAGS,
+        )
+        .unwrap();
+        assert_eq!(checked[0].pubkey, key);
+        assert_eq!(
+            verify(
+                &[0x51],
+                vec![sig.clone(), key.clone()],
+                &script_pubkey,
+                ALL_FLAGS
+            ),
+            Err(VerifyError::WitnessMalleated)
+        );
+        assert_eq!(
+            verify(&[], vec![key.clone()], &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::WitnessProgramMismatch)
+        );
+        // A legacy signature hash doesn't verify
+        let legacy = sign_ecdsa(2, &script_pubkey, &p2pkh(&key), false);
+        assert_eq!(
+            verify(&[], vec![legacy, key.clone()], &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::EvalFalse)
+        );
+        // Anyone can spend before segwit
+        assert!(verify(&[], Vec::new(), &script_pubkey, VERIFY_P2SH).is_ok());
+    }
+
+    #[test]
+    fn test_verify_taproot() {
+        let internal = schnorrsig::KeyPair::from_seckey_slice(secp(), &[3; 32]).unwrap();
+        let internal_key = schnorrsig::PublicKey::from_keypair(secp(), &internal);
+        let leaf_keypair = schnorrsig::KeyPair::from_seckey_slice(secp(), &[4; 32]).unwrap();
+        let leaf_key = schnorrsig::PublicKey::from_keypair(secp(), &leaf_keypair);
+        let mut leaf_script = push(&leaf_key.serialize());
+        leaf_script.push(0xac);
+        let leaf_hash = sighash::tapleaf_hash(sighash::TAPROOT_LEAF_TAPSCRIPT, &leaf_script);
+
+        let mut tweak_data = internal_key.serialize().to_vec();
+        tweak_data.extend_from_slice(&leaf_hash);
+        let tweak = sighash::tagged_hash("TapTweak", &tweak_data);
+        let mut output_key = internal_key;
+        let parity = output_key.tweak_add_assign(secp(), &tweak).unwrap();
+        let mut script_pubkey = vec![0x51, 0x20];
+        script_pubkey.extend_from_slice(&output_key.serialize());
+
+        let tx = fixtures::spending_tx(&[], Vec::new(), 0, 0xffffffff);
+        let spent = fixtures::output(100_000_000, &script_pubkey);
+        let cache = SighashCache::new(&tx, vec![&spent]);
+
+        // Key path
+        let mut tweaked = internal;
+        tweaked.tweak_add_assign(secp(), &tweak).unwrap();
+        let msg =
+            Message::from_slice(&cache.taproot(0, SIGHASH_DEFAULT, None, None).unwrap()).unwrap();
+        let sig = secp()
+            .schnorrsig_sign_no_aux_rand(&msg, &tweaked)
+            .as_ref()
+            .to_vec();
+        let checked = verify(&[], vec![sig.clone()], &script_pubkey, ALL_FLAGS).unwrap();
+        assert!(checked[0].schnorr);
+        assert_eq!(checked[0].pubkey, output_key.serialize().to_vec());
+        let mut explicit_all = sig.clone();
+        explicit_all.push(SIGHASH_ALL);
+        assert_eq!(
+            verify(&[], vec![explicit_all], &script_pubkey, ALL_FLAGS),
+            Err(VerifyError::SchnorrSig)
+        );
+        assert!(verify(
+            &[],
+            vec![vec![0; 64]],
+            &script_pubkey,
+            ALL_FLAGS & !VERIFY_TAPROOT
+        )
+        .is_ok());
+
+        // Script path
+        let mut control = vec![sighash::TAPROOT_LEAF_TAPSCRIPT | parity as u8];
+        control.extend_from_slice(&internal_key.serialize());
+        let leaf = Some((&leaf_hash, 0xffffffff));
+        let msg =
+            Message::from_slice(&cache.taproot(0, SIGHASH_DEFAULT, None, leaf).unwrap()).unwrap();
+        let sig = secp()
+            .schnorrsig_sign_no_aux_rand(&msg, &leaf_keypair)
+            .as_ref()
+            .to_vec();
+        let checked = verify(
+            &[],
+            vec![sig.clone(), leaf_script.clone(), control.clone()],
+            &script_pubkey,
+            ALL_FLAGS,
+        )
+        .unwrap();
+        assert_eq!(checked[0].pubkey, leaf_key.serialize().to_vec());
+        // An empty signature makes OP_CHECKSIG push false
+        assert_eq!(
+            verify(
+                &[],
+                vec![Vec::new(), leaf_script.clone(), control.clone()],
+                &script_pubkey,
+                ALL_FLAGS
+            ),
+            Err(VerifyError::EvalFalse)
+        );
+        control[0] ^= 1;
+        assert_eq!(
+            verify(
+                &[],
+                vec![sig, leaf_script, control],
+                &script_pubkey,
+                ALL_FLAGS
+            ),
+            Err(VerifyError::WitnessProgramMismatch)
+        );
+    }
+
+    #[test]
+    fn test_find_and_delete() {
+        let mut script = vec![0x02, 0xaa, 0xbb, 0x02, 0xaa, 0xbb, 0xac];
+        find_and_delete(&mut script, &[0xaa, 0xbb]);
+        assert_eq!(script, vec![0xac]);
+        // Only matches at instruction boundaries
+        let mut script = vec![0x03, 0x02, 0xaa, 0xbb, 0xac];
+        find_and_delete(&mut script, &[0xaa, 0xbb]);
+        assert_eq!(script, vec![0x03, 0x02, 0xaa, 0xbb, 0xac]);
+    }
+}
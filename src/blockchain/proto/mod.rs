@@ -2,10 +2,19 @@ use std::fmt;
 
 use crate::common::utils;
 
+pub mod auxpow;
 pub mod block;
+pub mod bytes;
+pub mod descriptor;
 pub mod header;
+pub mod inscription;
+pub mod interpreter;
+pub mod mweb;
 pub mod opcodes;
 pub mod script;
+pub mod sighash;
+pub mod target;
+pub mod timelock;
 pub mod tx;
 pub mod varuint;
 
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::common::utils;
+
+/// Transactions with this flag carry witness data
+pub const WITNESS_FLAG: u8 = 0x01;
+/// Transactions with this flag carry MWEB data. If it is empty, the transaction is the HogEx.
+pub const MWEB_FLAG: u8 = 0x08;
+
+/// Header of a Litecoin MimbleWimble extension block, see mw::Header
+#[derive(Clone)]
+pub struct MwebHeader {
+    pub height: u64,
+    pub output_root: [u8; 32],
+    pub kernel_root: [u8; 32],
+    pub leafset_root: [u8; 32],
+    pub kernel_offset: [u8; 32],
+    pub stealth_offset: [u8; 32],
+    pub output_mmr_size: u64,
+    pub kernel_mmr_size: u64,
+}
+
+/// Inputs, outputs and kernels as serialized in the block, see mw::TxBody.
+/// They are kept raw, amounts are hidden in commitments anyway.
+#[derive(Clone, Default)]
+pub struct MwebTxBody {
+    pub inputs: Vec<Vec<u8>>,
+    pub outputs: Vec<Vec<u8>>,
+    pub kernels: Vec<Vec<u8>>,
+}
+
+/// Extension block following the HogEx (integrating transaction) of a Litecoin block (LIP-0002, LIP-0003)
+#[derive(Clone)]
+pub struct MwebBlock {
+    pub header: MwebHeader,
+    pub body: MwebTxBody,
+}
+
+impl fmt::Debug for MwebHeader {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MwebHeader")
+            .field("height", &self.height)
+            .field("output_root", &utils::arr_to_hex_swapped(&self.output_root))
+            .field("kernel_root", &utils::arr_to_hex_swapped(&self.kernel_root))
+            .field("output_mmr_size", &self.output_mmr_size)
+            .field("kernel_mmr_size", &self.kernel_mmr_size)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MwebTxBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MwebTxBody")
+            .field("inputs", &self.inputs.len())
+            .field("outputs", &self.outputs.len())
+            .field("kernels", &self.kernels.len())
+            .finish()
+    }
+}
+
+impl fmt::Debug for MwebBlock {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MwebBlock")
+            .field("header", &self.header)
+            .field("body", &self.body)
+            .finish()
+    }
+}
@@ -1,13 +1,18 @@
 use std::convert::From;
 use std::error;
-use std::error::Error;
 use std::fmt;
 
-use rust_base58::ToBase58;
+use rust_base58::{FromBase58, ToBase58};
 
 use crate::blockchain::proto::opcodes;
+use crate::common::bech32;
 use crate::common::utils;
 
+/// Maximum number of keys of a multisig script, see MAX_PUBKEYS_PER_MULTISIG
+pub const MAX_MULTISIG_KEYS: u8 = 20;
+/// Scripts above this size are unspendable
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ScriptError {
     UnexpectedEof,
@@ -16,31 +21,32 @@ pub enum ScriptError {
 
 impl fmt::Display for ScriptError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
-    }
-}
-
-impl error::Error for ScriptError {
-    fn description(&self) -> &str {
         match *self {
-            ScriptError::UnexpectedEof => "Unexpected EOF",
-            ScriptError::InvalidFormat => "Invalid Script format",
+            ScriptError::UnexpectedEof => write!(f, "Unexpected EOF"),
+            ScriptError::InvalidFormat => write!(f, "Invalid Script format"),
         }
     }
 }
 
+impl error::Error for ScriptError {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ScriptPattern {
     /// Null Data
     /// Pubkey Script: OP_RETURN <0 to 80 bytes of data> (formerly 40 bytes)
     /// Null data scripts cannot be spent, so there's no signature script.
-    DataOutput(String),
+    /// Holds the exact bytes following OP_RETURN, push opcodes included.
+    DataOutput(Vec<u8>),
 
     /// Pay to Multisig [BIP11]
     /// Pubkey script: <m> <A pubkey>[B pubkey][C pubkey...] <n> OP_CHECKMULTISIG
     /// Signature script: OP_0 <A sig>[B sig][C sig...]
-    /// TODO: Implement Pay2MultiSig: For now only 2n3 MultiSigs are detected
-    Pay2MultiSig,
+    /// Detected for 1 <= m <= n <= 20, the keys are kept in script order.
+    Pay2MultiSig {
+        m: u8,
+        n: u8,
+        keys: Vec<Vec<u8>>,
+    },
 
     /// Pay to Public Key (p2pk) scripts are a simplified form of the p2pkh,
     /// but aren't commonly used in new transactions anymore,
@@ -58,6 +64,25 @@ pub enum ScriptPattern {
     /// Signature script: <sig>[sig][sig...] <redeemScript>
     Pay2ScriptHash,
 
+    /// Pay to Witness Public Key Hash [BIP141]
+    /// Pubkey script: OP_0 <20 byte Hash160(pubkey)>
+    /// Witness: <sig> <pubkey>
+    Pay2WitnessPublicKeyHash,
+
+    /// Pay to Witness Script Hash [BIP141]
+    /// Pubkey script: OP_0 <32 byte SHA256(witnessScript)>
+    /// Witness: <sig>[sig...] <witnessScript>
+    Pay2WitnessScriptHash,
+
+    /// Pay to Taproot [BIP341]
+    /// Pubkey script: OP_1 <32 byte x-only output key>
+    /// Witness: <schnorr sig> or <inputs...> <tapscript> <control block>
+    Pay2Taproot,
+
+    /// Witness program of a version reserved for future soft forks (OP_1 with another length, OP_2 to OP_16).
+    /// Anyone can spend them until their rules are defined.
+    WitnessUnknown(u8),
+
     /// SigScript pubkey sig pattern for tx inputs
     ScriptSig(Vec<u8>, Vec<u8>),
 
@@ -85,10 +110,14 @@ impl fmt::Display for ScriptPattern {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ScriptPattern::DataOutput(_) => write!(f, "DataOutput (OP_RETURN)"),
-            ScriptPattern::Pay2MultiSig => write!(f, "Pay2MultiSig"),
+            ScriptPattern::Pay2MultiSig { m, n, .. } => write!(f, "Pay2MultiSig ({}-of-{})", m, n),
             ScriptPattern::Pay2PublicKey => write!(f, "Pay2PublicKey"),
             ScriptPattern::Pay2PublicKeyHash => write!(f, "Pay2PublicKeyHash"),
             ScriptPattern::Pay2ScriptHash => write!(f, "Pay2ScriptHash"),
+            ScriptPattern::Pay2WitnessPublicKeyHash => write!(f, "Pay2WitnessPublicKeyHash"),
+            ScriptPattern::Pay2WitnessScriptHash => write!(f, "Pay2WitnessScriptHash"),
+            ScriptPattern::Pay2Taproot => write!(f, "Pay2Taproot"),
+            ScriptPattern::WitnessUnknown(version) => write!(f, "WitnessUnknown (v{})", version),
             ScriptPattern::ScriptSig(_,_) => write!(f, "ScriptSig"),
             ScriptPattern::NotRecognised => write!(f, "NotRecognised"),
             ScriptPattern::Error(ref err) => write!(f, "ScriptError: {}", err),
@@ -96,6 +125,67 @@ impl fmt::Display for ScriptPattern {
     }
 }
 
+/// Protocols embedding data in OP_RETURN outputs.
+/// VeriBlock proofs of proof and OpenTimestamps calendar commitments carry no marker in the output,
+/// they are bare headers and digests which can't be told apart from other data and are reported as Unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DataProtocol {
+    /// Omni Layer class C, prefixed with "omni"
+    Omni,
+    /// Prefixed with "CNTRPRTY", usually ARC4 encrypted with the txid of the first input
+    Counterparty,
+    /// Coinbase commitment to the witness merkle root [BIP141], prefixed with 0xaa21a9ed
+    WitnessCommitment,
+    /// Runestones, marked with OP_13 right after OP_RETURN
+    Runes,
+    /// Stacks block commits and other operations, prefixed with "X2" ("T2" on testnet)
+    Stacks,
+    Unknown,
+}
+
+impl DataProtocol {
+    /// Classifies the pushed data of a DataOutput by its prefix
+    pub fn classify(payload: &[u8]) -> DataProtocol {
+        if payload.starts_with(b"omni") {
+            DataProtocol::Omni
+        } else if payload.starts_with(COUNTERPARTY_PREFIX) {
+            DataProtocol::Counterparty
+        } else if payload.len() >= 36 && payload.starts_with(&[0xaa, 0x21, 0xa9, 0xed]) {
+            DataProtocol::WitnessCommitment
+        } else if payload.starts_with(b"X2") || payload.starts_with(b"T2") {
+            DataProtocol::Stacks
+        } else {
+            DataProtocol::Unknown
+        }
+    }
+}
+
+impl fmt::Display for DataProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DataProtocol::Omni => write!(f, "Omni"),
+            DataProtocol::Counterparty => write!(f, "Counterparty"),
+            DataProtocol::WitnessCommitment => write!(f, "WitnessCommitment"),
+            DataProtocol::Runes => write!(f, "Runes"),
+            DataProtocol::Stacks => write!(f, "Stacks"),
+            DataProtocol::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+const COUNTERPARTY_PREFIX: &[u8] = b"CNTRPRTY";
+
+/// Concatenated data pushed by the bytes of a DataOutput, which protocols are recognised by
+fn pushed_data(payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for instruction in Instructions::new(payload) {
+        if let Ok(Instruction::PushBytes(_, d)) = instruction {
+            data.extend_from_slice(d);
+        }
+    }
+    data
+}
+
 pub enum StackElement {
     Op(opcodes::All),
     Data(Vec<u8>),
@@ -111,6 +201,15 @@ impl StackElement {
             StackElement::Data(ref d) => Ok(d.clone()),
         }
     }
+
+    /// Data and opcodes up to OP_16 count as pushes, see IsPushOnly
+    #[inline]
+    pub fn is_push(&self) -> bool {
+        match *self {
+            StackElement::Op(op) => op as u8 <= opcodes::All::OP_PUSHNUM_16 as u8,
+            StackElement::Data(_) => true,
+        }
+    }
 }
 
 impl PartialEq for StackElement {
@@ -133,7 +232,7 @@ impl fmt::Debug for StackElement {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             StackElement::Op(ref op) => write!(f, "{:?}", &op),
-            StackElement::Data(ref d) => write!(f, "{}", &utils::arr_to_hex(&d)),
+            StackElement::Data(ref d) => write!(f, "{}", &utils::arr_to_hex(d)),
         }
     }
 }
@@ -158,9 +257,48 @@ impl fmt::Debug for Stack {
     }
 }
 
+/// Address and key encoding of a coin, see https://en.bitcoin.it/wiki/List_of_address_prefixes
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressParams {
+    pub p2pkh_version: u8,
+    pub p2sh_version: u8,
+    pub bech32_hrp: Option<String>, // None if the coin has no segwit addresses
+    pub wif_prefix: u8,
+}
+
+impl Default for AddressParams {
+    /// Bitcoin mainnet
+    fn default() -> Self {
+        AddressParams {
+            p2pkh_version: 0x00,
+            p2sh_version: 0x05,
+            bech32_hrp: Some(String::from("bc")),
+            wif_prefix: 0x80,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct EvaluatedScript {
     pub address: Option<String>,
     pub pattern: ScriptPattern,
+    pub key_addresses: Vec<String>, // P2PKH addresses of the keys of a bare multisig script
+    pub data_protocol: Option<DataProtocol>, // Only set for DataOutput
+}
+
+impl EvaluatedScript {
+    /// Counterparty encrypts its payload with ARC4. The key is the txid of the first input
+    /// in RPC byte order, so it can only be checked with the spending transaction at hand.
+    pub fn detect_counterparty(&mut self, key: &[u8; 32]) {
+        if self.data_protocol != Some(DataProtocol::Unknown) {
+            return;
+        }
+        if let ScriptPattern::DataOutput(ref payload) = self.pattern {
+            if utils::arc4(key, &pushed_data(payload)).starts_with(COUNTERPARTY_PREFIX) {
+                self.data_protocol = Some(DataProtocol::Counterparty);
+            }
+        }
+    }
 }
 
 /// Evaluates scripts
@@ -172,7 +310,7 @@ pub struct ScriptEvaluator<'a> {
 
 impl<'a> ScriptEvaluator<'a> {
     #[inline]
-    pub fn new(bytes: &'a [u8]) -> ScriptEvaluator {
+    pub fn new(bytes: &'a [u8]) -> ScriptEvaluator<'a> {
         ScriptEvaluator {
             bytes,
             n_bytes: bytes.len(),
@@ -183,76 +321,34 @@ impl<'a> ScriptEvaluator<'a> {
     /// Evaluates script by loading all data into the stack
     pub fn eval(&mut self) -> Result<Stack, ScriptError> {
         let mut elements = Vec::with_capacity(10);
-        //print!("Script(");
-        while self.ip < self.n_bytes {
-            let opcode = opcodes::All::from(self.bytes[self.ip]);
-            let opcode_class = opcode.classify();
-            let data_len = self.maybe_push_data(opcode, opcode_class)?;
-            self.ip += 1;
-
-            if data_len > 0 {
-                //print!(" ");
-                if self.ip + data_len > self.n_bytes {
-                    //println!("!ip: {}, data_len: {}, n_bytes: {}!", self.ip, data_len, self.n_bytes);
-                    return Err(ScriptError::UnexpectedEof);
-                } else {
-                    let data = Vec::from(&self.bytes[self.ip..self.ip + data_len]);
-                    //print!("{}", utils::arr_to_hex(&data));
-                    elements.push(StackElement::Data(data));
-                    self.ip += data_len;
-                }
-            } else if opcode_class != opcodes::Class::NoOp {
-                elements.push(StackElement::Op(opcode));
-                //print!("{:?} ", opcode);
-            }
-        }
-        //println!(")\n");
-        let pattern = ScriptEvaluator::eval_script_pattern(&elements);
-        Ok(Stack { elements, pattern })
-    }
-
-    /// Checks Opcode if should to push some bytes
-    /// Especially opcodes between 0x00 and 0x4e
-    fn maybe_push_data(
-        &mut self,
-        opcode: opcodes::All,
-        opcode_class: opcodes::Class,
-    ) -> Result<usize, ScriptError> {
-        let data_len = if let opcodes::Class::PushBytes(n) = opcode_class {
-            n as usize
-        } else {
-            match opcode {
-                opcodes::All::OP_PUSHDATA1 => {
-                    if self.ip + 1 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 1)?;
-                    self.ip += 1;
-                    val
-                }
-                opcodes::All::OP_PUSHDATA2 => {
-                    if self.ip + 2 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 2)?;
-                    self.ip += 2;
-                    val
+        for instruction in Instructions::new(&self.bytes[self.ip..]) {
+            match instruction? {
+                Instruction::PushBytes(_, data) if !data.is_empty() => {
+                    elements.push(StackElement::Data(data.to_vec()))
                 }
-                opcodes::All::OP_PUSHDATA4 => {
-                    if self.ip + 4 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
+                Instruction::PushBytes(opcode, _) | Instruction::Op(opcode) => {
+                    if opcode.classify() != opcodes::Class::NoOp {
+                        elements.push(StackElement::Op(opcode));
                     }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 4)?;
-                    self.ip += 4;
-                    val
                 }
-                _ => 0,
             }
+        }
+        self.ip = self.n_bytes;
+        let pattern = match witness_program(self.bytes) {
+            Some((0, program)) if program.len() == 20 => ScriptPattern::Pay2WitnessPublicKeyHash,
+            Some((0, program)) if program.len() == 32 => ScriptPattern::Pay2WitnessScriptHash,
+            Some((1, program)) if program.len() == 32 => ScriptPattern::Pay2Taproot,
+            Some((version, _)) if version > 0 => ScriptPattern::WitnessUnknown(version),
+            // Version 0 programs of other lengths fail to validate
+            _ => ScriptEvaluator::eval_script_pattern(self.bytes, &elements)?,
         };
-        Ok(data_len)
+        Ok(Stack { elements, pattern })
     }
 
-    fn eval_script_pattern(elements: &[StackElement]) -> ScriptPattern {
+    fn eval_script_pattern(
+        bytes: &[u8],
+        elements: &[StackElement],
+    ) -> Result<ScriptPattern, ScriptError> {
         // Pay to Public Key Hash (p2pkh)
         let p2pkh = [
             StackElement::Op(opcodes::All::OP_DUP),
@@ -261,8 +357,8 @@ impl<'a> ScriptEvaluator<'a> {
             StackElement::Op(opcodes::All::OP_EQUALVERIFY),
             StackElement::Op(opcodes::All::OP_CHECKSIG),
         ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &p2pkh) {
-            return ScriptPattern::Pay2PublicKeyHash;
+        if ScriptEvaluator::match_stack_pattern(elements, &p2pkh) {
+            return Ok(ScriptPattern::Pay2PublicKeyHash);
         }
 
         // Pay to Public Key (p2pk)
@@ -270,8 +366,8 @@ impl<'a> ScriptEvaluator<'a> {
             StackElement::Data(Vec::new()),
             StackElement::Op(opcodes::All::OP_CHECKSIG),
         ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &p2pk) {
-            return ScriptPattern::Pay2PublicKey;
+        if ScriptEvaluator::match_stack_pattern(elements, &p2pk) {
+            return Ok(ScriptPattern::Pay2PublicKey);
         }
 
         // ScriptSig
@@ -279,10 +375,10 @@ impl<'a> ScriptEvaluator<'a> {
             StackElement::Data(Vec::new()),
             StackElement::Data(Vec::new()),
         ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &script_sig) {
-            let signature = elements[0].data().expect("no signature");
-            let pubkey = elements[1].data().expect("no pubkey");
-            return ScriptPattern::ScriptSig(signature, pubkey);
+        if ScriptEvaluator::match_stack_pattern(elements, &script_sig) {
+            let signature = elements[0].data()?;
+            let pubkey = elements[1].data()?;
+            return Ok(ScriptPattern::ScriptSig(signature, pubkey));
         }
 
         // Pay to Script Hash (p2sh)
@@ -291,35 +387,22 @@ impl<'a> ScriptEvaluator<'a> {
             StackElement::Data(Vec::new()),
             StackElement::Op(opcodes::All::OP_EQUAL),
         ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &p2sh) {
-            return ScriptPattern::Pay2ScriptHash;
+        if ScriptEvaluator::match_stack_pattern(elements, &p2sh) {
+            return Ok(ScriptPattern::Pay2ScriptHash);
         }
 
         // Data output
-        // pubkey: OP_RETURN <0 to 40 bytes of data>
-        let data_output = [
-            StackElement::Op(opcodes::All::OP_RETURN),
-            StackElement::Data(Vec::new()),
-        ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &data_output) {
-            if let Ok(data) = elements[1].data() {
-                return ScriptPattern::DataOutput(String::from_utf8_lossy(&data).into_owned());
-            } else {
-                return ScriptPattern::Error(ScriptError::InvalidFormat);
-            }
+        // pubkey: OP_RETURN <push only data>, see TX_NULL_DATA
+        if bytes.first() == Some(&(opcodes::All::OP_RETURN as u8))
+            && elements[1..].iter().all(StackElement::is_push)
+        {
+            return Ok(ScriptPattern::DataOutput(bytes[1..].to_vec()));
         }
 
-        //TODO: implement n to m multisig
-        let multisig_2n3 = [
-            StackElement::Op(opcodes::All::OP_PUSHNUM_2),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Op(opcodes::All::OP_PUSHNUM_3),
-            StackElement::Op(opcodes::All::OP_CHECKMULTISIG),
-        ];
-        if ScriptEvaluator::match_stack_pattern(&elements, &multisig_2n3) {
-            return ScriptPattern::Pay2MultiSig;
+        // Pay to Multisig
+        // pubkey: <m> <pubkey>... <n> OP_CHECKMULTISIG
+        if let Some(pattern) = ScriptEvaluator::match_multisig(elements) {
+            return Ok(pattern);
         }
         /* TODO:
         // The Genesis Block, self-payments, and pay-by-IP-address payments look like:
@@ -329,20 +412,40 @@ impl<'a> ScriptEvaluator<'a> {
 
          }*/
 
-        ScriptPattern::NotRecognised
+        Ok(ScriptPattern::NotRecognised)
     }
 
-    /// Read a script-encoded unsigned integer.
-    #[inline]
-    fn read_uint(data: &[u8], size: usize) -> Result<usize, ScriptError> {
-        if data.len() < size {
-            Err(ScriptError::UnexpectedEof)
-        } else {
-            let mut ret = 0;
-            for (i, item) in data.iter().enumerate().take(size) {
-                ret += (*item as usize) << (i * 8);
+    /// Matches bare m-of-n multisig scripts like Bitcoin Core's MatchMultisig
+    fn match_multisig(elements: &[StackElement]) -> Option<ScriptPattern> {
+        let len = elements.len();
+        if len < 4 || elements[len - 1] != StackElement::Op(opcodes::All::OP_CHECKMULTISIG) {
+            return None;
+        }
+        let m = ScriptEvaluator::read_small_number(&elements[0])?;
+        let n = ScriptEvaluator::read_small_number(&elements[len - 2])?;
+        if m < 1 || m > n || n > MAX_MULTISIG_KEYS || n as usize != len - 3 {
+            return None;
+        }
+        let mut keys = Vec::with_capacity(n as usize);
+        for element in &elements[1..len - 2] {
+            match *element {
+                StackElement::Data(ref key) if is_pubkey_size(key) => keys.push(key.clone()),
+                _ => return None,
             }
-            Ok(ret)
+        }
+        Some(ScriptPattern::Pay2MultiSig { m, n, keys })
+    }
+
+    /// Reads key counts: OP_1 to OP_16, or a minimally encoded single byte push for 17 to 20
+    #[inline]
+    fn read_small_number(element: &StackElement) -> Option<u8> {
+        match *element {
+            StackElement::Op(op) => match op.classify() {
+                opcodes::Class::PushNum(n) if (1..=16).contains(&n) => Some(n as u8),
+                _ => None,
+            },
+            StackElement::Data(ref data) if data.len() == 1 && data[0] > 16 => Some(data[0]),
+            StackElement::Data(_) => None,
         }
     }
 
@@ -363,61 +466,300 @@ impl<'a> ScriptEvaluator<'a> {
     }
 }
 
+/// A single script operation as read by Bitcoin Core's GetOp
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction<'a> {
+    /// OP_0 to OP_PUSHDATA4 with the pushed data
+    PushBytes(opcodes::All, &'a [u8]),
+    Op(opcodes::All),
+}
+
+impl<'a> Instruction<'a> {
+    /// Returns false if the data could have been pushed with a shorter opcode, see CheckMinimalPush
+    pub fn is_minimal_push(&self) -> bool {
+        let (opcode, data) = match *self {
+            Instruction::PushBytes(opcode, data) => (opcode as u8, data),
+            Instruction::Op(_) => return true,
+        };
+        match data.len() {
+            0 => opcode == opcodes::All::OP_PUSHBYTES_0 as u8,
+            1 if (1..=16).contains(&data[0]) => {
+                opcode == opcodes::All::OP_PUSHNUM_1 as u8 + data[0] - 1
+            }
+            1 if data[0] == 0x81 => opcode == opcodes::All::OP_PUSHNUM_NEG1 as u8,
+            len @ 1..=75 => opcode as usize == len,
+            76..=0xff => opcode == opcodes::All::OP_PUSHDATA1 as u8,
+            len if len <= 0xffff => opcode == opcodes::All::OP_PUSHDATA2 as u8,
+            _ => true,
+        }
+    }
+}
+
+/// Iterates over the instructions of a script.
+/// After a truncated push, UnexpectedEof is returned and the iteration ends.
+#[derive(Clone)]
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    len: usize,
+    failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+    #[inline]
+    pub fn new(bytes: &'a [u8]) -> Instructions<'a> {
+        Instructions {
+            bytes,
+            len: bytes.len(),
+            failed: false,
+        }
+    }
+
+    /// Offset of the next instruction within the script
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.len - self.bytes.len()
+    }
+
+    /// Splits off `n` bytes, or fails the iteration if the script is too short
+    #[inline]
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ScriptError> {
+        if self.bytes.len() < n {
+            self.failed = true;
+            return Err(ScriptError::UnexpectedEof);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn read_push(&mut self, opcode: opcodes::All) -> Result<Instruction<'a>, ScriptError> {
+        let len = match opcode {
+            opcodes::All::OP_PUSHDATA1 => self.take(1)?[0] as usize,
+            opcodes::All::OP_PUSHDATA2 => {
+                let len = self.take(2)?;
+                len[0] as usize | (len[1] as usize) << 8
+            }
+            opcodes::All::OP_PUSHDATA4 => {
+                let len = self.take(4)?;
+                len.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize)
+            }
+            n => n as usize,
+        };
+        Ok(Instruction::PushBytes(opcode, self.take(len)?))
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, ScriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.bytes.is_empty() {
+            return None;
+        }
+        let opcode = opcodes::All::from(self.bytes[0]);
+        self.bytes = &self.bytes[1..];
+        if opcode as u8 <= opcodes::All::OP_PUSHDATA4 as u8 {
+            Some(self.read_push(opcode))
+        } else {
+            Some(Ok(Instruction::Op(opcode)))
+        }
+    }
+}
+
+/// Disassembles a script like Bitcoin Core's ScriptToAsmStr.
+/// Pushes of up to 4 bytes are shown as numbers, unparsable scripts end with `[error]`.
+/// With `decode_sighash`, the hash type of DER signatures is appended as e.g. `[ALL]`, as done for scriptSigs.
+pub fn to_asm(script: &[u8], decode_sighash: bool) -> String {
+    // Data of unspendable scripts may look like a signature
+    let unspendable =
+        script.first() == Some(&(opcodes::All::OP_RETURN as u8)) || script.len() > MAX_SCRIPT_SIZE;
+    let decode_sighash = decode_sighash && !unspendable;
+    let mut asm = Vec::new();
+    for instruction in Instructions::new(script) {
+        match instruction {
+            Ok(Instruction::PushBytes(_, data)) if data.len() <= 4 => {
+                asm.push(decode_script_num(data).to_string())
+            }
+            Ok(Instruction::PushBytes(_, data)) => match sighash_name(data) {
+                Some(name) if decode_sighash => asm.push(format!(
+                    "{}[{}]",
+                    utils::arr_to_hex(&data[..data.len() - 1]),
+                    name
+                )),
+                _ => asm.push(utils::arr_to_hex(data)),
+            },
+            Ok(Instruction::Op(opcode)) => asm.push(asm_opcode_name(opcode)),
+            Err(_) => {
+                asm.push(String::from("[error]"));
+                break;
+            }
+        }
+    }
+    asm.join(" ")
+}
+
+/// Returns the opcode name used by Bitcoin Core
+fn asm_opcode_name(opcode: opcodes::All) -> String {
+    if let opcodes::Class::PushNum(n) = opcode.classify() {
+        return n.to_string();
+    }
+    match opcode as u8 {
+        0xb1 => String::from("OP_CHECKLOCKTIMEVERIFY"),
+        0xb2 => String::from("OP_CHECKSEQUENCEVERIFY"),
+        0xba => String::from("OP_CHECKSIGADD"),
+        0xbb..=0xfe => String::from("OP_UNKNOWN"),
+        0xff => String::from("OP_INVALIDOPCODE"),
+        _ => format!("{:?}", opcode),
+    }
+}
+
+/// Decodes a little endian number with sign bit as used by script, without checking for minimal encoding
+pub fn decode_script_num(data: &[u8]) -> i64 {
+    let value = data.iter().rev().fold(0i64, |acc, &b| acc << 8 | b as i64);
+    match data.last() {
+        Some(last) if last & 0x80 != 0 => -(value & !(0x80 << (8 * (data.len() - 1)))),
+        _ => value,
+    }
+}
+
+/// Returns the name of the hash type if `sig` is a strictly DER encoded signature with a defined hash type
+fn sighash_name(sig: &[u8]) -> Option<&'static str> {
+    if !is_valid_signature_encoding(sig) {
+        return None;
+    }
+    match sig[sig.len() - 1] {
+        0x01 => Some("ALL"),
+        0x81 => Some("ALL|ANYONECANPAY"),
+        0x02 => Some("NONE"),
+        0x82 => Some("NONE|ANYONECANPAY"),
+        0x03 => Some("SINGLE"),
+        0x83 => Some("SINGLE|ANYONECANPAY"),
+        _ => None,
+    }
+}
+
+/// Checks the strict DER encoding of a signature followed by the hash type byte [BIP66]
+pub fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() || sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    // Null bytes at the start of R are only allowed to keep it positive
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    !(len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0)
+}
+
 /// Extracts evaluated address from ScriptPubKey
-pub fn eval_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+pub fn eval_from_bytes(bytes: &[u8], params: &AddressParams) -> EvaluatedScript {
     match ScriptEvaluator::new(bytes).eval() {
-        Ok(stack) => eval_from_stack(stack, version_id),
+        Ok(stack) => eval_from_stack(stack, params),
         Err(err) => EvaluatedScript {
             address: None,
             pattern: ScriptPattern::Error(err),
+            key_addresses: Vec::new(),
+            data_protocol: None,
         },
     }
 }
 
 /// Extracts evaluated address from script stack
-pub fn eval_from_stack(stack: Stack, version_id: u8) -> EvaluatedScript {
+pub fn eval_from_stack(stack: Stack, params: &AddressParams) -> EvaluatedScript {
     // Wrap everything in a closure to early catch try!()
     match (|| -> Result<EvaluatedScript, ScriptError> {
         let script = match stack.pattern {
             ref p @ ScriptPattern::Pay2PublicKey => {
                 let pub_key = stack.elements[0].data()?;
                 EvaluatedScript {
-                    address: Some(public_key_to_addr(&pub_key, version_id)),
+                    address: Some(public_key_to_addr(&pub_key, params.p2pkh_version)),
                     pattern: p.clone(),
+                    key_addresses: Vec::new(),
+                    data_protocol: None,
                 }
             }
             ref p @ ScriptPattern::Pay2PublicKeyHash => {
                 let h160 = stack.elements[2].data()?;
                 EvaluatedScript {
-                    address: Some(hash_160_to_address(&h160, version_id)),
+                    address: Some(hash_160_to_address(&h160, params.p2pkh_version)),
                     pattern: p.clone(),
+                    key_addresses: Vec::new(),
+                    data_protocol: None,
                 }
             }
             ref p @ ScriptPattern::Pay2ScriptHash => {
                 let h160 = stack.elements[1].data()?;
                 EvaluatedScript {
-                    address: Some(hash_160_to_address(&h160, 5)),
+                    address: Some(hash_160_to_address(&h160, params.p2sh_version)),
                     pattern: p.clone(),
+                    key_addresses: Vec::new(),
+                    data_protocol: None,
                 }
             }
-            ScriptPattern::DataOutput(ref data) => EvaluatedScript {
-                address: None,
-                pattern: ScriptPattern::DataOutput(data.clone()),
-            },
-            ref p @ ScriptPattern::Pay2MultiSig => {
-                stack.elements[1].data()?;
+            ref p @ ScriptPattern::Pay2WitnessPublicKeyHash
+            | ref p @ ScriptPattern::Pay2WitnessScriptHash
+            | ref p @ ScriptPattern::Pay2Taproot
+            | ref p @ ScriptPattern::WitnessUnknown(_) => {
+                let version = match stack.elements[0] {
+                    StackElement::Op(op) => {
+                        witness_version(op).ok_or(ScriptError::InvalidFormat)?
+                    }
+                    StackElement::Data(_) => return Err(ScriptError::InvalidFormat),
+                };
+                let program = stack.elements[1].data()?;
                 EvaluatedScript {
-                    address: None,
+                    address: params
+                        .bech32_hrp
+                        .as_ref()
+                        .map(|hrp| bech32::encode_witness_address(hrp, version, &program)),
                     pattern: p.clone(),
+                    key_addresses: Vec::new(),
+                    data_protocol: None,
                 }
             }
+            ScriptPattern::DataOutput(ref data) => {
+                let protocol = match stack.elements.get(1) {
+                    Some(StackElement::Op(opcodes::All::OP_PUSHNUM_13)) => DataProtocol::Runes,
+                    _ => DataProtocol::classify(&pushed_data(data)),
+                };
+                EvaluatedScript {
+                    address: None,
+                    pattern: ScriptPattern::DataOutput(data.clone()),
+                    key_addresses: Vec::new(),
+                    data_protocol: Some(protocol),
+                }
+            }
+            ScriptPattern::Pay2MultiSig { ref keys, .. } => EvaluatedScript {
+                address: None,
+                pattern: stack.pattern.clone(),
+                key_addresses: keys
+                    .iter()
+                    .map(|key| public_key_to_addr(key, params.p2pkh_version))
+                    .collect(),
+                data_protocol: None,
+            },
             ref p @ ScriptPattern::NotRecognised => EvaluatedScript {
                 address: None,
                 pattern: p.clone(),
+                key_addresses: Vec::new(),
+                data_protocol: None,
             },
             ref p => EvaluatedScript {
                 address: None,
                 pattern: p.clone(),
+                key_addresses: Vec::new(),
+                data_protocol: None,
             },
         };
         Ok(script)
@@ -426,10 +768,42 @@ pub fn eval_from_stack(stack: Stack, version_id: u8) -> EvaluatedScript {
         Err(e) => EvaluatedScript {
             address: None,
             pattern: ScriptPattern::Error(e),
+            key_addresses: Vec::new(),
+            data_protocol: None,
         },
     }
 }
 
+/// Returns witness version and program if the script is a witness program, see BIP141.
+/// It consists of a version opcode followed by a direct push of 2 to 40 bytes.
+pub fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 || script[1] as usize + 2 != script.len() {
+        return None;
+    }
+    let version = witness_version(opcodes::All::from(script[0]))?;
+    Some((version, &script[2..]))
+}
+
+/// OP_0 and OP_1 to OP_16 select the witness version
+#[inline]
+fn witness_version(op: opcodes::All) -> Option<u8> {
+    match op.classify() {
+        opcodes::Class::PushBytes(0) => Some(0),
+        opcodes::Class::PushNum(n) if (1..=16).contains(&n) => Some(n as u8),
+        _ => None,
+    }
+}
+
+/// Compressed keys start with 0x02 or 0x03, uncompressed and hybrid ones with 0x04, 0x06 or 0x07
+#[inline]
+fn is_pubkey_size(key: &[u8]) -> bool {
+    match key.first() {
+        Some(0x02) | Some(0x03) => key.len() == 33,
+        Some(0x04) | Some(0x06) | Some(0x07) => key.len() == 65,
+        _ => false,
+    }
+}
+
 /// Takes full ECDSA public key (65 bytes) and a version id
 fn public_key_to_addr(pub_key: &[u8], version: u8) -> String {
     let h160 = utils::ridemp160(&utils::sha256(pub_key));
@@ -439,17 +813,114 @@ fn public_key_to_addr(pub_key: &[u8], version: u8) -> String {
 /// Takes 20 byte public key and version id
 #[inline]
 fn hash_160_to_address(h160: &[u8], version: u8) -> String {
-    let mut hash = Vec::with_capacity(h160.len() + 5);
-    hash.push(version);
-    hash.extend_from_slice(&h160);
-    let checksum = &utils::sha256(&utils::sha256(&hash))[0..4];
-    hash.extend_from_slice(checksum);
-    hash.to_base58()
+    let mut payload = Vec::with_capacity(h160.len() + 5);
+    payload.push(version);
+    payload.extend_from_slice(h160);
+    encode_base58check(payload)
+}
+
+/// Returns the pubkey script paying to `address`, or None if it isn't a valid address of the coin
+pub fn address_to_script_pubkey(address: &str, params: &AddressParams) -> Option<Vec<u8>> {
+    if let Some(ref hrp) = params.bech32_hrp {
+        if let Ok((version, program)) = bech32::decode_witness_address(hrp, address) {
+            let version_op = match version {
+                0 => opcodes::All::OP_PUSHBYTES_0 as u8,
+                n => opcodes::All::OP_PUSHNUM_1 as u8 + n - 1,
+            };
+            let mut script = vec![version_op, program.len() as u8];
+            script.extend(program);
+            return Some(script);
+        }
+    }
+    let payload = decode_base58check(address)?;
+    if payload.len() != 21 {
+        return None;
+    }
+    let (version, h160) = (payload[0], &payload[1..]);
+    let mut script = Vec::with_capacity(25);
+    if version == params.p2pkh_version {
+        script.extend_from_slice(&[
+            opcodes::All::OP_DUP as u8,
+            opcodes::All::OP_HASH160 as u8,
+            0x14,
+        ]);
+        script.extend_from_slice(h160);
+        script.extend_from_slice(&[
+            opcodes::All::OP_EQUALVERIFY as u8,
+            opcodes::All::OP_CHECKSIG as u8,
+        ]);
+    } else if version == params.p2sh_version {
+        script.extend_from_slice(&[opcodes::All::OP_HASH160 as u8, 0x14]);
+        script.extend_from_slice(h160);
+        script.push(opcodes::All::OP_EQUAL as u8);
+    } else {
+        return None;
+    }
+    Some(script)
+}
+
+/// Encodes a private key in wallet import format.
+/// `compressed` marks keys whose public key is used in compressed form.
+pub fn encode_wif(secret: &[u8; 32], compressed: bool, params: &AddressParams) -> String {
+    let mut payload = Vec::with_capacity(38);
+    payload.push(params.wif_prefix);
+    payload.extend_from_slice(secret);
+    if compressed {
+        payload.push(0x01);
+    }
+    encode_base58check(payload)
+}
+
+/// Decodes a private key in wallet import format into the key and its compressed flag
+pub fn decode_wif(wif: &str, params: &AddressParams) -> Option<([u8; 32], bool)> {
+    let payload = decode_base58check(wif)?;
+    let compressed = match payload.len() {
+        33 => false,
+        34 if payload[33] == 0x01 => true,
+        _ => return None,
+    };
+    if payload[0] != params.wif_prefix {
+        return None;
+    }
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&payload[1..33]);
+    Some((secret, compressed))
+}
+
+/// Appends the 4 byte checksum and encodes the payload in base58
+fn encode_base58check(mut payload: Vec<u8>) -> String {
+    let checksum = utils::sha256(&utils::sha256(&payload));
+    payload.extend_from_slice(&checksum[0..4]);
+    payload.to_base58()
+}
+
+/// Returns the payload if the checksum is valid
+fn decode_base58check(encoded: &str) -> Option<Vec<u8>> {
+    let mut payload = encoded.from_base58().ok()?;
+    if payload.len() < 4 {
+        return None;
+    }
+    let checksum = payload.split_off(payload.len() - 4);
+    if utils::sha256(&utils::sha256(&payload))[0..4] != checksum[..] {
+        return None;
+    }
+    Some(payload)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{eval_from_bytes, eval_from_stack, ScriptError, ScriptEvaluator, ScriptPattern};
+    use super::{
+        address_to_script_pubkey, decode_wif, encode_wif, eval_from_bytes, eval_from_stack, to_asm,
+        AddressParams, DataProtocol, Instruction, Instructions, ScriptError, ScriptEvaluator,
+        ScriptPattern,
+    };
+    use crate::blockchain::parser::types::{CoinRegistry, CoinType};
+    use crate::blockchain::proto::opcodes;
+    use crate::common::utils;
+
+    fn coin(id: &str) -> AddressParams {
+        id.parse::<CoinType>().unwrap().address_params()
+    }
 
     #[test]
     fn test_bitcoin_script_p2pkh() {
@@ -466,7 +937,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, &AddressParams::default());
         assert_eq!(
             script.address,
             Some(String::from("12higDjoCCNXSA95xZMWUdPvXNmkAduhWv"))
@@ -492,7 +963,7 @@ mod tests {
         assert_eq!("044bca633a91de10df85a63d0a24cb09783148fe0e16c92e937fc4491580c860757148effa0595a955f44078b48ba67fa198782e8bb68115da0daa8fde5301f7f9 OP_CHECKSIG",
             format!("{:?}", stack));
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, &AddressParams::default());
         assert_eq!(
             script.address,
             Some(String::from("1LEWwJkDj8xriE87ALzQYcHjTmD8aqDj1f"))
@@ -506,7 +977,6 @@ mod tests {
         // OP_2 33 0x022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da
         // 33 0x03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9
         // 33 0x021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_3 OP_CHECKMULTISIG
-        //TODO: complete this test
         let bytes = [
             0x52, 0x21, 0x02, 0x2d, 0xf8, 0x75, 0x04, 0x80, 0xad, 0x5b, 0x26, 0x95, 0x0b, 0x25,
             0xc7, 0xba, 0x79, 0xd3, 0xe3, 0x7d, 0x75, 0xf6, 0x40, 0xf8, 0xe5, 0xd9, 0xbc, 0xd5,
@@ -525,7 +995,75 @@ mod tests {
                    03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9 \
                    021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_PUSHNUM_3 OP_CHECKMULTISIG",
                    format!("{:?}", stack));
-        assert_eq!(stack.pattern, ScriptPattern::Pay2MultiSig);
+        let keys = vec![
+            bytes[2..35].to_vec(),
+            bytes[36..69].to_vec(),
+            bytes[70..103].to_vec(),
+        ];
+        assert_eq!(
+            stack.pattern,
+            ScriptPattern::Pay2MultiSig { m: 2, n: 3, keys }
+        );
+        assert_eq!(format!("{}", stack.pattern), "Pay2MultiSig (2-of-3)");
+
+        let script = eval_from_stack(stack, &AddressParams::default());
+        assert_eq!(script.address, None);
+        assert_eq!(
+            script.key_addresses,
+            vec![
+                "1EiY9DVNGHFWrkpNiU9PDx747w95Fi29RS",
+                "1FWTTERvzzeyaepf6Mq5PrxvszvfWBVFch",
+                "1GFaeQwaDwfZHdXxML257nDheKJtqneFAY"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2ms_m_of_n() {
+        let compressed = format!("21{}", "02".repeat(33));
+        let uncompressed = format!("41{}", "04".repeat(65));
+        let multisig = |m: &str, keys: &[&str], n: &str| {
+            let script = utils::hex_to_vec(&format!("{}{}{}ae", m, keys.concat(), n));
+            eval_from_bytes(&script, &AddressParams::default())
+        };
+
+        // 1-of-2 with an uncompressed key, as used by data-carrying outputs
+        let script = multisig("51", &[&compressed, &uncompressed], "52");
+        match script.pattern {
+            ScriptPattern::Pay2MultiSig {
+                m: 1,
+                n: 2,
+                ref keys,
+            } => assert_eq!(keys[1].len(), 65),
+            ref p => panic!("Unexpected pattern {}", p),
+        }
+        assert_eq!(script.key_addresses.len(), 2);
+
+        // Counts above 16 are pushed as data
+        let keys = vec![compressed.as_str(); 20];
+        let script = multisig("0111", &keys, "0114");
+        assert_eq!(format!("{}", script.pattern), "Pay2MultiSig (17-of-20)");
+        assert_eq!(script.key_addresses.len(), 20);
+        assert_eq!(multisig("60", &keys[..16], "60").key_addresses.len(), 16);
+
+        // m > n, key count mismatch, m = 0, non-minimal count, invalid key and too many keys
+        let keys = vec![compressed.as_str(); 21];
+        let invalid = [
+            multisig("53", &keys[..2], "52"),
+            multisig("51", &keys[..3], "52"),
+            multisig("00", &keys[..1], "51"),
+            multisig("0102", &keys[..2], "52"),
+            multisig(
+                "51",
+                &[&compressed, &format!("21{}", "04".repeat(33))],
+                "52",
+            ),
+            multisig("0115", &keys, "0115"),
+        ];
+        for script in invalid.iter() {
+            assert_eq!(script.pattern, ScriptPattern::NotRecognised);
+            assert!(script.key_addresses.is_empty());
+        }
     }
 
     #[test]
@@ -544,7 +1082,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, &AddressParams::default());
         assert_eq!(
             script.address,
             Some(String::from("3P14159f73E4gFr7JterCCQh9QjiTjiZrG"))
@@ -552,6 +1090,232 @@ mod tests {
         assert_eq!(script.pattern, ScriptPattern::Pay2ScriptHash);
     }
 
+    #[test]
+    fn test_bitcoin_script_witness_programs() {
+        // Test vectors from BIP173 and BIP350
+        let bitcoin = AddressParams::default();
+        let vectors = [
+            (
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                ScriptPattern::Pay2WitnessPublicKeyHash,
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            ),
+            (
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                ScriptPattern::Pay2WitnessScriptHash,
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+            ),
+            (
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                ScriptPattern::Pay2Taproot,
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+            ),
+            (
+                "6002751e",
+                ScriptPattern::WitnessUnknown(16),
+                "bc1sw50qgdz25j",
+            ),
+            (
+                "5210751e76e8199196d454941c45d1b3a323",
+                ScriptPattern::WitnessUnknown(2),
+                "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs",
+            ),
+        ];
+        for (script_pubkey, pattern, address) in vectors.iter() {
+            let script = eval_from_bytes(&utils::hex_to_vec(script_pubkey), &bitcoin);
+            assert_eq!(script.pattern, *pattern);
+            assert_eq!(script.address.as_deref(), Some(*address));
+            assert_eq!(
+                address_to_script_pubkey(address, &bitcoin),
+                Some(utils::hex_to_vec(script_pubkey))
+            );
+        }
+
+        // The human readable part depends on the coin, coins without segwit have no address
+        let litecoin = coin("litecoin");
+        let script = eval_from_bytes(&utils::hex_to_vec(vectors[0].0), &litecoin);
+        assert!(script.address.unwrap().starts_with("ltc1q"));
+        let dogecoin = coin("dogecoin");
+        let script = eval_from_bytes(&utils::hex_to_vec(vectors[0].0), &dogecoin);
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+        assert_eq!(script.address, None);
+
+        // Invalid version 0 length, too short, too long
+        let not_witness = [
+            "0015751e76e8199196d454941c45d1b3a323f1433bd6aa",
+            "5101aa",
+            "5129751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6aa",
+        ];
+        for script_pubkey in not_witness.iter() {
+            let script = eval_from_bytes(&utils::hex_to_vec(script_pubkey), &bitcoin);
+            assert_eq!(
+                script.pattern,
+                ScriptPattern::NotRecognised,
+                "{}",
+                script_pubkey
+            );
+            assert_eq!(script.address, None);
+        }
+    }
+
+    #[test]
+    fn test_address_prefixes() {
+        // P2SH and WIF prefixes differ between coins
+        let h160 = utils::hex_to_vec("e9c3dd0c07aac76179ebc76a6c78d4d67c6c160a");
+        let p2sh = [&[0xa9, 0x14][..], &h160, &[0x87]].concat();
+        let expected = [
+            ("litecoin", "M"),
+            ("dogecoin", "A"),
+            ("testnet3", "2"),
+            ("regtest", "2"),
+        ];
+        for (id, prefix) in expected.iter() {
+            let address = eval_from_bytes(&p2sh, &coin(id)).address.unwrap();
+            assert!(address.starts_with(prefix), "{}: {}", id, address);
+        }
+        let p2wpkh = [&[0x00, 0x14][..], &h160].concat();
+        let address = eval_from_bytes(&p2wpkh, &coin("regtest")).address.unwrap();
+        assert!(address.starts_with("bcrt1q"), "{}", address);
+
+        // Test vectors from https://en.bitcoin.it/wiki/Wallet_import_format
+        let bitcoin = AddressParams::default();
+        let mut secret = [0u8; 32];
+        secret.copy_from_slice(&utils::hex_to_vec(
+            "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d",
+        ));
+        let uncompressed = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let compressed = "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617";
+        assert_eq!(encode_wif(&secret, false, &bitcoin), uncompressed);
+        assert_eq!(encode_wif(&secret, true, &bitcoin), compressed);
+        assert_eq!(decode_wif(uncompressed, &bitcoin), Some((secret, false)));
+        assert_eq!(decode_wif(compressed, &bitcoin), Some((secret, true)));
+        // Wrong coin and broken checksum
+        assert_eq!(decode_wif(compressed, &coin("litecoin")), None);
+        assert_eq!(decode_wif(&compressed.replace('K', "L"), &bitcoin), None);
+    }
+
+    #[test]
+    fn test_script_to_asm() {
+        // Expected output of Bitcoin Core's decodescript
+        let sig = "304502207fa7a6d1e0ee81132a269ad84e68d695483745cde8b541e3bf630749894e342a022100c1f7ab20e13e22fb95281a870f3dcf38d782e53023ee313d741ad0b4f9bcb5cd";
+        let pubkey = "03b0da749730dc9b4b1f4a14d6902877a92541f5368778853d9c4a0cb7802dcfb2";
+        let script_sig = format!("48{}01 21{}", sig, pubkey).replace(' ', "");
+        assert_eq!(
+            to_asm(&utils::hex_to_vec(&script_sig), true),
+            format!("{}[ALL] {}", sig, pubkey)
+        );
+        assert_eq!(
+            to_asm(&utils::hex_to_vec(&script_sig), false),
+            format!("{}01 {}", sig, pubkey)
+        );
+        let script_sig = format!("48{}83", sig);
+        assert_eq!(
+            to_asm(&utils::hex_to_vec(&script_sig), true),
+            format!("{}[SINGLE|ANYONECANPAY]", sig)
+        );
+        // Undefined hash type, data of OP_RETURN outputs is never decoded
+        let script_sig = format!("48{}04", sig);
+        assert_eq!(
+            to_asm(&utils::hex_to_vec(&script_sig), true),
+            format!("{}04", sig)
+        );
+        let data_output = format!("6a48{}01", sig);
+        assert_eq!(
+            to_asm(&utils::hex_to_vec(&data_output), true),
+            format!("OP_RETURN {}01", sig)
+        );
+
+        let vectors = [
+            (
+                "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac",
+                "OP_DUP OP_HASH160 12ab8dc588ca9d5787dde7eb29569da63c3a238c OP_EQUALVERIFY OP_CHECKSIG",
+            ),
+            ("00 4f 51 60 0100 0181 03ffffff 0480969800", "0 -1 1 16 0 -1 -8388607 10000000"),
+            ("4c0105 4d0100ff 4e01000000ff", "5 -127 -127"),
+            (
+                "6a 61 b1 b2 b3 ba bb ff 50 89",
+                "OP_RETURN OP_NOP OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_NOP4 OP_CHECKSIGADD OP_UNKNOWN \
+                 OP_INVALIDOPCODE OP_RESERVED OP_RESERVED1",
+            ),
+            ("", ""),
+            ("51 4c", "1 [error]"),
+            ("51 4d01", "1 [error]"),
+            ("51 05aabb", "1 [error]"),
+        ];
+        for (script, asm) in vectors.iter() {
+            assert_eq!(
+                to_asm(&utils::hex_to_vec(&script.replace(' ', "")), true),
+                *asm
+            );
+        }
+    }
+
+    #[test]
+    fn test_script_instructions() {
+        let script = utils::hex_to_vec(
+            &format!("00 4c00 0111 4c0105 51 4c4b{}", "aa".repeat(75)).replace(' ', ""),
+        );
+        let minimal: Vec<bool> = Instructions::new(&script)
+            .map(|i| i.unwrap().is_minimal_push())
+            .collect();
+        assert_eq!(minimal, vec![true, false, true, false, true, false]);
+        let instructions: Vec<_> = Instructions::new(&script[..6]).collect();
+        assert_eq!(
+            instructions[2],
+            Ok(Instruction::PushBytes(
+                opcodes::All::OP_PUSHBYTES_1,
+                &[0x11]
+            ))
+        );
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[3], Err(ScriptError::UnexpectedEof));
+
+        // OP_PUSHDATA lengths follow the opcode
+        let mut script = ScriptEvaluator::new(&[0x6a, 0x4c, 0x02, 0xaa, 0xbb]);
+        let stack = script.eval().unwrap();
+        assert_eq!(format!("{:?}", stack), "OP_RETURN aabb");
+        assert_eq!(
+            stack.pattern,
+            ScriptPattern::DataOutput(vec![0x4c, 0x02, 0xaa, 0xbb])
+        );
+    }
+
+    #[test]
+    fn test_address_roundtrip_all_coins() {
+        let h160 = utils::hex_to_vec("751e76e8199196d454941c45d1b3a323f1433bd6");
+        let secret = [0x11; 32];
+        let scripts = [
+            [&[0x76, 0xa9, 0x14][..], &h160, &[0x88, 0xac]].concat(),
+            [&[0xa9, 0x14][..], &h160, &[0x87]].concat(),
+            [&[0x00, 0x14][..], &h160].concat(),
+        ];
+        for id in CoinRegistry::builtin().ids() {
+            let params = coin(id);
+            for script_pubkey in scripts.iter() {
+                match eval_from_bytes(script_pubkey, &params).address {
+                    Some(address) => {
+                        assert_eq!(
+                            address_to_script_pubkey(&address, &params).as_ref(),
+                            Some(script_pubkey),
+                            "{}",
+                            id
+                        )
+                    }
+                    None => assert!(params.bech32_hrp.is_none(), "{}", id),
+                }
+            }
+            for compressed in [false, true].iter() {
+                let wif = encode_wif(&secret, *compressed, &params);
+                assert_eq!(
+                    decode_wif(&wif, &params),
+                    Some((secret, *compressed)),
+                    "{}",
+                    id
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_bitcoin_script_data_output() {
         // Raw output script: 6a13636861726c6579206c6f766573206865696469
@@ -567,12 +1331,96 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, &AddressParams::default());
         assert_eq!(script.address, None);
         assert_eq!(
             script.pattern,
-            ScriptPattern::DataOutput(String::from("charley loves heidi"))
+            ScriptPattern::DataOutput(bytes[1..].to_vec())
+        );
+        assert_eq!(script.data_protocol, Some(DataProtocol::Unknown));
+    }
+
+    #[test]
+    fn test_data_output_protocols() {
+        let classify = |hex: &str| {
+            let script = eval_from_bytes(&utils::hex_to_vec(hex), &AddressParams::default());
+            assert!(
+                matches!(script.pattern, ScriptPattern::DataOutput(_)),
+                "{}",
+                hex
+            );
+            script.data_protocol.unwrap()
+        };
+        // Omni simple send, Stacks block commit, witness commitment
+        assert_eq!(
+            classify("6a146f6d6e69000000000000001f000000002b752ee0"),
+            DataProtocol::Omni
+        );
+        assert_eq!(
+            classify(&format!("6a4c50{}", hex_repeat("58325b", 3, 80))),
+            DataProtocol::Stacks
+        );
+        assert_eq!(
+            classify(&format!("6a24aa21a9ed{}", "11".repeat(32))),
+            DataProtocol::WitnessCommitment
+        );
+        // Payloads without a marker, like calendar digests and VeriBlock headers, are not guessed by their size
+        assert_eq!(
+            classify(&format!("6a20{}", "22".repeat(32))),
+            DataProtocol::Unknown
         );
+        assert_eq!(
+            classify(&format!("6a4c50{}", "33".repeat(80))),
+            DataProtocol::Unknown
+        );
+        // Runestone: OP_RETURN OP_13 <data pushes>
+        assert_eq!(classify("6a5d0614c0a2331441"), DataProtocol::Runes);
+        // Bare OP_RETURN and pushes of numbers are still null data, their bytes are kept as is
+        assert_eq!(classify("6a"), DataProtocol::Unknown);
+        let script = eval_from_bytes(
+            &utils::hex_to_vec("6a00514f021234"),
+            &AddressParams::default(),
+        );
+        assert_eq!(
+            script.pattern,
+            ScriptPattern::DataOutput(vec![0x00, 0x51, 0x4f, 0x02, 0x12, 0x34])
+        );
+        // OP_RETURN must be the first opcode
+        let script = eval_from_bytes(&utils::hex_to_vec("616a021234"), &AddressParams::default());
+        assert_eq!(script.pattern, ScriptPattern::NotRecognised);
+        // Other opcodes after OP_RETURN are not
+        let script = eval_from_bytes(&utils::hex_to_vec("6a021234ac"), &AddressParams::default());
+        assert_eq!(script.pattern, ScriptPattern::NotRecognised);
+        assert_eq!(script.data_protocol, None);
+    }
+
+    #[test]
+    fn test_data_output_counterparty() {
+        let key = [0x5a; 32];
+        let payload = utils::arc4(
+            &key,
+            b"CNTRPRTY\x00\x00\x00\x14\x00\x00\x00\x00\x00\x00\x00\x01",
+        );
+        let script_pubkey = [&[0x6a, payload.len() as u8][..], &payload].concat();
+        let mut script = eval_from_bytes(&script_pubkey, &AddressParams::default());
+        assert_eq!(script.data_protocol, Some(DataProtocol::Unknown));
+        script.detect_counterparty(&[0xa5; 32]);
+        assert_eq!(script.data_protocol, Some(DataProtocol::Unknown));
+        script.detect_counterparty(&key);
+        assert_eq!(script.data_protocol, Some(DataProtocol::Counterparty));
+
+        // Unencrypted payloads are detected by prefix
+        let script = eval_from_bytes(
+            &utils::hex_to_vec("6a0a434e54525052545900ff"),
+            &AddressParams::default(),
+        );
+        assert_eq!(script.data_protocol, Some(DataProtocol::Counterparty));
+    }
+
+    /// Repeats `prefix` hex `n` times and pads it with zeros to `len` bytes
+    fn hex_repeat(prefix: &str, n: usize, len: usize) -> String {
+        let hex = prefix.repeat(n);
+        format!("{}{}", hex, "00".repeat(len - hex.len() / 2))
     }
 
     #[test]
@@ -587,7 +1435,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, &AddressParams::default());
         assert_eq!(script.address, None);
         assert_eq!(script.pattern, ScriptPattern::NotRecognised);
     }
@@ -595,7 +1443,7 @@ mod tests {
     #[test]
     fn test_bitcoin_bogus_script() {
         let bytes = [0x4c, 0xFF, 0x00];
-        let script = eval_from_bytes(&bytes, 0x00);
+        let script = eval_from_bytes(&bytes, &AddressParams::default());
         assert_eq!(script.address, None);
         assert_eq!(
             script.pattern,
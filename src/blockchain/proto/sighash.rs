@@ -0,0 +1,473 @@
+use std::cell::OnceCell;
+
+use crate::blockchain::proto::opcodes;
+use crate::blockchain::proto::script::{Instruction, Instructions};
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::ToRaw;
+use crate::common::utils;
+
+/// Taproot only: same as SIGHASH_ALL, but the hash type isn't appended to the signature
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+pub const SIGHASH_ALL: u8 = 0x01;
+pub const SIGHASH_NONE: u8 = 0x02;
+pub const SIGHASH_SINGLE: u8 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// Leaf version of tapscripts [BIP342]
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+
+/// Computes the message hashes signed by the inputs of a transaction.
+/// Hashes over all inputs and outputs are computed once per transaction, see PrecomputedTransactionData.
+pub struct SighashCache<'a> {
+    tx: &'a EvaluatedTx,
+    spent: Vec<&'a TxOutput>,
+    shared: OnceCell<SharedHashes>,
+}
+
+/// Single SHA256 hashes of BIP341, BIP143 uses the double SHA256 of the first three
+struct SharedHashes {
+    prevouts: [u8; 32],
+    sequences: [u8; 32],
+    outputs: [u8; 32],
+    amounts: [u8; 32],
+    scripts: [u8; 32],
+}
+
+impl<'a> SighashCache<'a> {
+    /// `spent` holds the output spent by each input of `tx`
+    pub fn new(tx: &'a EvaluatedTx, spent: Vec<&'a TxOutput>) -> Self {
+        assert_eq!(
+            tx.inputs.len(),
+            spent.len(),
+            "Each input needs the output it spends"
+        );
+        SighashCache {
+            tx,
+            spent,
+            shared: OnceCell::new(),
+        }
+    }
+
+    #[inline]
+    pub fn tx(&self) -> &'a EvaluatedTx {
+        self.tx
+    }
+
+    /// Output spent by input `vin`
+    #[inline]
+    pub fn spent(&self, vin: usize) -> &'a TxOutput {
+        self.spent[vin]
+    }
+
+    fn shared(&self) -> &SharedHashes {
+        self.shared.get_or_init(|| {
+            let mut prevouts = Vec::with_capacity(36 * self.tx.inputs.len());
+            let mut sequences = Vec::with_capacity(4 * self.tx.inputs.len());
+            for input in &self.tx.inputs {
+                prevouts.extend_from_slice(&input.input.outpoint.to_bytes());
+                sequences.extend_from_slice(&input.input.seq_no.to_le_bytes());
+            }
+            let mut outputs = Vec::new();
+            for output in &self.tx.outputs {
+                outputs.extend_from_slice(&output.out.to_bytes());
+            }
+            let mut amounts = Vec::with_capacity(8 * self.spent.len());
+            let mut scripts = Vec::new();
+            for spent in &self.spent {
+                amounts.extend_from_slice(&spent.value.to_le_bytes());
+                write_script(&mut scripts, &spent.script_pubkey);
+            }
+            SharedHashes {
+                prevouts: utils::sha256(&prevouts),
+                sequences: utils::sha256(&sequences),
+                outputs: utils::sha256(&outputs),
+                amounts: utils::sha256(&amounts),
+                scripts: utils::sha256(&scripts),
+            }
+        })
+    }
+
+    /// Original algorithm, `script_code` already has the signature removed (FindAndDelete).
+    /// Keeps the SIGHASH_SINGLE bug: without a matching output the hash is 1.
+    pub fn legacy(&self, vin: usize, script_code: &[u8], hash_type: u8) -> [u8; 32] {
+        let tx = self.tx;
+        let base_type = hash_type & 0x1f;
+        if base_type == SIGHASH_SINGLE && vin >= tx.outputs.len() {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return one;
+        }
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+        let mut msg = Vec::with_capacity(128 + script_code.len());
+        msg.extend_from_slice(&tx.version.to_le_bytes());
+        let inputs = if anyone_can_pay {
+            vin..vin + 1
+        } else {
+            0..tx.inputs.len()
+        };
+        msg.extend_from_slice(&VarUint::compact(inputs.len() as u64).to_bytes());
+        for i in inputs {
+            let input = &tx.inputs[i].input;
+            msg.extend_from_slice(&input.outpoint.to_bytes());
+            if i == vin {
+                write_legacy_script_code(&mut msg, script_code);
+            } else {
+                msg.push(0x00);
+            }
+            if i != vin && (base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE) {
+                msg.extend_from_slice(&0u32.to_le_bytes());
+            } else {
+                msg.extend_from_slice(&input.seq_no.to_le_bytes());
+            }
+        }
+        match base_type {
+            SIGHASH_NONE => msg.push(0x00),
+            SIGHASH_SINGLE => {
+                msg.extend_from_slice(&VarUint::compact(vin as u64 + 1).to_bytes());
+                for _ in 0..vin {
+                    // Blanked outputs have a value of -1 and an empty script
+                    msg.extend_from_slice(&[0xff; 8]);
+                    msg.push(0x00);
+                }
+                msg.extend_from_slice(&tx.outputs[vin].out.to_bytes());
+            }
+            _ => {
+                msg.extend_from_slice(&VarUint::compact(tx.outputs.len() as u64).to_bytes());
+                for output in &tx.outputs {
+                    msg.extend_from_slice(&output.out.to_bytes());
+                }
+            }
+        }
+        msg.extend_from_slice(&tx.locktime.to_le_bytes());
+        msg.extend_from_slice(&(hash_type as u32).to_le_bytes());
+        utils::sha256(&utils::sha256(&msg))
+    }
+
+    /// Segwit v0 algorithm [BIP143], `amount` is the value of the spent output
+    pub fn witness_v0(
+        &self,
+        vin: usize,
+        script_code: &[u8],
+        amount: u64,
+        hash_type: u8,
+    ) -> [u8; 32] {
+        let tx = self.tx;
+        let base_type = hash_type & 0x1f;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        let zero = [0u8; 32];
+
+        let hash_prevouts = if anyone_can_pay {
+            zero
+        } else {
+            utils::sha256(&self.shared().prevouts)
+        };
+        let hash_sequence =
+            if anyone_can_pay || base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE {
+                zero
+            } else {
+                utils::sha256(&self.shared().sequences)
+            };
+        let hash_outputs = if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+            utils::sha256(&self.shared().outputs)
+        } else if base_type == SIGHASH_SINGLE && vin < tx.outputs.len() {
+            utils::sha256(&utils::sha256(&tx.outputs[vin].out.to_bytes()))
+        } else {
+            zero
+        };
+
+        let input = &tx.inputs[vin].input;
+        let mut msg = Vec::with_capacity(160 + script_code.len());
+        msg.extend_from_slice(&tx.version.to_le_bytes());
+        msg.extend_from_slice(&hash_prevouts);
+        msg.extend_from_slice(&hash_sequence);
+        msg.extend_from_slice(&input.outpoint.to_bytes());
+        write_script(&mut msg, script_code);
+        msg.extend_from_slice(&amount.to_le_bytes());
+        msg.extend_from_slice(&input.seq_no.to_le_bytes());
+        msg.extend_from_slice(&hash_outputs);
+        msg.extend_from_slice(&tx.locktime.to_le_bytes());
+        msg.extend_from_slice(&(hash_type as u32).to_le_bytes());
+        utils::sha256(&utils::sha256(&msg))
+    }
+
+    /// Taproot algorithm [BIP341]. For script path spends, `leaf` holds the tapleaf hash
+    /// and the position of the last executed OP_CODESEPARATOR [BIP342].
+    /// Returns None for undefined hash types and SIGHASH_SINGLE without a matching output.
+    pub fn taproot(
+        &self,
+        vin: usize,
+        hash_type: u8,
+        annex_hash: Option<&[u8; 32]>,
+        leaf: Option<(&[u8; 32], u32)>,
+    ) -> Option<[u8; 32]> {
+        if !(hash_type <= 0x03 || (0x81..=0x83).contains(&hash_type)) {
+            return None;
+        }
+        let tx = self.tx;
+        let output_type = if hash_type == SIGHASH_DEFAULT {
+            SIGHASH_ALL
+        } else {
+            hash_type & 0x03
+        };
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+        // Epoch 0
+        let mut msg = vec![0x00, hash_type];
+        msg.extend_from_slice(&tx.version.to_le_bytes());
+        msg.extend_from_slice(&tx.locktime.to_le_bytes());
+        if !anyone_can_pay {
+            let shared = self.shared();
+            msg.extend_from_slice(&shared.prevouts);
+            msg.extend_from_slice(&shared.amounts);
+            msg.extend_from_slice(&shared.scripts);
+            msg.extend_from_slice(&shared.sequences);
+        }
+        if output_type == SIGHASH_ALL {
+            msg.extend_from_slice(&self.shared().outputs);
+        }
+        let ext_flag = if leaf.is_some() { 1 } else { 0 };
+        msg.push((ext_flag << 1) + annex_hash.is_some() as u8);
+        if anyone_can_pay {
+            let input = &tx.inputs[vin].input;
+            msg.extend_from_slice(&input.outpoint.to_bytes());
+            msg.extend_from_slice(&self.spent[vin].value.to_le_bytes());
+            write_script(&mut msg, &self.spent[vin].script_pubkey);
+            msg.extend_from_slice(&input.seq_no.to_le_bytes());
+        } else {
+            msg.extend_from_slice(&(vin as u32).to_le_bytes());
+        }
+        if let Some(annex_hash) = annex_hash {
+            msg.extend_from_slice(annex_hash);
+        }
+        if output_type == SIGHASH_SINGLE {
+            let output = tx.outputs.get(vin)?;
+            msg.extend_from_slice(&utils::sha256(&output.out.to_bytes()));
+        }
+        if let Some((tapleaf_hash, codesep_pos)) = leaf {
+            msg.extend_from_slice(tapleaf_hash);
+            // Key version 0
+            msg.push(0x00);
+            msg.extend_from_slice(&codesep_pos.to_le_bytes());
+        }
+        Some(tagged_hash("TapSighash", &msg))
+    }
+}
+
+/// SHA256 with a domain separation tag [BIP340]
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = utils::sha256(tag.as_bytes());
+    let mut msg = Vec::with_capacity(64 + data.len());
+    msg.extend_from_slice(&tag_hash);
+    msg.extend_from_slice(&tag_hash);
+    msg.extend_from_slice(data);
+    utils::sha256(&msg)
+}
+
+/// Hash of a script leaf committed to by a taproot output
+pub fn tapleaf_hash(leaf_version: u8, script: &[u8]) -> [u8; 32] {
+    let mut msg = Vec::with_capacity(6 + script.len());
+    msg.push(leaf_version);
+    write_script(&mut msg, script);
+    tagged_hash("TapLeaf", &msg)
+}
+
+/// Hash of an inner node of the script tree, children are sorted
+pub fn tapbranch_hash(a: &[u8], b: &[u8]) -> [u8; 32] {
+    let mut msg = Vec::with_capacity(64);
+    if a < b {
+        msg.extend_from_slice(a);
+        msg.extend_from_slice(b);
+    } else {
+        msg.extend_from_slice(b);
+        msg.extend_from_slice(a);
+    }
+    tagged_hash("TapBranch", &msg)
+}
+
+/// Script with its length prefix
+#[inline]
+fn write_script(buf: &mut Vec<u8>, script: &[u8]) {
+    buf.extend_from_slice(&VarUint::compact(script.len() as u64).to_bytes());
+    buf.extend_from_slice(script);
+}
+
+/// Script with all OP_CODESEPARATORs removed, see CTransactionSignatureSerializer::SerializeScriptCode.
+/// Like Core, the length prefix doesn't account for bytes after an unparsable instruction.
+fn write_legacy_script_code(buf: &mut Vec<u8>, script: &[u8]) {
+    let mut code = Vec::with_capacity(script.len());
+    let mut instructions = Instructions::new(script);
+    let mut start = 0;
+    let mut separators = 0;
+    while let Some(Ok(instruction)) = instructions.next() {
+        if instruction == Instruction::Op(opcodes::All::OP_CODESEPARATOR) {
+            code.extend_from_slice(&script[start..instructions.position() - 1]);
+            start = instructions.position();
+            separators += 1;
+        }
+    }
+    if start != script.len() {
+        code.extend_from_slice(&script[start..instructions.position()]);
+    }
+    buf.extend_from_slice(&VarUint::compact((script.len() - separators) as u64).to_bytes());
+    buf.extend_from_slice(&code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::proto::script::AddressParams;
+    use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+    use crate::common::{fixtures, utils};
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    fn outpoint(txid: &str, index: u32) -> TxOutpoint {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&utils::hex_to_vec(txid));
+        TxOutpoint::new(hash, index)
+    }
+
+    /// Native P2WPKH example of BIP143
+    #[test]
+    fn test_witness_v0_sighash() {
+        let inputs = vec![
+            fixtures::input(
+                outpoint(
+                    "fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f",
+                    0,
+                ),
+                &[],
+                0xffffffee,
+                Vec::new(),
+            ),
+            fixtures::input(
+                outpoint(
+                    "ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a",
+                    1,
+                ),
+                &[],
+                0xffffffff,
+                Vec::new(),
+            ),
+        ];
+        let outputs = vec![
+            fixtures::output(
+                112340000,
+                &utils::hex_to_vec("76a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac"),
+            ),
+            fixtures::output(
+                223450000,
+                &utils::hex_to_vec("76a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac"),
+            ),
+        ];
+        let tx = EvaluatedTx::new(
+            1,
+            VarUint::from(2u8),
+            inputs,
+            VarUint::from(2u8),
+            outputs,
+            0x11,
+            &AddressParams::default(),
+        );
+        let spent = [
+            fixtures::output(
+                625000000,
+                &utils::hex_to_vec(
+                    "2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac",
+                ),
+            ),
+            fixtures::output(
+                600000000,
+                &utils::hex_to_vec("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1"),
+            ),
+        ];
+        let cache = SighashCache::new(&tx, spent.iter().collect());
+        let script_code = utils::hex_to_vec("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac");
+        assert_eq!(
+            utils::arr_to_hex(&cache.witness_v0(1, &script_code, 600000000, SIGHASH_ALL)),
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+        );
+    }
+
+    #[test]
+    fn test_legacy_sighash() {
+        let inputs = vec![fixtures::input(
+            outpoint(
+                "fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f",
+                0,
+            ),
+            &[],
+            0xffffffff,
+            Vec::new(),
+        )];
+        let tx = EvaluatedTx::new(
+            1,
+            VarUint::from(1u8),
+            inputs,
+            VarUint::from(0u8),
+            Vec::new(),
+            0,
+            &AddressParams::default(),
+        );
+        let spent = [fixtures::output(1000, &[0x51])];
+        let cache = SighashCache::new(&tx, spent.iter().collect());
+        // SIGHASH_SINGLE without a matching output signs 1
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        assert_eq!(cache.legacy(0, &[0x51], SIGHASH_SINGLE), one);
+        // OP_CODESEPARATORs are not part of the signed script
+        assert_eq!(
+            cache.legacy(0, &[0xab, 0x51, 0xab], SIGHASH_ALL),
+            cache.legacy(0, &[0x51], SIGHASH_ALL)
+        );
+        assert!(cache.taproot(0, 0x04, None, None).is_none());
+        assert!(cache.taproot(0, SIGHASH_SINGLE, None, None).is_none());
+    }
+
+    /// Vectors of Bitcoin Core's sighash.json, the script still holds its OP_CODESEPARATORs
+    #[test]
+    fn test_legacy_sighash_vectors() {
+        let entries: Vec<Vec<serde_json::Value>> =
+            serde_json::from_str(include_str!("../parser/testdata/sighash.json")).unwrap();
+        let mut checked = 0;
+        // Single strings are comments
+        for entry in entries.iter().filter(|entry| entry.len() > 1) {
+            let raw = utils::hex_to_vec(entry[0].as_str().unwrap());
+            let mut txs = Cursor::new(raw).read_txs(1, &Arc::default()).unwrap();
+            let tx = EvaluatedTx::from(txs.remove(0));
+            let script_code = utils::hex_to_vec(entry[1].as_str().unwrap());
+            let vin = entry[2].as_u64().unwrap() as usize;
+            let hash_type = entry[3].as_i64().unwrap() as u8;
+            // Legacy hashes don't commit to the spent outputs
+            let spent = vec![fixtures::output(0, &[]); tx.inputs.len()];
+            let cache = SighashCache::new(&tx, spent.iter().collect());
+            assert_eq!(
+                utils::arr_to_hex_swapped(&cache.legacy(vin, &script_code, hash_type)),
+                entry[4].as_str().unwrap(),
+                "{:?}",
+                entry
+            );
+            checked += 1;
+        }
+        assert!(checked > 100);
+    }
+
+    #[test]
+    fn test_tagged_hashes() {
+        // Script tree of a BIP341 wallet test vector with a single leaf
+        let script = utils::hex_to_vec(
+            "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac",
+        );
+        assert_eq!(
+            utils::arr_to_hex(&tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, &script)),
+            "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"
+        );
+        assert_eq!(
+            tapbranch_hash(&[1; 32], &[2; 32]),
+            tapbranch_hash(&[2; 32], &[1; 32])
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// 256 bit unsigned integer used for proof-of-work targets.
+/// Limbs are stored least significant first, like hashes are compared as little endian numbers.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Target([u64; 4]);
+
+/// Result of decoding a compact `nBits` value
+#[derive(Debug, PartialEq)]
+pub struct CompactTarget {
+    pub target: Target,
+    pub negative: bool,
+    pub overflow: bool,
+}
+
+impl Target {
+    pub const ZERO: Target = Target([0; 4]);
+
+    /// Interprets a hash as little endian number
+    pub fn from_hash(hash: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&hash[i * 8..i * 8 + 8]);
+            *limb = u64::from_le_bytes(bytes);
+        }
+        Target(limbs)
+    }
+
+    /// Decodes the compact representation used in `nBits`, see arith_uint256::SetCompact()
+    pub fn from_compact(bits: u32) -> CompactTarget {
+        let size = bits >> 24;
+        let mut word = bits & 0x007f_ffff;
+        let target = if size <= 3 {
+            word >>= 8 * (3 - size);
+            Target([u64::from(word), 0, 0, 0])
+        } else {
+            Target([u64::from(word), 0, 0, 0]).shl(8 * (size - 3))
+        };
+        CompactTarget {
+            target,
+            negative: word != 0 && bits & 0x0080_0000 != 0,
+            overflow: word != 0
+                && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32)),
+        }
+    }
+
+    /// Encodes the target as `nBits`, see arith_uint256::GetCompact()
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.0[0] << (8 * (3 - size))) as u32
+        } else {
+            self.shr(8 * (size - 3)).0[0] as u32
+        };
+        // The sign bit is set, move the mantissa one byte to the right
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size << 24)
+    }
+
+    /// Returns the position of the highest set bit plus one
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + 64 - self.0[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Target::ZERO
+    }
+
+    pub fn shl(&self, shift: u32) -> Self {
+        let mut result = [0u64; 4];
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for i in (limbs..4).rev() {
+            result[i] = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                result[i] |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        Target(result)
+    }
+
+    pub fn shr(&self, shift: u32) -> Self {
+        let mut result = [0u64; 4];
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for (i, limb) in result
+            .iter_mut()
+            .enumerate()
+            .take(4usize.saturating_sub(limbs))
+        {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs < 3 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        Target(result)
+    }
+
+    /// Multiplies by `factor`, overflowing bits are dropped like in arith_uint256
+    pub fn mul_u64(&self, factor: u64) -> Self {
+        let mut result = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, limb) in self.0.iter().enumerate() {
+            let product = u128::from(*limb) * u128::from(factor) + carry;
+            result[i] = product as u64;
+            carry = product >> 64;
+        }
+        Target(result)
+    }
+
+    pub fn div_u64(&self, divisor: u64) -> Self {
+        let mut result = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let dividend = (remainder << 64) | u128::from(self.0[i]);
+            result[i] = (dividend / u128::from(divisor)) as u64;
+            remainder = dividend % u128::from(divisor);
+        }
+        Target(result)
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}{:016x}{:016x}{:016x}",
+            self.0[3], self.0[2], self.0[1], self.0[0]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    #[test]
+    fn test_compact_roundtrip() {
+        // Test vectors from bitcoin/src/test/arith_uint256_tests.cpp
+        let decoded = Target::from_compact(0x01123456);
+        assert_eq!(decoded.target, Target([0x12, 0, 0, 0]));
+        assert_eq!(decoded.target.to_compact(), 0x01120000);
+
+        let decoded = Target::from_compact(0x04923456);
+        assert!(decoded.negative);
+        assert_eq!(decoded.target, Target([0x12345600, 0, 0, 0]));
+
+        assert_eq!(
+            Target::from_compact(0x05009234).target.to_compact(),
+            0x05009234
+        );
+        assert_eq!(
+            Target::from_compact(0x20123456).target.to_compact(),
+            0x20123456
+        );
+        assert!(Target::from_compact(0xff123456).overflow);
+        assert_eq!(Target([0x80, 0, 0, 0]).to_compact(), 0x02008000);
+
+        let limit = Target::from_compact(0x1d00ffff).target;
+        assert_eq!(limit, Target([0, 0, 0, 0x0000_0000_ffff_0000]));
+        assert_eq!(limit.bits(), 224);
+        assert_eq!(limit.to_compact(), 0x1d00ffff);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let value = Target([u64::MAX, 1, 0, 0]);
+        assert_eq!(value.shl(64), Target([0, u64::MAX, 1, 0]));
+        assert_eq!(value.shl(4).shr(4), value);
+        assert_eq!(value.shr(1), Target([u64::MAX, 0, 0, 0]));
+        assert_eq!(value.mul_u64(2), Target([u64::MAX - 1, 3, 0, 0]));
+        assert_eq!(value.mul_u64(3).div_u64(3), value);
+        assert!(value > Target([u64::MAX, 0, 0, 0]));
+        assert!(Target([0, 0, 0, 1]) > Target([u64::MAX, u64::MAX, u64::MAX, 0]));
+    }
+
+    #[test]
+    fn test_genesis_hash_meets_target() {
+        let hash = utils::hex_to_arr32_swapped(
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        );
+        let hash = Target::from_hash(&hash);
+        assert!(hash <= Target::from_compact(0x1d00ffff).target);
+        assert!(hash > Target::from_compact(0x1b0404cb).target);
+    }
+}
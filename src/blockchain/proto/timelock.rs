@@ -0,0 +1,202 @@
+use std::fmt;
+
+use crate::blockchain::proto::inscription;
+use crate::blockchain::proto::opcodes::All;
+use crate::blockchain::proto::script::{Instruction, Instructions};
+use crate::blockchain::proto::tx::TxInput;
+
+/// Locktimes below are block heights, others unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Inputs with this sequence don't enforce the locktime
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// Set if the sequence isn't a relative locktime [BIP68]
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// Set for relative locktimes in units of 512 seconds, otherwise in blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// Absolute locktime of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockTime {
+    Height(u32),
+    Time(u32),
+}
+
+impl LockTime {
+    /// Decodes nLockTime, None if it is 0
+    pub fn new(locktime: u32) -> Option<LockTime> {
+        match locktime {
+            0 => None,
+            l if l < LOCKTIME_THRESHOLD => Some(LockTime::Height(l)),
+            l => Some(LockTime::Time(l)),
+        }
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Height(_) => write!(f, "height"),
+            LockTime::Time(_) => write!(f, "time"),
+        }
+    }
+}
+
+/// The locktime is only enforced if at least one input isn't final
+#[inline]
+pub fn is_locktime_enforced<I: IntoIterator<Item = u32>>(sequences: I) -> bool {
+    sequences
+        .into_iter()
+        .any(|sequence| sequence != SEQUENCE_FINAL)
+}
+
+/// Relative locktime of an input [BIP68]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeLock {
+    Blocks(u16),
+    /// Seconds, a multiple of 512
+    Time(u32),
+}
+
+impl RelativeLock {
+    /// Decodes nSequence, None if relative locktimes are disabled or the transaction version is below 2
+    pub fn from_sequence(version: u32, sequence: u32) -> Option<RelativeLock> {
+        if version < 2 || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = sequence & SEQUENCE_LOCKTIME_MASK;
+        if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLock::Time(value << SEQUENCE_LOCKTIME_GRANULARITY))
+        } else {
+            Some(RelativeLock::Blocks(value as u16))
+        }
+    }
+}
+
+/// Opt-in replace-by-fee signalling of an input [BIP125]
+#[inline]
+pub fn signals_rbf(sequence: u32) -> bool {
+    sequence < SEQUENCE_FINAL - 1
+}
+
+/// Counts OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY, up to the first invalid push
+pub fn count_timelock_ops(script: &[u8]) -> (u64, u64) {
+    let mut cltv = 0;
+    let mut csv = 0;
+    for instruction in Instructions::new(script) {
+        match instruction {
+            Ok(Instruction::Op(All::OP_NOP2)) => cltv += 1,
+            Ok(Instruction::Op(All::OP_NOP3)) => csv += 1,
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    (cltv, csv)
+}
+
+/// Returns the script revealed by a P2SH, P2WSH or tapscript spend.
+/// The spent output isn't known here, so the scriptSig and witness are matched by their shape:
+/// keys and signatures are never taken for scripts.
+pub fn revealed_script(input: &TxInput) -> Option<&[u8]> {
+    let script = if input.witness.is_empty() {
+        let mut last = None;
+        for instruction in Instructions::new(&input.script_sig) {
+            match instruction {
+                Ok(Instruction::PushBytes(_, data)) => last = Some(data),
+                _ => return None,
+            }
+        }
+        last?
+    } else if let Some(script) = inscription::tapscript(&input.witness) {
+        return Some(script);
+    } else {
+        match input.witness.as_slice() {
+            [_, key] if key.len() == 33 => return None,
+            [.., script] if input.witness.len() >= 2 => script,
+            _ => return None,
+        }
+    };
+    if script.is_empty() || is_key_or_signature(script) {
+        return None;
+    }
+    Some(script)
+}
+
+/// Compressed, uncompressed or hybrid keys and DER signatures by size and prefix
+fn is_key_or_signature(data: &[u8]) -> bool {
+    match (data[0], data.len()) {
+        (0x02, 33) | (0x03, 33) | (0x04, 65) | (0x06, 65) | (0x07, 65) => true,
+        (0x30, len) => (9..=73).contains(&len),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::{fixtures, utils};
+
+    fn input(script_sig: &[u8], witness: Vec<Vec<u8>>) -> TxInput {
+        fixtures::input(
+            TxOutpoint::new([0x11; 32], 0),
+            script_sig,
+            SEQUENCE_FINAL,
+            witness,
+        )
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(LockTime::new(0), None);
+        assert_eq!(LockTime::new(840000), Some(LockTime::Height(840000)));
+        assert_eq!(LockTime::new(1700000000), Some(LockTime::Time(1700000000)));
+        assert!(!is_locktime_enforced(vec![SEQUENCE_FINAL, SEQUENCE_FINAL]));
+        assert!(is_locktime_enforced(vec![
+            SEQUENCE_FINAL,
+            SEQUENCE_FINAL - 1
+        ]));
+
+        assert_eq!(
+            RelativeLock::from_sequence(2, 144),
+            Some(RelativeLock::Blocks(144))
+        );
+        assert_eq!(
+            RelativeLock::from_sequence(2, SEQUENCE_LOCKTIME_TYPE_FLAG | 2),
+            Some(RelativeLock::Time(1024))
+        );
+        assert_eq!(RelativeLock::from_sequence(1, 144), None);
+        assert_eq!(RelativeLock::from_sequence(2, SEQUENCE_FINAL - 1), None);
+
+        assert!(signals_rbf(0xfffffffd));
+        assert!(!signals_rbf(0xfffffffe));
+    }
+
+    #[test]
+    fn test_revealed_script() {
+        // <expiry> OP_CHECKLOCKTIMEVERIFY OP_DROP <key> OP_CHECKSIG, and a CSV branch
+        let key =
+            utils::hex_to_vec("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        let script = [
+            &[0x03, 0x40, 0xd0, 0x0c, 0xb1, 0x75, 0x21][..],
+            &key,
+            &[0xac, 0x60, 0xb2],
+        ]
+        .concat();
+        assert_eq!(count_timelock_ops(&script), (1, 1));
+        let signature = vec![0x30; 71];
+
+        // P2SH, P2WSH and nothing for P2PKH and P2WPKH
+        let script_sig = [&[0x47][..], &signature, &[script.len() as u8], &script].concat();
+        assert_eq!(
+            revealed_script(&input(&script_sig, Vec::new())),
+            Some(&script[..])
+        );
+        let witness = vec![signature.clone(), script.clone()];
+        assert_eq!(revealed_script(&input(&[], witness)), Some(&script[..]));
+        let script_sig = [&[0x47][..], &signature, &[0x21], &key].concat();
+        assert_eq!(revealed_script(&input(&script_sig, Vec::new())), None);
+        assert_eq!(revealed_script(&input(&[], vec![signature, key])), None);
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt;
+use std::sync::Arc;
 
 use p256::NonZeroScalar;
 use utils::arr_to_hex;
 
-use crate::blockchain::proto::script;
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::descriptor;
+use crate::blockchain::proto::script::{self, AddressParams};
 use crate::blockchain::proto::varuint::VarUint;
 use crate::blockchain::proto::ToRaw;
 use crate::common::utils;
@@ -15,7 +18,8 @@ pub struct RawTx {
     pub out_count: VarUint,
     pub outputs: Vec<TxOutput>,
     pub locktime: u32,
-    pub version_id: u8,
+    pub address_params: Arc<AddressParams>,
+    pub hog_ex: bool, // Litecoin integrating transaction, followed by the extension block
 }
 
 /// Simple transaction struct
@@ -37,17 +41,24 @@ impl EvaluatedTx {
         out_count: VarUint,
         outputs: Vec<TxOutput>,
         locktime: u32,
-        version_id: u8,
+        address_params: &AddressParams,
     ) -> Self {
         // Evaluate and wrap all outputs to process them later
-        let outputs = outputs
+        let mut outputs: Vec<EvaluatedTxOut> = outputs
             .into_iter()
-            .map(|o| EvaluatedTxOut::eval_script(o, version_id))
+            .map(|o| EvaluatedTxOut::eval_script(o, address_params))
             .collect();
+        if let Some(first) = inputs.first() {
+            let mut key = first.outpoint.txid;
+            key.reverse();
+            for output in outputs.iter_mut() {
+                output.script.detect_counterparty(&key);
+            }
+        }
         // also evaluate TxInputs
         let inputs = inputs
             .into_iter()
-            .map(|i| EvaluatedTxIn::eval_script(i, version_id))
+            .map(|i| EvaluatedTxIn::eval_script(i, address_params))
             .collect();
         EvaluatedTx {
             version,
@@ -61,11 +72,12 @@ impl EvaluatedTx {
 
     #[inline]
     pub fn is_coinbase(&self) -> bool {
-        if self.in_count.value == 1 {
-            let input = self.inputs.first().unwrap();
-            return input.input.outpoint.txid == [0u8; 32] && input.input.outpoint.index == 0xFFFFFFFF;
+        match self.inputs.first() {
+            Some(input) if self.in_count.value == 1 => {
+                input.input.outpoint.txid == [0u8; 32] && input.input.outpoint.index == 0xFFFFFFFF
+            }
+            _ => false,
         }
-        false
     }
 }
 
@@ -89,7 +101,7 @@ impl From<RawTx> for EvaluatedTx {
             tx.out_count,
             tx.outputs,
             tx.locktime,
-            tx.version_id,
+            &tx.address_params,
         )
     }
 }
@@ -153,8 +165,9 @@ impl fmt::Debug for TxOutpoint {
 pub struct TxInput {
     pub outpoint: TxOutpoint,
     pub script_len: VarUint,
-    pub script_sig: Vec<u8>,
+    pub script_sig: Bytes,
     pub seq_no: u32,
+    pub witness: Vec<Bytes>, // Witness stack, empty for legacy inputs. Not part of to_bytes().
 }
 
 impl ToRaw for TxInput {
@@ -176,6 +189,7 @@ impl fmt::Debug for TxInput {
             .field("script_len", &self.script_len)
             .field("script_sig", &self.script_sig)
             .field("seq_no", &self.seq_no)
+            .field("witness", &self.witness.len())
             .finish()
     }
 }
@@ -187,22 +201,22 @@ pub struct EvaluatedTxIn {
 }
 
 impl EvaluatedTxIn {
-    pub fn eval_script(input: TxInput, version_id: u8) -> EvaluatedTxIn {
+    pub fn eval_script(input: TxInput, address_params: &AddressParams) -> EvaluatedTxIn {
         EvaluatedTxIn {
-            script: script::eval_from_bytes(&input.script_sig, version_id),
+            script: script::eval_from_bytes(&input.script_sig, address_params),
             input,
         }
     }
 
     pub fn as_csv(&self, r: NonZeroScalar, s: NonZeroScalar,
-                  pubkey: &Vec<u8>, txid: &str,
+                  pubkey: &[u8], txid: &str,
                   message_hash_str: String, block_time: u32) -> String {
         // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
         format!(
             "{:x};{:x};{};{};{};{}\n",
             r,
             s,
-            arr_to_hex(&pubkey),
+            arr_to_hex(pubkey),
             txid,
             message_hash_str,
             block_time
@@ -211,6 +225,7 @@ impl EvaluatedTxIn {
 }
 
 /// Evaluates script_pubkey and wraps TxOutput
+#[derive(Clone)]
 pub struct EvaluatedTxOut {
     pub script: script::EvaluatedScript,
     pub out: TxOutput,
@@ -218,19 +233,26 @@ pub struct EvaluatedTxOut {
 
 impl EvaluatedTxOut {
     #[inline]
-    pub fn eval_script(out: TxOutput, version_id: u8) -> EvaluatedTxOut {
+    pub fn eval_script(out: TxOutput, address_params: &AddressParams) -> EvaluatedTxOut {
         EvaluatedTxOut {
-            script: script::eval_from_bytes(&out.script_pubkey, version_id),
+            script: script::eval_from_bytes(&out.script_pubkey, address_params),
             out,
         }
     }
+
+    /// Output descriptor with checksum, see descriptor::infer()
+    #[inline]
+    pub fn descriptor(&self) -> descriptor::Descriptor {
+        descriptor::infer(&self.out.script_pubkey, &self.script)
+    }
 }
 
 /// Holds TxOutput informations
+#[derive(Clone)]
 pub struct TxOutput {
     pub value: u64,
     pub script_len: VarUint,
-    pub script_pubkey: Vec<u8>,
+    pub script_pubkey: Bytes,
 }
 
 impl ToRaw for TxOutput {
@@ -17,9 +17,16 @@ pub struct VarUint {
 impl VarUint {
     #[inline]
     fn new(value: u64, buf: Vec<u8>) -> VarUint {
-        VarUint {
-            value: value as u64,
-            buf,
+        VarUint { value, buf }
+    }
+
+    /// Shortest encoding of `value`, see WriteCompactSize
+    pub fn compact(value: u64) -> VarUint {
+        match value {
+            0..=0xfc => VarUint::from(value as u8),
+            0xfd..=0xffff => VarUint::from(value as u16),
+            0x10000..=0xffffffff => VarUint::from(value as u32),
+            _ => VarUint::from(value),
         }
     }
 
@@ -65,7 +72,7 @@ impl From<u64> for VarUint {
         let mut buf: Vec<u8> = Vec::with_capacity(9);
         buf.push(0xff);
         buf.extend_from_slice(&value.to_le_bytes());
-        VarUint::new(value as u64, buf)
+        VarUint::new(value, buf)
     }
 }
 
@@ -98,6 +105,17 @@ mod tests {
         assert_eq!(vec![0xfa], test.to_bytes());
     }
 
+    #[test]
+    fn test_varuint_compact() {
+        assert_eq!(vec![0xfc], VarUint::compact(0xfc).to_bytes());
+        assert_eq!(vec![0xfd, 0xfd, 0x00], VarUint::compact(0xfd).to_bytes());
+        assert_eq!(
+            vec![0xfe, 0x00, 0x00, 0x01, 0x00],
+            VarUint::compact(0x10000).to_bytes()
+        );
+        assert_eq!(9, VarUint::compact(0x100000000).to_bytes().len());
+    }
+
     #[test]
     fn test_varuint_u16() {
         let v: u16 = 4444;
@@ -131,7 +149,7 @@ mod tests {
 
         let test = VarUint::from(v);
         assert_eq!(9000000000000000000, test.value);
-        assert_eq!(v, test.value as u64);
+        assert_eq!(v, test.value);
         assert_eq!(9, test.to_bytes().len());
         assert_eq!(
             vec![0xff, 0x00, 0x00, 0x84, 0xe2, 0x50, 0x6c, 0xe6, 0x7c],
@@ -1,13 +1,16 @@
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use clap::{App, Arg, ArgMatches, SubCommand};
 
 use crate::blockchain::parser::types::CoinType;
-use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
+use crate::blockchain::proto::tx::{EvaluatedTxIn, EvaluatedTxOut, TxOutpoint};
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::{common, Callback, TxContext};
+use crate::common::utils;
 use crate::errors::OpResult;
 
 /// Dumps all addresses with non-zero balance in a csv file
@@ -23,6 +26,18 @@ pub struct Balances {
 }
 
 impl Balances {
+    /// Creates the callback, writing its csv files to `dump_folder`
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let cb = Balances {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Balances::create_writer(4000000, dump_folder.join("balances.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            end_height: 0,
+        };
+        Ok(cb)
+    }
+
     fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
         Ok(BufWriter::with_capacity(cap, File::create(&path)?))
     }
@@ -49,15 +64,7 @@ impl Callback for Balances {
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.value_of("dump-folder").unwrap());
-        let cb = Balances {
-            dump_folder: PathBuf::from(dump_folder),
-            writer: Balances::create_writer(4000000, dump_folder.join("balances.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
-            start_height: 0,
-            end_height: 0,
-        };
-        Ok(cb)
+        Balances::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))
     }
 
     fn on_start(&mut self, _: &CoinType, block_height: u64) -> OpResult<()> {
@@ -66,21 +73,62 @@ impl Callback for Balances {
         Ok(())
     }
 
-    /// For each transaction in the block
-    ///   1. apply input transactions (remove (TxID == prevTxIDOut and prevOutID == spentOutID))
-    ///   2. apply output transactions (add (TxID + curOutID -> HashMapVal))
+    /// Removes the spent output (TxID == prevTxIDOut and prevOutID == spentOutID)
+    fn on_input(
+        &mut self,
+        _: &TxContext,
+        _: usize,
+        input: &EvaluatedTxIn,
+        _: Option<&EvaluatedTxOut>,
+    ) -> OpResult<()> {
+        self.unspents.remove(&input.input.outpoint.to_bytes());
+        Ok(())
+    }
+
+    /// Adds the output (TxID + curOutID -> HashMapVal)
+    ///
     /// For each address, retain:
     ///   * block height as "last modified"
     ///   * output_val
     ///   * address
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        for tx in &block.txs {
-            common::remove_unspents(&tx, &mut self.unspents);
-            common::insert_unspents(&tx, block_height, &mut self.unspents);
+    fn on_output(&mut self, ctx: &TxContext, vout: usize, output: &EvaluatedTxOut) -> OpResult<()> {
+        match &output.script.address {
+            Some(address) => {
+                let unspent = common::UnspentValue {
+                    block_height: ctx.block_height,
+                    address: address.clone(),
+                    value: output.out.value,
+                    descriptor: None,
+                };
+                let key = TxOutpoint::new(ctx.tx.hash, vout as u32).to_bytes();
+                self.unspents.insert(key, unspent);
+            }
+            None => {
+                debug!(
+                    target: "callback", "Ignoring invalid utxo in: {} ({})",
+                    utils::arr_to_hex_swapped(&ctx.tx.hash),
+                    output.script.pattern
+                );
+            }
         }
         Ok(())
     }
 
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::new();
+        state.write_u64::<LittleEndian>(self.start_height)?;
+        common::write_unspents(&mut state, &self.unspents)?;
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.unspents = common::read_unspents(&mut state)?;
+        info!(target: "callback", "Resuming `balances` at block {} with {} unspents and dump folder: {} ...",
+              block_height, self.unspents.len(), &self.dump_folder.display());
+        Ok(())
+    }
+
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
         self.end_height = block_height;
 
@@ -1,15 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::blockchain::proto::script::ScriptPattern;
 use crate::blockchain::proto::tx::EvaluatedTx;
 use crate::blockchain::proto::tx::TxOutpoint;
 use crate::blockchain::proto::Hashed;
 use crate::blockchain::proto::ToRaw;
+use crate::callbacks::pubkeys;
 use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 pub struct UnspentValue {
     pub block_height: u64,
     pub value: u64,
     pub address: String,
+    pub descriptor: Option<(String, String)>, // Descriptor and its normalised keys, only kept if requested
 }
 
 /// Iterates over transaction inputs and removes spent outputs from HashMap.
@@ -28,20 +37,33 @@ pub fn remove_unspents(
 }
 
 /// Iterates over transaction outputs and adds valid unspents to HashMap.
+/// With `descriptors`, outputs without an address are kept as well, except for OP_RETURN.
 /// Returns the total number of valid outputs.
 pub fn insert_unspents(
     tx: &Hashed<EvaluatedTx>,
     block_height: u64,
     unspents: &mut HashMap<Vec<u8>, UnspentValue>,
+    descriptors: bool,
 ) -> u64 {
     let mut count = 0;
     for (i, output) in tx.value.outputs.iter().enumerate() {
-        match &output.script.address {
+        let address = match output.script.pattern {
+            ScriptPattern::DataOutput(_) => None,
+            _ if descriptors => Some(output.script.address.clone().unwrap_or_default()),
+            _ => output.script.address.clone(),
+        };
+        match address {
             Some(address) => {
                 let unspent = UnspentValue {
                     block_height,
-                    address: address.clone(),
+                    address,
                     value: output.out.value,
+                    descriptor: if descriptors {
+                        let descriptor = output.descriptor();
+                        Some((descriptor.desc, pubkeys::normalised_keys(&descriptor.keys)))
+                    } else {
+                        None
+                    },
                 };
 
                 let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
@@ -60,26 +82,268 @@ pub fn insert_unspents(
     count
 }
 
+/// Opens a file for writing without truncating it.
+/// Call reset_writer() before writing to it.
+pub fn open_writer(cap: usize, path: &Path) -> OpResult<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    Ok(BufWriter::with_capacity(cap, file))
+}
+
+/// Truncates the file to `len` bytes and appends further writes.
+/// Used with 0 on a fresh start and with the checkpointed length on resume.
+pub fn reset_writer(writer: &mut BufWriter<File>, len: u64) -> OpResult<()> {
+    writer.flush()?;
+    let file = writer.get_mut();
+    let file_len = file.metadata()?.len();
+    if file_len < len {
+        return Err(
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                "Output file is shorter than at the checkpoint ({} < {} bytes).",
+                file_len, len
+            )),
+        );
+    }
+    file.set_len(len)?;
+    file.seek(SeekFrom::Start(len))?;
+    Ok(())
+}
+
+/// Flushes the writer and returns the length of the written file
+pub fn flush_writer(writer: &mut BufWriter<File>) -> OpResult<u64> {
+    writer.flush()?;
+    Ok(writer.get_mut().stream_position()?)
+}
+
+/// Stores a command line flag which changes the output format in a checkpoint
+pub fn write_flag(state: &mut Vec<u8>, flag: bool) -> OpResult<()> {
+    state.write_u8(flag as u8)?;
+    Ok(())
+}
+
+/// Restores a flag written by write_flag(), resuming with a different value would mix two formats
+pub fn check_flag(state: &mut &[u8], name: &str, flag: bool) -> OpResult<()> {
+    let saved = state.read_u8()? != 0;
+    if saved != flag {
+        return Err(
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                "Checkpoint was written {} --{}, pass the same flags to resume.",
+                if saved { "with" } else { "without" },
+                name
+            )),
+        );
+    }
+    Ok(())
+}
+
+/// Serializes the unspent map for a checkpoint
+pub fn write_unspents(
+    state: &mut Vec<u8>,
+    unspents: &HashMap<Vec<u8>, UnspentValue>,
+) -> OpResult<()> {
+    state.write_u64::<LittleEndian>(unspents.len() as u64)?;
+    for (key, value) in unspents {
+        state.write_u8(key.len() as u8)?;
+        state.write_all(key)?;
+        state.write_u64::<LittleEndian>(value.block_height)?;
+        state.write_u64::<LittleEndian>(value.value)?;
+        state.write_u32::<LittleEndian>(value.address.len() as u32)?;
+        state.write_all(value.address.as_bytes())?;
+        let (descriptor, keys) = match value.descriptor {
+            Some((ref descriptor, ref keys)) => (descriptor.as_str(), keys.as_str()),
+            None => ("", ""),
+        };
+        state.write_u32::<LittleEndian>(descriptor.len() as u32)?;
+        state.write_all(descriptor.as_bytes())?;
+        state.write_u32::<LittleEndian>(keys.len() as u32)?;
+        state.write_all(keys.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Restores an unspent map serialized by write_unspents()
+pub fn read_unspents(state: &mut &[u8]) -> OpResult<HashMap<Vec<u8>, UnspentValue>> {
+    let len = state.read_u64::<LittleEndian>()? as usize;
+    let mut unspents = HashMap::with_capacity(len);
+    for _ in 0..len {
+        let mut key = vec![0u8; state.read_u8()? as usize];
+        state.read_exact(&mut key)?;
+        let block_height = state.read_u64::<LittleEndian>()?;
+        let value = state.read_u64::<LittleEndian>()?;
+        let mut address = vec![0u8; state.read_u32::<LittleEndian>()? as usize];
+        state.read_exact(&mut address)?;
+        let address = String::from_utf8(address)?;
+        let mut descriptor = vec![0u8; state.read_u32::<LittleEndian>()? as usize];
+        state.read_exact(&mut descriptor)?;
+        let mut keys = vec![0u8; state.read_u32::<LittleEndian>()? as usize];
+        state.read_exact(&mut keys)?;
+        // Descriptors are never empty
+        let descriptor = if descriptor.is_empty() {
+            None
+        } else {
+            Some((String::from_utf8(descriptor)?, String::from_utf8(keys)?))
+        };
+        unspents.insert(
+            key,
+            UnspentValue {
+                block_height,
+                value,
+                address,
+                descriptor,
+            },
+        );
+    }
+    Ok(unspents)
+}
+
+/// Number of blocks which can be rolled back on a reorg
+pub const MAX_REORG_DEPTH: usize = 100;
+
+/// Callback state before each of the last MAX_REORG_DEPTH blocks, oldest first
+pub struct UndoRing<T> {
+    states: VecDeque<(u64, T)>,
+}
+
+impl<T> UndoRing<T> {
+    pub fn new() -> Self {
+        UndoRing {
+            states: VecDeque::with_capacity(MAX_REORG_DEPTH + 1),
+        }
+    }
+
+    /// Keeps `state` as the state before `block_height`, dropping the oldest one if full
+    pub fn push(&mut self, block_height: u64, state: T) {
+        if self.states.len() == MAX_REORG_DEPTH {
+            self.states.pop_front();
+        }
+        self.states.push_back((block_height, state));
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Returns the state before `fork_height` and drops it with all later ones
+    pub fn rollback(&mut self, fork_height: u64) -> OpResult<T> {
+        let pos = self
+            .states
+            .iter()
+            .position(|(block_height, _)| *block_height == fork_height);
+        match pos.and_then(|pos| self.states.drain(pos..).next()) {
+            Some((_, state)) => Ok(state),
+            None => Err(OpError::new(OpErrorKind::CallbackError).join_msg(&format!(
+                "Unable to roll back to block {}, only the last {} blocks are kept.",
+                fork_height, MAX_REORG_DEPTH
+            ))),
+        }
+    }
+}
+
+impl<T> Default for UndoRing<T> {
+    fn default() -> Self {
+        UndoRing::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::blockchain::parser::reader::BlockchainRead;
-    use crate::blockchain::proto::header::BlockHeader;
-    use crate::blockchain::proto::varuint::VarUint;
-    use blockchain::proto::block::Block;
+    use crate::blockchain::proto::descriptor;
+    use crate::common::fixtures;
     use std::io::{BufReader, Cursor};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_resume_writer() {
+        let path = std::env::temp_dir().join(format!("resume-writer-{}.csv", std::process::id()));
+        std::fs::write(&path, b"stale output\n").unwrap();
+
+        let mut writer = open_writer(64, &path).unwrap();
+        reset_writer(&mut writer, 0).unwrap();
+        writer.write_all(b"block 0\n").unwrap();
+        let checkpoint_len = flush_writer(&mut writer).unwrap();
+        writer.write_all(b"block 1 (lost)\n").unwrap();
+        drop(writer);
+
+        let mut writer = open_writer(64, &path).unwrap();
+        reset_writer(&mut writer, checkpoint_len).unwrap();
+        writer.write_all(b"block 1\n").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read(&path).unwrap(), b"block 0\nblock 1\n");
+
+        // The output must not be shorter than at the checkpoint
+        let mut writer = open_writer(64, &path).unwrap();
+        assert!(reset_writer(&mut writer, 1000).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_undo_ring() {
+        let mut undo = UndoRing::new();
+        for height in 0..MAX_REORG_DEPTH as u64 + 10 {
+            undo.push(height, height * 2);
+        }
+        assert_eq!(undo.rollback(105).unwrap(), 210);
+        // Later states are dropped with it, the oldest ones were evicted
+        assert!(undo.rollback(106).is_err());
+        assert!(undo.rollback(5).is_err());
+        assert_eq!(undo.rollback(10).unwrap(), 20);
+        undo.clear();
+        assert!(undo.rollback(0).is_err());
+    }
+
+    #[test]
+    fn test_unspents_roundtrip() {
+        let mut unspents = HashMap::new();
+        for i in 0..3u32 {
+            let value = UnspentValue {
+                block_height: i as u64,
+                value: 1000 * i as u64,
+                address: format!("1Address{}", i),
+                descriptor: match i {
+                    1 => Some((format!("addr(1Address{})", i), String::new())),
+                    // Longer than 16-bit lengths allow, as for the keys of a large bare multisig
+                    2 => Some((String::from("pk(02aa)"), vec!["02aa"; 20000].join(","))),
+                    _ => None,
+                },
+            };
+            unspents.insert(TxOutpoint::new([i as u8; 32], i).to_bytes(), value);
+        }
+
+        let mut state = Vec::new();
+        write_unspents(&mut state, &unspents).unwrap();
+        let restored = read_unspents(&mut state.as_slice()).unwrap();
+        assert_eq!(restored.len(), 3);
+        for (key, value) in &unspents {
+            let r = &restored[key];
+            assert_eq!(r.block_height, value.block_height);
+            assert_eq!(r.value, value.value);
+            assert_eq!(r.address, value.address);
+            assert_eq!(r.descriptor, value.descriptor);
+        }
+        assert!(read_unspents(&mut &state[..state.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_resume_flags() {
+        let mut state = Vec::new();
+        write_flag(&mut state, true).unwrap();
+        write_flag(&mut state, false).unwrap();
+        let mut restored = state.as_slice();
+        check_flag(&mut restored, "asm", true).unwrap();
+        check_flag(&mut restored, "descriptors", false).unwrap();
+        assert!(restored.is_empty());
+        assert!(check_flag(&mut state.as_slice(), "asm", false).is_err());
+    }
 
     #[test]
     fn test_callback() {
         let mut unspents: HashMap<Vec<u8>, UnspentValue> = HashMap::new();
-        let header = BlockHeader {
-            version: 0,
-            prev_hash: [0u8; 32],
-            merkle_root: [0u8; 32],
-            timestamp: 0,
-            bits: 0,
-            nonce: 0,
-        };
 
         // Create a mock of txid fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4,
         // which increases balance of address 1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn by 5.56 BTC.
@@ -105,12 +369,12 @@ mod tests {
             0x7c, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
-        let block1 = Block::new(0, header.clone(), VarUint::from(1u8), txs);
+        let txs = reader.read_txs(1, &Arc::default()).unwrap();
+        let block1 = fixtures::block(txs);
 
         for tx in &block1.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 100000, &mut unspents);
+            remove_unspents(tx, &mut unspents);
+            insert_unspents(tx, 100000, &mut unspents, false);
         }
         let value = unspents
             .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
@@ -243,18 +507,16 @@ mod tests {
             0x72, 0xdc, 0x35, 0x92, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
-        let block2 = Block::new(0, header.clone(), VarUint::from(1u8), txs);
+        let txs = reader.read_txs(1, &Arc::default()).unwrap();
+        let block2 = fixtures::block(txs);
 
         for tx in &block2.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 105001, &mut unspents);
+            remove_unspents(tx, &mut unspents);
+            insert_unspents(tx, 105001, &mut unspents, true);
         }
 
         // Original unspent should no longer exist in the hashmap
-        assert!(unspents
-            .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
-            .is_none());
+        assert!(!unspents.contains_key(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes()));
 
         let value = unspents
             .get(&TxOutpoint::new(block2.txs[0].hash, 0).to_bytes())
@@ -263,5 +525,7 @@ mod tests {
         assert_eq!(value.block_height, 105001);
         assert_eq!(value.value, 9070000000);
         assert_eq!(value.address, "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
+        let descriptor = descriptor::with_checksum("addr(1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL)");
+        assert_eq!(value.descriptor, Some((descriptor, String::new())));
     }
 }
@@ -1,12 +1,13 @@
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use clap::{App, Arg, ArgMatches, SubCommand};
 
 use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::Callback;
+use crate::callbacks::{common, Callback};
 use crate::common::utils;
 use crate::errors::OpResult;
 
@@ -18,6 +19,8 @@ pub struct CsvDump {
     tx_writer: BufWriter<File>,
     txin_writer: BufWriter<File>,
     txout_writer: BufWriter<File>,
+    asm: bool,         // Adds the scripts in ASM next to their hex
+    descriptors: bool, // Adds the output descriptor and its keys after the address and the spent one to tx_in
 
     start_height: u64,
     end_height: u64,
@@ -27,8 +30,38 @@ pub struct CsvDump {
 }
 
 impl CsvDump {
+    /// Creates the callback, writing its csv files to `dump_folder`
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let cap = 4000000;
+        let cb = CsvDump {
+            dump_folder: PathBuf::from(dump_folder),
+            block_writer: CsvDump::create_writer(cap, dump_folder.join("blocks.csv.tmp"))?,
+            tx_writer: CsvDump::create_writer(cap, dump_folder.join("transactions.csv.tmp"))?,
+            txin_writer: CsvDump::create_writer(cap, dump_folder.join("tx_in.csv.tmp"))?,
+            txout_writer: CsvDump::create_writer(cap, dump_folder.join("tx_out.csv.tmp"))?,
+            asm: false,
+            descriptors: false,
+            start_height: 0,
+            end_height: 0,
+            tx_count: 0,
+            in_count: 0,
+            out_count: 0,
+        };
+        Ok(cb)
+    }
+
     fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(&path)?))
+        common::open_writer(cap, &path)
+    }
+
+    /// Keep in sync with c'tor
+    fn writers(&mut self) -> [&mut BufWriter<File>; 4] {
+        [
+            &mut self.block_writer,
+            &mut self.tx_writer,
+            &mut self.txin_writer,
+            &mut self.txout_writer,
+        ]
     }
 }
 
@@ -47,31 +80,34 @@ impl Callback for CsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::with_name("asm")
+                    .long("asm")
+                    .help("Adds scriptSig and scriptPubKey columns in Bitcoin Core's ASM format"),
+            )
+            .arg(
+                Arg::with_name("descriptors")
+                    .long("descriptors")
+                    .help("Adds output descriptor and normalised key columns to tx_out and tx_in"),
+            )
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.value_of("dump-folder").unwrap());
-        let cap = 4000000;
-        let cb = CsvDump {
-            dump_folder: PathBuf::from(dump_folder),
-            block_writer: CsvDump::create_writer(cap, dump_folder.join("blocks.csv.tmp"))?,
-            tx_writer: CsvDump::create_writer(cap, dump_folder.join("transactions.csv.tmp"))?,
-            txin_writer: CsvDump::create_writer(cap, dump_folder.join("tx_in.csv.tmp"))?,
-            txout_writer: CsvDump::create_writer(cap, dump_folder.join("tx_out.csv.tmp"))?,
-            start_height: 0,
-            end_height: 0,
-            tx_count: 0,
-            in_count: 0,
-            out_count: 0,
-        };
+        let mut cb =
+            CsvDump::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))?;
+        cb.asm = matches.is_present("asm");
+        cb.descriptors = matches.is_present("descriptors");
         Ok(cb)
     }
 
     fn on_start(&mut self, _: &CoinType, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
+        for writer in self.writers().iter_mut() {
+            common::reset_writer(writer, 0)?;
+        }
         info!(target: "callback", "Using `csvdump` with dump folder: {} ...", &self.dump_folder.display());
         Ok(())
     }
@@ -90,15 +126,22 @@ impl Callback for CsvDump {
 
             // serialize inputs
             for input in &tx.value.inputs {
-                self.txin_writer
-                    .write_all(input.input.as_csv(&txid_str).as_bytes())?;
+                self.txin_writer.write_all(
+                    input
+                        .input
+                        .as_csv(&txid_str, self.asm, self.descriptors)
+                        .as_bytes(),
+                )?;
             }
             self.in_count += tx.value.in_count.value;
 
             // serialize outputs
             for (i, output) in tx.value.outputs.iter().enumerate() {
-                self.txout_writer
-                    .write_all(output.as_csv(&txid_str, i as u32).as_bytes())?;
+                self.txout_writer.write_all(
+                    output
+                        .as_csv(&txid_str, i as u32, self.asm, self.descriptors)
+                        .as_bytes(),
+                )?;
             }
             self.out_count += tx.value.out_count.value;
         }
@@ -106,6 +149,39 @@ impl Callback for CsvDump {
         Ok(())
     }
 
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::with_capacity(8 * 8);
+        for &value in &[
+            self.start_height,
+            self.tx_count,
+            self.in_count,
+            self.out_count,
+        ] {
+            state.write_u64::<LittleEndian>(value)?;
+        }
+        common::write_flag(&mut state, self.asm)?;
+        common::write_flag(&mut state, self.descriptors)?;
+        for writer in self.writers().iter_mut() {
+            state.write_u64::<LittleEndian>(common::flush_writer(writer)?)?;
+        }
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.tx_count = state.read_u64::<LittleEndian>()?;
+        self.in_count = state.read_u64::<LittleEndian>()?;
+        self.out_count = state.read_u64::<LittleEndian>()?;
+        common::check_flag(&mut state, "asm", self.asm)?;
+        common::check_flag(&mut state, "descriptors", self.descriptors)?;
+        for writer in self.writers().iter_mut() {
+            common::reset_writer(writer, state.read_u64::<LittleEndian>()?)?;
+        }
+        info!(target: "callback", "Resuming `csvdump` at block {} with dump folder: {} ...",
+              block_height, &self.dump_folder.display());
+        Ok(())
+    }
+
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
         self.end_height = block_height;
 
@@ -0,0 +1,355 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::inscription;
+use crate::callbacks::{common, ParallelCallback};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Extracts ordinals inscriptions from taproot script path spends.
+/// Bodies are stored as `content/<sha256>`, each inscription gets a line in the index csv file.
+pub struct Inscriptions {
+    dump_folder: PathBuf,
+    content_folder: PathBuf,
+    index_writer: BufWriter<File>,
+
+    start_height: u64,
+    end_height: u64,
+    inscription_count: u64,
+    content_count: u64,
+
+    // Bytes written to index_writer
+    written: u64,
+    // Output position and counters before each of the last blocks, oldest first
+    undo: common::UndoRing<InscriptionsUndo>,
+}
+
+/// State before a block was dumped
+struct InscriptionsUndo {
+    written: u64,
+    inscription_count: u64,
+}
+
+impl Inscriptions {
+    /// Creates the callback, writing the index to `dump_folder` and the bodies to its `content` subfolder
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let content_folder = dump_folder.join("content");
+        fs::create_dir_all(&content_folder)?;
+        let cb = Inscriptions {
+            dump_folder: PathBuf::from(dump_folder),
+            content_folder,
+            index_writer: common::open_writer(4000000, &dump_folder.join("inscriptions.csv.tmp"))?,
+            start_height: 0,
+            end_height: 0,
+            inscription_count: 0,
+            content_count: 0,
+            written: 0,
+            undo: common::UndoRing::new(),
+        };
+        Ok(cb)
+    }
+
+    /// Writes a body unless the same content is already stored
+    fn write_content(&mut self, hash: &[u8; 32], body: &[u8]) -> OpResult<()> {
+        let path = self.content_folder.join(utils::arr_to_hex(hash));
+        if path.exists() {
+            return Ok(());
+        }
+        // Don't leave truncated files behind if the parser gets interrupted
+        let tmp_path = path.with_extension("part");
+        fs::write(&tmp_path, body)?;
+        fs::rename(&tmp_path, &path)?;
+        self.content_count += 1;
+        Ok(())
+    }
+}
+
+/// Makes a text field safe for a csv column
+fn field_str(value: Option<&[u8]>) -> String {
+    value
+        .map(|value| String::from_utf8_lossy(value).replace([';', '\n', '\r'], " "))
+        .unwrap_or_default()
+}
+
+/// Inscriptions of a single block
+#[derive(Default)]
+pub struct InscriptionsBlock {
+    block_height: u64,
+    csv: Vec<u8>,
+    count: u64,
+    /// Bodies with their SHA256
+    contents: Vec<([u8; 32], Vec<u8>)>,
+}
+
+/// Dumped consecutive blocks in height order
+pub struct InscriptionsBatch(Vec<InscriptionsBlock>);
+
+impl ParallelCallback for Inscriptions {
+    type Output = InscriptionsBatch;
+
+    fn build_subcommand<'a, 'b>() -> App<'a, 'b>
+    where
+        Self: Sized,
+    {
+        SubCommand::with_name("inscriptions")
+            .about("Extracts ordinals inscriptions from taproot witnesses")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::with_name("dump-folder")
+                    .help("Folder to store the index csv file and the content folder")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Inscriptions::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))
+    }
+
+    fn on_start(&mut self, _: &CoinType, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        common::reset_writer(&mut self.index_writer, 0)?;
+        self.written = 0;
+        self.undo.clear();
+        info!(target: "callback", "Using `inscriptions` with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<InscriptionsBatch> {
+        let mut batch = InscriptionsBlock {
+            block_height,
+            ..Default::default()
+        };
+        for tx in &block.txs {
+            let txid_str = utils::arr_to_hex_swapped(&tx.hash);
+            // The index counts the envelopes of all inputs
+            let mut index = 0;
+            for (vin, input) in tx.value.inputs.iter().enumerate() {
+                for inscription in inscription::from_witness(&input.input.witness) {
+                    // Content types may contain ';', so they come last
+                    let content_type = inscription
+                        .content_type()
+                        .map(|value| String::from_utf8_lossy(value).replace(['\n', '\r'], " "))
+                        .unwrap_or_default();
+                    let parents = inscription
+                        .parents()
+                        .iter()
+                        .map(|(txid, index)| {
+                            format!("{}i{}", utils::arr_to_hex_swapped(txid), index)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let pointer = inscription
+                        .pointer()
+                        .map(|pointer| pointer.to_string())
+                        .unwrap_or_default();
+                    let metadata = inscription
+                        .metadata()
+                        .map(|value| utils::arr_to_hex(&value))
+                        .unwrap_or_default();
+                    let content_encoding = field_str(inscription.content_encoding());
+                    let metaprotocol = field_str(inscription.metaprotocol());
+                    let body = inscription.body.unwrap_or_default();
+                    let hash = utils::sha256(&body);
+                    // (inscription_id; txid; input; height; size; sha256; pointer; parents; content_encoding;
+                    //  metaprotocol; metadata; content_type)
+                    writeln!(
+                        batch.csv,
+                        "{}i{};{};{};{};{};{};{};{};{};{};{};{}",
+                        txid_str,
+                        index,
+                        txid_str,
+                        vin,
+                        block_height,
+                        body.len(),
+                        utils::arr_to_hex(&hash),
+                        pointer,
+                        parents,
+                        content_encoding,
+                        metaprotocol,
+                        metadata,
+                        content_type
+                    )?;
+                    if !body.is_empty() {
+                        batch.contents.push((hash, body));
+                    }
+                    batch.count += 1;
+                    index += 1;
+                }
+            }
+        }
+        Ok(InscriptionsBatch(vec![batch]))
+    }
+
+    fn merge(&self, mut first: InscriptionsBatch, second: InscriptionsBatch) -> InscriptionsBatch {
+        first.0.extend(second.0);
+        first
+    }
+
+    fn on_batch(&mut self, batch: InscriptionsBatch) -> OpResult<()> {
+        for block in batch.0 {
+            self.undo.push(
+                block.block_height,
+                InscriptionsUndo {
+                    written: self.written,
+                    inscription_count: self.inscription_count,
+                },
+            );
+            for (hash, body) in &block.contents {
+                self.write_content(hash, body)?;
+            }
+            self.index_writer.write_all(&block.csv)?;
+            self.written += block.csv.len() as u64;
+            self.inscription_count += block.count;
+        }
+        Ok(())
+    }
+
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::with_capacity(4 * 8);
+        for &value in &[
+            self.start_height,
+            self.inscription_count,
+            self.content_count,
+        ] {
+            state.write_u64::<LittleEndian>(value)?;
+        }
+        state.write_u64::<LittleEndian>(common::flush_writer(&mut self.index_writer)?)?;
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.inscription_count = state.read_u64::<LittleEndian>()?;
+        self.content_count = state.read_u64::<LittleEndian>()?;
+        self.written = state.read_u64::<LittleEndian>()?;
+        common::reset_writer(&mut self.index_writer, self.written)?;
+        self.undo.clear();
+        info!(target: "callback", "Resuming `inscriptions` at block {} with dump folder: {} ...",
+              block_height, &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn supports_reorg(&self) -> bool {
+        true
+    }
+
+    fn on_reorg(&mut self, fork_height: u64) -> OpResult<()> {
+        let undo = self.undo.rollback(fork_height)?;
+        // Stored bodies are kept, the new chain likely inscribes the same content again
+        common::reset_writer(&mut self.index_writer, undo.written)?;
+        self.written = undo.written;
+        self.inscription_count = undo.inscription_count;
+        info!(target: "callback", "Rolled back `inscriptions` to block {}", fork_height);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.end_height = block_height;
+        self.index_writer.flush()?;
+        fs::rename(
+            self.dump_folder.join("inscriptions.csv.tmp"),
+            self.dump_folder.join(format!(
+                "inscriptions-{}-{}.csv",
+                self.start_height, self.end_height
+            )),
+        )?;
+
+        info!(target: "callback", "Done.\nDumped all {} blocks:\n\
+                                   \t-> inscriptions: {:9}\n\
+                                   \t-> new contents: {:9}",
+             self.end_height, self.inscription_count, self.content_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::{Callback, Parallel};
+    use crate::common::fixtures;
+
+    /// Block with a single transaction revealing `scripts` in its inputs
+    fn block(scripts: &[Vec<u8>]) -> Block {
+        let control = [&[0xc0][..], &[0x11; 32]].concat();
+        let inputs = scripts
+            .iter()
+            .map(|script| {
+                let witness = vec![vec![0x33; 64], script.clone(), control.clone()];
+                fixtures::input(TxOutpoint::new([0x22; 32], 0), &[], 0xffffffff, witness)
+            })
+            .collect();
+        fixtures::block(vec![fixtures::tx(2, inputs, Vec::new(), 0)])
+    }
+
+    #[test]
+    fn test_inscriptions() {
+        let dump_folder = std::env::temp_dir().join(format!("inscriptions-{}", std::process::id()));
+        let mut cb = Parallel(Inscriptions::with_dump_folder(&dump_folder).unwrap());
+        cb.on_start(&CoinType::default(), 0).unwrap();
+
+        // OP_FALSE OP_IF "ord" 1 "text/plain;charset=utf-8" OP_0 "hi" OP_ENDIF, twice in the first input
+        let envelope = utils::hex_to_vec(
+            "0063036f72640101187465 78742f706c61696e3b636861727365743d7574662d38 0002686968"
+                .replace(' ', "")
+                .as_str(),
+        );
+        // 2 0x01, 3 <parent txid>, 5 a0, 7 "a;b", 9 "br", no body
+        let tagged = utils::hex_to_vec(
+            &format!(
+                "0063036f7264010201010103 20{} 010501a0 010703613b62 0109026272 68",
+                "44".repeat(32)
+            )
+            .replace(' ', ""),
+        );
+        let block = block(&[
+            [envelope.clone(), envelope.clone()].concat(),
+            vec![0x51],
+            envelope,
+            tagged,
+        ]);
+        cb.on_block(&block, 840000).unwrap();
+        cb.on_complete(840000).unwrap();
+
+        let txid = utils::arr_to_hex_swapped(&block.txs[0].hash);
+        let hash = utils::arr_to_hex(&utils::sha256(b"hi"));
+        let csv = fs::read_to_string(dump_folder.join("inscriptions-0-840000.csv")).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            format!(
+                "{0}i0;{0};0;840000;2;{1};;;;;;text/plain;charset=utf-8",
+                txid, hash
+            )
+        );
+        assert!(lines[1].starts_with(&format!("{0}i1;{0};0;", txid)));
+        assert!(lines[2].starts_with(&format!("{0}i2;{0};2;", txid)));
+        assert_eq!(
+            lines[3],
+            format!(
+                "{0}i3;{0};3;840000;0;{1};1;{2}i0;br;a b;a0;",
+                txid,
+                utils::arr_to_hex(&utils::sha256(b"")),
+                "44".repeat(32)
+            )
+        );
+        assert_eq!(
+            fs::read(dump_folder.join("content").join(&hash)).unwrap(),
+            b"hi"
+        );
+        assert_eq!(cb.0.content_count, 1);
+        fs::remove_dir_all(dump_folder).unwrap();
+    }
+}
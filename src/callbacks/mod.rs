@@ -1,13 +1,19 @@
 use clap::{App, ArgMatches};
+use rayon::prelude::*;
 
 use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
-use crate::errors::OpResult;
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxIn, EvaluatedTxOut};
+use crate::blockchain::proto::Hashed;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 pub mod balances;
 mod common;
 pub mod csvdump;
+pub mod inscriptions;
+pub mod pubkeys;
 pub mod stats;
+pub mod timelocks;
 pub mod unspentcsvdump;
 pub mod sigdump;
 
@@ -16,6 +22,11 @@ pub mod sigdump;
 /// At this stage the main chain is already determined and orphans/stales are removed.
 /// Note: These callbacks are only triggered with ParseMode::FullData.
 /// (The first run to determine longest chain is running in ParseMode::Indexing)
+///
+/// Besides on_block(), the parser calls on_tx(), on_input() and on_output() for every
+/// transaction of the block, after on_block() returned and before the next block.
+/// All of them do nothing by default, so a callback only implements the hooks it needs.
+/// Callbacks receiving batches (is_parallel()) don't get the transaction hooks.
 pub trait Callback {
     /// Builds SubCommand to specify callback name and required args,
     /// exits if some required args are missing.
@@ -32,8 +43,261 @@ pub trait Callback {
     fn on_start(&mut self, coin_type: &CoinType, block_height: u64) -> OpResult<()>;
 
     /// Gets called if a new block is available.
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()>;
+    fn on_block(&mut self, _block: &Block, _block_height: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Gets called for each transaction, before its inputs and outputs.
+    fn on_tx(&mut self, _ctx: &TxContext) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Gets called for each input. `prevout` is the spent output if needs_prevouts() is true
+    /// and the output was created within the parsed range.
+    fn on_input(
+        &mut self,
+        _ctx: &TxContext,
+        _vin: usize,
+        _input: &EvaluatedTxIn,
+        _prevout: Option<&EvaluatedTxOut>,
+    ) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Gets called for each output, after all inputs of the transaction.
+    fn on_output(
+        &mut self,
+        _ctx: &TxContext,
+        _vout: usize,
+        _output: &EvaluatedTxOut,
+    ) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Returns true if on_input() should receive the spent outputs.
+    /// The parser keeps all unspent outputs in memory as long as one callback needs them.
+    fn needs_prevouts(&self) -> bool {
+        false
+    }
+
+    /// Gets called with consecutive blocks, the first one at `block_height`.
+    /// Unless is_parallel() is true, this gets a single block at a time.
+    /// The default implementation calls on_block() for each block.
+    fn on_blocks(&mut self, blocks: &[Block], block_height: u64) -> OpResult<()> {
+        for (i, block) in blocks.iter().enumerate() {
+            self.on_block(block, block_height + i as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the callback benefits from receiving blocks in batches via on_blocks().
+    /// Such callbacks don't get the transaction hooks.
+    fn is_parallel(&self) -> bool {
+        false
+    }
+
+    /// Flushes all outputs and returns the state needed to continue with `block_height`,
+    /// or None if the callback can't be resumed. Gets called periodically if checkpoints are enabled.
+    fn on_checkpoint(&mut self, _block_height: u64) -> OpResult<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Gets called instead of on_start() when resuming at `block_height`,
+    /// with the state returned by on_checkpoint().
+    fn on_resume(
+        &mut self,
+        _coin_type: &CoinType,
+        _block_height: u64,
+        _state: &[u8],
+    ) -> OpResult<()> {
+        Err(resume_unsupported())
+    }
+
+    /// Returns true if the callback implements on_reorg().
+    /// In follow mode the parser stops before a reorg if a callback doesn't support it.
+    fn supports_reorg(&self) -> bool {
+        false
+    }
+
+    /// Gets called in follow mode if the blocks from `fork_height` on were replaced by a reorg.
+    /// Everything derived from these blocks must be dropped,
+    /// the blocks of the new chain are passed again starting at `fork_height`.
+    /// Only called if supports_reorg() returns true.
+    fn on_reorg(&mut self, _fork_height: u64) -> OpResult<()> {
+        Ok(())
+    }
 
     /// Gets called if the parser has finished and all blocks are handled
     fn on_complete(&mut self, block_height: u64) -> OpResult<()>;
 }
+
+fn resume_unsupported() -> OpError {
+    OpError::new(OpErrorKind::InvalidArgsError).join_msg("Callback doesn't support resuming.")
+}
+
+/// Position of a transaction in the chain, passed to the transaction level hooks
+pub struct TxContext<'a> {
+    pub block_height: u64,
+    pub block_hash: &'a [u8; 32],
+    pub block_time: u32,
+    pub tx_index: usize,
+    pub tx: &'a Hashed<EvaluatedTx>,
+}
+
+/// Calls the transaction level hooks of `callback` for all transactions in `block`.
+/// `prevouts` holds the spent outputs for each input of each transaction, it may be empty.
+pub fn walk_txs(
+    callback: &mut dyn Callback,
+    block: &Block,
+    block_height: u64,
+    prevouts: &[Vec<Option<EvaluatedTxOut>>],
+) -> OpResult<()> {
+    for (tx_index, tx) in block.txs.iter().enumerate() {
+        let ctx = TxContext {
+            block_height,
+            block_hash: &block.header.hash,
+            block_time: block.header.value.timestamp,
+            tx_index,
+            tx,
+        };
+        callback.on_tx(&ctx)?;
+        let tx_prevouts = prevouts.get(tx_index);
+        for (vin, input) in tx.value.inputs.iter().enumerate() {
+            let prevout = tx_prevouts
+                .and_then(|p| p.get(vin))
+                .and_then(|p| p.as_ref());
+            callback.on_input(&ctx, vin, input, prevout)?;
+        }
+        for (vout, output) in tx.value.outputs.iter().enumerate() {
+            callback.on_output(&ctx, vout, output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Implement this trait for a Callback whose per-block work doesn't depend on other blocks.
+/// Blocks are mapped concurrently and in no particular order, the partial results are merged
+/// and passed to on_batch() strictly in height order.
+/// Use `Parallel<C>` to run such a callback wherever a `Callback` is expected.
+pub trait ParallelCallback: Send + Sync {
+    /// Partial result of one or more consecutive blocks
+    type Output: Send;
+
+    /// Builds SubCommand to specify callback name and required args,
+    /// exits if some required args are missing.
+    fn build_subcommand<'a, 'b>() -> App<'a, 'b>
+    where
+        Self: Sized;
+
+    /// Instantiates callback
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized;
+
+    /// Gets called shortly before the blocks are parsed.
+    fn on_start(&mut self, coin_type: &CoinType, block_height: u64) -> OpResult<()>;
+
+    /// Processes a single block. Gets called from multiple threads at once.
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<Self::Output>;
+
+    /// Combines two partial results, `first` always covers the lower heights.
+    /// Must be associative, but doesn't need to be commutative.
+    fn merge(&self, first: Self::Output, second: Self::Output) -> Self::Output;
+
+    /// Gets called with the merged result of consecutive blocks, in height order.
+    fn on_batch(&mut self, output: Self::Output) -> OpResult<()>;
+
+    /// See Callback::on_checkpoint()
+    fn on_checkpoint(&mut self, _block_height: u64) -> OpResult<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// See Callback::on_resume()
+    fn on_resume(
+        &mut self,
+        _coin_type: &CoinType,
+        _block_height: u64,
+        _state: &[u8],
+    ) -> OpResult<()> {
+        Err(resume_unsupported())
+    }
+
+    /// See Callback::supports_reorg()
+    fn supports_reorg(&self) -> bool {
+        false
+    }
+
+    /// See Callback::on_reorg()
+    fn on_reorg(&mut self, _fork_height: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Gets called if the parser has finished and all blocks are handled
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()>;
+}
+
+/// Drives a ParallelCallback through the `Callback` interface
+pub struct Parallel<C: ParallelCallback>(pub C);
+
+impl<C: ParallelCallback> Callback for Parallel<C> {
+    fn build_subcommand<'a, 'b>() -> App<'a, 'b>
+    where
+        Self: Sized,
+    {
+        C::build_subcommand()
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Parallel(C::new(matches)?))
+    }
+
+    fn on_start(&mut self, coin_type: &CoinType, block_height: u64) -> OpResult<()> {
+        self.0.on_start(coin_type, block_height)
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let output = self.0.map_block(block, block_height)?;
+        self.0.on_batch(output)
+    }
+
+    fn on_blocks(&mut self, blocks: &[Block], block_height: u64) -> OpResult<()> {
+        let callback = &self.0;
+        // Rayon keeps the order of indexed iterators when reducing
+        let merged = blocks
+            .par_iter()
+            .enumerate()
+            .map(|(i, block)| callback.map_block(block, block_height + i as u64))
+            .reduce_with(|first, second| Ok(callback.merge(first?, second?)));
+        match merged {
+            Some(output) => self.0.on_batch(output?),
+            None => Ok(()),
+        }
+    }
+
+    fn is_parallel(&self) -> bool {
+        true
+    }
+
+    fn on_checkpoint(&mut self, block_height: u64) -> OpResult<Option<Vec<u8>>> {
+        self.0.on_checkpoint(block_height)
+    }
+
+    fn on_resume(&mut self, coin_type: &CoinType, block_height: u64, state: &[u8]) -> OpResult<()> {
+        self.0.on_resume(coin_type, block_height, state)
+    }
+
+    fn supports_reorg(&self) -> bool {
+        self.0.supports_reorg()
+    }
+
+    fn on_reorg(&mut self, fork_height: u64) -> OpResult<()> {
+        self.0.on_reorg(fork_height)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.0.on_complete(block_height)
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use secp256k1::PublicKey;
+
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{self, AddressParams, ScriptPattern};
+use crate::blockchain::proto::tx::{EvaluatedTxIn, EvaluatedTxOut};
+use crate::callbacks::ParallelCallback;
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Encodings in which a key was seen, see PubkeyInfo::forms
+pub const FORM_COMPRESSED: u8 = 1;
+pub const FORM_UNCOMPRESSED: u8 = 2;
+pub const FORM_HYBRID: u8 = 4;
+pub const FORM_XONLY: u8 = 8;
+
+/// Dumps every public key revealed on chain with its first appearance and addresses
+pub struct Pubkeys {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    params: AddressParams,
+
+    // key: compressed key, or the raw bytes of invalid keys
+    keys: HashMap<Vec<u8>, PubkeyInfo>,
+
+    start_height: u64,
+    end_height: u64,
+}
+
+/// Appearances of a single key
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubkeyInfo {
+    pub valid: bool, // False if the key is not on the curve or has a wrong prefix
+    pub forms: u8,   // FORM_* flags
+    pub first_height: u64,
+    pub count: u64,
+    pub addresses: BTreeSet<String>, // Addresses of the outputs paying to or spent with the key
+}
+
+/// A key as it appeared in a script or witness
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubkeySighting {
+    pub id: Vec<u8>,
+    pub valid: bool,
+    pub form: u8,
+    pub block_height: u64,
+    pub address: Option<String>,
+}
+
+impl PubkeySighting {
+    /// Normalises `key` to its compressed encoding, x-only keys are the point with even y [BIP340].
+    /// Invalid keys keep their raw bytes, None if `key` doesn't have the size of any encoding.
+    pub fn new(key: &[u8], block_height: u64, address: Option<String>) -> Option<Self> {
+        let form = match (key.len(), key.first()) {
+            (32, _) => FORM_XONLY,
+            (33, _) => FORM_COMPRESSED,
+            (65, Some(0x06)) | (65, Some(0x07)) => FORM_HYBRID,
+            (65, _) => FORM_UNCOMPRESSED,
+            _ => return None,
+        };
+        let point = if form == FORM_XONLY {
+            PublicKey::from_slice(&[&[0x02][..], key].concat()).ok()
+        } else {
+            PublicKey::from_slice(key).ok()
+        };
+        Some(PubkeySighting {
+            id: match point {
+                Some(ref point) => point.serialize().to_vec(),
+                None => key.to_vec(),
+            },
+            valid: point.is_some(),
+            form,
+            block_height,
+            address,
+        })
+    }
+}
+
+impl Pubkeys {
+    /// Creates the callback, writing its csv file to `dump_folder`
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let cb = Pubkeys {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: BufWriter::with_capacity(
+                4000000,
+                File::create(dump_folder.join("pubkeys.csv.tmp"))?,
+            ),
+            params: AddressParams::default(),
+            keys: HashMap::with_capacity(10000000),
+            start_height: 0,
+            end_height: 0,
+        };
+        Ok(cb)
+    }
+
+    /// Adds the keys of P2PK, bare multisig and taproot outputs
+    fn output_sightings(
+        &self,
+        output: &EvaluatedTxOut,
+        block_height: u64,
+        sightings: &mut Vec<PubkeySighting>,
+    ) {
+        match output.script.pattern {
+            ScriptPattern::Pay2PublicKey => {
+                if let Some(Ok(script::Instruction::PushBytes(_, key))) =
+                    script::Instructions::new(&output.out.script_pubkey).next()
+                {
+                    sightings.extend(PubkeySighting::new(
+                        key,
+                        block_height,
+                        output.script.address.clone(),
+                    ));
+                }
+            }
+            ScriptPattern::Pay2MultiSig { ref keys, .. } => {
+                for key in keys {
+                    sightings.extend(PubkeySighting::new(key, block_height, None));
+                }
+            }
+            ScriptPattern::Pay2Taproot => {
+                let key = &output.out.script_pubkey[2..];
+                sightings.extend(PubkeySighting::new(
+                    key,
+                    block_height,
+                    output.script.address.clone(),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds the keys revealed by P2PKH and P2WPKH spends and by P2SH and P2WSH multisig spends.
+    /// The spent output is unknown, so scriptSigs and witnesses are matched by their shape.
+    fn input_sightings(
+        &self,
+        input: &EvaluatedTxIn,
+        block_height: u64,
+        sightings: &mut Vec<PubkeySighting>,
+    ) {
+        let script_sig = &input.input.script_sig;
+        let witness = &input.input.witness;
+        let pushes: Vec<&[u8]> = match script::Instructions::new(script_sig)
+            .map(|i| match i {
+                Ok(script::Instruction::PushBytes(_, data)) => Some(data),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(pushes) => pushes,
+            None => return,
+        };
+
+        if witness.is_empty() {
+            match pushes.as_slice() {
+                // <sig> <pubkey>
+                [_, key] if looks_like_key(key) => {
+                    let script_pubkey = [
+                        &[0x76, 0xa9, 0x14][..],
+                        &utils::ridemp160(&utils::sha256(key)),
+                        &[0x88, 0xac],
+                    ]
+                    .concat();
+                    sightings.extend(PubkeySighting::new(
+                        key,
+                        block_height,
+                        self.address(&script_pubkey),
+                    ));
+                }
+                // OP_0 <sig>... <redeemScript>
+                [.., redeem_script] if pushes.len() >= 2 => {
+                    let script_pubkey = [
+                        &[0xa9, 0x14][..],
+                        &utils::ridemp160(&utils::sha256(redeem_script)),
+                        &[0x87],
+                    ]
+                    .concat();
+                    self.multisig_sightings(redeem_script, &script_pubkey, block_height, sightings);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        // Native or nested in P2SH
+        let nested = match pushes.as_slice() {
+            [] => None,
+            [program] => Some(*program),
+            _ => return,
+        };
+        let spent_script_pubkey = |program: Vec<u8>| match nested {
+            Some(nested) => [
+                &[0xa9, 0x14][..],
+                &utils::ridemp160(&utils::sha256(nested)),
+                &[0x87],
+            ]
+            .concat(),
+            None => program,
+        };
+        match witness.as_slice() {
+            // <sig> <pubkey>
+            [_, key] if key.len() == 33 => {
+                let program = [&[0x00, 0x14][..], &utils::ridemp160(&utils::sha256(key))].concat();
+                let address = self.address(&spent_script_pubkey(program));
+                sightings.extend(PubkeySighting::new(key, block_height, address));
+            }
+            // <> <sig>... <witnessScript>
+            [.., witness_script] => {
+                let program = [&[0x00, 0x20][..], &utils::sha256(witness_script)].concat();
+                let script_pubkey = spent_script_pubkey(program);
+                self.multisig_sightings(witness_script, &script_pubkey, block_height, sightings);
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds the keys of `script` if it is a multisig script, `script_pubkey` is the spent output
+    fn multisig_sightings(
+        &self,
+        script: &[u8],
+        script_pubkey: &[u8],
+        block_height: u64,
+        sightings: &mut Vec<PubkeySighting>,
+    ) {
+        if let ScriptPattern::Pay2MultiSig { ref keys, .. } =
+            script::eval_from_bytes(script, &self.params).pattern
+        {
+            let address = self.address(script_pubkey);
+            for key in keys {
+                sightings.extend(PubkeySighting::new(key, block_height, address.clone()));
+            }
+        }
+    }
+
+    #[inline]
+    fn address(&self, script_pubkey: &[u8]) -> Option<String> {
+        script::eval_from_bytes(script_pubkey, &self.params).address
+    }
+
+    /// Counts a sighting, the first one of a key determines its first_height
+    fn insert(&mut self, sighting: PubkeySighting) {
+        let info = self.keys.entry(sighting.id).or_insert(PubkeyInfo {
+            valid: sighting.valid,
+            forms: 0,
+            first_height: sighting.block_height,
+            count: 0,
+            addresses: BTreeSet::new(),
+        });
+        info.forms |= sighting.form;
+        info.count += 1;
+        if let Some(address) = sighting.address {
+            info.addresses.insert(address);
+        }
+    }
+}
+
+/// Compressed, uncompressed and hybrid keys by size and prefix, they may still be off the curve
+#[inline]
+fn looks_like_key(data: &[u8]) -> bool {
+    match data.first() {
+        Some(0x02) | Some(0x03) => data.len() == 33,
+        Some(0x04) | Some(0x06) | Some(0x07) => data.len() == 65,
+        _ => false,
+    }
+}
+
+/// Names of the FORM_* flags in `forms`
+fn form_names(forms: u8) -> String {
+    let names = [
+        (FORM_COMPRESSED, "compressed"),
+        (FORM_UNCOMPRESSED, "uncompressed"),
+        (FORM_HYBRID, "hybrid"),
+        (FORM_XONLY, "xonly"),
+    ];
+    names
+        .iter()
+        .filter(|(form, _)| forms & form != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Keys normalised like the ids of pubkeys.csv, in hex and separated by commas
+pub fn normalised_keys(keys: &[Vec<u8>]) -> String {
+    keys.iter()
+        .filter_map(|key| PubkeySighting::new(key, 0, None))
+        .map(|sighting| utils::arr_to_hex(&sighting.id))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl ParallelCallback for Pubkeys {
+    /// Sightings of consecutive blocks in height order
+    type Output = Vec<PubkeySighting>;
+
+    fn build_subcommand<'a, 'b>() -> App<'a, 'b>
+    where
+        Self: Sized,
+    {
+        SubCommand::with_name("pubkeys")
+            .about("Dumps all public keys revealed on chain to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::with_name("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Pubkeys::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))
+    }
+
+    fn on_start(&mut self, coin_type: &CoinType, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.params = coin_type.address_params();
+        info!(target: "callback", "Using `pubkeys` with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<Vec<PubkeySighting>> {
+        let mut sightings = Vec::new();
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                for input in &tx.value.inputs {
+                    self.input_sightings(input, block_height, &mut sightings);
+                }
+            }
+            for output in &tx.value.outputs {
+                self.output_sightings(output, block_height, &mut sightings);
+            }
+        }
+        Ok(sightings)
+    }
+
+    fn merge(
+        &self,
+        mut first: Vec<PubkeySighting>,
+        second: Vec<PubkeySighting>,
+    ) -> Vec<PubkeySighting> {
+        first.extend(second);
+        first
+    }
+
+    fn on_batch(&mut self, sightings: Vec<PubkeySighting>) -> OpResult<()> {
+        for sighting in sightings {
+            self.insert(sighting);
+        }
+        Ok(())
+    }
+
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::new();
+        state.write_u64::<LittleEndian>(self.start_height)?;
+        state.write_u64::<LittleEndian>(self.keys.len() as u64)?;
+        for (id, info) in &self.keys {
+            state.write_u8(id.len() as u8)?;
+            state.write_all(id)?;
+            state.write_u8(info.valid as u8)?;
+            state.write_u8(info.forms)?;
+            state.write_u64::<LittleEndian>(info.first_height)?;
+            state.write_u64::<LittleEndian>(info.count)?;
+            if info.addresses.len() > u32::MAX as usize {
+                return Err(OpError::new(OpErrorKind::CallbackError).join_msg(&format!(
+                    "Too many addresses for key {}",
+                    utils::arr_to_hex(id)
+                )));
+            }
+            state.write_u32::<LittleEndian>(info.addresses.len() as u32)?;
+            for address in &info.addresses {
+                state.write_u16::<LittleEndian>(address.len() as u16)?;
+                state.write_all(address.as_bytes())?;
+            }
+        }
+        Ok(Some(state))
+    }
+
+    fn on_resume(
+        &mut self,
+        coin_type: &CoinType,
+        block_height: u64,
+        mut state: &[u8],
+    ) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.params = coin_type.address_params();
+        let len = state.read_u64::<LittleEndian>()? as usize;
+        self.keys = HashMap::with_capacity(len);
+        for _ in 0..len {
+            let mut id = vec![0u8; state.read_u8()? as usize];
+            state.read_exact(&mut id)?;
+            let valid = state.read_u8()? != 0;
+            let forms = state.read_u8()?;
+            let first_height = state.read_u64::<LittleEndian>()?;
+            let count = state.read_u64::<LittleEndian>()?;
+            let n_addresses = state.read_u32::<LittleEndian>()?;
+            let mut addresses = BTreeSet::new();
+            for _ in 0..n_addresses {
+                let mut address = vec![0u8; state.read_u16::<LittleEndian>()? as usize];
+                state.read_exact(&mut address)?;
+                addresses.insert(String::from_utf8(address)?);
+            }
+            let info = PubkeyInfo {
+                valid,
+                forms,
+                first_height,
+                count,
+                addresses,
+            };
+            self.keys.insert(id, info);
+        }
+        info!(target: "callback", "Resuming `pubkeys` at block {} with {} keys and dump folder: {} ...",
+              block_height, self.keys.len(), &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.end_height = block_height;
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{}\n",
+                "pubkey", "valid", "forms", "firstHeight", "count", "addresses"
+            )
+            .as_bytes(),
+        )?;
+        for (id, info) in self.keys.iter() {
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{};{}\n",
+                    utils::arr_to_hex(id),
+                    info.valid as u8,
+                    form_names(info.forms),
+                    info.first_height,
+                    info.count,
+                    info.addresses.iter().cloned().collect::<Vec<_>>().join(",")
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.writer.flush()?;
+
+        fs::rename(
+            self.dump_folder.as_path().join("pubkeys.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "pubkeys-{}-{}.csv",
+                self.start_height, self.end_height
+            )),
+        )?;
+
+        info!(target: "callback", "Done.\nDumped all {} blocks:\n\
+                                   \t-> public keys:  {:9}",
+             self.end_height, self.keys.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::fixtures;
+
+    const KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    /// x = 0 is not on the curve
+    const OFF_CURVE: [u8; 33] = [
+        0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0,
+    ];
+    const UNCOMPRESSED: &str = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+                                483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+
+    fn input(script_sig: Vec<u8>, witness: Vec<Vec<u8>>) -> EvaluatedTxIn {
+        let input = fixtures::input(
+            TxOutpoint::new([0x11; 32], 0),
+            &script_sig,
+            0xffffffff,
+            witness,
+        );
+        EvaluatedTxIn::eval_script(input, &AddressParams::default())
+    }
+
+    fn output(script_pubkey: Vec<u8>) -> EvaluatedTxOut {
+        EvaluatedTxOut::eval_script(
+            fixtures::output(1000, &script_pubkey),
+            &AddressParams::default(),
+        )
+    }
+
+    #[test]
+    fn test_normalise() {
+        let key = utils::hex_to_vec(KEY);
+        let compressed = PubkeySighting::new(&key, 1, None).unwrap();
+        assert!(compressed.valid);
+        assert_eq!(compressed.id, key);
+        let uncompressed = PubkeySighting::new(&utils::hex_to_vec(UNCOMPRESSED), 2, None).unwrap();
+        assert_eq!(
+            (uncompressed.id, uncompressed.form),
+            (key.clone(), FORM_UNCOMPRESSED)
+        );
+        let xonly = PubkeySighting::new(&key[1..], 3, None).unwrap();
+        assert_eq!((xonly.id, xonly.form), (key.clone(), FORM_XONLY));
+
+        let off_curve = PubkeySighting::new(&OFF_CURVE, 4, None).unwrap();
+        assert!(!off_curve.valid);
+        assert_eq!(off_curve.id, OFF_CURVE);
+
+        // Sizes of no encoding
+        assert_eq!(PubkeySighting::new(&key[..20], 5, None), None);
+        assert_eq!(
+            PubkeySighting::new(&[key.clone(), key.clone()].concat(), 5, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_normalised_keys() {
+        // The keys of pk(uncompressed) and rawtr() join with the compressed key
+        let key = utils::hex_to_vec(KEY);
+        assert_eq!(normalised_keys(&[utils::hex_to_vec(UNCOMPRESSED)]), KEY);
+        assert_eq!(
+            normalised_keys(&[key[1..].to_vec(), key.clone()]),
+            format!("{0},{0}", KEY)
+        );
+        assert_eq!(normalised_keys(&[]), "");
+    }
+
+    #[test]
+    fn test_sightings() {
+        let dump_folder = std::env::temp_dir().join(format!("pubkeys-{}", std::process::id()));
+        fs::create_dir_all(&dump_folder).unwrap();
+        let mut cb = Pubkeys::with_dump_folder(&dump_folder).unwrap();
+        let key = utils::hex_to_vec(KEY);
+        let uncompressed = utils::hex_to_vec(UNCOMPRESSED);
+        let signature = vec![0x30; 71];
+        let mut sightings = Vec::new();
+
+        // P2PK output, P2PKH spend with the uncompressed key, P2WPKH spend and a 1-of-2 P2SH multisig
+        cb.output_sightings(
+            &output([&[0x21][..], &key, &[0xac]].concat()),
+            10,
+            &mut sightings,
+        );
+        let script_sig = [&[0x47][..], &signature, &[0x41], &uncompressed].concat();
+        cb.input_sightings(&input(script_sig, Vec::new()), 11, &mut sightings);
+        cb.input_sightings(
+            &input(Vec::new(), vec![signature.clone(), key.clone()]),
+            12,
+            &mut sightings,
+        );
+        let redeem_script = [&[0x51, 0x21][..], &key, &[0x21], &OFF_CURVE, &[0x52, 0xae]].concat();
+        let script_sig = [
+            &[0x00, 0x47][..],
+            &signature,
+            &[0x4c, redeem_script.len() as u8],
+            &redeem_script,
+        ]
+        .concat();
+        cb.input_sightings(&input(script_sig, Vec::new()), 13, &mut sightings);
+        assert_eq!(sightings.len(), 5);
+        cb.on_batch(sightings).unwrap();
+
+        let info = &cb.keys[&key];
+        assert!(info.valid);
+        assert_eq!(info.first_height, 10);
+        assert_eq!(info.count, 4);
+        assert_eq!(info.forms, FORM_COMPRESSED | FORM_UNCOMPRESSED);
+        assert_eq!(info.addresses.len(), 4);
+        assert!(info
+            .addresses
+            .contains("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"));
+        assert!(info
+            .addresses
+            .contains("1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm"));
+        assert!(info
+            .addresses
+            .contains("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"));
+        assert!(info
+            .addresses
+            .iter()
+            .any(|address| address.starts_with('3')));
+        assert!(!cb.keys[&OFF_CURVE[..]].valid);
+
+        // Checkpoints keep all keys
+        let state = cb.on_checkpoint(13).unwrap().unwrap();
+        let mut resumed = Pubkeys::with_dump_folder(&dump_folder).unwrap();
+        resumed.on_resume(&CoinType::default(), 14, &state).unwrap();
+        assert_eq!(resumed.keys, cb.keys);
+        fs::remove_dir_all(dump_folder).unwrap();
+    }
+}
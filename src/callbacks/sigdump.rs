@@ -1,7 +1,8 @@
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use clap::{App, Arg, ArgMatches, SubCommand};
 use ecdsa::Signature;
 use p256::{
@@ -10,23 +11,27 @@ use p256::{
 use bitcoin_explorer::{BitcoinDB, Txid, Transaction, FromHex};
 
 use blockchain::proto::script::ScriptPattern::ScriptSig;
+use blockchain::proto::script;
 use blockchain::proto::ToRaw;
 use blockchain::proto::tx::TxOutpoint;
 use blockchain::proto::varuint::VarUint;
 
-use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::parser::types::{CoinType, SighashVariant};
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::descriptor;
+use crate::blockchain::proto::bytes::Bytes;
 use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
 use crate::blockchain::proto::Hashed;
-use crate::callbacks::Callback;
+use crate::callbacks::{common, pubkeys, ParallelCallback};
 use crate::common::utils;
-use crate::errors::OpResult;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 /// Dumps the whole blockchain into csv files
 pub struct SigDump {
     // Each structure gets stored in a separate csv file
     dump_folder: PathBuf,
     sig_writer: BufWriter<File>,
+    asm: bool, // Adds the scriptSig and the previous scriptPubKey in ASM
 
     start_height: u64,
     end_height: u64,
@@ -35,14 +40,60 @@ pub struct SigDump {
     out_count: u64,
     blocks_count: u64,
     db: BitcoinDB,
+
+    // Bytes written to sig_writer
+    written: u64,
+    // Output position and counters before each of the last blocks, oldest first
+    undo: common::UndoRing<SigDumpUndo>,
+}
+
+/// State before a block was dumped
+struct SigDumpUndo {
+    written: u64,
+    tx_count: u64,
+    in_count: u64,
+    out_count: u64,
+    blocks_count: u64,
 }
 
 impl SigDump {
+    /// Creates the callback, writing signatures to `dump_folder`.
+    /// Previous outputs are looked up in the node's data directory `bitcoin_folder`.
+    pub fn with_folders(dump_folder: &Path, bitcoin_folder: &Path) -> OpResult<Self> {
+        if !dump_folder.exists() {
+            info!(target: "callback", "Creating dump folder {} ...", dump_folder.display());
+            fs::create_dir_all(dump_folder)?;
+        }
+        let cap = 4000000;
+        let db = BitcoinDB::new(bitcoin_folder, true).map_err(|e| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Unable to open Bitcoin DB in {}: {}",
+                bitcoin_folder.display(),
+                e
+            ))
+        })?;
+        let cb = SigDump {
+            dump_folder: PathBuf::from(dump_folder),
+            sig_writer: SigDump::create_writer(cap, dump_folder.join("signatures.csv.tmp"))?,
+            asm: false,
+            start_height: 0,
+            end_height: 0,
+            tx_count: 0,
+            in_count: 0,
+            out_count: 0,
+            blocks_count: 0,
+            db,
+            written: 0,
+            undo: common::UndoRing::new(),
+        };
+        Ok(cb)
+    }
+
     fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(&path)?))
+        common::open_writer(cap, &path)
     }
 
-    fn get_previous_outputs(&mut self, previous_txid: Vec<u8>) -> Option<Vec<Vec<u8>>> {
+    fn get_previous_outputs(&self, previous_txid: Vec<u8>) -> Option<Vec<Vec<u8>>> {
         let txid_str = utils::arr_to_hex_swapped(&previous_txid);
         let txid = Txid::from_hex(&txid_str).ok()?;
         let tx: Transaction = self.db.get_transaction(&txid).ok()?;
@@ -58,7 +109,22 @@ impl SigDump {
 }
 
 
-impl Callback for SigDump {
+/// Signatures and counters of a single block
+#[derive(Default)]
+pub struct SigDumpBlock {
+    block_height: u64,
+    csv: Vec<u8>,
+    tx_count: u64,
+    in_count: u64,
+    out_count: u64,
+}
+
+/// Dumped consecutive blocks in height order
+pub struct SigDumpBatch(Vec<SigDumpBlock>);
+
+impl ParallelCallback for SigDump {
+    type Output = SigDumpBatch;
+
     fn build_subcommand<'a, 'b>() -> App<'a, 'b>
         where
             Self: Sized,
@@ -79,49 +145,44 @@ impl Callback for SigDump {
                     .index(2)
                     .required(true),
             )
+            .arg(
+                Arg::with_name("asm").long("asm").help(
+                    "Adds the scriptSig and the spent scriptPubKey in Bitcoin Core's ASM format",
+                ),
+            )
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
         where
             Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.value_of("dump-folder").unwrap());
-        if !dump_folder.exists() {
-            eprintln!("Dump folder {} does not exist. Attempting to create it...", dump_folder.display());
-            match fs::create_dir_all(dump_folder) {
-                Ok(_) => {
-                    eprintln!("Successfully created dump folder.")
-                }
-                Err(_) => {
-                    panic!("Failed to create dump folder: {}", dump_folder.display());
-                }
-            }
-        }
-        let bitcoin_folder = &PathBuf::from(matches.value_of("bitcoin-folder").unwrap());
-        let cap = 4000000;
-        let db = BitcoinDB::new(bitcoin_folder, true).expect("Failure to create Bitcoin DB");
-        let cb = SigDump {
-            dump_folder: PathBuf::from(dump_folder),
-            sig_writer: SigDump::create_writer(cap, dump_folder.join("signatures.csv.tmp"))?,
-            start_height: 0,
-            end_height: 0,
-            tx_count: 0,
-            in_count: 0,
-            out_count: 0,
-            blocks_count: 0,
-            db,
-        };
+        let mut cb = SigDump::with_folders(
+            Path::new(matches.value_of("dump-folder").unwrap()),
+            Path::new(matches.value_of("bitcoin-folder").unwrap()),
+        )?;
+        cb.asm = matches.is_present("asm");
         Ok(cb)
     }
 
-    fn on_start(&mut self, _: &CoinType, block_height: u64) -> OpResult<()> {
+    fn on_start(&mut self, coin_type: &CoinType, block_height: u64) -> OpResult<()> {
+        if coin_type.sighash != SighashVariant::Legacy {
+            warn!(target: "callback", "{} uses {:?} signature hashes, `sigdump` only computes legacy message hashes.",
+                  coin_type.name, coin_type.sighash);
+        }
         self.start_height = block_height;
+        common::reset_writer(&mut self.sig_writer, 0)?;
+        self.written = 0;
+        self.undo.clear();
         info!(target: "callback", "Using `sigdump` with dump folder: {} ...", &self.dump_folder.display());
         Ok(())
     }
 
-    fn on_block(&mut self, block: &Block, _block_height: u64) -> OpResult<()> {
-        self.blocks_count += 1;
+    #[allow(clippy::explicit_counter_loop, clippy::single_match)]
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<SigDumpBatch> {
+        let mut batch = SigDumpBlock {
+            block_height,
+            ..Default::default()
+        };
         let block_time: u32 = block.header.value.timestamp;
 
         for tx in &block.txs {
@@ -148,6 +209,7 @@ impl Callback for SigDump {
 
                                 // make a copy of message to be signed
                                 let mut tbs_message = message_to_be_signed.clone();
+                                let mut spent_script = Vec::new();
 
                                 // build modified inputs and add them to message to be signed
                                 let mut raw_input_index = 0;
@@ -158,8 +220,9 @@ impl Callback for SigDump {
                                             index: raw_input.input.outpoint.index,
                                         },
                                         script_len: 0u8.into(),
-                                        script_sig: [].to_vec(),
+                                        script_sig: Bytes::default(),
                                         seq_no: raw_input.input.seq_no,
+                                        witness: Vec::new(),
                                     };
 
                                     if raw_input_index == input_index {
@@ -177,7 +240,8 @@ impl Callback for SigDump {
                                         };
 
                                         let script_len = VarUint::from(subscript.len() as u8);
-                                        r_input.script_sig = subscript.clone();
+                                        spent_script = subscript.clone();
+                                        r_input.script_sig = Bytes::from(subscript.clone());
                                         r_input.script_len = script_len;
                                     }
 
@@ -227,11 +291,24 @@ impl Callback for SigDump {
 
                                 let message_hash_str = utils::arr_to_hex(&message_hash);
 
-                                self.sig_writer
-                                    .write_all(
-                                        input.as_csv(
-                                            r, s, pubkey, &txid_str, message_hash_str, block_time,
-                                        ).as_bytes())?;
+                                let mut csv = input.as_csv(
+                                    r,
+                                    s,
+                                    pubkey,
+                                    &txid_str,
+                                    message_hash_str,
+                                    block_time,
+                                );
+                                if self.asm {
+                                    // Appended, so the other columns keep their position
+                                    let asm = format!(
+                                        ";{};{}",
+                                        script::to_asm(&input.input.script_sig, true),
+                                        script::to_asm(&spent_script, false)
+                                    );
+                                    csv.insert_str(csv.len() - 1, &asm);
+                                }
+                                batch.csv.write_all(csv.as_bytes())?;
                             }
                             Err(_e) => {}
                         }
@@ -240,10 +317,82 @@ impl Callback for SigDump {
                 }
                 input_index += 1;
             } // end for input
-            self.in_count += tx.value.in_count.value;
-            self.out_count += tx.value.out_count.value;
+            batch.in_count += tx.value.in_count.value;
+            batch.out_count += tx.value.out_count.value;
         } // end for tx
-        self.tx_count += block.tx_count.value;
+        batch.tx_count += block.tx_count.value;
+        Ok(SigDumpBatch(vec![batch]))
+    }
+
+    fn merge(&self, mut first: SigDumpBatch, second: SigDumpBatch) -> SigDumpBatch {
+        first.0.extend(second.0);
+        first
+    }
+
+    fn on_batch(&mut self, batch: SigDumpBatch) -> OpResult<()> {
+        for block in batch.0 {
+            self.undo.push(
+                block.block_height,
+                SigDumpUndo {
+                    written: self.written,
+                    tx_count: self.tx_count,
+                    in_count: self.in_count,
+                    out_count: self.out_count,
+                    blocks_count: self.blocks_count,
+                },
+            );
+            self.sig_writer.write_all(&block.csv)?;
+            self.written += block.csv.len() as u64;
+            self.tx_count += block.tx_count;
+            self.in_count += block.in_count;
+            self.out_count += block.out_count;
+            self.blocks_count += 1;
+        }
+        Ok(())
+    }
+
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::with_capacity(6 * 8);
+        for &value in &[
+            self.start_height,
+            self.tx_count,
+            self.in_count,
+            self.out_count,
+            self.blocks_count,
+        ] {
+            state.write_u64::<LittleEndian>(value)?;
+        }
+        state.write_u64::<LittleEndian>(common::flush_writer(&mut self.sig_writer)?)?;
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.tx_count = state.read_u64::<LittleEndian>()?;
+        self.in_count = state.read_u64::<LittleEndian>()?;
+        self.out_count = state.read_u64::<LittleEndian>()?;
+        self.blocks_count = state.read_u64::<LittleEndian>()?;
+        self.written = state.read_u64::<LittleEndian>()?;
+        common::reset_writer(&mut self.sig_writer, self.written)?;
+        self.undo.clear();
+        info!(target: "callback", "Resuming `sigdump` at block {} with dump folder: {} ...",
+              block_height, &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn supports_reorg(&self) -> bool {
+        true
+    }
+
+    fn on_reorg(&mut self, fork_height: u64) -> OpResult<()> {
+        let undo = self.undo.rollback(fork_height)?;
+        common::reset_writer(&mut self.sig_writer, undo.written)?;
+        self.written = undo.written;
+        self.tx_count = undo.tx_count;
+        self.in_count = undo.in_count;
+        self.out_count = undo.out_count;
+        self.blocks_count = undo.blocks_count;
+        info!(target: "callback", "Rolled back `sigdump` to block {}", fork_height);
         Ok(())
     }
 
@@ -251,16 +400,14 @@ impl Callback for SigDump {
         self.end_height = block_height;
 
         // Keep in sync with c'tor
-        for f in &["signatures"] {
-            // Rename temp files
-            fs::rename(
-                self.dump_folder.as_path().join(format!("{}.csv.tmp", f)),
-                self.dump_folder.as_path().join(format!(
-                    "{}-{}-{}.csv",
-                    f, self.start_height, self.end_height
-                )),
-            )?;
-        }
+        // Rename temp file
+        fs::rename(
+            self.dump_folder.as_path().join("signatures.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "signatures-{}-{}.csv",
+                self.start_height, self.end_height
+            )),
+        )?;
 
         info!(target: "callback", "Done.\nDumped all {} blocks:\n\
                                    \t-> transactions: {:9}\n\
@@ -306,38 +453,65 @@ impl Hashed<EvaluatedTx> {
 
 impl TxInput {
     #[inline]
-    pub fn as_csv(&self, txid: &str) -> String {
-        // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
+    pub fn as_csv(&self, txid: &str, asm: bool, descriptor: bool) -> String {
+        // (@txid, @hashPrevOut, indexPrevOut, scriptSig, [scriptSigAsm,] sequence, [descriptor, normalisedKeys])
+        let mut script_sig = utils::arr_to_hex(&self.script_sig);
+        if asm {
+            script_sig.push(';');
+            script_sig.push_str(&script::to_asm(&self.script_sig, true));
+        }
+        let mut seq_no = self.seq_no.to_string();
+        if descriptor {
+            seq_no.push_str(
+                &match descriptor::infer_spend(&self.script_sig, &self.witness) {
+                    Some(descriptor) => format!(
+                        ";{};{}",
+                        descriptor.desc,
+                        pubkeys::normalised_keys(&descriptor.keys)
+                    ),
+                    None => String::from(";;"),
+                },
+            );
+        }
         format!(
             "{};{};{};{};{}\n",
             &txid,
             &utils::arr_to_hex_swapped(&self.outpoint.txid),
             &self.outpoint.index,
-            &utils::arr_to_hex(&self.script_sig),
-            &self.seq_no
+            &script_sig,
+            &seq_no
         )
     }
 }
 
 impl EvaluatedTxOut {
     #[inline]
-    pub fn as_csv(&self, txid: &str, index: u32) -> String {
-        let address = match self.script.address.clone() {
+    pub fn as_csv(&self, txid: &str, index: u32, asm: bool, descriptor: bool) -> String {
+        let mut address = match self.script.address.clone() {
             Some(address) => address,
             None => {
                 debug!(target: "sigdump", "Unable to evaluate address for utxo in txid: {} ({})", txid, self.script.pattern);
                 String::new()
             }
         };
+        if descriptor {
+            let descriptor = self.descriptor();
+            address.push_str(&format!(
+                ";{};{}",
+                descriptor.desc,
+                pubkeys::normalised_keys(&descriptor.keys)
+            ));
+        }
 
-        // (@txid, indexOut, value, @scriptPubKey, address)
+        // (@txid, indexOut, value, @scriptPubKey, [scriptPubKeyAsm,] address, [descriptor, normalisedKeys])
+        let mut script_pubkey = utils::arr_to_hex(&self.out.script_pubkey);
+        if asm {
+            script_pubkey.push(';');
+            script_pubkey.push_str(&script::to_asm(&self.out.script_pubkey, false));
+        }
         format!(
             "{};{};{};{};{}\n",
-            &txid,
-            &index,
-            &self.out.value,
-            &utils::arr_to_hex(&self.out.script_pubkey),
-            &address
+            &txid, &index, &self.out.value, &script_pubkey, &address
         )
     }
 }
@@ -1,13 +1,14 @@
 use std::collections::HashMap;
 use std::io::{self, Write};
+use std::mem;
 
 use clap::{App, ArgMatches, SubCommand};
 
 use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::{self, Block};
-use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::script::{DataProtocol, ScriptPattern};
 use crate::blockchain::proto::ToRaw;
-use crate::callbacks::Callback;
+use crate::callbacks::ParallelCallback;
 use crate::common::utils;
 use crate::errors::OpResult;
 
@@ -32,9 +33,15 @@ pub struct SimpleStats {
     /// First occurence of transaction type
     /// (block_height, txid)
     tx_first_occs: HashMap<ScriptPattern, (u64, [u8; 32], u32)>,
+    /// Contains OP_RETURN protocol count
+    n_data_protocols: HashMap<DataProtocol, u64>,
+    /// First occurence of OP_RETURN protocol
+    /// (block_height, txid, index)
+    data_protocol_first_occs: HashMap<DataProtocol, (u64, [u8; 32], u32)>,
 
     /// Time stats
     t_between_blocks: Vec<u32>,
+    first_timestamp: u32,
     last_timestamp: u32,
 }
 
@@ -47,9 +54,14 @@ impl SimpleStats {
         txid: [u8; 32],
         index: u32,
     ) {
-        // Strip exact OP_RETURN bytes
+        // Strip exact OP_RETURN bytes and multisig keys
         let pattern = match script_pattern {
-            ScriptPattern::DataOutput(_) => ScriptPattern::DataOutput(String::new()),
+            ScriptPattern::DataOutput(_) => ScriptPattern::DataOutput(Vec::new()),
+            ScriptPattern::Pay2MultiSig { m, n, .. } => ScriptPattern::Pay2MultiSig {
+                m,
+                n,
+                keys: Vec::new(),
+            },
             p => p,
         };
         if !self.n_tx_types.contains_key(&pattern) {
@@ -62,6 +74,66 @@ impl SimpleStats {
         }
     }
 
+    /// Saves OP_RETURN protocol with txid of first occurence
+    fn process_data_protocol(
+        &mut self,
+        protocol: DataProtocol,
+        block_height: u64,
+        txid: [u8; 32],
+        index: u32,
+    ) {
+        *self.n_data_protocols.entry(protocol).or_insert(0) += 1;
+        self.data_protocol_first_occs
+            .entry(protocol)
+            .or_insert((block_height, txid, index));
+    }
+
+    /// Combines the stats of two consecutive ranges of blocks
+    fn merge_stats(mut self, other: SimpleStats) -> SimpleStats {
+        self.n_valid_blocks += other.n_valid_blocks;
+        self.block_sizes.extend(other.block_sizes);
+
+        self.n_tx += other.n_tx;
+        self.n_tx_inputs += other.n_tx_inputs;
+        self.n_tx_outputs += other.n_tx_outputs;
+        self.n_tx_total_fee += other.n_tx_total_fee;
+        self.n_tx_total_volume += other.n_tx_total_volume;
+
+        // On ties the earlier transaction wins, like in a sequential run
+        if other.tx_biggest_value.0 > self.tx_biggest_value.0 {
+            self.tx_biggest_value = other.tx_biggest_value;
+        }
+        if other.tx_biggest_size.0 > self.tx_biggest_size.0 {
+            self.tx_biggest_size = other.tx_biggest_size;
+        }
+        for (pattern, count) in other.n_tx_types {
+            *self.n_tx_types.entry(pattern).or_insert(0) += count;
+        }
+        for (pattern, occ) in other.tx_first_occs {
+            self.tx_first_occs.entry(pattern).or_insert(occ);
+        }
+        for (protocol, count) in other.n_data_protocols {
+            *self.n_data_protocols.entry(protocol).or_insert(0) += count;
+        }
+        for (protocol, occ) in other.data_protocol_first_occs {
+            self.data_protocol_first_occs.entry(protocol).or_insert(occ);
+        }
+
+        // Add the gap between both ranges
+        if self.last_timestamp > 0 && other.first_timestamp > 0 {
+            self.t_between_blocks
+                .push(other.first_timestamp.saturating_sub(self.last_timestamp));
+        }
+        self.t_between_blocks.extend(other.t_between_blocks);
+        if self.first_timestamp == 0 {
+            self.first_timestamp = other.first_timestamp;
+        }
+        if other.last_timestamp > 0 {
+            self.last_timestamp = other.last_timestamp;
+        }
+        self
+    }
+
     fn print_simple_stats(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         writeln!(buffer, "SimpleStats:")?;
         writeln!(buffer, "   -> valid blocks:\t\t{}", self.n_valid_blocks)?;
@@ -164,9 +236,36 @@ impl SimpleStats {
         }
         Ok(())
     }
+
+    fn print_data_protocols(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "OP_RETURN Protocols:")?;
+        let n_data_outputs: u64 = self.n_data_protocols.values().sum();
+        let mut protocols: Vec<_> = self.n_data_protocols.iter().collect();
+        protocols.sort();
+        for (protocol, count) in protocols {
+            writeln!(
+                buffer,
+                "   -> {}: {} ({:.2}%)",
+                protocol,
+                count,
+                (*count as f64 / n_data_outputs as f64) * 100.00
+            )?;
+
+            let pos = self.data_protocol_first_occs.get(protocol).unwrap();
+            writeln!(
+                buffer,
+                "        first seen in block #{}, txid: {}\n",
+                pos.0,
+                utils::arr_to_hex_swapped(&pos.1)
+            )?;
+        }
+        Ok(())
+    }
 }
 
-impl Callback for SimpleStats {
+impl ParallelCallback for SimpleStats {
+    type Output = SimpleStats;
+
     fn build_subcommand<'a, 'b>() -> App<'a, 'b>
     where
         Self: Sized,
@@ -189,54 +288,58 @@ impl Callback for SimpleStats {
         Ok(())
     }
 
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        self.n_valid_blocks += 1;
-        self.n_tx += block.tx_count.value;
-        self.block_sizes.push(block.size);
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<SimpleStats> {
+        let mut stats = SimpleStats {
+            n_valid_blocks: 1,
+            n_tx: block.tx_count.value,
+            block_sizes: vec![block.size],
+            first_timestamp: block.header.value.timestamp,
+            last_timestamp: block.header.value.timestamp,
+            ..Default::default()
+        };
 
         for tx in &block.txs {
             // Collect fee rewards
             if tx.value.is_coinbase() {
-                self.n_tx_total_fee += tx.value.outputs[0]
+                stats.n_tx_total_fee += tx.value.outputs[0]
                     .out
                     .value
-                    .checked_sub(block::get_base_reward(block_height as u64))
-                    .unwrap_or_default();
+                    .saturating_sub(block::get_base_reward(block_height));
             }
 
-            self.n_tx_inputs += tx.value.in_count.value;
-            self.n_tx_outputs += tx.value.out_count.value;
+            stats.n_tx_inputs += tx.value.in_count.value;
+            stats.n_tx_outputs += tx.value.out_count.value;
 
             let mut tx_value = 0;
             for (i, o) in tx.value.outputs.iter().enumerate() {
-                self.process_tx_pattern(o.script.pattern.clone(), block_height, tx.hash, i as u32);
+                stats.process_tx_pattern(o.script.pattern.clone(), block_height, tx.hash, i as u32);
+                if let Some(protocol) = o.script.data_protocol {
+                    stats.process_data_protocol(protocol, block_height, tx.hash, i as u32);
+                }
                 tx_value += o.out.value;
             }
             // Calculate and save biggest value transaction
-            if tx_value > self.tx_biggest_value.0 {
-                self.tx_biggest_value = (tx_value, block_height, tx.hash);
+            if tx_value > stats.tx_biggest_value.0 {
+                stats.tx_biggest_value = (tx_value, block_height, tx.hash);
             }
 
-            self.n_tx_total_volume += tx_value;
+            stats.n_tx_total_volume += tx_value;
 
             // Calculate and save biggest size transaction
             let tx_size = tx.value.to_bytes().len();
-            if tx_size > self.tx_biggest_size.0 {
-                self.tx_biggest_size = (tx_size, block_height, tx.hash);
+            if tx_size > stats.tx_biggest_size.0 {
+                stats.tx_biggest_size = (tx_size, block_height, tx.hash);
             }
         }
+        Ok(stats)
+    }
 
-        // Save time between blocks
-        if self.last_timestamp > 0 {
-            let diff = block
-                .header
-                .value
-                .timestamp
-                .checked_sub(self.last_timestamp)
-                .unwrap_or_default();
-            self.t_between_blocks.push(diff);
-        }
-        self.last_timestamp = block.header.value.timestamp;
+    fn merge(&self, first: SimpleStats, second: SimpleStats) -> SimpleStats {
+        first.merge_stats(second)
+    }
+
+    fn on_batch(&mut self, output: SimpleStats) -> OpResult<()> {
+        *self = mem::take(self).merge_stats(output);
         Ok(())
     }
 
@@ -248,7 +351,63 @@ impl Callback for SimpleStats {
         self.print_averages(&mut buffer)?;
         writeln!(&mut buffer)?;
         self.print_transaction_types(&mut buffer)?;
+        self.print_data_protocols(&mut buffer)?;
         info!(target: "simplestats", "\n\n{}", String::from_utf8_lossy(&buffer));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::callbacks::{Callback, Parallel};
+    use crate::common::fixtures::GENESIS;
+    use crate::common::utils;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    /// Returns copies of the genesis block with increasing timestamps
+    fn blocks(n: u32) -> Vec<Block> {
+        (0..n)
+            .map(|i| {
+                let mut raw = utils::hex_to_vec(GENESIS);
+                let timestamp = 1231006505 + i * i * 60;
+                raw[68..72].copy_from_slice(&timestamp.to_le_bytes());
+                let size = raw.len() as u32;
+                Cursor::new(raw)
+                    .read_block(size, &Arc::default(), false)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let blocks = blocks(37);
+
+        let mut sequential = Parallel(SimpleStats::default());
+        for (height, block) in blocks.iter().enumerate() {
+            sequential.on_block(block, height as u64).unwrap();
+        }
+        let mut parallel = Parallel(SimpleStats::default());
+        for (i, chunk) in blocks.chunks(8).enumerate() {
+            parallel.on_blocks(chunk, i as u64 * 8).unwrap();
+        }
+
+        let (a, b) = (sequential.0, parallel.0);
+        assert_eq!(a.n_valid_blocks, 37);
+        assert_eq!(a.n_valid_blocks, b.n_valid_blocks);
+        assert_eq!(a.n_tx, b.n_tx);
+        assert_eq!(a.n_tx_total_volume, b.n_tx_total_volume);
+        assert_eq!(a.block_sizes, b.block_sizes);
+        assert_eq!(a.t_between_blocks, b.t_between_blocks);
+        assert_eq!(a.t_between_blocks.len(), 36);
+        assert_eq!(a.t_between_blocks[35], (36 * 36 - 35 * 35) * 60);
+        assert_eq!(a.tx_biggest_value.1, 0);
+        assert_eq!(a.n_tx_types, b.n_tx_types);
+        assert_eq!(a.tx_first_occs, b.tx_first_occs);
+        assert_eq!(a.n_data_protocols, b.n_data_protocols);
+        assert_eq!(a.data_protocol_first_occs, b.data_protocol_first_occs);
+    }
+}
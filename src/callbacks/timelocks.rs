@@ -0,0 +1,428 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::timelock::{self, LockTime, RelativeLock};
+use crate::callbacks::{common, ParallelCallback};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Wallets using anti fee sniping set the locktime to the tip, sometimes up to 100 blocks earlier
+const ANTI_FEE_SNIPING_DEPTH: u64 = 101;
+
+/// Decodes absolute and relative locktimes, RBF signalling and timelock opcodes of every transaction.
+/// Writes one line per transaction and aggregated counters per block.
+pub struct Timelocks {
+    dump_folder: PathBuf,
+    tx_writer: BufWriter<File>,
+    block_writer: BufWriter<File>,
+
+    start_height: u64,
+    end_height: u64,
+    totals: TimelockStats,
+
+    // Bytes written to tx_writer and block_writer
+    written: (u64, u64),
+    // Output positions and totals before each of the last blocks, oldest first
+    undo: common::UndoRing<TimelocksUndo>,
+}
+
+/// State before a block was dumped
+struct TimelocksUndo {
+    written: (u64, u64),
+    totals: TimelockStats,
+}
+
+/// Counters of a block or the whole range. Coinbase transactions are skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimelockStats {
+    pub txs: u64,
+    /// Transactions with an enforced height or time locktime
+    pub locktime_height: u64,
+    pub locktime_time: u64,
+    /// Enforced height locktimes at most ANTI_FEE_SNIPING_DEPTH blocks below the block
+    pub anti_fee_sniping: u64,
+    /// Transactions signalling replaceability [BIP125]
+    pub rbf: u64,
+    /// Inputs with a relative locktime in blocks or time [BIP68]
+    pub relative_blocks: u64,
+    pub relative_time: u64,
+    /// OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY in revealed scripts
+    pub cltv: u64,
+    pub csv: u64,
+}
+
+impl TimelockStats {
+    const LEN: usize = 9;
+
+    fn values(&self) -> [u64; TimelockStats::LEN] {
+        [
+            self.txs,
+            self.locktime_height,
+            self.locktime_time,
+            self.anti_fee_sniping,
+            self.rbf,
+            self.relative_blocks,
+            self.relative_time,
+            self.cltv,
+            self.csv,
+        ]
+    }
+
+    fn from_values(v: [u64; TimelockStats::LEN]) -> Self {
+        TimelockStats {
+            txs: v[0],
+            locktime_height: v[1],
+            locktime_time: v[2],
+            anti_fee_sniping: v[3],
+            rbf: v[4],
+            relative_blocks: v[5],
+            relative_time: v[6],
+            cltv: v[7],
+            csv: v[8],
+        }
+    }
+
+    fn add(&mut self, other: &TimelockStats) {
+        let mut values = self.values();
+        for (value, other) in values.iter_mut().zip(other.values().iter()) {
+            *value += other;
+        }
+        *self = TimelockStats::from_values(values);
+    }
+}
+
+impl Timelocks {
+    /// Creates the callback, writing both csv files to `dump_folder`
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let cb = Timelocks {
+            dump_folder: PathBuf::from(dump_folder),
+            tx_writer: common::open_writer(4000000, &dump_folder.join("timelocks.csv.tmp"))?,
+            block_writer: common::open_writer(
+                400000,
+                &dump_folder.join("timelocks-blocks.csv.tmp"),
+            )?,
+            start_height: 0,
+            end_height: 0,
+            totals: TimelockStats::default(),
+            written: (0, 0),
+            undo: common::UndoRing::new(),
+        };
+        Ok(cb)
+    }
+}
+
+/// Timelocks of a single block
+#[derive(Default)]
+pub struct TimelocksBlock {
+    block_height: u64,
+    csv: Vec<u8>,
+    stats: TimelockStats,
+}
+
+/// Dumped consecutive blocks in height order
+pub struct TimelocksBatch(Vec<TimelocksBlock>);
+
+impl ParallelCallback for Timelocks {
+    type Output = TimelocksBatch;
+
+    fn build_subcommand<'a, 'b>() -> App<'a, 'b>
+    where
+        Self: Sized,
+    {
+        SubCommand::with_name("timelocks")
+            .about(
+                "Dumps locktimes, relative locks, RBF signalling and timelock opcodes to CSV files",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::with_name("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Timelocks::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))
+    }
+
+    fn on_start(&mut self, _: &CoinType, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        common::reset_writer(&mut self.tx_writer, 0)?;
+        common::reset_writer(&mut self.block_writer, 0)?;
+        self.written = (0, 0);
+        self.undo.clear();
+        info!(target: "callback", "Using `timelocks` with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn map_block(&self, block: &Block, block_height: u64) -> OpResult<TimelocksBatch> {
+        let mut batch = TimelocksBlock {
+            block_height,
+            ..Default::default()
+        };
+        let stats = &mut batch.stats;
+        for tx in block.txs.iter().filter(|tx| !tx.value.is_coinbase()) {
+            let tx_value = &tx.value;
+            let enforced =
+                timelock::is_locktime_enforced(tx_value.inputs.iter().map(|i| i.input.seq_no));
+            let locktime = LockTime::new(tx_value.locktime);
+            // Blocks between the locktime and the block, the tip height at signing for anti fee sniping
+            let height_delta = match locktime {
+                Some(LockTime::Height(height)) => Some(block_height as i64 - height as i64),
+                _ => None,
+            };
+
+            let mut tx_stats = TimelockStats {
+                txs: 1,
+                ..Default::default()
+            };
+            match locktime {
+                Some(LockTime::Height(_)) if enforced => tx_stats.locktime_height = 1,
+                Some(LockTime::Time(_)) if enforced => tx_stats.locktime_time = 1,
+                _ => {}
+            }
+            if let Some(delta) = height_delta {
+                if enforced && delta > 0 && delta as u64 <= ANTI_FEE_SNIPING_DEPTH {
+                    tx_stats.anti_fee_sniping = 1;
+                }
+            }
+            for input in &tx_value.inputs {
+                if timelock::signals_rbf(input.input.seq_no) {
+                    tx_stats.rbf = 1;
+                }
+                match RelativeLock::from_sequence(tx_value.version, input.input.seq_no) {
+                    Some(RelativeLock::Blocks(_)) => tx_stats.relative_blocks += 1,
+                    Some(RelativeLock::Time(_)) => tx_stats.relative_time += 1,
+                    None => {}
+                }
+                if let Some(script) = timelock::revealed_script(&input.input) {
+                    let (cltv, csv) = timelock::count_timelock_ops(script);
+                    tx_stats.cltv += cltv;
+                    tx_stats.csv += csv;
+                }
+            }
+
+            // (txid; height; version; locktime; locktime_type; enforced; height_delta; rbf;
+            //  relative_blocks; relative_time; cltv; csv)
+            writeln!(
+                batch.csv,
+                "{};{};{};{};{};{};{};{};{};{};{};{}",
+                utils::arr_to_hex_swapped(&tx.hash),
+                block_height,
+                tx_value.version,
+                tx_value.locktime,
+                locktime.map(|l| l.to_string()).unwrap_or_default(),
+                enforced as u8,
+                height_delta.map(|d| d.to_string()).unwrap_or_default(),
+                tx_stats.rbf,
+                tx_stats.relative_blocks,
+                tx_stats.relative_time,
+                tx_stats.cltv,
+                tx_stats.csv
+            )?;
+            stats.add(&tx_stats);
+        }
+        Ok(TimelocksBatch(vec![batch]))
+    }
+
+    fn merge(&self, mut first: TimelocksBatch, second: TimelocksBatch) -> TimelocksBatch {
+        first.0.extend(second.0);
+        first
+    }
+
+    fn on_batch(&mut self, batch: TimelocksBatch) -> OpResult<()> {
+        for block in batch.0 {
+            self.undo.push(
+                block.block_height,
+                TimelocksUndo {
+                    written: self.written,
+                    totals: self.totals,
+                },
+            );
+            let values = block.stats.values();
+            let line = format!(
+                "{};{}\n",
+                block.block_height,
+                values
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(";")
+            );
+            self.tx_writer.write_all(&block.csv)?;
+            self.block_writer.write_all(line.as_bytes())?;
+            self.written.0 += block.csv.len() as u64;
+            self.written.1 += line.len() as u64;
+            self.totals.add(&block.stats);
+        }
+        Ok(())
+    }
+
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::with_capacity((TimelockStats::LEN + 3) * 8);
+        state.write_u64::<LittleEndian>(self.start_height)?;
+        for &value in self.totals.values().iter() {
+            state.write_u64::<LittleEndian>(value)?;
+        }
+        state.write_u64::<LittleEndian>(common::flush_writer(&mut self.tx_writer)?)?;
+        state.write_u64::<LittleEndian>(common::flush_writer(&mut self.block_writer)?)?;
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        let mut values = [0u64; TimelockStats::LEN];
+        for value in values.iter_mut() {
+            *value = state.read_u64::<LittleEndian>()?;
+        }
+        self.totals = TimelockStats::from_values(values);
+        self.written = (
+            state.read_u64::<LittleEndian>()?,
+            state.read_u64::<LittleEndian>()?,
+        );
+        common::reset_writer(&mut self.tx_writer, self.written.0)?;
+        common::reset_writer(&mut self.block_writer, self.written.1)?;
+        self.undo.clear();
+        info!(target: "callback", "Resuming `timelocks` at block {} with dump folder: {} ...",
+              block_height, &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn supports_reorg(&self) -> bool {
+        true
+    }
+
+    fn on_reorg(&mut self, fork_height: u64) -> OpResult<()> {
+        let undo = self.undo.rollback(fork_height)?;
+        common::reset_writer(&mut self.tx_writer, undo.written.0)?;
+        common::reset_writer(&mut self.block_writer, undo.written.1)?;
+        self.written = undo.written;
+        self.totals = undo.totals;
+        info!(target: "callback", "Rolled back `timelocks` to block {}", fork_height);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.end_height = block_height;
+        self.tx_writer.flush()?;
+        self.block_writer.flush()?;
+        for name in &["timelocks", "timelocks-blocks"] {
+            fs::rename(
+                self.dump_folder.join(format!("{}.csv.tmp", name)),
+                self.dump_folder.join(format!(
+                    "{}-{}-{}.csv",
+                    name, self.start_height, self.end_height
+                )),
+            )?;
+        }
+
+        let totals = &self.totals;
+        info!(target: "callback", "Done.\nDumped all {} blocks:\n\
+                                   \t-> transactions:      {:9}\n\
+                                   \t-> height locktimes:  {:9} (anti fee sniping: {})\n\
+                                   \t-> time locktimes:    {:9}\n\
+                                   \t-> RBF signalling:    {:9}\n\
+                                   \t-> relative locks:    {:9} blocks, {} time\n\
+                                   \t-> CLTV / CSV ops:    {:9} / {}",
+             self.end_height, totals.txs, totals.locktime_height, totals.anti_fee_sniping,
+             totals.locktime_time, totals.rbf, totals.relative_blocks, totals.relative_time,
+             totals.cltv, totals.csv);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{TxInput, TxOutpoint};
+    use crate::callbacks::{Callback, Parallel};
+    use crate::common::fixtures;
+
+    fn input(seq_no: u32, witness: Vec<Vec<u8>>) -> TxInput {
+        fixtures::input(TxOutpoint::new([0x22; 32], 0), &[], seq_no, witness)
+    }
+
+    #[test]
+    fn test_timelocks() {
+        let dump_folder = std::env::temp_dir().join(format!("timelocks-{}", std::process::id()));
+        fs::create_dir_all(&dump_folder).unwrap();
+        let mut cb = Parallel(Timelocks::with_dump_folder(&dump_folder).unwrap());
+        cb.on_start(&CoinType::default(), 0).unwrap();
+
+        // <144> OP_CHECKSEQUENCEVERIFY in a P2WSH witness
+        let witness_script = utils::hex_to_vec("029000b2");
+        let coinbase_input = fixtures::input(
+            TxOutpoint::new([0u8; 32], 0xffffffff),
+            &[],
+            timelock::SEQUENCE_FINAL,
+            Vec::new(),
+        );
+        let coinbase = fixtures::tx(1, vec![coinbase_input], Vec::new(), 0);
+        let txs = vec![
+            coinbase,
+            // Anti fee sniping and RBF
+            fixtures::tx(2, vec![input(0xfffffffd, Vec::new())], Vec::new(), 839999),
+            // Relative lock of 144 blocks
+            fixtures::tx(
+                2,
+                vec![input(144, vec![vec![0x01], witness_script])],
+                Vec::new(),
+                0,
+            ),
+            // Time locktime disabled by final inputs
+            fixtures::tx(
+                1,
+                vec![input(timelock::SEQUENCE_FINAL, Vec::new())],
+                Vec::new(),
+                1700000000,
+            ),
+        ];
+        let block = fixtures::block(txs);
+        cb.on_block(&block, 840000).unwrap();
+        cb.on_complete(840000).unwrap();
+
+        let expected = TimelockStats {
+            txs: 3,
+            locktime_height: 1,
+            anti_fee_sniping: 1,
+            rbf: 2,
+            relative_blocks: 1,
+            csv: 1,
+            ..Default::default()
+        };
+        assert_eq!(cb.0.totals, expected);
+
+        let csv = fs::read_to_string(dump_folder.join("timelocks-0-840000.csv")).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let txid = utils::arr_to_hex_swapped(&block.txs[1].hash);
+        assert_eq!(
+            lines[0],
+            format!("{};840000;2;839999;height;1;1;1;0;0;0;0", txid)
+        );
+        assert!(
+            lines[1].ends_with(";840000;2;0;;1;;1;1;0;0;1"),
+            "{}",
+            lines[1]
+        );
+        assert!(
+            lines[2].ends_with(";840000;1;1700000000;time;0;;0;0;0;0;0"),
+            "{}",
+            lines[2]
+        );
+        let blocks = fs::read_to_string(dump_folder.join("timelocks-blocks-0-840000.csv")).unwrap();
+        assert_eq!(blocks, "840000;3;1;0;1;2;1;0;0;1\n");
+        fs::remove_dir_all(dump_folder).unwrap();
+    }
+}
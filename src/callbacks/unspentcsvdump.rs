@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use clap::{App, Arg, ArgMatches, SubCommand};
 
 use crate::blockchain::parser::types::CoinType;
@@ -19,6 +19,7 @@ pub struct UnspentCsvDump {
 
     // key: txid + index
     unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    descriptors: bool, // Adds the output descriptor and its keys, keeps outputs without address
 
     start_height: u64,
     tx_count: u64,
@@ -27,6 +28,21 @@ pub struct UnspentCsvDump {
 }
 
 impl UnspentCsvDump {
+    /// Creates the callback, writing its csv files to `dump_folder`
+    pub fn with_dump_folder(dump_folder: &Path) -> OpResult<Self> {
+        let cb = UnspentCsvDump {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: UnspentCsvDump::create_writer(4000000, dump_folder.join("unspent.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            descriptors: false,
+            start_height: 0,
+            tx_count: 0,
+            in_count: 0,
+            out_count: 0,
+        };
+        Ok(cb)
+    }
+
     fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
         Ok(BufWriter::with_capacity(cap, File::create(&path)?))
     }
@@ -47,22 +63,20 @@ impl Callback for UnspentCsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::with_name("descriptors")
+                    .long("descriptors")
+                    .help("Adds output descriptor and normalised key columns and keeps outputs without address"),
+            )
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.value_of("dump-folder").unwrap());
-        let cb = UnspentCsvDump {
-            dump_folder: PathBuf::from(dump_folder),
-            writer: UnspentCsvDump::create_writer(4000000, dump_folder.join("unspent.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
-            start_height: 0,
-            tx_count: 0,
-            in_count: 0,
-            out_count: 0,
-        };
+        let mut cb =
+            UnspentCsvDump::with_dump_folder(Path::new(matches.value_of("dump-folder").unwrap()))?;
+        cb.descriptors = matches.is_present("descriptors");
         Ok(cb)
     }
 
@@ -75,38 +89,77 @@ impl Callback for UnspentCsvDump {
     /// For each transaction in the block
     ///   1. apply input transactions (remove (TxID == prevTxIDOut and prevOutID == spentOutID))
     ///   2. apply output transactions (add (TxID + curOutID -> HashMapVal))
+    ///
     /// For each address, retain:
     ///   * block height as "last modified"
     ///   * output_val
     ///   * address
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
         for tx in &block.txs {
-            self.in_count += common::remove_unspents(&tx, &mut self.unspents);
-            self.out_count += common::insert_unspents(&tx, block_height, &mut self.unspents);
+            self.in_count += common::remove_unspents(tx, &mut self.unspents);
+            self.out_count +=
+                common::insert_unspents(tx, block_height, &mut self.unspents, self.descriptors);
         }
         self.tx_count += block.tx_count.value;
         Ok(())
     }
 
+    fn on_checkpoint(&mut self, _: u64) -> OpResult<Option<Vec<u8>>> {
+        let mut state = Vec::new();
+        for &value in &[
+            self.start_height,
+            self.tx_count,
+            self.in_count,
+            self.out_count,
+        ] {
+            state.write_u64::<LittleEndian>(value)?;
+        }
+        common::write_flag(&mut state, self.descriptors)?;
+        common::write_unspents(&mut state, &self.unspents)?;
+        Ok(Some(state))
+    }
+
+    fn on_resume(&mut self, _: &CoinType, block_height: u64, mut state: &[u8]) -> OpResult<()> {
+        self.start_height = state.read_u64::<LittleEndian>()?;
+        self.tx_count = state.read_u64::<LittleEndian>()?;
+        self.in_count = state.read_u64::<LittleEndian>()?;
+        self.out_count = state.read_u64::<LittleEndian>()?;
+        common::check_flag(&mut state, "descriptors", self.descriptors)?;
+        self.unspents = common::read_unspents(&mut state)?;
+        info!(target: "callback", "Resuming `unspentcsvdump` at block {} with {} unspents and dump folder: {} ...",
+              block_height, self.unspents.len(), &self.dump_folder.display());
+        Ok(())
+    }
+
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let descriptor_header = if self.descriptors {
+            ";descriptor;normalisedKeys"
+        } else {
+            ""
+        };
         self.writer.write_all(
             format!(
-                "{};{};{};{};{}\n",
-                "txid", "indexOut", "height", "value", "address"
+                "{};{};{};{};{}{}\n",
+                "txid", "indexOut", "height", "value", "address", descriptor_header
             )
             .as_bytes(),
         )?;
         for (key, value) in self.unspents.iter() {
             let txid = &key[0..32];
             let mut index = &key[32..];
+            let descriptor = match value.descriptor {
+                Some((ref descriptor, ref keys)) => format!(";{};{}", descriptor, keys),
+                None => String::new(),
+            };
             self.writer.write_all(
                 format!(
-                    "{};{};{};{};{}\n",
+                    "{};{};{};{};{}{}\n",
                     utils::arr_to_hex_swapped(txid),
                     index.read_u32::<LittleEndian>()?,
                     value.block_height,
                     value.value,
-                    value.address
+                    value.address,
+                    descriptor
                 )
                 .as_bytes(),
             )?;
@@ -0,0 +1,351 @@
+use std::error;
+use std::fmt;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+const MAX_LENGTH: usize = 90;
+
+/// Checksum variant, BIP350 replaced bech32 with bech32m for witness versions 1 and above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// BIP173
+    Bech32,
+    /// BIP350
+    Bech32m,
+}
+
+impl Variant {
+    #[inline]
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => 1,
+            Variant::Bech32m => 0x2bc8_30a3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bech32Error {
+    InvalidLength,
+    MixedCase,
+    InvalidChar(char),
+    MissingSeparator,
+    InvalidChecksum,
+    /// Human readable part differs from the expected one
+    InvalidHrp,
+    /// Witness version, program length or padding violate BIP173/BIP350
+    InvalidWitnessProgram,
+}
+
+impl fmt::Display for Bech32Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bech32Error::InvalidLength => write!(f, "Invalid length"),
+            Bech32Error::MixedCase => write!(f, "Mixed case"),
+            Bech32Error::InvalidChar(c) => write!(f, "Invalid character {:?}", c),
+            Bech32Error::MissingSeparator => write!(f, "Missing separator"),
+            Bech32Error::InvalidChecksum => write!(f, "Invalid checksum"),
+            Bech32Error::InvalidHrp => write!(f, "Invalid human readable part"),
+            Bech32Error::InvalidWitnessProgram => write!(f, "Invalid witness program"),
+        }
+    }
+}
+
+impl error::Error for Bech32Error {}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk = 1u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x01ff_ffff) << 5 ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 0x1f));
+    expanded
+}
+
+/// Encodes 5 bit values with the given human readable part
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; 6]);
+    let checksum = polymod(&values) ^ variant.constant();
+
+    let mut encoded = String::with_capacity(hrp.len() + 1 + data.len() + 6);
+    encoded.push_str(hrp);
+    encoded.push('1');
+    for value in data {
+        encoded.push(CHARSET[*value as usize] as char);
+    }
+    for i in 0..6 {
+        encoded.push(CHARSET[((checksum >> (5 * (5 - i))) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes a bech32 or bech32m string into its lowercase human readable part and 5 bit values
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant), Bech32Error> {
+    if s.len() > MAX_LENGTH {
+        return Err(Bech32Error::InvalidLength);
+    }
+    if let Some(c) = s.chars().find(|c| !('!'..='~').contains(c)) {
+        return Err(Bech32Error::InvalidChar(c));
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(Bech32Error::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let pos = s.rfind('1').ok_or(Bech32Error::MissingSeparator)?;
+    if pos == 0 || pos + 7 > s.len() {
+        return Err(Bech32Error::InvalidLength);
+    }
+    let (hrp, rest) = s.split_at(pos);
+    let mut data = Vec::with_capacity(rest.len() - 1);
+    for c in rest[1..].chars() {
+        match CHARSET.iter().position(|x| *x as char == c) {
+            Some(value) => data.push(value as u8),
+            None => return Err(Bech32Error::InvalidChar(c)),
+        }
+    }
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    let variant = match polymod(&values) {
+        c if c == Variant::Bech32.constant() => Variant::Bech32,
+        c if c == Variant::Bech32m.constant() => Variant::Bech32m,
+        _ => return Err(Bech32Error::InvalidChecksum),
+    };
+    data.truncate(data.len() - 6);
+    Ok((hrp.to_string(), data, variant))
+}
+
+/// Regroups bits, e.g. bytes into 5 bit values. Returns None if a value
+/// is out of range or, without `pad`, the leftover bits are invalid padding.
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let max_value = (1u32 << to) - 1;
+    let max_acc = (1u32 << (from + to - 1)) - 1;
+    let mut converted = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        let value = u32::from(*value);
+        if value >> from != 0 {
+            return None;
+        }
+        acc = ((acc << from) | value) & max_acc;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max_value != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+/// Encodes a segwit address, bech32 for version 0 and bech32m for later versions
+pub fn encode_witness_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    let mut data = vec![version];
+    // Padding 8 bit values can't fail
+    data.extend(convert_bits(program, 8, 5, true).unwrap_or_default());
+    encode(hrp, &data, variant)
+}
+
+/// Decodes a segwit address into witness version and program
+pub fn decode_witness_address(hrp: &str, address: &str) -> Result<(u8, Vec<u8>), Bech32Error> {
+    let (decoded_hrp, data, variant) = decode(address)?;
+    if decoded_hrp != hrp {
+        return Err(Bech32Error::InvalidHrp);
+    }
+    let (version, data) = match data.split_first() {
+        Some((version, data)) if *version <= 16 => (*version, data),
+        _ => return Err(Bech32Error::InvalidWitnessProgram),
+    };
+    let program = convert_bits(data, 5, 8, false).ok_or(Bech32Error::InvalidWitnessProgram)?;
+    let expected_variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    if variant != expected_variant {
+        return Err(Bech32Error::InvalidChecksum);
+    }
+    if !is_valid_witness_program(version, program.len()) {
+        return Err(Bech32Error::InvalidWitnessProgram);
+    }
+    Ok((version, program))
+}
+
+/// Version 0 programs are 20 byte key hashes or 32 byte script hashes, see BIP141
+#[inline]
+pub fn is_valid_witness_program(version: u8, len: usize) -> bool {
+    match version {
+        0 => len == 20 || len == 32,
+        1..=16 => (2..=40).contains(&len),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    #[test]
+    fn test_valid_checksums() {
+        // Test vectors from BIP173 and BIP350
+        let bech32 = [
+            "A12UEL5L",
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ];
+        let bech32m = [
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ];
+        for (strings, variant) in [(&bech32, Variant::Bech32), (&bech32m, Variant::Bech32m)].iter()
+        {
+            for s in strings.iter() {
+                let (hrp, data, decoded_variant) = decode(s).unwrap();
+                assert_eq!(decoded_variant, *variant, "{}", s);
+                assert_eq!(encode(&hrp, &data, *variant), s.to_ascii_lowercase());
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_checksums() {
+        assert_eq!(decode(" 1nwldj5"), Err(Bech32Error::InvalidChar(' ')));
+        assert_eq!(
+            decode("an84characterslonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1569pvx"),
+            Err(Bech32Error::InvalidLength)
+        );
+        assert_eq!(decode("pzry9x0s0muk"), Err(Bech32Error::MissingSeparator));
+        assert_eq!(decode("1pzry9x0s0muk"), Err(Bech32Error::InvalidLength));
+        assert_eq!(decode("x1b4n0q5v"), Err(Bech32Error::InvalidChar('b')));
+        assert_eq!(decode("li1dgmt3"), Err(Bech32Error::InvalidLength));
+        assert_eq!(decode("A1G7SGD8"), Err(Bech32Error::InvalidChecksum));
+        assert_eq!(decode("a12UEL5L"), Err(Bech32Error::MixedCase));
+    }
+
+    #[test]
+    fn test_witness_addresses() {
+        // Test vectors from BIP350
+        let valid = [
+            ("bc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", "0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+            (
+                "tb",
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc",
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            ("bc", "BC1SW50QGDZ25J", "6002751e"),
+            ("bc", "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", "5210751e76e8199196d454941c45d1b3a323"),
+            (
+                "tb",
+                "tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy",
+                "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "tb",
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "bc",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ];
+        for (hrp, address, script_pubkey) in valid.iter() {
+            let script_pubkey = utils::hex_to_vec(script_pubkey);
+            let version = match script_pubkey[0] {
+                0 => 0,
+                op => op - 0x50,
+            };
+            let program = &script_pubkey[2..];
+            assert_eq!(
+                decode_witness_address(hrp, address),
+                Ok((version, program.to_vec()))
+            );
+            assert_eq!(
+                encode_witness_address(hrp, version, program),
+                address.to_ascii_lowercase()
+            );
+        }
+
+        let invalid = [
+            // Invalid human-readable part
+            "tc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq5zuyut",
+            // Invalid checksums (bech32 instead of bech32m and vice versa)
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            "tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf",
+            "BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+            "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47",
+            // Invalid character in checksum
+            "bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4",
+            // Invalid witness version
+            "BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R",
+            // Invalid program lengths
+            "bc1pw5dgrnzv",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav",
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+            // Mixed case
+            "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47Zagq",
+            // Zero padding of more than 4 bits, non-zero padding
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf",
+            "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j",
+            // Empty data section
+            "bc1gmk9yu",
+        ];
+        for address in invalid.iter() {
+            let hrp = if address.to_ascii_lowercase().starts_with("tb") {
+                "tb"
+            } else {
+                "bc"
+            };
+            assert!(decode_witness_address(hrp, address).is_err(), "{}", address);
+        }
+    }
+}
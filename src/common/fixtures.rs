@@ -0,0 +1,87 @@
+//! Shared test data
+
+use std::sync::Arc;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::bytes::Bytes;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::script::AddressParams;
+use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, TxInput, TxOutpoint, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+
+/// Raw bitcoin genesis block
+pub const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+/// Header with all fields zeroed
+pub fn header() -> BlockHeader {
+    BlockHeader {
+        version: 0,
+        prev_hash: [0u8; 32],
+        merkle_root: [0u8; 32],
+        timestamp: 0,
+        bits: 0,
+        nonce: 0,
+        aux_pow: None,
+    }
+}
+
+/// Block of `txs` with a zeroed header
+pub fn block(txs: Vec<RawTx>) -> Block {
+    Block::new(0, header(), VarUint::compact(txs.len() as u64), txs)
+}
+
+pub fn input(
+    outpoint: TxOutpoint,
+    script_sig: &[u8],
+    seq_no: u32,
+    witness: Vec<Vec<u8>>,
+) -> TxInput {
+    TxInput {
+        outpoint,
+        script_len: VarUint::compact(script_sig.len() as u64),
+        script_sig: Bytes::from(script_sig),
+        seq_no,
+        witness: witness.into_iter().map(Bytes::from).collect(),
+    }
+}
+
+pub fn output(value: u64, script_pubkey: &[u8]) -> TxOutput {
+    TxOutput {
+        value,
+        script_len: VarUint::compact(script_pubkey.len() as u64),
+        script_pubkey: Bytes::from(script_pubkey),
+    }
+}
+
+pub fn tx(version: u32, inputs: Vec<TxInput>, outputs: Vec<TxOutput>, locktime: u32) -> RawTx {
+    RawTx {
+        version,
+        in_count: VarUint::compact(inputs.len() as u64),
+        inputs,
+        out_count: VarUint::compact(outputs.len() as u64),
+        outputs,
+        locktime,
+        address_params: Arc::default(),
+        hog_ex: false,
+    }
+}
+
+/// Version 2 transaction spending a single output with `script_sig` and `witness`, paying to OP_TRUE
+pub fn spending_tx(
+    script_sig: &[u8],
+    witness: Vec<Vec<u8>>,
+    locktime: u32,
+    seq_no: u32,
+) -> EvaluatedTx {
+    let input = input(TxOutpoint::new([0x11; 32], 0), script_sig, seq_no, witness);
+    let outputs = vec![output(100_000_000, &[0x51])];
+    EvaluatedTx::new(
+        2,
+        VarUint::from(1u8),
+        vec![input],
+        VarUint::from(1u8),
+        outputs,
+        locktime,
+        &AddressParams::default(),
+    )
+}
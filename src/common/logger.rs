@@ -19,7 +19,7 @@ impl SimpleLogger {
         format!(
             "[{} UTC] {} - {}: {}\n",
             OffsetDateTime::now_utc().format("%T"),
-            record.level().to_string(),
+            record.level(),
             record.target(),
             record.args()
         )
@@ -1,2 +1,5 @@
+pub mod bech32;
+#[cfg(test)]
+pub mod fixtures;
 pub mod logger;
 pub mod utils;
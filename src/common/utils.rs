@@ -2,8 +2,11 @@ use std::path::PathBuf;
 
 use crate::blockchain::parser::types::CoinType;
 use crate::crypto::digest::Digest;
+use crate::crypto::rc4::Rc4;
 use crate::crypto::ripemd160::Ripemd160;
+use crate::crypto::sha1::Sha1;
 use crate::crypto::sha2::Sha256;
+use crate::crypto::symmetriccipher::SynchronousStreamCipher;
 
 #[inline]
 pub fn ridemp160(data: &[u8]) -> [u8; 20] {
@@ -14,6 +17,15 @@ pub fn ridemp160(data: &[u8]) -> [u8; 20] {
     out
 }
 
+#[inline]
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut out = [0u8; 20];
+    let mut hasher = Sha1::new();
+    hasher.input(data);
+    hasher.result(&mut out);
+    out
+}
+
 #[inline]
 pub fn sha256(data: &[u8]) -> [u8; 32] {
     let mut out = [0u8; 32];
@@ -23,6 +35,13 @@ pub fn sha256(data: &[u8]) -> [u8; 32] {
     out
 }
 
+/// ARC4 stream cipher, encryption and decryption are the same operation
+pub fn arc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    Rc4::new(key).process(data, &mut out);
+    out
+}
+
 /// Calculates merkle root for the whole block
 /// See: https://en.bitcoin.it/wiki/Protocol_documentation#Merkle_Trees
 pub fn merkle_root(hashes: &[[u8; 32]]) -> [u8; 32] {
@@ -61,7 +80,7 @@ pub fn arr_to_hex_swapped(data: &[u8]) -> String {
 }
 
 pub fn hex_to_vec(hex_str: &str) -> Vec<u8> {
-    if hex_str.len() % 2 != 0 {
+    if !hex_str.len().is_multiple_of(2) {
         panic!("string length is not even");
     }
 
@@ -109,6 +128,13 @@ pub fn get_mean(slice: &[u32]) -> f64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_arc4() {
+        let ciphertext = arc4(b"Key", b"Plaintext");
+        assert_eq!(arr_to_hex(&ciphertext), "bbf316e8d940af0ad3");
+        assert_eq!(arc4(b"Key", &ciphertext), b"Plaintext");
+    }
+
     #[test]
     fn test_arr_to_hex() {
         let test = [
@@ -139,7 +165,7 @@ mod tests {
             0xf7, 0x4f, 0x93, 0x1e, 0x83, 0x65, 0xe1, 0x5a, 0x08, 0x9c, 0x68, 0xd6, 0x19, 0x00,
             0x00, 0x00, 0x00, 0x00,
         ];
-        assert_eq!(hex_to_arr32_swapped(&test), expected);
+        assert_eq!(hex_to_arr32_swapped(test), expected);
     }
 
     #[test]
@@ -167,42 +193,38 @@ mod tests {
 
     #[test]
     fn test_merkle_root() {
-        let mut hashes: Vec<[u8; 32]> = Vec::new();
-        hashes.push([
-            0x8c, 0xb1, 0xdf, 0x74, 0xdb, 0xe9, 0x80, 0xc6, 0xb9, 0x20, 0x2e, 0x91, 0x95, 0x97,
-            0xa5, 0xea, 0xbe, 0xb2, 0xd3, 0x2e, 0x4d, 0xe0, 0x21, 0x4a, 0x39, 0xf8, 0x0c, 0x5f,
-            0xab, 0x9e, 0x45, 0x3a,
-        ]);
-
-        hashes.push([
-            0xb7, 0xa6, 0x06, 0x8e, 0x58, 0x14, 0x73, 0x84, 0x22, 0x76, 0x8b, 0x92, 0xb7, 0xff,
-            0x81, 0xb8, 0x07, 0xfd, 0x51, 0x58, 0x71, 0xed, 0x6a, 0x41, 0x72, 0xba, 0xcc, 0x0e,
-            0x6f, 0xf4, 0x38, 0xbe,
-        ]);
-
-        hashes.push([
-            0xbe, 0x32, 0x73, 0x29, 0xc9, 0x6d, 0x01, 0xbb, 0x0e, 0xf9, 0x39, 0x77, 0xd0, 0x26,
-            0xb8, 0x02, 0xdb, 0x0b, 0x59, 0xbb, 0x7b, 0xfe, 0xd9, 0x77, 0x3a, 0xf6, 0x6f, 0x2b,
-            0xa1, 0xf2, 0x73, 0xd1,
-        ]);
-
-        hashes.push([
-            0x2f, 0x05, 0xc7, 0x5f, 0x38, 0x82, 0x9e, 0xee, 0xaf, 0x84, 0x34, 0x55, 0xdf, 0x87,
-            0xaa, 0xc0, 0xa7, 0xf2, 0xbb, 0x3c, 0xf2, 0x4f, 0x23, 0x91, 0xb4, 0xbb, 0x68, 0x52,
-            0x3e, 0xe8, 0xd1, 0x59,
-        ]);
-
-        hashes.push([
-            0x0c, 0xc6, 0x7a, 0x79, 0xdd, 0x56, 0x4d, 0x24, 0x55, 0xdf, 0x58, 0xb3, 0x71, 0xaf,
-            0xde, 0xb1, 0xa3, 0x1f, 0x44, 0xff, 0xa0, 0x08, 0x3b, 0x9e, 0xb7, 0xef, 0x06, 0x9d,
-            0xa6, 0x77, 0xce, 0xf1,
-        ]);
-
-        hashes.push([
-            0xe0, 0x52, 0xdf, 0x8e, 0x7d, 0x50, 0xda, 0x4b, 0xe4, 0x74, 0xcd, 0x50, 0x5b, 0x21,
-            0x99, 0x6b, 0x74, 0xe3, 0xd0, 0x2f, 0xbf, 0xa1, 0xaf, 0xd3, 0x9f, 0x65, 0xfe, 0x91,
-            0xba, 0x3c, 0x05, 0x84,
-        ]);
+        let hashes: Vec<[u8; 32]> = vec![
+            [
+                0x8c, 0xb1, 0xdf, 0x74, 0xdb, 0xe9, 0x80, 0xc6, 0xb9, 0x20, 0x2e, 0x91, 0x95, 0x97,
+                0xa5, 0xea, 0xbe, 0xb2, 0xd3, 0x2e, 0x4d, 0xe0, 0x21, 0x4a, 0x39, 0xf8, 0x0c, 0x5f,
+                0xab, 0x9e, 0x45, 0x3a,
+            ],
+            [
+                0xb7, 0xa6, 0x06, 0x8e, 0x58, 0x14, 0x73, 0x84, 0x22, 0x76, 0x8b, 0x92, 0xb7, 0xff,
+                0x81, 0xb8, 0x07, 0xfd, 0x51, 0x58, 0x71, 0xed, 0x6a, 0x41, 0x72, 0xba, 0xcc, 0x0e,
+                0x6f, 0xf4, 0x38, 0xbe,
+            ],
+            [
+                0xbe, 0x32, 0x73, 0x29, 0xc9, 0x6d, 0x01, 0xbb, 0x0e, 0xf9, 0x39, 0x77, 0xd0, 0x26,
+                0xb8, 0x02, 0xdb, 0x0b, 0x59, 0xbb, 0x7b, 0xfe, 0xd9, 0x77, 0x3a, 0xf6, 0x6f, 0x2b,
+                0xa1, 0xf2, 0x73, 0xd1,
+            ],
+            [
+                0x2f, 0x05, 0xc7, 0x5f, 0x38, 0x82, 0x9e, 0xee, 0xaf, 0x84, 0x34, 0x55, 0xdf, 0x87,
+                0xaa, 0xc0, 0xa7, 0xf2, 0xbb, 0x3c, 0xf2, 0x4f, 0x23, 0x91, 0xb4, 0xbb, 0x68, 0x52,
+                0x3e, 0xe8, 0xd1, 0x59,
+            ],
+            [
+                0x0c, 0xc6, 0x7a, 0x79, 0xdd, 0x56, 0x4d, 0x24, 0x55, 0xdf, 0x58, 0xb3, 0x71, 0xaf,
+                0xde, 0xb1, 0xa3, 0x1f, 0x44, 0xff, 0xa0, 0x08, 0x3b, 0x9e, 0xb7, 0xef, 0x06, 0x9d,
+                0xa6, 0x77, 0xce, 0xf1,
+            ],
+            [
+                0xe0, 0x52, 0xdf, 0x8e, 0x7d, 0x50, 0xda, 0x4b, 0xe4, 0x74, 0xcd, 0x50, 0x5b, 0x21,
+                0x99, 0x6b, 0x74, 0xe3, 0xd0, 0x2f, 0xbf, 0xa1, 0xaf, 0xd3, 0x9f, 0x65, 0xfe, 0x91,
+                0xba, 0x3c, 0x05, 0x84,
+            ],
+        ];
 
         let expected = [
             0x52, 0xed, 0x57, 0x8c, 0xb6, 0xed, 0x9a, 0xe5, 0xf5, 0x31, 0x6d, 0x45, 0x42, 0x9b,
@@ -2,11 +2,13 @@ use std::convert::{self, From};
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use std::string;
 use std::sync;
 
 use rusty_leveldb::Status;
 
+use crate::blockchain::parser::consensus::ConsensusError;
 use crate::blockchain::proto::script;
 
 /// Returns a string with filename, current code line and column
@@ -49,6 +51,67 @@ impl OpError {
             message: self.message,
         }
     }
+
+    /// Wraps the error into a BlockError carrying the location of the affected block.
+    /// Errors which already carry a location are returned unchanged.
+    pub fn in_block(self, location: DataLocation) -> Self {
+        match self.kind {
+            OpErrorKind::BlockError(..) | OpErrorKind::IndexError(..) => self,
+            kind => OpError {
+                kind: OpErrorKind::BlockError(location, Box::new(kind)),
+                message: self.message,
+            },
+        }
+    }
+
+    /// Wraps the error into an IndexError carrying the location of the malformed record
+    pub fn in_index(self, location: DataLocation) -> Self {
+        match self.kind {
+            OpErrorKind::BlockError(..) | OpErrorKind::IndexError(..) => self,
+            kind => OpError {
+                kind: OpErrorKind::IndexError(location, Box::new(kind)),
+                message: self.message,
+            },
+        }
+    }
+
+    /// Returns the location of the malformed data, if the error was caused by it
+    pub fn location(&self) -> Option<&DataLocation> {
+        match self.kind {
+            OpErrorKind::BlockError(ref location, _) | OpErrorKind::IndexError(ref location, _) => {
+                Some(location)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Describes where malformed data was encountered
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataLocation {
+    pub file: Option<PathBuf>,
+    pub offset: Option<u64>,
+    pub height: Option<u64>,
+}
+
+impl fmt::Display for DataLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::with_capacity(3);
+        if let Some(height) = self.height {
+            parts.push(format!("height {}", height));
+        }
+        if let Some(ref file) = self.file {
+            parts.push(format!("file {}", file.display()));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset {}", offset));
+        }
+        if parts.is_empty() {
+            write!(f, "unknown location")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
 }
 
 impl fmt::Display for OpError {
@@ -77,6 +140,7 @@ pub enum OpErrorKind {
     ByteOrderError(io::Error),
     Utf8Error(string::FromUtf8Error),
     ScriptError(script::ScriptError),
+    ConsensusError(ConsensusError),
     InvalidArgsError,
     CallbackError,
     ValidateError,
@@ -84,6 +148,10 @@ pub enum OpErrorKind {
     PoisonError,
     SendError,
     LevelDBError(String),
+    /// Malformed or invalid block data
+    BlockError(DataLocation, Box<OpErrorKind>),
+    /// Malformed record in the block index
+    IndexError(DataLocation, Box<OpErrorKind>),
 }
 
 impl fmt::Display for OpErrorKind {
@@ -93,13 +161,20 @@ impl fmt::Display for OpErrorKind {
             OpErrorKind::ByteOrderError(ref err) => write!(f, "ByteOrder: {}", err),
             OpErrorKind::Utf8Error(ref err) => write!(f, "Utf8 Conversion: {}", err),
             OpErrorKind::ScriptError(ref err) => write!(f, "Script: {}", err),
+            OpErrorKind::ConsensusError(ref err) => write!(f, "Consensus: {}", err),
             OpErrorKind::LevelDBError(ref err) => write!(f, "LevelDB: {}", err),
-            ref err @ OpErrorKind::PoisonError => write!(f, "Threading Error: {}", err),
-            ref err @ OpErrorKind::SendError => write!(f, "Sync: {}", err),
-            ref err @ OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs: {}", err),
-            ref err @ OpErrorKind::CallbackError => write!(f, "Callback: {}", err),
-            ref err @ OpErrorKind::ValidateError => write!(f, "Validation: {}", err),
-            ref err @ OpErrorKind::RuntimeError => write!(f, "RuntimeError: {}", err),
+            OpErrorKind::BlockError(ref location, ref err) => {
+                write!(f, "Block at {}: {}", location, err)
+            }
+            OpErrorKind::IndexError(ref location, ref err) => {
+                write!(f, "Index at {}: {}", location, err)
+            }
+            OpErrorKind::PoisonError => write!(f, "Threading Error"),
+            OpErrorKind::SendError => write!(f, "Sync Error"),
+            OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs"),
+            OpErrorKind::CallbackError => write!(f, "Callback Error"),
+            OpErrorKind::ValidateError => write!(f, "Validation Error"),
+            OpErrorKind::RuntimeError => write!(f, "RuntimeError"),
             OpErrorKind::None => write!(f, ""),
         }
     }
@@ -112,8 +187,9 @@ impl error::Error for OpErrorKind {
             OpErrorKind::ByteOrderError(ref err) => Some(err),
             OpErrorKind::Utf8Error(ref err) => Some(err),
             OpErrorKind::ScriptError(ref err) => Some(err),
-            ref err @ OpErrorKind::PoisonError => Some(err),
-            ref err @ OpErrorKind::SendError => Some(err),
+            OpErrorKind::ConsensusError(ref err) => Some(err),
+            OpErrorKind::BlockError(_, ref err) => Some(err.as_ref()),
+            OpErrorKind::IndexError(_, ref err) => Some(err.as_ref()),
             _ => None,
         }
     }
@@ -155,6 +231,12 @@ impl convert::From<string::FromUtf8Error> for OpError {
     }
 }
 
+impl convert::From<ConsensusError> for OpError {
+    fn from(err: ConsensusError) -> Self {
+        Self::new(OpErrorKind::ConsensusError(err))
+    }
+}
+
 impl convert::From<rusty_leveldb::Status> for OpError {
     fn from(status: Status) -> Self {
         Self::new(OpErrorKind::LevelDBError(status.err))
@@ -175,4 +257,33 @@ mod tests {
         let err = err.join_msg("Cannot proceed.");
         assert_eq!(format!("{}", err), "Cannot proceed. I/O Error: oh no!");
     }
+
+    #[test]
+    fn test_unit_kinds() {
+        let err = OpError::new(OpErrorKind::ValidateError).join_msg("Chain is incomplete.");
+        assert_eq!(format!("{}", err), "Chain is incomplete. Validation Error");
+        assert!(error::Error::source(&OpErrorKind::PoisonError).is_none());
+    }
+
+    #[test]
+    fn test_block_error() {
+        let location = DataLocation {
+            file: Some(PathBuf::from("blk00001.dat")),
+            offset: Some(8),
+            height: Some(42),
+        };
+        let kind = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        let err = OpError::from(kind).in_block(location.clone());
+        assert_eq!(err.location(), Some(&location));
+        assert_eq!(
+            format!("{}", err),
+            "Block at height 42, file blk00001.dat, offset 8: I/O Error: eof"
+        );
+        assert!(error::Error::source(&err.kind).is_some());
+
+        // The innermost location is kept
+        let err = err.in_block(DataLocation::default());
+        assert_eq!(err.location(), Some(&location));
+        assert!(OpError::new(OpErrorKind::RuntimeError).location().is_none());
+    }
 }
@@ -0,0 +1,487 @@
+//! Blockchain parser for most common cryptocurrencies based on Bitcoin.
+//!
+//! Blocks are read directly from the blk files of a full node, ordered by height
+//! using the node's LevelDB index. Either iterate over them with `ChainStorage`:
+//!
+//! ```no_run
+//! use rusty_blockparser::blockchain::parser::chain::ChainStorage;
+//! use rusty_blockparser::ParserOptions;
+//!
+//! let options = ParserOptions::builder()
+//!     .blockchain_dir("/home/user/.bitcoin/blocks")
+//!     .range(0, Some(1000))
+//!     .build()
+//!     .unwrap();
+//! for result in ChainStorage::new(&options).unwrap() {
+//!     let (height, block) = result.unwrap();
+//!     println!("{}: {} txs", height, block.tx_count.value);
+//! }
+//! ```
+//!
+//! or let `BlockchainParser` drive one or more `Callback`s:
+//!
+//! ```no_run
+//! use rusty_blockparser::blockchain::parser::BlockchainParser;
+//! use rusty_blockparser::callbacks::stats::SimpleStats;
+//! use rusty_blockparser::callbacks::Parallel;
+//! use rusty_blockparser::ParserOptions;
+//!
+//! let options = ParserOptions::builder()
+//!     .callback("simplestats", Parallel(SimpleStats::default()))
+//!     .build()
+//!     .unwrap();
+//! BlockchainParser::new(options).unwrap().start().unwrap();
+//! ```
+
+#[macro_use]
+extern crate log;
+extern crate crypto;
+extern crate time;
+extern crate clap;
+extern crate byteorder;
+extern crate rayon;
+extern crate memmap2;
+extern crate rust_base58;
+extern crate rusty_leveldb;
+extern crate ecdsa;
+extern crate p256;
+extern crate bitcoin_explorer;
+extern crate secp256k1;
+#[macro_use]
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+#[cfg(unix)]
+extern crate libc;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use time::{Date, Format, OffsetDateTime};
+
+use crate::blockchain::parser::blkfile::BlkReadMode;
+use crate::blockchain::parser::types::{CoinType, SighashVariant};
+use crate::callbacks::Callback;
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+#[macro_use]
+pub mod errors;
+pub mod blockchain;
+pub mod common;
+#[macro_use]
+pub mod callbacks;
+
+pub struct ParseRange {
+    start: usize,
+    end: Option<usize>,
+}
+
+impl ParseRange {
+    pub fn new(start: usize, end: Option<usize>) -> OpResult<Self> {
+        if end.is_some() && start >= end.unwrap() {
+            return Err(OpError::from(String::from(
+                "--start value must be lower than --end value",
+            )));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl fmt::Display for ParseRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let end = match self.end {
+            Some(e) => e.to_string(),
+            None => String::from(""),
+        };
+        write!(f, "{}..{}", self.start, end)
+    }
+}
+
+/// Identifies a block of the longest chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockRef {
+    Height(usize),
+    /// Block hash in internal byte order
+    Hash([u8; 32]),
+    /// First block whose median time past is at or after the given unix time
+    Time(i64),
+}
+
+impl FromStr for BlockRef {
+    type Err = OpError;
+
+    /// Parses a height, a block hash in hex or an RFC 3339 timestamp.
+    /// Dates without time (`2013-03-01`) refer to midnight UTC.
+    fn from_str(s: &str) -> OpResult<Self> {
+        let s = s.trim();
+        if let Ok(height) = s.parse::<usize>() {
+            return Ok(BlockRef::Height(height));
+        }
+        if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(BlockRef::Hash(utils::hex_to_arr32_swapped(s)));
+        }
+        if let Ok(datetime) = OffsetDateTime::parse(s, Format::Rfc3339) {
+            return Ok(BlockRef::Time(datetime.unix_timestamp()));
+        }
+        if let Ok(date) = Date::parse(s, "%F") {
+            return Ok(BlockRef::Time(
+                date.midnight().assume_utc().unix_timestamp(),
+            ));
+        }
+        Err(
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                "'{}' is neither a block height, a block hash nor an RFC 3339 timestamp.",
+                s
+            )),
+        )
+    }
+}
+
+impl fmt::Display for BlockRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockRef::Height(height) => write!(f, "{}", height),
+            BlockRef::Hash(ref hash) => write!(f, "{}", utils::arr_to_hex_swapped(hash)),
+            BlockRef::Time(time) => write!(
+                f,
+                "{}",
+                OffsetDateTime::from_unix_timestamp(time).format(Format::Rfc3339)
+            ),
+        }
+    }
+}
+
+/// Defines how blocks with malformed or invalid data are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPolicy {
+    /// Stops parsing at the first malformed block
+    Abort,
+    /// Skips malformed blocks, they are only listed in the final report
+    Skip,
+    /// Skips malformed blocks and logs a warning for each of them
+    Log,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = OpError;
+
+    fn from_str(s: &str) -> OpResult<Self> {
+        match s {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "skip" => Ok(ErrorPolicy::Skip),
+            "log" => Ok(ErrorPolicy::Log),
+            _ => Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "'{}' is not a valid error policy, expected abort, skip or log.",
+                    s
+                )),
+            ),
+        }
+    }
+}
+
+/// Holds all available user arguments
+pub struct ParserOptions {
+    // Callbacks which get executed for each block, along with their names. (See callbacks/mod.rs)
+    callbacks: Vec<(String, Box<dyn Callback>)>,
+    // Holds the name of the coin we want to parse
+    coin_type: CoinType,
+    // Enable this if you want to check linkage, merkle root, proof of work and difficulty of each block.
+    verify: bool,
+    // Runs the scripts of all inputs spending outputs of the parsed range
+    verify_scripts: bool,
+    // Path to directory where blk.dat files are stored
+    blockchain_dir: PathBuf,
+    // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
+    log_level_filter: log::LevelFilter,
+    // First block to parse (inclusive)
+    start: BlockRef,
+    // Block to stop at (exclusive), None parses until the tip
+    end: Option<BlockRef>,
+    // Arbitrary blocks to parse instead of the whole range
+    selection: Option<Vec<BlockRef>>,
+    // Number of threads reading and decoding blocks ahead of the callback
+    worker_threads: usize,
+    // Defines how blocks are read from blk files
+    read_mode: BlkReadMode,
+    // File to store checkpoints in, None disables checkpoints
+    checkpoint: Option<PathBuf>,
+    // Time between two checkpoints
+    checkpoint_interval: Duration,
+    // Continue from the last checkpoint instead of range.start
+    resume: bool,
+    // Keep polling for new blocks after reaching the tip, None stops at the tip
+    follow: Option<Duration>,
+    // Handling of blocks with malformed or invalid data
+    on_error: ErrorPolicy,
+}
+
+impl ParserOptions {
+    /// Returns a builder with default options for the Bitcoin mainnet
+    pub fn builder() -> ParserOptionsBuilder {
+        ParserOptionsBuilder::default()
+    }
+
+    #[inline]
+    pub fn coin_type(&self) -> &CoinType {
+        &self.coin_type
+    }
+
+    #[inline]
+    pub fn blockchain_dir(&self) -> &PathBuf {
+        &self.blockchain_dir
+    }
+
+    #[inline]
+    pub fn log_level_filter(&self) -> log::LevelFilter {
+        self.log_level_filter
+    }
+}
+
+/// Builds ParserOptions, validating them in build()
+pub struct ParserOptionsBuilder {
+    callbacks: Vec<(String, Box<dyn Callback>)>,
+    coin_type: CoinType,
+    verify: bool,
+    verify_scripts: bool,
+    blockchain_dir: Option<PathBuf>,
+    log_level_filter: log::LevelFilter,
+    start: BlockRef,
+    end: Option<BlockRef>,
+    selection: Option<Vec<BlockRef>>,
+    worker_threads: Option<usize>,
+    read_mode: BlkReadMode,
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: Duration,
+    resume: bool,
+    follow: Option<Duration>,
+    on_error: ErrorPolicy,
+}
+
+impl Default for ParserOptionsBuilder {
+    fn default() -> Self {
+        Self {
+            callbacks: Vec::new(),
+            coin_type: CoinType::default(),
+            verify: false,
+            verify_scripts: false,
+            blockchain_dir: None,
+            log_level_filter: log::LevelFilter::Info,
+            start: BlockRef::Height(0),
+            end: None,
+            selection: None,
+            worker_threads: None,
+            read_mode: BlkReadMode::default(),
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(600),
+            resume: false,
+            follow: None,
+            on_error: ErrorPolicy::Abort,
+        }
+    }
+}
+
+impl ParserOptionsBuilder {
+    /// Adds a callback. Callbacks are executed in the order they are added.
+    pub fn callback<C: Callback + 'static>(mut self, name: &str, callback: C) -> Self {
+        self.callbacks
+            .push((String::from(name), Box::new(callback)));
+        self
+    }
+
+    /// Adds an already boxed callback
+    pub fn boxed_callback(mut self, name: &str, callback: Box<dyn Callback>) -> Self {
+        self.callbacks.push((String::from(name), callback));
+        self
+    }
+
+    pub fn coin_type(mut self, coin_type: CoinType) -> Self {
+        self.coin_type = coin_type;
+        self
+    }
+
+    /// Checks linkage, merkle root, proof of work and difficulty of each block
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Verifies the scripts of each input with the consensus rules active at its height.
+    /// Only inputs spending outputs created within the parsed range can be verified.
+    pub fn verify_scripts(mut self, verify_scripts: bool) -> Self {
+        self.verify_scripts = verify_scripts;
+        self
+    }
+
+    /// Directory containing the blk files (default: the coin's folder in the home directory)
+    pub fn blockchain_dir<P: Into<PathBuf>>(mut self, blockchain_dir: P) -> Self {
+        self.blockchain_dir = Some(blockchain_dir.into());
+        self
+    }
+
+    pub fn log_level_filter(mut self, log_level_filter: log::LevelFilter) -> Self {
+        self.log_level_filter = log_level_filter;
+        self
+    }
+
+    /// Parses heights `start..end`, or until the tip if `end` is None
+    pub fn range(mut self, start: usize, end: Option<usize>) -> Self {
+        self.start = BlockRef::Height(start);
+        self.end = end.map(BlockRef::Height);
+        self
+    }
+
+    /// Starts parsing at the given block (inclusive)
+    pub fn start(mut self, start: BlockRef) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Stops parsing before the given block (exclusive)
+    pub fn end(mut self, end: BlockRef) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Parses only the given blocks, in height order.
+    /// Blocks outside of the start/end range are skipped.
+    pub fn selection(mut self, selection: Vec<BlockRef>) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Number of threads decoding blocks (default: number of CPUs)
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    pub fn read_mode(mut self, read_mode: BlkReadMode) -> Self {
+        self.read_mode = read_mode;
+        self
+    }
+
+    /// Periodically saves the progress of all callbacks to `path`
+    pub fn checkpoint<P: Into<PathBuf>>(mut self, path: P, interval: Duration) -> Self {
+        self.checkpoint = Some(path.into());
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    /// Continues from the checkpoint file set with checkpoint()
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Keeps parsing new blocks as the node appends them, checking for them every `interval`.
+    /// Reorgs are passed to the callbacks with on_reorg().
+    pub fn follow(mut self, interval: Duration) -> Self {
+        self.follow = Some(interval);
+        self
+    }
+
+    pub fn on_error(mut self, on_error: ErrorPolicy) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    pub fn build(self) -> OpResult<ParserOptions> {
+        if let (BlockRef::Height(start), Some(BlockRef::Height(end))) = (self.start, self.end) {
+            ParseRange::new(start, Some(end))?;
+        }
+        let worker_threads = self
+            .worker_threads
+            .unwrap_or_else(rayon::current_num_threads);
+        if worker_threads == 0 {
+            return Err(OpError::from(String::from(
+                "--threads value must be at least 1",
+            )));
+        }
+        if let BlkReadMode::Buffered(0) | BlkReadMode::Mmap(0) = self.read_mode {
+            return Err(OpError::from(String::from(
+                "--max-open-files value must be at least 1",
+            )));
+        }
+        if self.resume && self.checkpoint.is_none() {
+            return Err(OpError::from(String::from(
+                "--resume requires --checkpoint",
+            )));
+        }
+        if self.follow.is_some() && (self.end.is_some() || self.selection.is_some()) {
+            return Err(OpError::from(String::from(
+                "--follow can't be combined with --end or --blocks-file",
+            )));
+        }
+        if self.verify_scripts && self.coin_type.sighash != SighashVariant::Legacy {
+            return Err(OpError::from(format!(
+                "--verify-scripts doesn't support the {:?} signature hashes of {}",
+                self.coin_type.sighash, self.coin_type.name
+            )));
+        }
+        let blockchain_dir = match self.blockchain_dir {
+            Some(dir) => dir,
+            None => utils::get_absolute_blockchain_dir(&self.coin_type),
+        };
+        Ok(ParserOptions {
+            callbacks: self.callbacks,
+            coin_type: self.coin_type,
+            verify: self.verify,
+            verify_scripts: self.verify_scripts,
+            blockchain_dir,
+            log_level_filter: self.log_level_filter,
+            start: self.start,
+            end: self.end,
+            selection: self.selection,
+            worker_threads,
+            read_mode: self.read_mode,
+            checkpoint: self.checkpoint,
+            checkpoint_interval: self.checkpoint_interval,
+            resume: self.resume,
+            follow: self.follow,
+            on_error: self.on_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_block_ref() {
+        assert_eq!("1000".parse::<BlockRef>().unwrap(), BlockRef::Height(1000));
+        let hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        assert_eq!(
+            hash.parse::<BlockRef>().unwrap(),
+            BlockRef::Hash(utils::hex_to_arr32_swapped(hash))
+        );
+        assert_eq!(
+            "2009-01-03T18:15:05Z".parse::<BlockRef>().unwrap(),
+            BlockRef::Time(1231006505)
+        );
+        assert_eq!(
+            "2009-01-03T19:15:05+01:00".parse::<BlockRef>().unwrap(),
+            BlockRef::Time(1231006505)
+        );
+        assert_eq!(
+            "2013-03-01".parse::<BlockRef>().unwrap(),
+            BlockRef::Time(1362096000)
+        );
+        assert!("March 2013".parse::<BlockRef>().is_err());
+        assert_eq!(
+            BlockRef::Time(1231006505).to_string(),
+            "2009-01-03T18:15:05+00:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_policy() {
+        assert_eq!("skip".parse::<ErrorPolicy>().unwrap(), ErrorPolicy::Skip);
+        assert_eq!("log".parse::<ErrorPolicy>().unwrap(), ErrorPolicy::Log);
+        assert!("ignore".parse::<ErrorPolicy>().is_err());
+    }
+}
@@ -1,85 +1,32 @@
-use clap::{App, Arg};
-use std::boxed::Box;
-use std::cell::RefCell;
-use std::fmt;
-use std::path::PathBuf;
-use std::process;
-
-use crate::blockchain::parser::chain::ChainStorage;
-use crate::blockchain::parser::types::{Bitcoin, CoinType};
-use crate::blockchain::parser::BlockchainParser;
-use crate::callbacks::balances::Balances;
-use crate::callbacks::csvdump::CsvDump;
-use crate::callbacks::sigdump::SigDump;
-use crate::callbacks::stats::SimpleStats;
-use crate::callbacks::unspentcsvdump::UnspentCsvDump;
-use crate::callbacks::Callback;
-use crate::common::logger::SimpleLogger;
-use crate::common::utils;
-use crate::errors::{OpError, OpResult};
-
 #[macro_use]
 extern crate log;
-extern crate crypto;
-extern crate time;
 #[macro_use]
 extern crate clap;
-extern crate byteorder;
-extern crate rayon;
-extern crate rust_base58;
-extern crate rusty_leveldb;
-extern crate ecdsa;
-extern crate p256;
-extern crate bitcoin_explorer;
-
-#[macro_use]
-pub mod errors;
-pub mod blockchain;
-pub mod common;
-#[macro_use]
-pub mod callbacks;
+extern crate rusty_blockparser;
 
-pub struct ParseRange {
-    start: usize,
-    end: Option<usize>,
-}
-
-impl ParseRange {
-    pub fn new(start: usize, end: Option<usize>) -> OpResult<Self> {
-        if end.is_some() && start >= end.unwrap() {
-            return Err(OpError::from(String::from(
-                "--start value must be lower than --end value",
-            )));
-        }
-        Ok(Self { start, end })
-    }
-}
-
-impl fmt::Display for ParseRange {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let end = match self.end {
-            Some(e) => e.to_string(),
-            None => String::from(""),
-        };
-        write!(f, "{}..{}", self.start, end)
-    }
-}
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::boxed::Box;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
 
-/// Holds all available user arguments
-pub struct ParserOptions {
-    // Name of the callback which gets executed for each block. (See callbacks/mod.rs)
-    callback: Box<dyn Callback>,
-    // Holds the name of the coin we want to parse
-    coin_type: CoinType,
-    // Enable this if you want to check the chain index integrity and merkle root for each block.
-    verify: bool,
-    // Path to directory where blk.dat files are stored
-    blockchain_dir: PathBuf,
-    // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
-    log_level_filter: log::LevelFilter,
-    // Range which is considered for parsing
-    range: ParseRange,
-}
+use rusty_blockparser::blockchain::parser::blkfile::{BlkReadMode, DEFAULT_OPEN_FILES};
+use rusty_blockparser::blockchain::parser::types::CoinRegistry;
+use rusty_blockparser::blockchain::parser::BlockchainParser;
+use rusty_blockparser::callbacks::balances::Balances;
+use rusty_blockparser::callbacks::csvdump::CsvDump;
+use rusty_blockparser::callbacks::inscriptions::Inscriptions;
+use rusty_blockparser::callbacks::pubkeys::Pubkeys;
+use rusty_blockparser::callbacks::sigdump::SigDump;
+use rusty_blockparser::callbacks::stats::SimpleStats;
+use rusty_blockparser::callbacks::timelocks::Timelocks;
+use rusty_blockparser::callbacks::unspentcsvdump::UnspentCsvDump;
+use rusty_blockparser::callbacks::{Callback, Parallel, ParallelCallback};
+use rusty_blockparser::common::logger::SimpleLogger;
+use rusty_blockparser::errors::{OpError, OpResult};
+use rusty_blockparser::{BlockRef, ErrorPolicy, ParserOptions};
 
 fn main() {
     let options = match parse_args() {
@@ -93,24 +40,18 @@ fn main() {
     };
 
     // Apply log filter based on verbosity
-    let log_level = options.borrow().log_level_filter;
+    let log_level = options.log_level_filter();
     SimpleLogger::init(log_level).expect("Unable to initialize logger!");
     info!(target: "main", "Starting rusty-blockparser v{} ...", env!("CARGO_PKG_VERSION"));
     debug!(target: "main", "Using LogLevel {}", log_level);
 
-    let chain_storage = match ChainStorage::new(&options) {
-        Ok(storage) => storage,
+    let mut parser = match BlockchainParser::new(options) {
+        Ok(parser) => parser,
         Err(e) => {
-            error!(
-                "Cannot load blockchain from: '{}'. {}",
-                options.borrow().blockchain_dir.display(),
-                e
-            );
+            error!("{}", e);
             process::exit(1);
         }
     };
-
-    let mut parser = BlockchainParser::new(&options, chain_storage);
     match parser.start() {
         Ok(_) => info!(target: "main", "Fin."),
         Err(why) => {
@@ -121,23 +62,38 @@ fn main() {
 }
 
 /// Parses args or panics if some requirements are not met.
-fn parse_args() -> OpResult<RefCell<ParserOptions>> {
-    let coins = &[
-        "bitcoin",
-        "testnet3",
-        "namecoin",
-        "litecoin",
-        "dogecoin",
-        "myriadcoin",
-        "unobtanium",
-    ];
+fn parse_args() -> OpResult<ParserOptions> {
+    let coin_help = format!(
+        "Specify blockchain coin (default: bitcoin). Built-in: {}",
+        CoinRegistry::builtin().ids().join(", ")
+    );
+    // Additional callbacks are separated by `--`
+    let args: Vec<String> = env::args().collect();
+    let mut segments = args.split(|arg| arg == "--");
+    let main_args = segments.next().unwrap_or(&[]);
+
     let matches = App::new("Multithreaded Blockchain Parser written in Rust")
         .version(crate_version!())
         .author("gcarq <egger.m@protonmail.com>")
         // Add flags
         .arg(Arg::with_name("verify")
             .long("verify")
-            .help("Verifies the leveldb index integrity and verifies merkle roots"))
+            .help("Verifies merkle roots, chain linkage, proof of work and difficulty retargeting of each block"))
+        .arg(Arg::with_name("verify-scripts")
+            .long("verify-scripts")
+            .help("Runs the scripts of all inputs with the consensus rules active at their height. \
+                   Only outputs created within the parsed range can be resolved"))
+        .arg(Arg::with_name("mmap")
+            .long("mmap")
+            .help("Memory-maps blk files instead of reading them (64-bit systems only)"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .requires("checkpoint")
+            .help("Continues from the file given with --checkpoint and appends to the partial output"))
+        .arg(Arg::with_name("follow")
+            .long("follow")
+            .conflicts_with("end")
+            .help("Keeps parsing new blocks as the node appends them instead of stopping at the tip"))
         .arg(Arg::with_name("verbosity")
             .short("v")
             .multiple(true)
@@ -147,8 +103,12 @@ fn parse_args() -> OpResult<RefCell<ParserOptions>> {
             .short("c")
             .long("coin")
             .value_name("NAME")
-            .help("Specify blockchain coin (default: bitcoin)")
-            .possible_values(coins)
+            .help(&coin_help)
+            .takes_value(true))
+        .arg(Arg::with_name("coin-config")
+            .long("coin-config")
+            .value_name("FILE")
+            .help("Loads additional coin definitions from a TOML or JSON file, see coins.toml for the format")
             .takes_value(true))
         .arg(Arg::with_name("blockchain-dir")
             .short("d")
@@ -158,67 +118,181 @@ fn parse_args() -> OpResult<RefCell<ParserOptions>> {
         .arg(Arg::with_name("start")
             .short("s")
             .long("start")
-            .value_name("NUMBER")
-            .help("Specify starting block for parsing (inclusive)")
+            .value_name("BLOCK")
+            .help("Specify starting block for parsing (inclusive). \
+                   Accepts a height, a block hash or an RFC 3339 timestamp")
             .takes_value(true))
         .arg(Arg::with_name("end")
             .short("e")
             .long("end")
+            .value_name("BLOCK")
+            .help("Specify block to stop parsing at (exclusive) (default: all known blocks). \
+                   Accepts a height, a block hash or an RFC 3339 timestamp")
+            .takes_value(true))
+        .arg(Arg::with_name("blocks-file")
+            .long("blocks-file")
+            .value_name("FILE")
+            .help("Only parses the blocks listed in FILE, one height or block hash per line")
+            .takes_value(true))
+        .arg(Arg::with_name("threads")
+            .short("t")
+            .long("threads")
             .value_name("NUMBER")
-            .help("Specify last block for parsing (inclusive) (default: all known blocks)")
+            .help("Number of threads used to read and decode blocks (default: number of CPUs)")
+            .takes_value(true))
+        .arg(Arg::with_name("max-open-files")
+            .long("max-open-files")
+            .value_name("NUMBER")
+            .help("Maximum number of blk files kept open or mapped (default: 32)")
+            .takes_value(true))
+        .arg(Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .value_name("FILE")
+            .help("Periodically saves the progress to FILE, so the run can be continued with --resume")
+            .takes_value(true))
+        .arg(Arg::with_name("checkpoint-interval")
+            .long("checkpoint-interval")
+            .value_name("SECONDS")
+            .help("Time between two checkpoints (default: 600)")
             .takes_value(true))
+        .arg(Arg::with_name("follow-interval")
+            .long("follow-interval")
+            .value_name("SECONDS")
+            .help("Time between two checks for new blocks with --follow (default: 30)")
+            .takes_value(true))
+        .arg(Arg::with_name("on-error")
+            .long("on-error")
+            .value_name("POLICY")
+            .help("What to do with malformed or invalid blocks: abort parsing, skip them \
+                   or skip them and log a warning for each. Skipped blocks are reported at the end (default: abort)")
+            .possible_values(&["abort", "skip", "log"])
+            .takes_value(true))
+        .after_help("Multiple callbacks can be run in a single pass by separating them with `--`, e.g.:\n    \
+                     rusty-blockparser sigdump <dump-folder> <bitcoin-folder> -- balances <dump-folder>")
         // Add callbacks
-        .subcommand(UnspentCsvDump::build_subcommand())
-        .subcommand(CsvDump::build_subcommand())
-        .subcommand(SigDump::build_subcommand())
-        .subcommand(SimpleStats::build_subcommand())
-        .subcommand(Balances::build_subcommand())
-        .get_matches();
+        .subcommands(callback_subcommands())
+        .get_matches_from(main_args);
 
-    let verify = matches.is_present("verify");
     let log_level_filter = match matches.occurrences_of("verbosity") {
         0 => log::LevelFilter::Info,
         1 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
+    let mut coins = CoinRegistry::builtin();
+    if let Some(path) = matches.value_of("coin-config") {
+        coins.load(Path::new(path))?;
+    }
+    let coin_type = coins.get(matches.value_of("coin").unwrap_or("bitcoin"))?;
+    let mut builder = ParserOptions::builder()
+        .verify(matches.is_present("verify"))
+        .verify_scripts(matches.is_present("verify-scripts"))
+        .log_level_filter(log_level_filter)
+        .coin_type(coin_type);
+    if let Some(start) = matches.value_of("start") {
+        builder = builder.start(start.parse()?);
+    }
+    if let Some(end) = matches.value_of("end") {
+        builder = builder.end(end.parse()?);
+    }
+    if let Some(path) = matches.value_of("blocks-file") {
+        builder = builder.selection(read_blocks_file(path)?);
+    }
+    if let Some(dir) = matches.value_of("blockchain-dir") {
+        builder = builder.blockchain_dir(dir);
+    }
+    if let Ok(n) = value_t!(matches, "threads", usize) {
+        builder = builder.worker_threads(n);
+    }
+    let max_open_files = value_t!(matches, "max-open-files", usize).unwrap_or(DEFAULT_OPEN_FILES);
+    if matches.is_present("mmap") && cfg!(target_pointer_width = "64") {
+        builder = builder.read_mode(BlkReadMode::Mmap(max_open_files));
+    } else {
+        if matches.is_present("mmap") {
+            eprintln!("--mmap is not supported on this platform, falling back to file handles.");
+        }
+        builder = builder.read_mode(BlkReadMode::Buffered(max_open_files));
+    }
 
-    let coin_type = value_t!(matches, "coin", CoinType).unwrap_or_else(|_| CoinType::from(Bitcoin));
-    let blockchain_dir = match matches.value_of("blockchain-dir") {
-        Some(p) => PathBuf::from(p),
-        None => utils::get_absolute_blockchain_dir(&coin_type),
-    };
-    let start = value_t!(matches, "start", usize).unwrap_or(0);
-    let end = value_t!(matches, "end", usize).ok();
-    let range = ParseRange::new(start, end)?;
+    if let Some(path) = matches.value_of("checkpoint") {
+        let interval = value_t!(matches, "checkpoint-interval", u64).unwrap_or(600);
+        builder = builder
+            .checkpoint(path, Duration::from_secs(interval))
+            .resume(matches.is_present("resume"));
+    }
 
-    // Set callback
-    let callback: Box<dyn Callback>;
-    if let Some(ref matches) = matches.subcommand_matches("simplestats") {
-        callback = Box::new(SimpleStats::new(matches)?);
-    } else if let Some(ref matches) = matches.subcommand_matches("csvdump") {
-        callback = Box::new(CsvDump::new(matches)?);
-    } else if let Some(ref matches) = matches.subcommand_matches("sigdump") {
-        callback = Box::new(SigDump::new(matches)?);
-    } else if let Some(ref matches) = matches.subcommand_matches("unspentcsvdump") {
-        callback = Box::new(UnspentCsvDump::new(matches)?);
-    } else if let Some(ref matches) = matches.subcommand_matches("balances") {
-        callback = Box::new(Balances::new(matches)?);
-    } else {
-        clap::Error {
+    if let Some(policy) = matches.value_of("on-error") {
+        builder = builder.on_error(policy.parse::<ErrorPolicy>()?);
+    }
+
+    if matches.is_present("follow") {
+        let interval = value_t!(matches, "follow-interval", u64).unwrap_or(30);
+        builder = builder.follow(Duration::from_secs(interval));
+    }
+
+    // Set callbacks
+    match build_callback(&matches)? {
+        Some((name, callback)) => builder = builder.boxed_callback(&name, callback),
+        None => clap::Error {
             message: String::from("error: No Callback specified.\nFor more information try --help"),
             kind: clap::ErrorKind::MissingSubcommand,
             info: None,
         }
-        .exit();
+        .exit(),
     }
+    for callback_args in segments {
+        let matches = App::new("rusty-blockparser")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommands(callback_subcommands())
+            .get_matches_from(args.iter().take(1).chain(callback_args));
+        if let Some((name, callback)) = build_callback(&matches)? {
+            builder = builder.boxed_callback(&name, callback);
+        }
+    }
+    builder.build()
+}
 
-    let options = ParserOptions {
-        coin_type,
-        callback,
-        verify,
-        blockchain_dir,
-        log_level_filter,
-        range,
+/// Reads heights and block hashes from a file, one per line.
+/// Empty lines and lines starting with `#` are ignored.
+fn read_blocks_file(path: &str) -> OpResult<Vec<BlockRef>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| OpError::from(e).join_msg(&format!("Cannot read blocks file '{}'.", path)))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+/// Returns the SubCommands of all available callbacks
+fn callback_subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        UnspentCsvDump::build_subcommand(),
+        CsvDump::build_subcommand(),
+        SigDump::build_subcommand(),
+        SimpleStats::build_subcommand(),
+        Balances::build_subcommand(),
+        Inscriptions::build_subcommand(),
+        Pubkeys::build_subcommand(),
+        Timelocks::build_subcommand(),
+    ]
+}
+
+/// Instantiates the callback selected by the given matches, if any.
+fn build_callback(matches: &ArgMatches) -> OpResult<Option<(String, Box<dyn Callback>)>> {
+    let callback: Box<dyn Callback> = match matches.subcommand() {
+        ("simplestats", Some(matches)) => Box::new(Parallel::<SimpleStats>::new(matches)?),
+        ("csvdump", Some(matches)) => Box::new(CsvDump::new(matches)?),
+        ("sigdump", Some(matches)) => Box::new(Parallel::<SigDump>::new(matches)?),
+        ("unspentcsvdump", Some(matches)) => Box::new(UnspentCsvDump::new(matches)?),
+        ("balances", Some(matches)) => Box::new(Balances::new(matches)?),
+        ("inscriptions", Some(matches)) => Box::new(Parallel::<Inscriptions>::new(matches)?),
+        ("pubkeys", Some(matches)) => Box::new(Parallel::<Pubkeys>::new(matches)?),
+        ("timelocks", Some(matches)) => Box::new(Parallel::<Timelocks>::new(matches)?),
+        _ => return Ok(None),
     };
-    Ok(RefCell::new(options))
+    Ok(Some((
+        String::from(matches.subcommand_name().unwrap_or_default()),
+        callback,
+    )))
 }